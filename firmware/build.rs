@@ -7,8 +7,24 @@ use std::{
 	path::{self, Path, PathBuf},
 };
 
-mandatory_and_unique!("mcu-esp32", "mcu-esp32c3", "mcu-nrf52832", "mcu-nrf52840");
-mandatory_and_unique!("imu-stubbed", "imu-mpu6050", "imu-bmi160");
+mandatory_and_unique!(
+	"mcu-esp32",
+	"mcu-esp32c3",
+	"mcu-nrf52832",
+	"mcu-nrf52840",
+	"mcu-rp2040"
+);
+mandatory_and_unique!(
+	"imu-stubbed",
+	"imu-mpu6050",
+	"imu-mpu9250",
+	"imu-bmi160",
+	"imu-bno085",
+	"imu-icm20948",
+	"imu-lsm6ds3",
+	"imu-lsm6dsv",
+	"imu-autodetect"
+);
 mandatory_and_unique!("log-rtt", "log-usb-serial", "log-uart");
 mandatory_and_unique!("net-wifi", "net-ble", "net-stubbed");
 
@@ -41,9 +57,82 @@ fn main() -> Result<()> {
 
 	// Any relevant env vars for the build script are listed here.
 	println!("cargo:rerun-if-env-changed=BOARD");
+	println!("cargo:rerun-if-env-changed=IMU_RATE");
+	println!("cargo:rerun-if-env-changed=GYRO_RANGE");
+	println!("cargo:rerun-if-env-changed=ACCEL_RANGE");
+	println!("cargo:rerun-if-env-changed=ACCEL_TOLERANCE_G");
+	println!("cargo:rerun-if-env-changed=FAKE_MOTION");
+	println!("cargo:rerun-if-env-changed=FAKE_MOTION_FILE");
+	println!("cargo:rerun-if-env-changed=OUTPUT_SMOOTHING");
+	println!("cargo:rerun-if-env-changed=PREDICTION_LEAD_MS");
+	println!("cargo:rerun-if-env-changed=TEMPERATURE_INTERVAL_S");
+	println!("cargo:rerun-if-env-changed=MAX_PAYLOAD");
 	let _ = dotenvy::dotenv();
 	#[cfg(all(feature = "mcu-nrf52832", feature = "log-usb-serial"))]
 	compile_error!("the nrf52832 doesn't support USB!");
+	#[cfg(all(feature = "fusion-madgwick", feature = "fusion-mahony"))]
+	compile_error!("only one `fusion-*` feature can be enabled at a time");
+	#[cfg(all(feature = "transport-spi", not(feature = "mcu-esp32c3")))]
+	compile_error!("SPI IMUs are only supported on the esp32c3 for now");
+	#[cfg(all(feature = "transport-spi", not(feature = "imu-bmi160")))]
+	compile_error!("only the BMI160 can be used over SPI for now");
+	#[cfg(all(feature = "battery-adc", not(feature = "mcu-esp32c3")))]
+	compile_error!("battery measurement is only supported on the esp32c3 for now");
+	#[cfg(all(
+		feature = "status-led",
+		not(any(
+			feature = "mcu-esp32c3",
+			feature = "mcu-nrf52832",
+			feature = "mcu-nrf52840"
+		))
+	))]
+	compile_error!("the status LED is only supported on the esp32c3 and nrf52 for now");
+	#[cfg(all(feature = "button", not(feature = "mcu-esp32c3")))]
+	compile_error!("the pairing button is only supported on the esp32c3 for now");
+	#[cfg(all(feature = "button", not(feature = "net-wifi")))]
+	compile_error!("the pairing button needs `net-wifi`");
+	#[cfg(all(
+		feature = "deep-sleep",
+		not(any(feature = "mcu-nrf52832", feature = "mcu-nrf52840"))
+	))]
+	compile_error!("deep sleep is only supported on the nrf52 for now");
+	#[cfg(all(
+		feature = "deep-sleep",
+		not(any(feature = "imu-mpu6050", feature = "imu-autodetect"))
+	))]
+	compile_error!(
+		"deep sleep needs an IMU that supports wake-on-motion, like the MPU6050"
+	);
+	#[cfg(all(
+		feature = "watchdog",
+		not(any(
+			feature = "mcu-esp32c3",
+			feature = "mcu-nrf52832",
+			feature = "mcu-nrf52840"
+		))
+	))]
+	compile_error!("the watchdog is only supported on the esp32c3 and nrf52 for now");
+	#[cfg(all(feature = "mcu-rp2040", not(feature = "net-stubbed")))]
+	compile_error!("the rp2040 has no networking yet, use `net-stubbed`");
+	#[cfg(all(feature = "mcu-rp2040", feature = "log-uart"))]
+	compile_error!("the rp2040 can only log over RTT or USB serial for now");
+	#[cfg(all(feature = "ota", not(feature = "net-wifi")))]
+	compile_error!("firmware updates are only supported over wifi");
+	#[cfg(all(feature = "ota", feature = "direct-boot"))]
+	compile_error!(
+		"firmware updates need the bootloader, so `direct-boot` can't be used"
+	);
+	#[cfg(all(feature = "transport-spi", feature = "mux-tca9548a"))]
+	compile_error!("the TCA9548A mux can't be used with SPI IMUs");
+	#[cfg(all(feature = "transport-spi", feature = "i2c-scan"))]
+	compile_error!("`i2c-scan` needs the IMU to be on I2C, not SPI");
+	#[cfg(all(
+		feature = "i2c-secondary",
+		not(any(feature = "mcu-esp32", feature = "mcu-rp2040"))
+	))]
+	compile_error!(
+		"a second I2C bus is only supported on the esp32 and rp2040 for now"
+	);
 
 	// NOTE: Can't use the `cfg_aliases` in the build script itself, only applies to
 	// rest of codebase.
@@ -54,7 +143,9 @@ fn main() -> Result<()> {
 			any(mcu_f_nrf52),
 			any(feature = "log-uart", feature = "log-usb-serial")
 		)},
-		cortex_m: { mcu_f_nrf52 },
+		// Native USB without `defmt-bbq`
+		usb_log: { all(feature = "mcu-rp2040", feature = "log-usb-serial") },
+		cortex_m: { any(mcu_f_nrf52, feature = "mcu-rp2040") },
 		xtensa: { any(feature = "mcu-esp32") },
 		riscv: { any(feature = "mcu-esp32c3") },
 	}
@@ -67,10 +158,228 @@ fn main() -> Result<()> {
 
 	memory_x!("mcu-nrf52832");
 	memory_x!("mcu-nrf52840");
+	memory_x!("mcu-rp2040");
 
 	let board_cfg = BoardConfig::from_file(&BoardConfig::get_path()?)?;
-	board_cfg.apply_to_env();
+	board_cfg.apply_to_env()?;
+
+	imu_rate()?;
+	imu_ranges()?;
+	fake_motion()?;
+	output_smoothing()?;
+	accel_tolerance()?;
+	prediction_lead()?;
+	temperature_interval()?;
+	max_payload()?;
+
+	Ok(())
+}
+
+/// Supported values of the `IMU_RATE` env var, in Hz.
+const IMU_RATES: [&str; 4] = ["50", "100", "200", "500"];
+
+/// Checks the `IMU_RATE` env var and passes it along as the `imu_rate` cfg.
+fn imu_rate() -> Result<()> {
+	let rate = env::var("IMU_RATE").unwrap_or_else(|_| String::from("100"));
+	if !IMU_RATES.contains(&rate.as_str()) {
+		return Err(eyre!(
+			"`IMU_RATE` must be one of {:?}, but it was {rate:?}",
+			IMU_RATES
+		));
+	}
+	println!("cargo:rustc-cfg=imu_rate=\"{rate}\"");
+	Ok(())
+}
+
+/// Supported values of the `GYRO_RANGE` env var, in degrees per second.
+const GYRO_RANGES: [&str; 4] = ["250", "500", "1000", "2000"];
+/// Supported values of the `ACCEL_RANGE` env var, in g.
+const ACCEL_RANGES: [&str; 4] = ["2", "4", "8", "16"];
 
+/// Checks the `GYRO_RANGE` and `ACCEL_RANGE` env vars and passes them along as the
+/// `gyro_range` and `accel_range` cfgs.
+fn imu_ranges() -> Result<()> {
+	let gyro = env::var("GYRO_RANGE").unwrap_or_else(|_| String::from("2000"));
+	if !GYRO_RANGES.contains(&gyro.as_str()) {
+		return Err(eyre!(
+			"`GYRO_RANGE` must be one of {:?}, but it was {gyro:?}",
+			GYRO_RANGES
+		));
+	}
+	let accel = env::var("ACCEL_RANGE").unwrap_or_else(|_| String::from("4"));
+	if !ACCEL_RANGES.contains(&accel.as_str()) {
+		return Err(eyre!(
+			"`ACCEL_RANGE` must be one of {:?}, but it was {accel:?}",
+			ACCEL_RANGES
+		));
+	}
+	println!("cargo:rustc-cfg=gyro_range=\"{gyro}\"");
+	println!("cargo:rustc-cfg=accel_range=\"{accel}\"");
+	Ok(())
+}
+
+/// Supported values of the `FAKE_MOTION` env var.
+const FAKE_MOTIONS: [&str; 4] = ["identity", "yaw-sweep", "tilt", "replay"];
+
+/// Checks the `FAKE_MOTION` env var, which picks what `imu-stubbed` pretends to do, and
+/// passes it along as the `fake_motion` cfg. For `replay`, the recording in the file at
+/// `FAKE_MOTION_FILE` is turned into an array that the firmware includes.
+fn fake_motion() -> Result<()> {
+	let motion = env::var("FAKE_MOTION").unwrap_or_else(|_| String::from("identity"));
+	if !FAKE_MOTIONS.contains(&motion.as_str()) {
+		return Err(eyre!(
+			"`FAKE_MOTION` must be one of {:?}, but it was {motion:?}",
+			FAKE_MOTIONS
+		));
+	}
+	println!("cargo:rustc-cfg=fake_motion=\"{motion}\"");
+	if motion != "replay" {
+		return Ok(());
+	}
+
+	let path = env::var("FAKE_MOTION_FILE")
+		.wrap_err("`FAKE_MOTION=replay` needs a recording in `FAKE_MOTION_FILE`")?;
+	println!("cargo:rerun-if-changed={path}");
+	let recording = fs::read_to_string(&path)
+		.wrap_err(format!("Failed to read the recording: {path}"))?;
+
+	// One quaternion per line as `w, i, j, k`, skipping empty lines and comments
+	let mut array = String::from("[\n");
+	let lines = recording.lines().enumerate();
+	for (i, line) in lines.filter(|(_, l)| !l.trim().is_empty() && !l.starts_with('#'))
+	{
+		let q = line
+			.split(',')
+			.map(|x| x.trim().parse::<f32>())
+			.collect::<Result<Vec<_>, _>>()
+			.ok()
+			.filter(|q| q.len() == 4 && q.iter().all(|x| x.is_finite()))
+			.ok_or_else(|| eyre!("Line {} of {path} isn't `w, i, j, k`", i + 1))?;
+		array += &format!("\t[{:?}, {:?}, {:?}, {:?}],\n", q[0], q[1], q[2], q[3]);
+	}
+	if array.len() == 2 {
+		return Err(eyre!("The recording in {path} is empty"));
+	}
+	array += "]\n";
+
+	let out = path::PathBuf::from(env::var("OUT_DIR").unwrap());
+	fs::write(out.join("fake_motion.rs"), array)?;
+	Ok(())
+}
+
+/// Checks the `OUTPUT_SMOOTHING` env var, and writes it to a file that the firmware
+/// includes as `imu::smoothing::OUTPUT_SMOOTHING`, since cfgs can't hold a float.
+fn output_smoothing() -> Result<()> {
+	let factor = env::var("OUTPUT_SMOOTHING").unwrap_or_else(|_| String::from("0"));
+	let parsed = factor
+		.parse::<f32>()
+		.ok()
+		.filter(|f| (0.0..1.0).contains(f))
+		.ok_or_else(|| {
+			eyre!(
+				"`OUTPUT_SMOOTHING` must be a number from 0 up to but not including 1, \
+				 but it was {factor:?}"
+			)
+		})?;
+
+	let out = path::PathBuf::from(env::var("OUT_DIR").unwrap());
+	fs::write(out.join("output_smoothing.rs"), format!("{parsed:?}"))?;
+	Ok(())
+}
+
+/// Checks the `ACCEL_TOLERANCE_G` env var, and writes it to a file that the firmware
+/// includes as `imu::fusion::ACCEL_TOLERANCE_G`, since cfgs can't hold a float.
+fn accel_tolerance() -> Result<()> {
+	let tolerance =
+		env::var("ACCEL_TOLERANCE_G").unwrap_or_else(|_| String::from("0.5"));
+	let parsed = tolerance
+		.parse::<f32>()
+		.ok()
+		.filter(|t| t.is_finite() && *t > 0.)
+		.ok_or_else(|| {
+			eyre!(
+				"`ACCEL_TOLERANCE_G` must be a positive number of g, but it was \
+				 {tolerance:?}"
+			)
+		})?;
+
+	let out = path::PathBuf::from(env::var("OUT_DIR").unwrap());
+	fs::write(out.join("accel_tolerance.rs"), format!("{parsed:?}"))?;
+	Ok(())
+}
+
+/// Longest `PREDICTION_LEAD_MS` allowed, which matches
+/// `imu::prediction::MAX_PREDICTION_LEAD`.
+const MAX_PREDICTION_LEAD_MS: u64 = 10;
+
+/// Checks the `PREDICTION_LEAD_MS` env var, and writes it to a file that the firmware
+/// includes as `imu::prediction::PREDICTION_LEAD`.
+fn prediction_lead() -> Result<()> {
+	let lead = env::var("PREDICTION_LEAD_MS").unwrap_or_else(|_| String::from("0"));
+	let parsed = lead
+		.parse::<u64>()
+		.ok()
+		.filter(|ms| *ms <= MAX_PREDICTION_LEAD_MS)
+		.ok_or_else(|| {
+			eyre!(
+				"`PREDICTION_LEAD_MS` must be a whole number of milliseconds from 0 to \
+				 {MAX_PREDICTION_LEAD_MS}, but it was {lead:?}"
+			)
+		})?;
+
+	let out = path::PathBuf::from(env::var("OUT_DIR").unwrap());
+	fs::write(out.join("prediction_lead.rs"), format!("{parsed}"))?;
+	Ok(())
+}
+
+/// Longest `TEMPERATURE_INTERVAL_S` allowed, an hour.
+const MAX_TEMPERATURE_INTERVAL_S: u64 = 3600;
+
+/// Checks the `TEMPERATURE_INTERVAL_S` env var, and writes it to a file that the
+/// firmware includes as `networking::protocol::TEMPERATURE_INTERVAL`.
+fn temperature_interval() -> Result<()> {
+	let interval =
+		env::var("TEMPERATURE_INTERVAL_S").unwrap_or_else(|_| String::from("10"));
+	let parsed = interval
+		.parse::<u64>()
+		.ok()
+		.filter(|s| *s <= MAX_TEMPERATURE_INTERVAL_S)
+		.ok_or_else(|| {
+			eyre!(
+				"`TEMPERATURE_INTERVAL_S` must be a whole number of seconds from 0 to \
+				 {MAX_TEMPERATURE_INTERVAL_S}, but it was {interval:?}"
+			)
+		})?;
+
+	let out = path::PathBuf::from(env::var("OUT_DIR").unwrap());
+	fs::write(out.join("temperature_interval.rs"), format!("{parsed}"))?;
+	Ok(())
+}
+
+/// Smallest `MAX_PAYLOAD` allowed, with room to spare for the longest packets that
+/// are sent on their own, like the handshake.
+const MIN_MAX_PAYLOAD: usize = 256;
+/// Largest `MAX_PAYLOAD` allowed, what is left of a 1500 byte Ethernet or Wi-Fi MTU
+/// after the IPv4 and UDP headers.
+const MAX_MAX_PAYLOAD: usize = 1472;
+
+/// Checks the `MAX_PAYLOAD` env var, and writes it to a file that the firmware
+/// includes as `networking::protocol::MAX_PAYLOAD`.
+fn max_payload() -> Result<()> {
+	let max = env::var("MAX_PAYLOAD").unwrap_or_else(|_| MAX_MAX_PAYLOAD.to_string());
+	let parsed = max
+		.parse::<usize>()
+		.ok()
+		.filter(|m| (MIN_MAX_PAYLOAD..=MAX_MAX_PAYLOAD).contains(m))
+		.ok_or_else(|| {
+			eyre!(
+				"`MAX_PAYLOAD` must be a whole number of bytes from {MIN_MAX_PAYLOAD} \
+				 to {MAX_MAX_PAYLOAD}, but it was {max:?}"
+			)
+		})?;
+
+	let out = path::PathBuf::from(env::var("OUT_DIR").unwrap());
+	fs::write(out.join("max_payload.rs"), format!("{parsed}"))?;
 	Ok(())
 }
 
@@ -135,6 +444,9 @@ impl MemoryLayout {
 
 #[derive(Debug, Deserialize)]
 struct BoardConfig {
+	/// Clock speed of the I2C bus. Slower speeds are more reliable with long wires or
+	/// weak pull-up resistors.
+	i2c_khz: Option<u32>,
 	pins: Pins,
 }
 #[derive(Debug, Deserialize)]
@@ -145,6 +457,20 @@ struct Pins {
 	int1: String,
 	tx: String,
 	rx: String,
+	// Only needed for IMUs on SPI
+	sck: Option<String>,
+	mosi: Option<String>,
+	miso: Option<String>,
+	cs: Option<String>,
+	// Only needed for measuring the battery
+	battery: Option<String>,
+	// Only needed for the status LED
+	led: Option<String>,
+	// Only needed for the pairing button
+	button: Option<String>,
+	// Only needed for the second I2C bus
+	scl2: Option<String>,
+	sda2: Option<String>,
 }
 impl BoardConfig {
 	/// Loads a board config from a file
@@ -177,12 +503,22 @@ impl BoardConfig {
 		let result = Some(boards_dir.join("xiao_sense.toml"));
 		#[cfg(feature = "mcu-nrf52832")]
 		let result = Some(boards_dir.join("nrf52832_tmp.toml"));
+		#[cfg(feature = "mcu-rp2040")]
+		let result = Some(boards_dir.join("pico.toml"));
 
 		result
 	}
 
 	/// Applies the board config to cargo's environment variables
-	fn apply_to_env(&self) {
+	fn apply_to_env(&self) -> Result<()> {
+		let i2c_khz = self.i2c_khz.unwrap_or(400);
+		if !(1..=1000).contains(&i2c_khz) {
+			return Err(eyre!(
+				"`i2c_khz` must be between 1 and 1000, but it was {i2c_khz}"
+			));
+		}
+		println!("cargo:rustc-env=I2C_KHZ={i2c_khz}");
+
 		macro_rules! set_var {
 			($var:literal, $field:ident) => {
 				println!("cargo:rustc-env={}={}", $var, self.pins.$field);
@@ -194,5 +530,30 @@ impl BoardConfig {
 		set_var!("PIN_INT1", int1);
 		set_var!("PIN_TX", tx);
 		set_var!("PIN_RX", rx);
+
+		macro_rules! set_opt_var {
+			($var:literal, $field:ident) => {
+				if let Some(pin) = &self.pins.$field {
+					println!("cargo:rustc-env={}={}", $var, pin);
+				}
+			};
+		}
+		set_opt_var!("PIN_SCK", sck);
+		set_opt_var!("PIN_MOSI", mosi);
+		set_opt_var!("PIN_MISO", miso);
+		set_opt_var!("PIN_CS", cs);
+		set_opt_var!("PIN_BATTERY", battery);
+		set_opt_var!("PIN_LED", led);
+		set_opt_var!("PIN_BUTTON", button);
+		set_opt_var!("PIN_SCL2", scl2);
+		set_opt_var!("PIN_SDA2", sda2);
+
+		#[cfg(feature = "i2c-secondary")]
+		if self.pins.scl2.is_none() || self.pins.sda2.is_none() {
+			return Err(eyre!(
+				"`i2c-secondary` needs the `scl2` and `sda2` pins in the board toml"
+			));
+		}
+		Ok(())
 	}
 }
@@ -1,15 +1,32 @@
 #![no_std]
 
-use atomic_polyfill::{AtomicBool, Ordering};
+use core::panic::PanicInfo;
+use core::ptr;
+
+use atomic_polyfill::{AtomicBool, AtomicPtr, Ordering};
 
 use defmt::error;
 
+/// The function set with [`set_hook()`], or null.
+static HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Sets a function that is called once after the first panic is logged, before the
+/// MCU halts. It can be used to report the panic somewhere else, on a best effort
+/// basis. If it panics itself, the second panic just halts.
+///
+/// Pass `None` to remove the hook.
+pub fn set_hook(hook: Option<fn(&PanicInfo)>) {
+	let hook = hook.map_or(ptr::null_mut(), |hook| hook as *mut ());
+	HOOK.store(hook, Ordering::SeqCst);
+}
+
 #[panic_handler]
-fn panic(info: &core::panic::PanicInfo) -> ! {
+fn panic(info: &PanicInfo) -> ! {
 	static PANICKED: AtomicBool = AtomicBool::new(false);
 
 	// TODO: What `Ordering` should this use?
-	if !PANICKED.swap(true, Ordering::SeqCst) {
+	let first = !PANICKED.swap(true, Ordering::SeqCst);
+	if first {
 		if let Some(location) = info.location() {
 			let (file, line, column) =
 				(location.file(), location.line(), location.column());
@@ -23,5 +40,13 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
 	}
 	error!("{:#?}", defmt::Debug2Format(info));
 
+	let hook = HOOK.load(Ordering::SeqCst);
+	if first && !hook.is_null() {
+		// SAFETY: Only `set_hook()` stores non-null pointers, and those came from a
+		// `fn(&PanicInfo)`
+		let hook = unsafe { core::mem::transmute::<*mut (), fn(&PanicInfo)>(hook) };
+		hook(info);
+	}
+
 	loop {}
 }
@@ -6,6 +6,18 @@ pub mod ඞ {
 	pub use esp32_hal::Delay as DelayConcrete;
 
 	pub type I2cConcrete<'a> = esp32_hal::i2c::I2C<esp32_hal::pac::I2C0>;
+	#[cfg(feature = "i2c-secondary")]
+	pub type I2c2Concrete<'a> = esp32_hal::i2c::I2C<esp32_hal::pac::I2C1>;
+
+	pub type UartConcrete<'a> = esp32_hal::Uart<esp32_hal::pac::UART0>;
+
+	pub type FlashConcrete<'a> = esp_storage::FlashStorage;
+	/// Last sector of a 4MB flash, past the end of the app partition.
+	pub const CONFIG_STORE_OFFSET: u32 = 0x3F_F000;
+	/// Layout of the app slots, matching `partitions.csv`.
+	pub const OTA_DATA_OFFSET: u32 = 0xD000;
+	pub const OTA_SLOTS: [u32; 2] = [0x1_0000, 0x1F_0000];
+	pub const OTA_SLOT_SIZE: u32 = 0x1E_0000;
 
 	pub type BbqPeripheral<'a> = ();
 }
@@ -17,6 +29,30 @@ pub mod ඞ {
 
 	pub type I2cConcrete<'a> = esp32c3_hal::i2c::I2C<esp32c3_hal::pac::I2C0>;
 
+	pub type UartConcrete<'a> = esp32c3_hal::Uart<esp32c3_hal::pac::UART0>;
+
+	pub type SpiConcrete<'a> = esp32c3_hal::spi::Spi<esp32c3_hal::pac::SPI2>;
+
+	#[cfg(feature = "battery-adc")]
+	pub type BatteryConcrete = crate::peripherals::ඞ::Adc;
+
+	#[cfg(feature = "status-led")]
+	pub type LedConcrete = crate::peripherals::ඞ::Led;
+
+	#[cfg(feature = "button")]
+	pub type ButtonConcrete = crate::peripherals::ඞ::Button;
+
+	#[cfg(feature = "watchdog")]
+	pub type WatchdogConcrete = crate::peripherals::ඞ::Watchdog;
+
+	pub type FlashConcrete<'a> = esp_storage::FlashStorage;
+	/// Last sector of a 4MB flash, past the end of the app partition.
+	pub const CONFIG_STORE_OFFSET: u32 = 0x3F_F000;
+	/// Layout of the app slots, matching `partitions.csv`.
+	pub const OTA_DATA_OFFSET: u32 = 0xD000;
+	pub const OTA_SLOTS: [u32; 2] = [0x1_0000, 0x1F_0000];
+	pub const OTA_SLOT_SIZE: u32 = 0x1E_0000;
+
 	pub type BbqPeripheral<'a> = ();
 }
 
@@ -30,6 +66,14 @@ pub mod ඞ {
 	pub type UartConcrete<'a> =
 		embassy_nrf::uarte::Uarte<'a, embassy_nrf::peripherals::UARTE0>;
 
+	pub type FlashConcrete<'a> = embassy_nrf::nvmc::Nvmc<'a>;
+	/// Last page of the flash.
+	#[cfg(feature = "mcu-nrf52840")]
+	pub const CONFIG_STORE_OFFSET: u32 = 0xF_F000;
+	/// Last page of the flash.
+	#[cfg(feature = "mcu-nrf52832")]
+	pub const CONFIG_STORE_OFFSET: u32 = 0x7_F000;
+
 	#[cfg(feature = "mcu-nrf52840")]
 	pub type UsbDriverConcrete<'a> = embassy_nrf::usb::Driver<
 		'a,
@@ -40,6 +84,15 @@ pub mod ඞ {
 	#[cfg(feature = "mcu-nrf52832")]
 	pub type UsbDriverConcrete<'a> = ();
 
+	#[cfg(feature = "status-led")]
+	pub type LedConcrete = crate::peripherals::ඞ::Led;
+
+	#[cfg(feature = "deep-sleep")]
+	pub type PowerConcrete = crate::peripherals::ඞ::Power;
+
+	#[cfg(feature = "watchdog")]
+	pub type WatchdogConcrete = crate::peripherals::ඞ::Watchdog;
+
 	#[cfg(all(bbq, feature = "log-usb-serial"))]
 	pub type BbqPeripheralConcrete<'a> = UsbDriverConcrete<'a>;
 	#[cfg(all(bbq, feature = "log-uart"))]
@@ -48,6 +101,42 @@ pub mod ඞ {
 	pub type BbqPeripheralConcrete<'a> = ();
 }
 
+#[cfg(feature = "mcu-rp2040")]
+pub mod ඞ {
+	pub use embassy_time::Delay as DelayConcrete;
+
+	pub type I2cConcrete<'a> = embassy_rp::i2c::I2c<
+		'a,
+		embassy_rp::peripherals::I2C0,
+		embassy_rp::i2c::Blocking,
+	>;
+	#[cfg(feature = "i2c-secondary")]
+	pub type I2c2Concrete<'a> = embassy_rp::i2c::I2c<
+		'a,
+		embassy_rp::peripherals::I2C1,
+		embassy_rp::i2c::Blocking,
+	>;
+
+	pub type UartConcrete<'a> = embassy_rp::uart::Uart<
+		'a,
+		embassy_rp::peripherals::UART0,
+		embassy_rp::uart::Blocking,
+	>;
+
+	pub type UsbDriverConcrete<'a> =
+		embassy_rp::usb::Driver<'a, embassy_rp::peripherals::USB>;
+
+	/// Size of the flash on the Pico. Boards with more flash can use it, but we only
+	/// touch the first 2MB.
+	pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+	pub type FlashConcrete<'a> =
+		embassy_rp::flash::Flash<'a, embassy_rp::peripherals::FLASH, FLASH_SIZE>;
+	/// Last sector of the flash.
+	pub const CONFIG_STORE_OFFSET: u32 = 0x1F_F000;
+
+	pub type BbqPeripheralConcrete<'a> = ();
+}
+
 pub trait I2c:
 	embedded_hal::blocking::i2c::Write<Error = <Self as I2c>::Error>
 	+ embedded_hal::blocking::i2c::WriteRead<Error = <Self as I2c>::Error>
@@ -65,6 +154,21 @@ impl<
 	type Error = E;
 }
 
+pub trait Spi:
+	embedded_hal::blocking::spi::Transfer<u8, Error = <Self as Spi>::Error>
+	+ embedded_hal::blocking::spi::Write<u8, Error = <Self as Spi>::Error>
+{
+	type Error: core::fmt::Debug;
+}
+impl<
+		T: embedded_hal::blocking::spi::Transfer<u8, Error = E>
+			+ embedded_hal::blocking::spi::Write<u8, Error = E>,
+		E: core::fmt::Debug,
+	> Spi for T
+{
+	type Error = E;
+}
+
 pub trait Delay:
 	embedded_hal::blocking::delay::DelayMs<u8> + embedded_hal::blocking::delay::DelayMs<u32>
 {
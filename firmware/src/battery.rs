@@ -0,0 +1,124 @@
+//! Measures the battery voltage through a voltage divider on an ADC pin, and reports
+//! it to the server.
+
+use defmt::{debug, trace, warn};
+use embassy_executor::task;
+use embassy_time::{Duration, Timer};
+
+use crate::aliases::ඞ::BatteryConcrete;
+use crate::status::{Flag, STATUS};
+use crate::utils::Unreliable;
+
+/// How often the battery is sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+/// How many samples are averaged. A single sample taken while the radio is
+/// transmitting can sag a lot, so we don't want to report it as is.
+const WINDOW: usize = 8;
+/// The battery is connected to the ADC pin through a divider that halves its voltage.
+const DIVIDER_RATIO: f32 = 2.;
+/// Below this charge level the status LED tells the user to charge the battery.
+const LOW_BATTERY_LEVEL: f32 = 0.1;
+
+/// Charge level of a single cell LiPo at a given voltage, from empty to full. Levels
+/// in between are interpolated linearly. Tweak this to match your battery.
+const LIPO_CURVE: [(f32, f32); 8] = [
+	(3.30, 0.),
+	(3.60, 0.05),
+	(3.70, 0.20),
+	(3.75, 0.35),
+	(3.80, 0.50),
+	(3.90, 0.65),
+	(4.00, 0.80),
+	(4.20, 1.),
+];
+
+/// A battery reading, as sent to the server.
+#[derive(defmt::Format, Debug, Copy, Clone, PartialEq)]
+pub struct BatteryLevel {
+	/// Battery voltage, in volts.
+	pub voltage: f32,
+	/// Remaining charge, from 0 to 1.
+	pub level: f32,
+}
+
+/// An ADC that is connected to the battery.
+pub trait BatteryAdc {
+	/// Reads the voltage at the ADC pin, in millivolts.
+	fn read_mv(&mut self) -> nb::Result<u32, ()>;
+}
+
+/// Samples the battery and signals the averaged level.
+#[task]
+pub async fn battery_task(
+	battery: &'static Unreliable<BatteryLevel>,
+	adc: BatteryConcrete,
+) -> ! {
+	battery_task_inner(battery, adc).await
+}
+
+/// Same as [`battery_task()`] but this version's arguments are type erased behind impl
+/// Trait to avoid accidentally accessing concrete behavior.
+async fn battery_task_inner(
+	battery: &Unreliable<BatteryLevel>,
+	mut adc: impl BatteryAdc,
+) -> ! {
+	debug!("Battery task");
+	let mut average = MovingAverage::new();
+	loop {
+		match nb::block!(adc.read_mv()) {
+			Ok(mv) => {
+				let voltage = mv as f32 / 1000. * DIVIDER_RATIO;
+				trace!("Battery sample: {}V", voltage);
+				let voltage = average.add(voltage);
+				let level = level_at(voltage);
+				STATUS.set(Flag::LowBattery, level < LOW_BATTERY_LEVEL);
+				battery.signal(BatteryLevel { voltage, level });
+			}
+			Err(()) => warn!("Failed to read battery voltage"),
+		}
+		Timer::after(SAMPLE_INTERVAL).await;
+	}
+}
+
+/// Looks up the charge level of a battery at `voltage` in [`LIPO_CURVE`].
+fn level_at(voltage: f32) -> f32 {
+	let (first, last) = (LIPO_CURVE[0], LIPO_CURVE[LIPO_CURVE.len() - 1]);
+	if voltage <= first.0 {
+		return first.1;
+	}
+	if voltage >= last.0 {
+		return last.1;
+	}
+	for pair in LIPO_CURVE.windows(2) {
+		let ((v0, l0), (v1, l1)) = (pair[0], pair[1]);
+		if voltage <= v1 {
+			return l0 + (voltage - v0) / (v1 - v0) * (l1 - l0);
+		}
+	}
+	last.1
+}
+
+/// Mean of the last [`WINDOW`] samples.
+struct MovingAverage {
+	samples: [f32; WINDOW],
+	/// Where the next sample goes.
+	next: usize,
+	len: usize,
+}
+impl MovingAverage {
+	fn new() -> Self {
+		Self {
+			samples: [0.; WINDOW],
+			next: 0,
+			len: 0,
+		}
+	}
+
+	/// Adds a sample, and returns the new average.
+	fn add(&mut self, sample: f32) -> f32 {
+		self.samples[self.next] = sample;
+		self.next = (self.next + 1) % WINDOW;
+		self.len = (self.len + 1).min(WINDOW);
+		self.samples[..self.len].iter().sum::<f32>() / self.len as f32
+	}
+}
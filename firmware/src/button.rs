@@ -0,0 +1,88 @@
+//! Reads the pairing button, and turns presses into [`ButtonEvent`]s.
+//!
+//! A short press makes the tracker look for the server again, and a long press
+//! forgets the WiFi credentials and calibration, and restarts into provisioning.
+
+use defmt::{debug, info, warn};
+use embassy_executor::task;
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::digital::v2::InputPin;
+
+use crate::aliases::ඞ::ButtonConcrete;
+
+/// How often the button is sampled.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long the button has to stay in the same state before we believe it. Switches
+/// bounce for a few milliseconds when they are pressed or released.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+/// Presses held at least this long are long presses.
+const LONG_PRESS: Duration = Duration::from_secs(5);
+
+/// The latest press of the button, waiting to be handled by the network task.
+pub static BUTTON_EVENTS: Signal<CriticalSectionRawMutex, ButtonEvent> = Signal::new();
+
+#[derive(defmt::Format, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ButtonEvent {
+	/// Pressed for less than [`LONG_PRESS`].
+	ShortPress,
+	/// Held for [`LONG_PRESS`]. This is sent as soon as the threshold is reached,
+	/// without waiting for the release.
+	LongPress,
+}
+
+/// Watches the button and signals [`BUTTON_EVENTS`].
+#[task]
+pub async fn button_task(button: ButtonConcrete) -> ! {
+	button_task_inner(button).await
+}
+
+/// Same as [`button_task()`] but this version's arguments are type erased behind impl
+/// Trait to avoid accidentally accessing concrete behavior.
+async fn button_task_inner(button: impl InputPin) -> ! {
+	debug!("Button task");
+	// A button that is held at boot is most likely stuck. If we took that as a long
+	// press, we would reset over and over, so it has to be released first.
+	if is_pressed(&button) {
+		warn!("Button is held at boot, ignoring it until it is released");
+	}
+	wait_for(&button, false).await;
+
+	loop {
+		wait_for(&button, true).await;
+		let pressed_at = Instant::now();
+		let released =
+			select(wait_for(&button, false), Timer::at(pressed_at + LONG_PRESS)).await;
+		match released {
+			Either::First(()) => {
+				info!("Button pressed");
+				BUTTON_EVENTS.signal(ButtonEvent::ShortPress);
+			}
+			Either::Second(()) => {
+				info!("Button held for {}s", LONG_PRESS.as_secs());
+				BUTTON_EVENTS.signal(ButtonEvent::LongPress);
+				wait_for(&button, false).await;
+			}
+		}
+	}
+}
+
+/// The button pulls the pin low when pressed.
+fn is_pressed(button: &impl InputPin) -> bool {
+	matches!(button.is_low(), Ok(true))
+}
+
+/// Waits until the button has been `pressed` (or released) for at least [`DEBOUNCE`].
+async fn wait_for(button: &impl InputPin, pressed: bool) {
+	let mut since = Instant::now();
+	loop {
+		if is_pressed(button) != pressed {
+			since = Instant::now();
+		} else if since.elapsed() >= DEBOUNCE {
+			return;
+		}
+		Timer::after(POLL_INTERVAL).await;
+	}
+}
@@ -0,0 +1,110 @@
+//! Gyroscope and magnetometer calibration, which is persisted to flash so that the
+//! tracker doesn't need to be held still on every boot.
+
+use crate::imu::{FusedImu, MagCalibration, Vec3, MAX_IMUS};
+use crate::peripherals::config::ConfigStore;
+use crate::status::{Flag, STATUS};
+
+use defmt::{debug, info, warn};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_storage::nor_flash::NorFlash;
+
+/// Signal this to make the IMU task recalibrate every IMU and store the result.
+#[allow(dead_code)]
+pub static RECALIBRATE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signal this to make the IMU task start calibrating the magnetometer of every IMU.
+/// The user has to wave the tracker around in a figure eight while it runs.
+#[allow(dead_code)]
+pub static RECALIBRATE_MAG: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The gyroscope bias of each IMU, indexed by sensor id. `None` for absent IMUs and
+/// for IMUs that calibrate themselves.
+pub type GyroBiases = [Option<Vec3>; MAX_IMUS];
+
+/// Biases that differ less than this (in rad/s, on every axis) from the stored ones
+/// aren't written, to avoid wearing out the flash.
+const WRITE_THRESHOLD: f32 = 0.002;
+
+/// Loads the biases stored in flash.
+pub fn load<F: NorFlash>(store: &mut ConfigStore<F>) -> GyroBiases {
+	store.load().gyro_biases
+}
+
+/// Stores `biases` in flash, unless they are close enough to `stored` already.
+pub fn save_if_changed<F: NorFlash>(
+	store: &mut ConfigStore<F>,
+	stored: &GyroBiases,
+	biases: &GyroBiases,
+) {
+	let changed = stored.iter().zip(biases).any(|(a, b)| match (a, b) {
+		(Some(a), Some(b)) => (a - b).amax() > WRITE_THRESHOLD,
+		(None, None) => false,
+		_ => true,
+	});
+	if !changed {
+		debug!("Calibration unchanged, not writing to flash");
+		return;
+	}
+
+	match store.update(|config| config.gyro_biases = *biases) {
+		Ok(()) => info!("Saved calibration to flash"),
+		Err(err) => warn!("Failed to save calibration: {}", defmt::Debug2Format(&err)),
+	}
+}
+
+/// The magnetometer calibration of each IMU, indexed by sensor id. `None` for IMUs
+/// without a calibrated magnetometer.
+pub type MagCalibrations = [Option<MagCalibration>; MAX_IMUS];
+
+/// Loads the magnetometer calibrations stored in flash.
+pub fn load_mag<F: NorFlash>(store: &mut ConfigStore<F>) -> MagCalibrations {
+	store.load().mag_calibrations
+}
+
+/// Stores the magnetometer calibration of every IMU whose calibration differs from
+/// `stored`, and updates `stored` to match. IMUs without a calibration keep the
+/// stored one, since they might just be temporarily missing.
+pub fn save_mag_if_changed<F: NorFlash>(
+	store: &mut ConfigStore<F>,
+	stored: &mut MagCalibrations,
+	imus: &[Option<impl FusedImu>],
+) {
+	let mut changed = false;
+	for (imu, stored) in imus.iter().zip(stored.iter_mut()) {
+		let cal = imu.as_ref().and_then(|imu| imu.mag_calibration());
+		if cal.is_some() && cal != *stored {
+			*stored = cal;
+			changed = true;
+		}
+	}
+	if !changed {
+		return;
+	}
+
+	match store.update(|config| config.mag_calibrations = *stored) {
+		Ok(()) => info!("Saved magnetometer calibration to flash"),
+		Err(err) => warn!(
+			"Failed to save magnetometer calibration: {}",
+			defmt::Debug2Format(&err)
+		),
+	}
+}
+
+/// Calibrates the IMU with sensor id `id`, logging the outcome.
+pub fn calibrate(id: usize, imu: &mut impl FusedImu, delay: &mut impl DelayMs<u32>) {
+	info!("Calibrating IMU {}, keep it still", id);
+	STATUS.set(Flag::Calibrating, true);
+	let result = imu.calibrate(delay);
+	STATUS.set(Flag::Calibrating, false);
+	match result {
+		Ok(()) => info!("Calibrated IMU {}", id),
+		Err(err) => warn!(
+			"Failed to calibrate IMU {}: {}",
+			id,
+			defmt::Debug2Format(&err)
+		),
+	}
+}
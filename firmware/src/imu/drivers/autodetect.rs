@@ -0,0 +1,276 @@
+//! Picks the IMU driver at runtime, by probing the I2C bus for known chips. This lets
+//! one firmware image work with any of the supported IMUs.
+
+use crate::aliases::I2c;
+use crate::imu::drivers::bmi160::{Bmi160, BmiError, I2cInterface};
+use crate::imu::drivers::bno085::{self, Bno085};
+use crate::imu::drivers::icm20948::{self, Icm20948};
+use crate::imu::drivers::mpu6050::Mpu6050;
+use crate::imu::drivers::stubbed::{FakeImu, Motion};
+use crate::imu::fusion::Fused;
+use crate::imu::{Accuracy, FusedImu, ImuConfig, ImuData, Quat, SampleRate, Vec3};
+
+use defmt::{debug, info, warn};
+use embassy_time::Duration;
+use embedded_hal::blocking::delay::DelayMs;
+use firmware_protocol::ImuType;
+
+/// The address that the MPU and BMI families use when AD0/SDO is low.
+const ADDR_PRIMARY: u8 = 0x68;
+/// The address that the MPU and BMI families use when AD0/SDO is high.
+const ADDR_ALTERNATE: u8 = 0x69;
+/// The BNO08x address when SA0 is high.
+const ADDR_BNO_ALTERNATE: u8 = 0x4B;
+
+/// WHO_AM_I register of the MPU family, and its value for the MPU6050.
+const MPU_WHO_AM_I: u8 = 0x75;
+const MPU6050_ID: u8 = 0x68;
+/// Both the ICM-20948 and the BMI160 keep their chip id in register 0.
+const CHIP_ID: u8 = 0x00;
+const ICM20948_ID: u8 = 0xEA;
+const BMI160_ID: u8 = 0xD1;
+
+/// Any of the IMUs that [`new_imu()`] can detect.
+pub enum AutoImu<I: I2c> {
+	Bmi160(Fused<Bmi160<I2cInterface<I>>>),
+	Bno085(Bno085<I>),
+	Icm20948(Fused<Icm20948<I>>),
+	Mpu6050(Mpu6050<I>),
+	/// Nothing answered, or the IMU that did failed to initialize.
+	Fake(FakeImu),
+}
+
+pub enum AutoError<I: I2c> {
+	Bmi160(BmiError<I>),
+	Bno085(<I as I2c>::Error),
+	Icm20948(icm20948::Error<I>),
+	Mpu6050(mpu6050_dmp::error::Error<I>),
+}
+impl<I> core::fmt::Debug for AutoError<I>
+where
+	I: I2c,
+{
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Self::Bmi160(err) => err.fmt(f),
+			Self::Bno085(err) => err.fmt(f),
+			Self::Icm20948(err) => err.fmt(f),
+			Self::Mpu6050(err) => err.fmt(f),
+		}
+	}
+}
+
+impl<I: I2c> FusedImu for AutoImu<I> {
+	type Error = AutoError<I>;
+
+	/// Not known until runtime, use [`FusedImu::imu_type()`] instead.
+	const IMU_TYPE: ImuType = ImuType::Unknown(0xFF);
+
+	fn quat(&mut self) -> nb::Result<Quat, Self::Error> {
+		match self {
+			Self::Bmi160(imu) => imu.quat().map_err(|e| e.map(AutoError::Bmi160)),
+			Self::Bno085(imu) => imu.quat().map_err(|e| e.map(AutoError::Bno085)),
+			Self::Icm20948(imu) => imu.quat().map_err(|e| e.map(AutoError::Icm20948)),
+			Self::Mpu6050(imu) => imu.quat().map_err(|e| e.map(AutoError::Mpu6050)),
+			// FakeImu never errors
+			Self::Fake(imu) => imu.quat().map_err(|e| e.map(|()| unreachable!())),
+		}
+	}
+
+	fn reinit(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		match self {
+			Self::Bmi160(imu) => imu.reinit(delay).map_err(AutoError::Bmi160),
+			Self::Bno085(imu) => imu.reinit(delay).map_err(AutoError::Bno085),
+			Self::Icm20948(imu) => imu.reinit(delay).map_err(AutoError::Icm20948),
+			Self::Mpu6050(imu) => imu.reinit(delay).map_err(AutoError::Mpu6050),
+			Self::Fake(_) => Ok(()),
+		}
+	}
+
+	fn set_rate(
+		&mut self,
+		rate: SampleRate,
+		delay: &mut impl DelayMs<u32>,
+	) -> Result<(), Self::Error> {
+		match self {
+			Self::Bmi160(imu) => imu.set_rate(rate, delay).map_err(AutoError::Bmi160),
+			Self::Bno085(imu) => imu.set_rate(rate, delay).map_err(AutoError::Bno085),
+			Self::Icm20948(imu) => {
+				imu.set_rate(rate, delay).map_err(AutoError::Icm20948)
+			}
+			Self::Mpu6050(imu) => imu.set_rate(rate, delay).map_err(AutoError::Mpu6050),
+			// FakeImu never errors
+			Self::Fake(imu) => imu.set_rate(rate, delay).map_err(|()| unreachable!()),
+		}
+	}
+
+	fn imu_type(&self) -> ImuType {
+		match self {
+			Self::Bmi160(imu) => imu.imu_type(),
+			Self::Bno085(imu) => imu.imu_type(),
+			Self::Icm20948(imu) => imu.imu_type(),
+			Self::Mpu6050(imu) => imu.imu_type(),
+			Self::Fake(imu) => imu.imu_type(),
+		}
+	}
+
+	fn gyro_bias(&self) -> Option<Vec3> {
+		match self {
+			Self::Bmi160(imu) => imu.gyro_bias(),
+			Self::Bno085(imu) => imu.gyro_bias(),
+			Self::Icm20948(imu) => imu.gyro_bias(),
+			Self::Mpu6050(imu) => imu.gyro_bias(),
+			Self::Fake(imu) => imu.gyro_bias(),
+		}
+	}
+
+	fn set_gyro_bias(&mut self, bias: Vec3) {
+		match self {
+			Self::Bmi160(imu) => imu.set_gyro_bias(bias),
+			Self::Bno085(imu) => imu.set_gyro_bias(bias),
+			Self::Icm20948(imu) => imu.set_gyro_bias(bias),
+			Self::Mpu6050(imu) => imu.set_gyro_bias(bias),
+			Self::Fake(imu) => imu.set_gyro_bias(bias),
+		}
+	}
+
+	fn calibrate(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		match self {
+			Self::Bmi160(imu) => imu.calibrate(delay).map_err(AutoError::Bmi160),
+			Self::Bno085(imu) => imu.calibrate(delay).map_err(AutoError::Bno085),
+			Self::Icm20948(imu) => imu.calibrate(delay).map_err(AutoError::Icm20948),
+			Self::Mpu6050(imu) => imu.calibrate(delay).map_err(AutoError::Mpu6050),
+			Self::Fake(_) => Ok(()),
+		}
+	}
+
+	fn accuracy(&self) -> Option<Accuracy> {
+		match self {
+			Self::Bmi160(imu) => imu.accuracy(),
+			Self::Bno085(imu) => imu.accuracy(),
+			Self::Icm20948(imu) => imu.accuracy(),
+			Self::Mpu6050(imu) => imu.accuracy(),
+			Self::Fake(imu) => imu.accuracy(),
+		}
+	}
+
+	fn raw_data(&self) -> Option<ImuData> {
+		match self {
+			Self::Bmi160(imu) => imu.raw_data(),
+			Self::Bno085(imu) => imu.raw_data(),
+			Self::Icm20948(imu) => imu.raw_data(),
+			Self::Mpu6050(imu) => imu.raw_data(),
+			Self::Fake(imu) => imu.raw_data(),
+		}
+	}
+
+	fn can_wake_on_motion(&self) -> bool {
+		match self {
+			Self::Bmi160(imu) => imu.can_wake_on_motion(),
+			Self::Bno085(imu) => imu.can_wake_on_motion(),
+			Self::Icm20948(imu) => imu.can_wake_on_motion(),
+			Self::Mpu6050(imu) => imu.can_wake_on_motion(),
+			Self::Fake(imu) => imu.can_wake_on_motion(),
+		}
+	}
+
+	fn wake_on_motion(self, threshold_mg: u16, latency: Duration) -> bool {
+		match self {
+			Self::Bmi160(imu) => imu.wake_on_motion(threshold_mg, latency),
+			Self::Bno085(imu) => imu.wake_on_motion(threshold_mg, latency),
+			Self::Icm20948(imu) => imu.wake_on_motion(threshold_mg, latency),
+			Self::Mpu6050(imu) => imu.wake_on_motion(threshold_mg, latency),
+			Self::Fake(imu) => imu.wake_on_motion(threshold_mg, latency),
+		}
+	}
+
+	fn sleep(self) {
+		match self {
+			Self::Bmi160(imu) => imu.sleep(),
+			Self::Bno085(imu) => imu.sleep(),
+			Self::Icm20948(imu) => imu.sleep(),
+			Self::Mpu6050(imu) => imu.sleep(),
+			Self::Fake(imu) => imu.sleep(),
+		}
+	}
+}
+
+/// Reads a single register, returning `None` if nothing acknowledged.
+fn read_reg(i2c: &mut impl I2c, addr: u8, reg: u8) -> Option<u8> {
+	let mut buf = [0];
+	i2c.write_read(addr, &[reg], &mut buf).ok().map(|()| buf[0])
+}
+
+/// Probes the bus for a supported IMU. Returns its type and address.
+fn detect(i2c: &mut impl I2c) -> Option<(ImuType, u8)> {
+	for addr in [ADDR_PRIMARY, ADDR_ALTERNATE] {
+		// The ICM and BMI have to be checked first, because the MPU WHO_AM_I register
+		// isn't meaningful for them.
+		match read_reg(i2c, addr, CHIP_ID) {
+			Some(ICM20948_ID) => return Some((ImuType::Icm20948, addr)),
+			Some(BMI160_ID) => return Some((ImuType::Bmi160, addr)),
+			_ => (),
+		}
+		if read_reg(i2c, addr, MPU_WHO_AM_I) == Some(MPU6050_ID) {
+			return Some((ImuType::Mpu6050, addr));
+		}
+	}
+	// The BNO08x has no WHO_AM_I register, but it always answers with an SHTP header
+	for addr in [bno085::ADDR, ADDR_BNO_ALTERNATE] {
+		let mut header = [0; 4];
+		if i2c.read(addr, &mut header).is_ok() {
+			return Some((ImuType::Bno085, addr));
+		}
+	}
+	None
+}
+
+#[allow(dead_code)]
+pub fn new_imu(
+	mut i2c: impl crate::aliases::I2c,
+	delay: &mut impl DelayMs<u32>,
+	config: ImuConfig,
+) -> Option<impl crate::imu::FusedImu> {
+	let rate = config.rate;
+	debug!("Autodetecting IMU...");
+	let Some((imu_type, addr)) = detect(&mut i2c) else {
+		warn!("No IMU detected, falling back to FakeImu");
+		return Some(AutoImu::Fake(FakeImu::new(Motion::Identity, rate)));
+	};
+	info!(
+		"Detected {} at address {:x}",
+		defmt::Debug2Format(&imu_type),
+		addr
+	);
+	if addr != ADDR_PRIMARY && addr != bno085::ADDR {
+		warn!("IMUs on the alternate address aren't supported yet, falling back to FakeImu");
+		return Some(AutoImu::Fake(FakeImu::new(Motion::Identity, rate)));
+	}
+
+	macro_rules! init_or_fake {
+		($ctor:expr, $variant:ident) => {
+			match $ctor {
+				Ok(imu) => AutoImu::$variant(imu),
+				Err(err) => {
+					warn!(
+						"Failed to initialize detected IMU, falling back to FakeImu: {}",
+						defmt::Debug2Format(&err)
+					);
+					AutoImu::Fake(FakeImu::new(Motion::Identity, rate))
+				}
+			}
+		};
+	}
+
+	Some(match imu_type {
+		ImuType::Bmi160 => {
+			init_or_fake!(Bmi160::new(i2c, delay).map(Fused::new), Bmi160)
+		}
+		ImuType::Bno085 => init_or_fake!(Bno085::new(i2c, delay, rate), Bno085),
+		ImuType::Icm20948 => {
+			init_or_fake!(Icm20948::new(i2c, delay, config).map(Fused::new), Icm20948)
+		}
+		ImuType::Mpu6050 => init_or_fake!(Mpu6050::new(i2c, delay, rate), Mpu6050),
+		_ => unreachable!("detect() only returns supported IMUs"),
+	})
+}
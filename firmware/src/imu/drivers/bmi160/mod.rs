@@ -1,50 +1,80 @@
+//! Driver for the BMI160. We only read the raw accelerometer and gyroscope and do the
+//! fusion ourselves.
+
 mod math;
 
 use self::math::{discrete_to_radians, GyroFsr};
-use crate::aliases::I2c;
-use crate::imu::{FusedImu, Quat};
+use crate::aliases::{I2c, Spi};
+use crate::imu::fusion::Fused;
+use crate::imu::{AccelRange, Imu, ImuData, SampleRate, Vec3};
 use crate::utils;
 
+use ::bmi160::interface::{ReadData, WriteData};
 use ::bmi160::{AccelerometerPowerMode, GyroscopePowerMode, SensorSelector};
-use defmt::{debug, trace};
+use core::convert::Infallible;
+use defmt::{debug, error, trace};
+use embassy_time::Duration;
 use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::digital::v2::OutputPin;
 use firmware_protocol::ImuType;
 
-type BmiDriver<I2c> = ::bmi160::Bmi160<bmi160::interface::I2cInterface<I2c>>;
+/// The gyroscope range after a reset, which the `bmi160` crate doesn't let us change.
+const GYRO_FSR: GyroFsr = GyroFsr::DEFAULT;
+/// The accelerometer range after a reset, which the `bmi160` crate doesn't let us
+/// change either.
+const ACCEL_RANGE: AccelRange = AccelRange::G2;
+/// The output data rate of the accelerometer and gyroscope after a reset.
+const RATE: SampleRate = SampleRate::Hz100;
+
+type BmiDriver<DI> = ::bmi160::Bmi160<DI>;
+pub type I2cInterface<I> = ::bmi160::interface::I2cInterface<I>;
+type SpiInterface<S> = ::bmi160::interface::SpiInterface<S, NoCs>;
 // Second generic is `()` because we don't have chip select errors in I2C.
-type BmiError<I> = ::bmi160::Error<<I as I2c>::Error, ()>;
+pub type BmiError<I> = ::bmi160::Error<<I as I2c>::Error, ()>;
+pub type BmiSpiError<S> = ::bmi160::Error<<S as Spi>::Error, Infallible>;
 
-pub struct InitError<I: I2c> {
-	pub i2c: I,
-	pub error: BmiError<I>,
+pub struct InitError<B, E> {
+	/// The bus that we tried to talk to the BMI160 over.
+	pub bus: B,
+	pub error: E,
 }
-impl<I> core::fmt::Debug for InitError<I>
+impl<B, E> core::fmt::Debug for InitError<B, E>
 where
-	I: I2c,
+	E: core::fmt::Debug,
 {
 	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
 		self.error.fmt(f)
 	}
 }
 
-pub struct Bmi160<I: I2c> {
-	driver: BmiDriver<I>,
+/// Chip select pin that does nothing, for when the SPI peripheral already drives the
+/// chip select in hardware.
+pub struct NoCs;
+impl OutputPin for NoCs {
+	type Error = Infallible;
+
+	fn set_low(&mut self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	fn set_high(&mut self) -> Result<(), Self::Error> {
+		Ok(())
+	}
 }
-impl<I: I2c> Bmi160<I> {
-	pub fn new(i2c: I, delay: &mut impl DelayMs<u32>) -> Result<Self, InitError<I>> {
+
+/// The BMI160, over either I2C or SPI. `DI` is the interface from the `bmi160` crate.
+pub struct Bmi160<DI> {
+	driver: BmiDriver<DI>,
+}
+impl<I: I2c> Bmi160<I2cInterface<I>> {
+	pub fn new(
+		i2c: I,
+		delay: &mut impl DelayMs<u32>,
+	) -> Result<Self, InitError<I, BmiError<I>>> {
 		debug!("Constructing BMI160...");
 		let addr = ::bmi160::SlaveAddr::Default;
 		debug!("I2C address: {:?}", defmt::Debug2Format(&addr));
 
-		macro_rules! unwrap_or_err {
-			($d:expr, $e:expr) => {
-				match $e {
-					Ok(v) => v,
-					Err(err) => return Err(($d.destroy(), err)),
-				}
-			};
-		}
-
 		utils::retry(
 			4,
 			i2c,
@@ -55,54 +85,139 @@ impl<I: I2c> Bmi160<I> {
 				delay.delay_ms(100);
 				trace!("Constructing IMU");
 				let mut driver = BmiDriver::new_with_i2c(i2c, addr);
-				let id = unwrap_or_err!(driver, driver.chip_id());
-				debug!("Constructed BMI with chip id: {}", id);
-				unwrap_or_err!(
-					driver,
-					driver.set_accel_power_mode(AccelerometerPowerMode::Normal)
-				);
-				unwrap_or_err!(
-					driver,
-					driver.set_gyro_power_mode(GyroscopePowerMode::Normal)
-				);
-				debug!("BMI power mode set to Normal");
+				match configure(&mut driver, delay) {
+					Ok(()) => Ok(Self { driver }),
+					Err(err) => Err((driver.destroy(), err)),
+				}
+			},
+			|i| debug!("Retrying IMU connection (attempts so far: {})", i + 1),
+		)
+		// Map converts from tuple -> struct
+		.map_err(|(bus, error)| InitError { bus, error })
+	}
+}
+impl<S: Spi> Bmi160<SpiInterface<S>> {
+	/// Uses the BMI160 over SPI. The SPI peripheral must drive the chip select.
+	pub fn new_spi(
+		spi: S,
+		delay: &mut impl DelayMs<u32>,
+	) -> Result<Self, InitError<S, BmiSpiError<S>>> {
+		debug!("Constructing BMI160 over SPI...");
+
+		utils::retry(
+			4,
+			spi,
+			|spi| {
 				delay.delay_ms(100);
-				Ok(Self { driver })
+				trace!("Constructing IMU");
+				let mut driver = BmiDriver::new_with_spi(spi, NoCs);
+				match configure(&mut driver, delay) {
+					Ok(()) => Ok(Self { driver }),
+					Err(err) => Err((driver.destroy().0, err)),
+				}
 			},
 			|i| debug!("Retrying IMU connection (attempts so far: {})", i + 1),
 		)
 		// Map converts from tuple -> struct
-		.map_err(|(i2c, error)| InitError { i2c, error })
+		.map_err(|(bus, error)| InitError { bus, error })
 	}
 }
 
-impl<I: I2c> FusedImu for Bmi160<I> {
-	type Error = BmiError<I>;
+/// Checks that the BMI160 responds, and powers up the accelerometer and gyroscope.
+fn configure<DI, CommE, PinE>(
+	driver: &mut BmiDriver<DI>,
+	delay: &mut impl DelayMs<u32>,
+) -> Result<(), ::bmi160::Error<CommE, PinE>>
+where
+	DI: ReadData<Error = ::bmi160::Error<CommE, PinE>>
+		+ WriteData<Error = ::bmi160::Error<CommE, PinE>>,
+{
+	let id = driver.chip_id()?;
+	debug!("Constructed BMI with chip id: {}", id);
+	driver.set_accel_power_mode(AccelerometerPowerMode::Normal)?;
+	driver.set_gyro_power_mode(GyroscopePowerMode::Normal)?;
+	debug!("BMI power mode set to Normal");
+	delay.delay_ms(100);
+	Ok(())
+}
+
+impl<DI, CommE, PinE> Imu for Bmi160<DI>
+where
+	DI: ReadData<Error = ::bmi160::Error<CommE, PinE>>
+		+ WriteData<Error = ::bmi160::Error<CommE, PinE>>,
+	CommE: core::fmt::Debug,
+	PinE: core::fmt::Debug,
+{
+	type Error = ::bmi160::Error<CommE, PinE>;
 
 	const IMU_TYPE: ImuType = ImuType::Bmi160;
 
-	fn quat(&mut self) -> nb::Result<Quat, Self::Error> {
-		let data = self.driver.data(SensorSelector::new().gyro())?;
-		let gyro_vel_euler = data.gyro.unwrap();
-
-		// TODO: We should probably query the IMU for the FSR instead of assuming the default one.
-		const FSR: GyroFsr = GyroFsr::DEFAULT;
-
-		// TODO: Check that bmi crates conventions for euler angles matches nalgebra.
-		// TODO: Implement sensor fusion and temperature compensation
-		// TODO: This should be integrated to position, lol
-		Ok(nalgebra::UnitQuaternion::from_euler_angles(
-			discrete_to_radians(FSR, gyro_vel_euler.x),
-			discrete_to_radians(FSR, gyro_vel_euler.y),
-			discrete_to_radians(FSR, gyro_vel_euler.z),
-		))
+	fn data(&mut self) -> nb::Result<ImuData, Self::Error> {
+		// The data registers keep the last sample until a new one is ready, and the
+		// status clears its flags once they are read. Without waiting for both, the
+		// fusion would integrate the same gyroscope reading twice.
+		let status = self.driver.status()?;
+		if !(status.accel_data_ready && status.gyro_data_ready) {
+			return Err(nb::Error::WouldBlock);
+		}
+		let data = self.driver.data(SensorSelector::new().accel().gyro())?;
+		let (Some(accel), Some(gyro)) = (data.accel, data.gyro) else {
+			return Err(nb::Error::WouldBlock);
+		};
+
+		let lsb_per_g = ACCEL_RANGE.lsb_per_g();
+		let accel =
+			Vec3::new(accel.x as f32, accel.y as f32, accel.z as f32) / lsb_per_g;
+		let gyro = Vec3::new(
+			discrete_to_radians(GYRO_FSR, gyro.x),
+			discrete_to_radians(GYRO_FSR, gyro.y),
+			discrete_to_radians(GYRO_FSR, gyro.z),
+		);
+		Ok(ImuData {
+			accel,
+			gyro,
+			temp: None,
+			mag: None,
+		})
+	}
+
+	fn sample_period(&self) -> Duration {
+		Duration::from_micros(RATE.period_us().into())
+	}
+
+	fn reinit(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		configure(&mut self.driver, delay)
 	}
 }
 
+/// The `bmi160` crate doesn't let us change the output data rate, so the BMI160 always
+/// runs at its default 100Hz.
 #[allow(dead_code)]
 pub fn new_imu(
 	i2c: impl crate::aliases::I2c,
 	delay: &mut impl DelayMs<u32>,
-) -> impl crate::imu::FusedImu {
-	Bmi160::new(i2c, delay).expect("Failed to initialize BMI160")
+	_rate: SampleRate,
+) -> Option<impl crate::imu::FusedImu> {
+	match Bmi160::new(i2c, delay) {
+		Ok(bmi) => Some(Fused::new(bmi)),
+		Err(err) => {
+			error!("Failed to initialize BMI160: {}", defmt::Debug2Format(&err));
+			None
+		}
+	}
+}
+
+#[allow(dead_code)]
+pub fn new_imu_spi(
+	spi: impl crate::aliases::Spi,
+	delay: &mut impl DelayMs<u32>,
+	_rate: SampleRate,
+) -> Option<impl crate::imu::FusedImu> {
+	match Bmi160::new_spi(spi, delay) {
+		Ok(bmi) => Some(Fused::new(bmi)),
+		Err(err) => {
+			error!("Failed to initialize BMI160: {}", defmt::Debug2Format(&err));
+			None
+		}
+	}
 }
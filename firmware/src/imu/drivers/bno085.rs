@@ -0,0 +1,311 @@
+//! Driver for the BNO085 (and the rest of the BNO08x family). These chips perform
+//! sensor fusion on-chip, so we just ask them for a rotation vector and pass it along.
+//!
+//! The chip talks using Hillcrest's "Sensor Hub Transport Protocol" (SHTP), where every
+//! transfer starts with a 4 byte header describing its length and channel. The SH-2
+//! reports that we care about are sent on top of that.
+
+use crate::aliases::I2c;
+use crate::imu::{Accuracy, FusedImu, Quat, SampleRate};
+use crate::utils;
+
+use defmt::{debug, error, trace, warn};
+use embedded_hal::blocking::delay::DelayMs;
+use firmware_protocol::ImuType;
+
+/// Default I2C address of the BNO085. It is `0x4B` if SA0 is pulled high.
+pub const ADDR: u8 = 0x4A;
+
+/// Size of the SHTP header that prefixes every transfer.
+const HEADER_LEN: usize = 4;
+/// Size of our receive buffer. Larger transfers (like the advertisement sent on reset)
+/// get truncated, which is fine because we only care about the sensor reports.
+const BUF_LEN: usize = 128;
+/// Set in the length field of the header when the transfer continues a previous one.
+const CONTINUATION_BIT: u16 = 1 << 15;
+
+/// The SHTP channels. Each one keeps track of its own sequence number.
+mod channel {
+	pub const EXECUTABLE: u8 = 1;
+	pub const CONTROL: u8 = 2;
+	pub const REPORTS: u8 = 3;
+	pub const WAKE_REPORTS: u8 = 4;
+
+	pub const NUM_CHANNELS: usize = 6;
+}
+
+/// SH-2 report ids that we send or receive.
+mod report {
+	pub const SET_FEATURE_COMMAND: u8 = 0xFD;
+	pub const BASE_TIMESTAMP: u8 = 0xFB;
+	pub const TIMESTAMP_REBASE: u8 = 0xFA;
+	pub const GAME_ROTATION_VECTOR: u8 = 0x08;
+
+	/// Length of the report, including the report id. `None` if we don't know how to
+	/// skip over it.
+	pub const fn len(id: u8) -> Option<usize> {
+		Some(match id {
+			BASE_TIMESTAMP => 5,
+			TIMESTAMP_REBASE => 5,
+			GAME_ROTATION_VECTOR => 12,
+			_ => return None,
+		})
+	}
+}
+
+/// Command on the executable channel that soft-resets the chip.
+const EXEC_RESET: u8 = 1;
+
+/// The game rotation vector is reported as fixed point numbers with 14 fractional bits.
+const Q14_SCALE: f32 = 1. / (1 << 14) as f32;
+
+// The chip reports how confident it is in its own calibration with every rotation
+// vector.
+impl Accuracy {
+	/// Reads the accuracy out of the status byte of a report.
+	const fn from_status(status: u8) -> Self {
+		match status & 0b11 {
+			0 => Self::Unreliable,
+			1 => Self::Low,
+			2 => Self::Medium,
+			_ => Self::High,
+		}
+	}
+}
+
+pub struct InitError<I: I2c> {
+	pub i2c: I,
+	pub error: <I as I2c>::Error,
+}
+impl<I> core::fmt::Debug for InitError<I>
+where
+	I: I2c,
+{
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		self.error.fmt(f)
+	}
+}
+
+pub struct Bno085<I: I2c> {
+	i2c: I,
+	/// The next sequence number to send, for each channel.
+	seq: [u8; channel::NUM_CHANNELS],
+	buf: [u8; BUF_LEN],
+	accuracy: Accuracy,
+	rate: SampleRate,
+}
+impl<I: I2c> Bno085<I> {
+	/// Sets up the chip to send us a rotation vector at `rate`.
+	pub fn new(
+		i2c: I,
+		delay: &mut impl DelayMs<u32>,
+		rate: SampleRate,
+	) -> Result<Self, InitError<I>> {
+		debug!("Constructing BNO085...");
+		debug!("I2C address: {:x}", ADDR);
+
+		utils::retry(
+			4,
+			i2c,
+			|i2c| {
+				let mut bno = Self {
+					i2c,
+					seq: [0; channel::NUM_CHANNELS],
+					buf: [0; BUF_LEN],
+					accuracy: Accuracy::Unreliable,
+					rate,
+				};
+				match bno.init(delay) {
+					Ok(()) => Ok(bno),
+					Err(err) => Err((bno.i2c, err)),
+				}
+			},
+			|i| warn!("Retrying IMU connection (attempts so far: {})", i + 1),
+		)
+		// Map converts from tuple -> struct
+		.map_err(|(i2c, error)| InitError { i2c, error })
+	}
+
+	/// Soft resets the chip and enables the rotation vector report.
+	fn init(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), <I as I2c>::Error> {
+		// The chip starts counting from zero again after a reset
+		self.seq = [0; channel::NUM_CHANNELS];
+		self.accuracy = Accuracy::Unreliable;
+		delay.delay_ms(100);
+		trace!("Soft resetting BNO085");
+		self.send(channel::EXECUTABLE, &[EXEC_RESET])?;
+		delay.delay_ms(300);
+
+		// After a reset the chip floods us with advertisement and reset
+		// complete packets. Drain them so they don't get confused for reports.
+		let drained = self.drain()?;
+		debug!("Drained {} packets from BNO085 after reset", drained);
+
+		self.enable_report(report::GAME_ROTATION_VECTOR, self.rate.period_us())?;
+		debug!("Enabled game rotation vector");
+		delay.delay_ms(100);
+		Ok(())
+	}
+
+	/// Asks the chip to periodically send us the report `id`.
+	fn enable_report(
+		&mut self,
+		id: u8,
+		interval_us: u32,
+	) -> Result<(), <I as I2c>::Error> {
+		let interval = interval_us.to_le_bytes();
+		#[rustfmt::skip]
+		let cmd = [
+			report::SET_FEATURE_COMMAND,
+			id,
+			0, // Feature flags
+			0, 0, // Change sensitivity
+			interval[0], interval[1], interval[2], interval[3], // Report interval
+			0, 0, 0, 0, // Batch interval
+			0, 0, 0, 0, // Sensor-specific config
+		];
+		self.send(channel::CONTROL, &cmd)
+	}
+
+	/// Sends `payload` on `channel`, prefixing it with the SHTP header.
+	fn send(&mut self, channel: u8, payload: &[u8]) -> Result<(), <I as I2c>::Error> {
+		// Our commands are all tiny, so we don't bother with a bigger buffer.
+		let mut packet = [0u8; HEADER_LEN + 17];
+		let len = HEADER_LEN + payload.len();
+		debug_assert!(len <= packet.len(), "SHTP payload too large");
+
+		let seq = &mut self.seq[usize::from(channel)];
+		packet[..HEADER_LEN].copy_from_slice(&[
+			len as u8,
+			(len >> 8) as u8,
+			channel,
+			*seq,
+		]);
+		packet[HEADER_LEN..len].copy_from_slice(payload);
+		*seq = seq.wrapping_add(1);
+
+		self.i2c.write(ADDR, &packet[..len])
+	}
+
+	/// Reads the next transfer into `self.buf`. Returns the channel it arrived on and
+	/// the number of bytes (including the header) now in the buffer.
+	///
+	/// Returns [`nb::Error::WouldBlock`] if the chip has nothing for us.
+	fn recv(&mut self) -> nb::Result<(u8, usize), <I as I2c>::Error> {
+		let mut header = [0u8; HEADER_LEN];
+		self.i2c.read(ADDR, &mut header)?;
+		let raw_len = u16::from_le_bytes([header[0], header[1]]);
+		let len = usize::from(raw_len & !CONTINUATION_BIT);
+		if len <= HEADER_LEN {
+			// Nothing is ready yet
+			return Err(nb::Error::WouldBlock);
+		}
+
+		// The chip sends the header again at the start of every read
+		let len = len.min(BUF_LEN);
+		self.i2c.read(ADDR, &mut self.buf[..len])?;
+		if raw_len & CONTINUATION_BIT != 0 {
+			// This is the tail of a transfer that we already truncated, ignore it.
+			trace!("Discarding continuation of SHTP transfer");
+			return Err(nb::Error::WouldBlock);
+		}
+		Ok((self.buf[2], len))
+	}
+
+	/// Reads and discards transfers until the chip has nothing left to send. Returns
+	/// the number of transfers discarded.
+	fn drain(&mut self) -> Result<usize, <I as I2c>::Error> {
+		// Bound the loop in case the chip keeps talking forever
+		for i in 0..32 {
+			match self.recv() {
+				Ok(_) => (),
+				Err(nb::Error::WouldBlock) => return Ok(i),
+				Err(nb::Error::Other(err)) => return Err(err),
+			}
+		}
+		Ok(32)
+	}
+}
+
+impl<I: I2c> FusedImu for Bno085<I> {
+	type Error = <I as I2c>::Error;
+
+	const IMU_TYPE: ImuType = ImuType::Bno085;
+
+	fn quat(&mut self) -> nb::Result<Quat, Self::Error> {
+		let (channel, len) = self.recv()?;
+		if channel != channel::REPORTS && channel != channel::WAKE_REPORTS {
+			trace!("Ignoring SHTP transfer on channel {}", channel);
+			return Err(nb::Error::WouldBlock);
+		}
+
+		// A single transfer can hold several reports back to back. We only care about
+		// the most recent rotation vector.
+		let mut latest = None;
+		let mut reports = &self.buf[HEADER_LEN..len];
+		while let Some(&id) = reports.first() {
+			let Some(report_len) = report::len(id) else {
+				trace!("Unknown SH-2 report id {:x}", id);
+				break;
+			};
+			if reports.len() < report_len {
+				break;
+			}
+			if id == report::GAME_ROTATION_VECTOR {
+				let r = &reports[..report_len];
+				let fixed =
+					|i: usize| i16::from_le_bytes([r[i], r[i + 1]]) as f32 * Q14_SCALE;
+				latest = Some((
+					Accuracy::from_status(r[2]),
+					nalgebra::Quaternion {
+						coords: nalgebra::vector![
+							fixed(4),
+							fixed(6),
+							fixed(8),
+							fixed(10)
+						],
+					},
+				));
+			}
+			reports = &reports[report_len..];
+		}
+
+		let (accuracy, q) = latest.ok_or(nb::Error::WouldBlock)?;
+		self.accuracy = accuracy;
+		Ok(Quat::from_quaternion(q))
+	}
+
+	fn reinit(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		self.init(delay)
+	}
+
+	/// Asks for the rotation vector at `rate` from then on, without a reset.
+	fn set_rate(
+		&mut self,
+		rate: SampleRate,
+		_delay: &mut impl DelayMs<u32>,
+	) -> Result<(), Self::Error> {
+		self.rate = rate;
+		self.enable_report(report::GAME_ROTATION_VECTOR, rate.period_us())
+	}
+
+	/// The accuracy that the chip reported alongside the most recent rotation vector.
+	fn accuracy(&self) -> Option<Accuracy> {
+		Some(self.accuracy)
+	}
+}
+
+#[allow(dead_code)]
+pub fn new_imu(
+	i2c: impl crate::aliases::I2c,
+	delay: &mut impl DelayMs<u32>,
+	rate: SampleRate,
+) -> Option<impl crate::imu::FusedImu> {
+	match Bno085::new(i2c, delay, rate) {
+		Ok(bno) => Some(bno),
+		Err(err) => {
+			error!("Failed to initialize BNO085: {}", defmt::Debug2Format(&err));
+			None
+		}
+	}
+}
@@ -0,0 +1,388 @@
+//! Driver for the ICM-20948, the successor to the MPU-9250. We read the raw
+//! accelerometer, gyroscope and magnetometer and fuse them ourselves with
+//! [`Madgwick`], like for the MPU-9250.
+//!
+//! The registers of this chip are split into 4 banks, and which one is visible is
+//! selected by writing to [`REG_BANK_SEL`], which is mapped in every bank. To make it
+//! impossible to read a register from the wrong bank, every [`Reg`] knows which bank it
+//! lives in and all register access goes through [`Icm20948::read`] and
+//! [`Icm20948::write`], which switch banks as necessary.
+
+use crate::aliases::I2c;
+use crate::imu::fusion::madgwick::Madgwick;
+use crate::imu::fusion::Fused;
+use crate::imu::{
+	divided_period, AccelRange, GyroRange, Imu, ImuConfig, ImuData, SampleRate, Vec3,
+};
+use crate::utils;
+
+use defmt::{debug, error, trace, warn};
+use embassy_time::Duration;
+use embedded_hal::blocking::delay::DelayMs;
+use firmware_protocol::ImuType;
+
+/// I2C address of the ICM-20948 when AD0 is low. It is `0x69` when AD0 is high.
+pub const ADDR: u8 = 0x68;
+/// The I2C address of the AK09916 magnetometer, once the ICM is in bypass mode.
+const MAG_ADDR: u8 = 0x0C;
+
+const WHO_AM_I_VALUE: u8 = 0xEA;
+const MAG_WHO_AM_I_VALUE: u8 = 0x09;
+
+/// Register that selects the bank. Present at the same address in every bank.
+const REG_BANK_SEL: u8 = 0x7F;
+
+#[derive(defmt::Format, Debug, Copy, Clone, Eq, PartialEq)]
+enum Bank {
+	B0 = 0,
+	B2 = 2,
+}
+
+/// A register address, along with the bank that it belongs to.
+#[derive(defmt::Format, Debug, Copy, Clone, Eq, PartialEq)]
+struct Reg {
+	bank: Bank,
+	addr: u8,
+}
+impl Reg {
+	const fn b0(addr: u8) -> Self {
+		Self {
+			bank: Bank::B0,
+			addr,
+		}
+	}
+
+	const fn b2(addr: u8) -> Self {
+		Self {
+			bank: Bank::B2,
+			addr,
+		}
+	}
+}
+
+mod reg {
+	use super::Reg;
+
+	pub const WHO_AM_I: Reg = Reg::b0(0x00);
+	pub const PWR_MGMT_1: Reg = Reg::b0(0x06);
+	pub const PWR_MGMT_2: Reg = Reg::b0(0x07);
+	pub const INT_PIN_CFG: Reg = Reg::b0(0x0F);
+	pub const INT_ENABLE_1: Reg = Reg::b0(0x11);
+	/// Cleared when read.
+	pub const INT_STATUS_1: Reg = Reg::b0(0x1A);
+	/// Start of accel xyz, followed by gyro xyz and temperature. All big endian i16.
+	pub const ACCEL_XOUT_H: Reg = Reg::b0(0x2D);
+
+	pub const GYRO_SMPLRT_DIV: Reg = Reg::b2(0x00);
+	pub const GYRO_CONFIG_1: Reg = Reg::b2(0x01);
+	pub const ACCEL_SMPLRT_DIV_2: Reg = Reg::b2(0x11);
+	pub const ACCEL_CONFIG: Reg = Reg::b2(0x14);
+}
+
+/// Registers of the AK09916. It doesn't have banks.
+mod mag_reg {
+	pub const WIA2: u8 = 0x01;
+	pub const ST1: u8 = 0x10;
+	/// Start of mag xyz, little endian i16, followed by a dummy and ST2.
+	pub const HXL: u8 = 0x11;
+	pub const CNTL2: u8 = 0x31;
+}
+
+const PWR_MGMT_1_RESET: u8 = 0x80;
+/// Clear sleep bit and automatically pick the best clock source.
+const PWR_MGMT_1_AUTO_CLOCK: u8 = 0x01;
+const INT_PIN_CFG_BYPASS_EN: u8 = 1 << 1;
+/// Raw data ready, in both `INT_ENABLE_1` and `INT_STATUS_1`.
+const RAW_DATA_0_RDY: u8 = 1 << 0;
+/// Continuous measurement at 100Hz.
+const MAG_MODE_CONTINUOUS_100HZ: u8 = 0x08;
+/// Magnetic sensor overflow, in ST2.
+const MAG_ST2_HOFL: u8 = 1 << 3;
+
+/// Enables the low pass filter, in both `GYRO_CONFIG_1` and `ACCEL_CONFIG`.
+const FCHOICE: u8 = 1;
+/// The accel and gyro sample at 1.125kHz before the rate divider.
+const BASE_RATE_HZ: u32 = 1125;
+
+const TEMP_LSB_PER_C: f32 = 333.87;
+/// The temperature sensor reads 0 at this temperature, in °C.
+const TEMP_OFFSET_C: f32 = 21.;
+/// The AK09916 has a fixed sensitivity of 0.15µT per LSB.
+const MAG_UT_PER_LSB: f32 = 0.15;
+
+pub struct InitError<I: I2c> {
+	pub i2c: I,
+	pub error: Error<I>,
+}
+impl<I> core::fmt::Debug for InitError<I>
+where
+	I: I2c,
+{
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		self.error.fmt(f)
+	}
+}
+
+pub enum Error<I: I2c> {
+	I2c(<I as I2c>::Error),
+	/// The chip at [`ADDR`] didn't identify as an ICM-20948.
+	WrongId(u8),
+}
+impl<I> core::fmt::Debug for Error<I>
+where
+	I: I2c,
+{
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Self::I2c(err) => f.debug_tuple("I2c").field(err).finish(),
+			Self::WrongId(id) => f.debug_tuple("WrongId").field(id).finish(),
+		}
+	}
+}
+
+pub struct Icm20948<I: I2c> {
+	i2c: I,
+	/// The bank that is currently selected, or `None` if we don't know.
+	bank: Option<Bank>,
+	has_mag: bool,
+	gyro_range: GyroRange,
+	accel_range: AccelRange,
+	/// Value of the sample rate divider registers.
+	smplrt_div: u8,
+}
+impl<I: I2c> Icm20948<I> {
+	/// Sets up the chip to sample at roughly `config.rate`, never slower than it, and
+	/// with the ranges of `config`.
+	pub fn new(
+		i2c: I,
+		delay: &mut impl DelayMs<u32>,
+		config: ImuConfig,
+	) -> Result<Self, InitError<I>> {
+		debug!("Constructing ICM-20948...");
+		debug!("I2C address: {:x}", ADDR);
+		let smplrt_div = config.rate.divider(BASE_RATE_HZ);
+		debug!(
+			"Sample rate: {}Hz",
+			BASE_RATE_HZ / (1 + u32::from(smplrt_div))
+		);
+
+		utils::retry(
+			4,
+			i2c,
+			|i2c| {
+				let mut icm = Self {
+					i2c,
+					bank: None,
+					has_mag: false,
+					smplrt_div,
+					gyro_range: config.gyro_range,
+					accel_range: config.accel_range,
+				};
+				match icm.init(delay) {
+					Ok(()) => Ok(icm),
+					Err(err) => Err((icm.i2c, err)),
+				}
+			},
+			|i| warn!("Retrying IMU connection (attempts so far: {})", i + 1),
+		)
+		// Map converts from tuple -> struct
+		.map_err(|(i2c, error)| InitError { i2c, error })
+	}
+
+	/// Resets the chip and configures the accel, gyro and magnetometer.
+	fn init(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Error<I>> {
+		delay.delay_ms(100);
+		trace!("Resetting ICM-20948");
+		self.write(reg::PWR_MGMT_1, PWR_MGMT_1_RESET)?;
+		delay.delay_ms(100);
+		// The reset also resets the bank selection
+		self.bank = None;
+
+		let id = self.read_u8(reg::WHO_AM_I)?;
+		debug!("Constructed ICM with chip id: {:x}", id);
+		if id != WHO_AM_I_VALUE {
+			return Err(Error::WrongId(id));
+		}
+
+		self.write(reg::PWR_MGMT_1, PWR_MGMT_1_AUTO_CLOCK)?;
+		// Enable all axes of the accel and gyro
+		self.write(reg::PWR_MGMT_2, 0)?;
+		delay.delay_ms(50);
+
+		let gyro_config = (self.gyro_range.fs_sel() << 1) | FCHOICE;
+		self.write(reg::GYRO_CONFIG_1, gyro_config)?;
+		self.write(reg::GYRO_SMPLRT_DIV, self.smplrt_div)?;
+		let accel_config = (self.accel_range.fs_sel() << 1) | FCHOICE;
+		self.write(reg::ACCEL_CONFIG, accel_config)?;
+		self.write(reg::ACCEL_SMPLRT_DIV_2, self.smplrt_div)?;
+		// The INT pin isn't connected, this only makes the status flag work
+		self.write(reg::INT_ENABLE_1, RAW_DATA_0_RDY)?;
+		debug!("Configured accel and gyro");
+
+		// The magnetometer is optional, failing to set it up is not fatal.
+		self.has_mag = match self.init_mag(delay) {
+			Ok(has_mag) => has_mag,
+			Err(err) => {
+				warn!(
+					"Failed to set up magnetometer: {}",
+					defmt::Debug2Format(&err)
+				);
+				false
+			}
+		};
+		debug!("Magnetometer present: {}", self.has_mag);
+		Ok(())
+	}
+
+	/// Puts the ICM in bypass mode so that the AK09916 on its auxiliary bus shows up on
+	/// our I2C bus, and starts continuous measurements. Returns whether the
+	/// magnetometer responded.
+	fn init_mag(&mut self, delay: &mut impl DelayMs<u32>) -> Result<bool, Error<I>> {
+		self.write(reg::INT_PIN_CFG, INT_PIN_CFG_BYPASS_EN)?;
+		delay.delay_ms(10);
+
+		let mut id = [0];
+		if self
+			.i2c
+			.write_read(MAG_ADDR, &[mag_reg::WIA2], &mut id)
+			.is_err()
+		{
+			return Ok(false);
+		}
+		if id[0] != MAG_WHO_AM_I_VALUE {
+			warn!("Unexpected magnetometer id: {:x}", id[0]);
+			return Ok(false);
+		}
+		self.i2c
+			.write(MAG_ADDR, &[mag_reg::CNTL2, MAG_MODE_CONTINUOUS_100HZ])
+			.map_err(Error::I2c)?;
+		delay.delay_ms(10);
+		Ok(true)
+	}
+
+	/// Reads the magnetic field in µT, in the axes of the accel and gyro. Returns
+	/// `None` if there is no magnetometer or no new reading.
+	fn mag(&mut self) -> Result<Option<Vec3>, Error<I>> {
+		if !self.has_mag {
+			return Ok(None);
+		}
+		let mut st1 = [0];
+		self.i2c
+			.write_read(MAG_ADDR, &[mag_reg::ST1], &mut st1)
+			.map_err(Error::I2c)?;
+		if st1[0] & 1 == 0 {
+			// Data not ready
+			return Ok(None);
+		}
+		// Reading all the way through ST2 is required to unlock the next sample.
+		let mut buf = [0; 8];
+		self.i2c
+			.write_read(MAG_ADDR, &[mag_reg::HXL], &mut buf)
+			.map_err(Error::I2c)?;
+		if buf[7] & MAG_ST2_HOFL != 0 {
+			trace!("Magnetometer overflowed");
+			return Ok(None);
+		}
+		let axis = |i: usize| i16::from_le_bytes([buf[i], buf[i + 1]]) as f32;
+		let mag = Vec3::new(axis(0), axis(2), axis(4)) * MAG_UT_PER_LSB;
+		// The y and z axes of the AK09916 point the other way than those of the ICM
+		Ok(Some(Vec3::new(mag.x, -mag.y, -mag.z)))
+	}
+
+	/// Makes sure `bank` is the selected one, skipping the write if it already is.
+	fn select_bank(&mut self, bank: Bank) -> Result<(), Error<I>> {
+		if self.bank == Some(bank) {
+			return Ok(());
+		}
+		// Forget the bank first, so that if the write fails we select it again next
+		// time instead of assuming it worked.
+		self.bank = None;
+		self.i2c
+			.write(ADDR, &[REG_BANK_SEL, (bank as u8) << 4])
+			.map_err(Error::I2c)?;
+		self.bank = Some(bank);
+		Ok(())
+	}
+
+	fn read(&mut self, reg: Reg, buf: &mut [u8]) -> Result<(), Error<I>> {
+		self.select_bank(reg.bank)?;
+		self.i2c
+			.write_read(ADDR, &[reg.addr], buf)
+			.map_err(Error::I2c)
+	}
+
+	fn read_u8(&mut self, reg: Reg) -> Result<u8, Error<I>> {
+		let mut buf = [0];
+		self.read(reg, &mut buf)?;
+		Ok(buf[0])
+	}
+
+	fn write(&mut self, reg: Reg, value: u8) -> Result<(), Error<I>> {
+		self.select_bank(reg.bank)?;
+		self.i2c.write(ADDR, &[reg.addr, value]).map_err(Error::I2c)
+	}
+}
+
+impl<I: I2c> Imu for Icm20948<I> {
+	type Error = Error<I>;
+
+	const IMU_TYPE: ImuType = ImuType::Icm20948;
+
+	fn data(&mut self) -> nb::Result<ImuData, Self::Error> {
+		// Without new data we would fuse the same reading twice
+		if self.read_u8(reg::INT_STATUS_1)? & RAW_DATA_0_RDY == 0 {
+			return Err(nb::Error::WouldBlock);
+		}
+		let mut buf = [0; 14];
+		self.read(reg::ACCEL_XOUT_H, &mut buf)?;
+		let axis = |i: usize| i16::from_be_bytes([buf[i], buf[i + 1]]) as f32;
+
+		let accel = Vec3::new(axis(0), axis(2), axis(4)) / self.accel_range.lsb_per_g();
+		let gyro = Vec3::new(axis(6), axis(8), axis(10))
+			/ self.gyro_range.lsb_per_dps()
+			* (core::f32::consts::PI / 180.);
+		let temp = axis(12) / TEMP_LSB_PER_C + TEMP_OFFSET_C;
+		Ok(ImuData {
+			accel,
+			gyro,
+			temp: Some(temp),
+			mag: self.mag()?,
+		})
+	}
+
+	fn sample_period(&self) -> Duration {
+		divided_period(BASE_RATE_HZ, self.smplrt_div)
+	}
+
+	fn reinit(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		self.init(delay)
+	}
+
+	fn set_rate(
+		&mut self,
+		rate: SampleRate,
+		delay: &mut impl DelayMs<u32>,
+	) -> Result<(), Self::Error> {
+		self.smplrt_div = rate.divider(BASE_RATE_HZ);
+		self.init(delay)
+	}
+}
+
+#[allow(dead_code)]
+pub fn new_imu(
+	i2c: impl crate::aliases::I2c,
+	delay: &mut impl DelayMs<u32>,
+	config: ImuConfig,
+) -> Option<impl crate::imu::FusedImu> {
+	match Icm20948::new(i2c, delay, config) {
+		// Madgwick, because the default fusion can't use the magnetometer
+		Ok(icm) => Some(Fused::<_, Madgwick>::new(icm)),
+		Err(err) => {
+			error!(
+				"Failed to initialize ICM-20948: {}",
+				defmt::Debug2Format(&err)
+			);
+			None
+		}
+	}
+}
@@ -0,0 +1,559 @@
+//! Driver for ST's LSM6DS3 family: the LSM6DS3, the LSM6DS3TR-C and the LSM6DSV. They
+//! all put their outputs in the same registers, but the LSM6DSV encodes the data rates
+//! and ranges differently.
+//!
+//! There are two ways to use them:
+//! - [`Lsm6ds3`] reads the raw accelerometer and gyroscope of any of them, and we do
+//!   the fusion ourselves.
+//! - [`Lsm6dsv`] uses the sensor fusion of the LSM6DSV, which ST calls SFLP. It puts a
+//!   game rotation vector into the FIFO, which we pass along like for the BNO085.
+//!
+//! The SFLP runs on the readings of the accelerometer and gyroscope, so [`Lsm6dsv`]
+//! sets them up with [`Lsm6ds3`] first and only adds the fusion on top.
+
+use crate::aliases::I2c;
+use crate::imu::fusion::Fused;
+use crate::imu::{
+	AccelRange, FusedImu, GyroRange, Imu, ImuConfig, ImuData, Quat, SampleRate, Vec3,
+};
+use crate::utils;
+
+use defmt::{debug, error, info, trace, warn};
+use embassy_time::Duration;
+use embedded_hal::blocking::delay::DelayMs;
+use firmware_protocol::ImuType;
+use nalgebra::ComplexField;
+
+/// I2C address of the chip when SA0 is low.
+pub const ADDR: u8 = 0x6A;
+/// I2C address of the chip when SA0 is high, like on many breakout boards.
+pub const ADDR_ALTERNATE: u8 = 0x6B;
+
+mod reg {
+	/// Switches to the registers of the embedded functions, see [`super::emb_reg`].
+	pub const FUNC_CFG_ACCESS: u8 = 0x01;
+	pub const FIFO_CTRL4: u8 = 0x0A;
+	pub const WHO_AM_I: u8 = 0x0F;
+	/// Accelerometer data rate, and on the LSM6DS3 also its range.
+	pub const CTRL1: u8 = 0x10;
+	/// Gyroscope data rate, and on the LSM6DS3 also its range.
+	pub const CTRL2: u8 = 0x11;
+	pub const CTRL3: u8 = 0x12;
+	/// Gyroscope range of the LSM6DSV.
+	pub const CTRL6: u8 = 0x15;
+	/// Accelerometer range of the LSM6DSV.
+	pub const CTRL8: u8 = 0x17;
+	/// Number of unread FIFO words, followed by FIFO_STATUS2 with its 9th bit.
+	pub const FIFO_STATUS1: u8 = 0x1B;
+	pub const STATUS: u8 = 0x1E;
+	/// Start of temperature, followed by gyro xyz and accel xyz. All little endian i16.
+	pub const OUT_TEMP_L: u8 = 0x20;
+	/// Tag of the oldest FIFO word, followed by its 6 bytes of data.
+	pub const FIFO_DATA_OUT_TAG: u8 = 0x78;
+}
+
+/// Registers of the embedded functions of the LSM6DSV, which replace the usual ones
+/// while [`FUNC_CFG_ACCESS_EMB`] is set.
+mod emb_reg {
+	pub const EMB_FUNC_EN_A: u8 = 0x04;
+	pub const EMB_FUNC_FIFO_EN_A: u8 = 0x44;
+	pub const SFLP_ODR: u8 = 0x5E;
+	pub const EMB_FUNC_INIT_A: u8 = 0x66;
+}
+
+const CTRL3_SW_RESET: u8 = 1 << 0;
+/// Increment the address during multi byte reads, and don't update the outputs while
+/// they are being read.
+const CTRL3_IF_INC_BDU: u8 = (1 << 2) | (1 << 6);
+const STATUS_XLDA: u8 = 1 << 0;
+const STATUS_GDA: u8 = 1 << 1;
+const FUNC_CFG_ACCESS_EMB: u8 = 1 << 7;
+/// The game rotation vector bit of `EMB_FUNC_EN_A`, `EMB_FUNC_FIFO_EN_A` and
+/// `EMB_FUNC_INIT_A`.
+const SFLP_GAME: u8 = 1 << 1;
+/// Bits of `SFLP_ODR` that have to keep their reset value.
+const SFLP_ODR_RESERVED: u8 = 0x43;
+/// Keep the newest words when the FIFO is full.
+const FIFO_MODE_CONTINUOUS: u8 = 0b110;
+/// Tag of the FIFO words with the game rotation vector of the SFLP.
+const TAG_SFLP_GAME: u8 = 0x13;
+
+/// The temperature sensor reads 0 at this temperature, in °C.
+const TEMP_OFFSET_C: f32 = 25.;
+
+/// The chips of the family that we know.
+#[derive(defmt::Format, Debug, Copy, Clone, Eq, PartialEq)]
+enum Chip {
+	Lsm6ds3,
+	Lsm6ds3trc,
+	Lsm6dsv,
+}
+impl Chip {
+	const fn from_id(id: u8) -> Option<Self> {
+		match id {
+			0x69 => Some(Self::Lsm6ds3),
+			0x6A => Some(Self::Lsm6ds3trc),
+			0x70 => Some(Self::Lsm6dsv),
+			_ => None,
+		}
+	}
+
+	const fn id(self) -> u8 {
+		match self {
+			Self::Lsm6ds3 => 0x69,
+			Self::Lsm6ds3trc => 0x6A,
+			Self::Lsm6dsv => 0x70,
+		}
+	}
+
+	/// The server has no type for the original LSM6DS3, so it shows up as the TR-C,
+	/// which replaced it and works the same.
+	const fn imu_type(self) -> ImuType {
+		match self {
+			Self::Lsm6ds3 | Self::Lsm6ds3trc => ImuType::Lsm6ds3trc,
+			Self::Lsm6dsv => ImuType::Lsm6dsv,
+		}
+	}
+
+	const fn temp_lsb_per_c(self) -> f32 {
+		match self {
+			Self::Lsm6ds3 => 16.,
+			Self::Lsm6ds3trc | Self::Lsm6dsv => 256.,
+		}
+	}
+
+	/// The slowest output data rate that isn't slower than `rate`, as the value of the
+	/// ODR fields and in Hz.
+	const fn odr(self, rate: SampleRate) -> (u8, u32) {
+		match (self, rate) {
+			(Self::Lsm6dsv, SampleRate::Hz50) => (0b0101, 60),
+			(Self::Lsm6dsv, SampleRate::Hz100) => (0b0110, 120),
+			(Self::Lsm6dsv, SampleRate::Hz200) => (0b0111, 240),
+			(Self::Lsm6dsv, SampleRate::Hz500) => (0b1001, 960),
+			(_, SampleRate::Hz50) => (0b0011, 52),
+			(_, SampleRate::Hz100) => (0b0100, 104),
+			(_, SampleRate::Hz200) => (0b0101, 208),
+			(_, SampleRate::Hz500) => (0b0111, 833),
+		}
+	}
+
+	/// The value of the gyroscope range field.
+	const fn gyro_fs(self, range: GyroRange) -> u8 {
+		match (self, range) {
+			(Self::Lsm6dsv, GyroRange::Dps250) => 0b0001,
+			(Self::Lsm6dsv, GyroRange::Dps500) => 0b0010,
+			(Self::Lsm6dsv, GyroRange::Dps1000) => 0b0011,
+			(Self::Lsm6dsv, GyroRange::Dps2000) => 0b0100,
+			// The LSM6DS3 goes up to 245dps instead of 250
+			(_, GyroRange::Dps250) => 0b00,
+			(_, GyroRange::Dps500) => 0b01,
+			(_, GyroRange::Dps1000) => 0b10,
+			(_, GyroRange::Dps2000) => 0b11,
+		}
+	}
+
+	/// The value of the accelerometer range field.
+	const fn accel_fs(self, range: AccelRange) -> u8 {
+		match (self, range) {
+			(Self::Lsm6dsv, AccelRange::G2) => 0b00,
+			(Self::Lsm6dsv, AccelRange::G4) => 0b01,
+			(Self::Lsm6dsv, AccelRange::G8) => 0b10,
+			(Self::Lsm6dsv, AccelRange::G16) => 0b11,
+			(_, AccelRange::G2) => 0b00,
+			(_, AccelRange::G4) => 0b10,
+			(_, AccelRange::G8) => 0b11,
+			(_, AccelRange::G16) => 0b01,
+		}
+	}
+}
+
+/// Sensitivity of the gyroscope of every chip in the family, in mdps per LSB.
+const fn mdps_per_lsb(range: GyroRange) -> f32 {
+	match range {
+		GyroRange::Dps250 => 8.75,
+		GyroRange::Dps500 => 17.5,
+		GyroRange::Dps1000 => 35.,
+		GyroRange::Dps2000 => 70.,
+	}
+}
+
+/// Sensitivity of the accelerometer of every chip in the family, in mg per LSB.
+const fn mg_per_lsb(range: AccelRange) -> f32 {
+	match range {
+		AccelRange::G2 => 0.061,
+		AccelRange::G4 => 0.122,
+		AccelRange::G8 => 0.244,
+		AccelRange::G16 => 0.488,
+	}
+}
+
+/// The SFLP data rate that is closest to `rate`, as the value of the ODR field and in
+/// Hz. It can't go faster than 480Hz.
+const fn sflp_odr(rate: SampleRate) -> (u8, u32) {
+	match rate {
+		SampleRate::Hz50 => (0b010, 60),
+		SampleRate::Hz100 => (0b011, 120),
+		SampleRate::Hz200 => (0b100, 240),
+		SampleRate::Hz500 => (0b101, 480),
+	}
+}
+
+pub struct InitError<I: I2c> {
+	pub i2c: I,
+	pub error: Error<I>,
+}
+impl<I> core::fmt::Debug for InitError<I>
+where
+	I: I2c,
+{
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		self.error.fmt(f)
+	}
+}
+
+pub enum Error<I: I2c> {
+	I2c(<I as I2c>::Error),
+	/// The chip at the address isn't one that we know, or isn't an LSM6DSV when the
+	/// SFLP was asked for.
+	WrongId(u8),
+}
+impl<I> core::fmt::Debug for Error<I>
+where
+	I: I2c,
+{
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Self::I2c(err) => f.debug_tuple("I2c").field(err).finish(),
+			Self::WrongId(id) => f.debug_tuple("WrongId").field(id).finish(),
+		}
+	}
+}
+
+/// Any chip of the family, giving us the raw readings. See the [module](self)
+/// documentation.
+pub struct Lsm6ds3<I: I2c> {
+	i2c: I,
+	/// Either [`ADDR`] or [`ADDR_ALTERNATE`], depending on SA0.
+	addr: u8,
+	chip: Chip,
+	config: ImuConfig,
+}
+impl<I: I2c> Lsm6ds3<I> {
+	/// Sets up the chip to sample at roughly `config.rate`, never slower than it, and
+	/// with the ranges of `config`. The chip may be at either [`ADDR`] or
+	/// [`ADDR_ALTERNATE`].
+	pub fn new(
+		i2c: I,
+		delay: &mut impl DelayMs<u32>,
+		config: ImuConfig,
+	) -> Result<Self, InitError<I>> {
+		debug!("Constructing LSM6DS3...");
+
+		utils::retry(
+			4,
+			i2c,
+			|mut i2c| {
+				let addr = find_address(&mut i2c).unwrap_or_else(|| {
+					warn!("No LSM6DS3 answered, trying {:x} anyway", ADDR);
+					ADDR
+				});
+				let mut id = [0];
+				if let Err(err) = i2c.write_read(addr, &[reg::WHO_AM_I], &mut id) {
+					return Err((i2c, Error::I2c(err)));
+				}
+				let Some(chip) = Chip::from_id(id[0]) else {
+					return Err((i2c, Error::WrongId(id[0])));
+				};
+				let mut lsm = Self {
+					i2c,
+					addr,
+					chip,
+					config,
+				};
+				match lsm.init(delay) {
+					Ok(()) => {
+						info!("Found {} at address {:x}", chip, addr);
+						Ok(lsm)
+					}
+					Err(err) => Err((lsm.i2c, err)),
+				}
+			},
+			|i| warn!("Retrying IMU connection (attempts so far: {})", i + 1),
+		)
+		// Map converts from tuple -> struct
+		.map_err(|(i2c, error)| InitError { i2c, error })
+	}
+
+	/// Resets the chip and configures the accel and gyro.
+	fn init(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Error<I>> {
+		trace!("Resetting {}", self.chip);
+		self.write(reg::CTRL3, CTRL3_SW_RESET)?;
+		delay.delay_ms(50);
+
+		let id = self.read_u8(reg::WHO_AM_I)?;
+		debug!("Constructed LSM with chip id: {:x}", id);
+		if id != self.chip.id() {
+			return Err(Error::WrongId(id));
+		}
+		self.write(reg::CTRL3, CTRL3_IF_INC_BDU)?;
+
+		let chip = self.chip;
+		let (odr, hz) = chip.odr(self.config.rate);
+		debug!("Sample rate: {}Hz", hz);
+		let gyro_fs = chip.gyro_fs(self.config.gyro_range);
+		let accel_fs = chip.accel_fs(self.config.accel_range);
+		match chip {
+			Chip::Lsm6dsv => {
+				self.write(reg::CTRL6, gyro_fs)?;
+				self.write(reg::CTRL8, accel_fs)?;
+				// Also picks the high performance mode
+				self.write(reg::CTRL1, odr)?;
+				self.write(reg::CTRL2, odr)?;
+			}
+			Chip::Lsm6ds3 | Chip::Lsm6ds3trc => {
+				self.write(reg::CTRL1, (odr << 4) | (accel_fs << 2))?;
+				self.write(reg::CTRL2, (odr << 4) | (gyro_fs << 2))?;
+			}
+		}
+		// The gyroscope takes a while to start up
+		delay.delay_ms(100);
+		debug!("Configured accel and gyro");
+		Ok(())
+	}
+
+	fn read(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), Error<I>> {
+		self.i2c
+			.write_read(self.addr, &[reg], buf)
+			.map_err(Error::I2c)
+	}
+
+	fn read_u8(&mut self, reg: u8) -> Result<u8, Error<I>> {
+		let mut buf = [0];
+		self.read(reg, &mut buf)?;
+		Ok(buf[0])
+	}
+
+	fn write(&mut self, reg: u8, value: u8) -> Result<(), Error<I>> {
+		self.i2c.write(self.addr, &[reg, value]).map_err(Error::I2c)
+	}
+}
+
+impl<I: I2c> Imu for Lsm6ds3<I> {
+	type Error = Error<I>;
+
+	const IMU_TYPE: ImuType = ImuType::Lsm6ds3trc;
+
+	fn data(&mut self) -> nb::Result<ImuData, Self::Error> {
+		// Reading the outputs clears the flags, so without new data we would fuse the
+		// same reading twice
+		let ready = STATUS_XLDA | STATUS_GDA;
+		if self.read_u8(reg::STATUS)? & ready != ready {
+			return Err(nb::Error::WouldBlock);
+		}
+		let mut buf = [0; 14];
+		self.read(reg::OUT_TEMP_L, &mut buf)?;
+		let axis = |i: usize| i16::from_le_bytes([buf[i], buf[i + 1]]) as f32;
+
+		let temp = axis(0) / self.chip.temp_lsb_per_c() + TEMP_OFFSET_C;
+		let gyro = Vec3::new(axis(2), axis(4), axis(6))
+			* (mdps_per_lsb(self.config.gyro_range) / 1000.)
+			* (core::f32::consts::PI / 180.);
+		let accel = Vec3::new(axis(8), axis(10), axis(12))
+			* (mg_per_lsb(self.config.accel_range) / 1000.);
+		Ok(ImuData {
+			accel,
+			gyro,
+			temp: Some(temp),
+			mag: None,
+		})
+	}
+
+	fn imu_type(&self) -> ImuType {
+		self.chip.imu_type()
+	}
+
+	fn sample_period(&self) -> Duration {
+		let (_, hz) = self.chip.odr(self.config.rate);
+		Duration::from_micros(1_000_000 / u64::from(hz))
+	}
+
+	fn reinit(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		self.init(delay)
+	}
+
+	fn set_rate(
+		&mut self,
+		rate: SampleRate,
+		delay: &mut impl DelayMs<u32>,
+	) -> Result<(), Self::Error> {
+		self.config.rate = rate;
+		self.init(delay)
+	}
+}
+
+/// An LSM6DSV that does the fusion itself. See the [module](self) documentation.
+pub struct Lsm6dsv<I: I2c> {
+	lsm: Lsm6ds3<I>,
+}
+impl<I: I2c> Lsm6dsv<I> {
+	/// Same as [`Lsm6ds3::new()`], then turns on the SFLP. Fails with
+	/// [`Error::WrongId`] on the chips without it.
+	pub fn new(
+		i2c: I,
+		delay: &mut impl DelayMs<u32>,
+		config: ImuConfig,
+	) -> Result<Self, InitError<I>> {
+		let lsm = Lsm6ds3::new(i2c, delay, config)?;
+		if lsm.chip != Chip::Lsm6dsv {
+			return Err(InitError {
+				error: Error::WrongId(lsm.chip.id()),
+				i2c: lsm.i2c,
+			});
+		}
+		let mut dsv = Self { lsm };
+		match dsv.init_sflp() {
+			Ok(()) => Ok(dsv),
+			Err(error) => Err(InitError {
+				i2c: dsv.lsm.i2c,
+				error,
+			}),
+		}
+	}
+
+	/// Turns on the game rotation vector of the SFLP, and has it put into the FIFO.
+	fn init_sflp(&mut self) -> Result<(), Error<I>> {
+		let (odr, hz) = sflp_odr(self.lsm.config.rate);
+		debug!("SFLP rate: {}Hz", hz);
+		let lsm = &mut self.lsm;
+		lsm.write(reg::FUNC_CFG_ACCESS, FUNC_CFG_ACCESS_EMB)?;
+		lsm.write(emb_reg::SFLP_ODR, SFLP_ODR_RESERVED | (odr << 3))?;
+		lsm.write(emb_reg::EMB_FUNC_FIFO_EN_A, SFLP_GAME)?;
+		lsm.write(emb_reg::EMB_FUNC_EN_A, SFLP_GAME)?;
+		lsm.write(emb_reg::EMB_FUNC_INIT_A, SFLP_GAME)?;
+		lsm.write(reg::FUNC_CFG_ACCESS, 0)?;
+		// Only the SFLP is batched, the raw readings stay out of the FIFO
+		lsm.write(reg::FIFO_CTRL4, FIFO_MODE_CONTINUOUS)?;
+		debug!("Configured SFLP");
+		Ok(())
+	}
+}
+
+impl<I: I2c> FusedImu for Lsm6dsv<I> {
+	type Error = Error<I>;
+
+	const IMU_TYPE: ImuType = ImuType::Lsm6dsv;
+
+	fn quat(&mut self) -> nb::Result<Quat, Self::Error> {
+		let mut status = [0; 2];
+		self.lsm.read(reg::FIFO_STATUS1, &mut status)?;
+		let unread = u16::from_le_bytes([status[0], status[1] & 1]);
+
+		// We only care about the most recent rotation vector
+		let mut latest = None;
+		for _ in 0..unread {
+			let mut word = [0; 7];
+			self.lsm.read(reg::FIFO_DATA_OUT_TAG, &mut word)?;
+			let tag = word[0] >> 3;
+			if tag != TAG_SFLP_GAME {
+				trace!("Ignoring FIFO word with tag {:x}", tag);
+				continue;
+			}
+			let half =
+				|i: usize| f16_to_f32(u16::from_le_bytes([word[i], word[i + 1]]));
+			latest = Some(Vec3::new(half(1), half(3), half(5)));
+		}
+
+		let v = latest.ok_or(nb::Error::WouldBlock)?;
+		// Only the vector part is in the FIFO, the real part follows from the
+		// quaternion being a unit one
+		let w = (1. - v.norm_squared()).max(0.).sqrt();
+		Ok(Quat::from_quaternion(nalgebra::Quaternion::new(
+			w, v.x, v.y, v.z,
+		)))
+	}
+
+	fn reinit(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		self.lsm.init(delay)?;
+		self.init_sflp()
+	}
+
+	fn set_rate(
+		&mut self,
+		rate: SampleRate,
+		delay: &mut impl DelayMs<u32>,
+	) -> Result<(), Self::Error> {
+		self.lsm.config.rate = rate;
+		self.reinit(delay)
+	}
+}
+
+/// Converts an IEEE 754 half precision float, which is what the SFLP puts into the
+/// FIFO.
+fn f16_to_f32(bits: u16) -> f32 {
+	let sign = u32::from(bits >> 15) << 31;
+	let exp = u32::from(bits >> 10) & 0x1F;
+	let frac = u32::from(bits & 0x3FF);
+	let magnitude = match exp {
+		// Subnormal, which is `frac / 2^10 * 2^-14`
+		0 => frac as f32 / (1 << 24) as f32,
+		// Infinity or NaN
+		0x1F => f32::from_bits((0xFF << 23) | (frac << 13)),
+		_ => f32::from_bits(((exp + 127 - 15) << 23) | (frac << 13)),
+	};
+	f32::from_bits(magnitude.to_bits() | sign)
+}
+
+/// Finds the address that the chip is at, by reading its WHO_AM_I at both. An address
+/// where some other chip answers is only taken if ours isn't at the other one, so that
+/// initializing reports what that chip is. `None` if nothing answered.
+fn find_address(i2c: &mut impl I2c) -> Option<u8> {
+	let mut answered = None;
+	for addr in [ADDR, ADDR_ALTERNATE] {
+		let mut id = [0];
+		if i2c.write_read(addr, &[reg::WHO_AM_I], &mut id).is_ok() {
+			if Chip::from_id(id[0]).is_some() {
+				return Some(addr);
+			}
+			answered = answered.or(Some(addr));
+		}
+	}
+	answered
+}
+
+/// Any chip of the family, fused in software.
+#[allow(dead_code)]
+pub fn new_imu(
+	i2c: impl crate::aliases::I2c,
+	delay: &mut impl DelayMs<u32>,
+	config: ImuConfig,
+) -> Option<impl FusedImu> {
+	match Lsm6ds3::new(i2c, delay, config) {
+		Ok(lsm) => Some(Fused::new(lsm)),
+		Err(err) => {
+			error!(
+				"Failed to initialize LSM6DS3: {}",
+				defmt::Debug2Format(&err)
+			);
+			None
+		}
+	}
+}
+
+/// An LSM6DSV, fused by its SFLP.
+#[allow(dead_code)]
+pub fn new_imu_sflp(
+	i2c: impl crate::aliases::I2c,
+	delay: &mut impl DelayMs<u32>,
+	config: ImuConfig,
+) -> Option<impl FusedImu> {
+	match Lsm6dsv::new(i2c, delay, config) {
+		Ok(dsv) => Some(dsv),
+		Err(err) => {
+			error!(
+				"Failed to initialize LSM6DSV: {}",
+				defmt::Debug2Format(&err)
+			);
+			None
+		}
+	}
+}
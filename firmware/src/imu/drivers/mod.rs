@@ -1,3 +1,8 @@
+pub mod autodetect;
 pub mod bmi160;
+pub mod bno085;
+pub mod icm20948;
+pub mod lsm6ds3;
 pub mod mpu6050;
+pub mod mpu9250;
 pub mod stubbed;
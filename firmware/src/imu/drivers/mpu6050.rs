@@ -1,23 +1,78 @@
 use crate::aliases::I2c;
-use crate::imu::{FusedImu, Quat};
+use crate::imu::{FusedImu, Quat, SampleRate};
 use crate::utils;
 
-use defmt::{debug, trace, warn};
+use defmt::{debug, error, trace, warn};
+use embassy_time::Duration;
 use embedded_hal::blocking::delay::DelayMs;
 use firmware_protocol::ImuType;
 use mpu6050_dmp::address::Address;
 use mpu6050_dmp::error::InitError;
 use mpu6050_dmp::sensor::Mpu6050 as LibMpu;
 
+/// The MPU samples at 1kHz before the rate divider, with the low pass filter enabled.
+const BASE_RATE_HZ: u32 = 1000;
+/// The DMP can't keep up with more than this.
+const MAX_DMP_RATE: SampleRate = SampleRate::Hz200;
+/// I2C address of the MPU, with AD0 low.
+const ADDR: u8 = 0x68;
+
+/// Registers and bits used to set up wake-on-motion. The DMP library doesn't expose
+/// these, so they are written directly.
+mod reg {
+	pub const ACCEL_CONFIG: u8 = 0x1C;
+	pub const MOT_THR: u8 = 0x1F;
+	pub const MOT_DUR: u8 = 0x20;
+	pub const INT_PIN_CFG: u8 = 0x37;
+	pub const INT_ENABLE: u8 = 0x38;
+	pub const INT_STATUS: u8 = 0x3A;
+	pub const MOT_DETECT_CTRL: u8 = 0x69;
+	pub const USER_CTRL: u8 = 0x6A;
+	pub const PWR_MGMT_1: u8 = 0x6B;
+	pub const PWR_MGMT_2: u8 = 0x6C;
+
+	/// Accelerometer high pass filter at 5Hz, so that only changes count as motion.
+	pub const ACCEL_HPF_5HZ: u8 = 0x01;
+	/// Keep the interrupt line high until INT_STATUS is read.
+	pub const LATCH_INT_EN: u8 = 0x20;
+	pub const MOT_EN: u8 = 0x40;
+	/// Delays the accelerometer by the maximum when powering it up for a sample.
+	pub const ACCEL_ON_DELAY: u8 = 0x30;
+	/// Puts all gyroscope axes in standby.
+	pub const STBY_GYRO: u8 = 0x07;
+	pub const CYCLE: u8 = 0x20;
+	pub const SLEEP: u8 = 0x40;
+	pub const TEMP_DIS: u8 = 0x08;
+}
+
+/// Values of LP_WAKE_CTRL and how long the MPU sleeps between samples with them,
+/// slowest first.
+const WAKE_RATES: [(u8, Duration); 4] = [
+	(0b00, Duration::from_millis(800)),
+	(0b01, Duration::from_millis(200)),
+	(0b10, Duration::from_millis(50)),
+	(0b11, Duration::from_millis(25)),
+];
+
 pub struct Mpu6050<I: I2c> {
 	mpu: LibMpu<I>,
 	fifo_buf: [u8; 28],
+	/// Value of the sample rate divider register.
+	smplrt_div: u8,
 }
 impl<I: I2c> Mpu6050<I> {
-	pub fn new(i2c: I, delay: &mut impl DelayMs<u32>) -> Result<Self, InitError<I>> {
+	/// Sets up the DMP to give us a quaternion at `rate`, or at 200Hz if `rate` is
+	/// faster than the DMP supports.
+	pub fn new(
+		i2c: I,
+		delay: &mut impl DelayMs<u32>,
+		rate: SampleRate,
+	) -> Result<Self, InitError<I>> {
 		debug!("Constructing MPU...");
-		let addr = Address::from(0x68);
+		let addr = Address::from(ADDR);
 		debug!("I2C address: {:x}", addr.0);
+		let rate = dmp_rate(rate);
+		let smplrt_div = rate.divider(BASE_RATE_HZ);
 
 		utils::retry(
 			4,
@@ -37,9 +92,15 @@ impl<I: I2c> Mpu6050<I> {
 					return Err((mpu.release(), error));
 				}
 				debug!("Initialized DMP");
+				// The DMP sets its own rate while initializing, so override it after
+				if let Err(error) = mpu.set_sample_rate_divider(smplrt_div) {
+					return Err((mpu.release(), error));
+				}
+				debug!("Sample rate: {}Hz", rate.hz());
 				Ok(Self {
 					mpu,
 					fifo_buf: [0; 28],
+					smplrt_div,
 				})
 			},
 			|i| warn!("Retrying IMU connection (attempts so far: {})", i + 1),
@@ -49,6 +110,16 @@ impl<I: I2c> Mpu6050<I> {
 	}
 }
 
+/// `rate`, or the fastest rate that the DMP supports if `rate` is faster.
+fn dmp_rate(rate: SampleRate) -> SampleRate {
+	if rate.hz() > MAX_DMP_RATE.hz() {
+		warn!("MPU6050 DMP only supports up to {}Hz", MAX_DMP_RATE.hz());
+		MAX_DMP_RATE
+	} else {
+		rate
+	}
+}
+
 impl<I: I2c> FusedImu for Mpu6050<I> {
 	type Error = mpu6050_dmp::error::Error<I>;
 
@@ -59,7 +130,8 @@ impl<I: I2c> FusedImu for Mpu6050<I> {
 			let data = self.mpu.read_fifo(&mut self.fifo_buf)?;
 			let opt = data.get(..16);
 			if let Some(data) = opt {
-				let q = mpu6050_dmp::quaternion::Quaternion::from_bytes(data).unwrap();
+				let q = mpu6050_dmp::quaternion::Quaternion::from_bytes(data)
+					.ok_or(nb::Error::WouldBlock)?;
 				let q = nalgebra::Quaternion {
 					coords: nalgebra::vector![q.x, q.y, q.z, q.w],
 				};
@@ -71,12 +143,109 @@ impl<I: I2c> FusedImu for Mpu6050<I> {
 			Err(nb::Error::WouldBlock)
 		}
 	}
+
+	fn reinit(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		self.mpu.initialize_dmp(delay)?;
+		self.mpu.set_sample_rate_divider(self.smplrt_div)
+	}
+
+	fn set_rate(
+		&mut self,
+		rate: SampleRate,
+		_delay: &mut impl DelayMs<u32>,
+	) -> Result<(), Self::Error> {
+		self.smplrt_div = dmp_rate(rate).divider(BASE_RATE_HZ);
+		self.mpu.set_sample_rate_divider(self.smplrt_div)
+	}
+
+	fn can_wake_on_motion(&self) -> bool {
+		true
+	}
+
+	fn wake_on_motion(self, threshold_mg: u16, latency: Duration) -> bool {
+		let mut i2c = self.mpu.release();
+		// The slowest rate that still notices motion within `latency`
+		let (rate, period) = WAKE_RATES
+			.into_iter()
+			.find(|&(_, period)| period <= latency)
+			.unwrap_or(WAKE_RATES[WAKE_RATES.len() - 1]);
+		// MOT_THR counts in units of 2mg
+		let threshold = (threshold_mg / 2).clamp(1, u8::MAX as u16) as u8;
+		debug!(
+			"MPU6050 wake-on-motion at {}mg, sampling every {}ms",
+			threshold as u16 * 2,
+			period.as_millis()
+		);
+
+		let writes = [
+			// Wake up, and stop the DMP so that it doesn't fight us
+			(reg::PWR_MGMT_1, 0),
+			(reg::USER_CTRL, 0),
+			(reg::INT_ENABLE, 0),
+			(reg::PWR_MGMT_2, reg::STBY_GYRO),
+			(reg::ACCEL_CONFIG, reg::ACCEL_HPF_5HZ),
+			(reg::MOT_THR, threshold),
+			(reg::MOT_DUR, 1),
+			(reg::MOT_DETECT_CTRL, reg::ACCEL_ON_DELAY),
+			(reg::INT_PIN_CFG, reg::LATCH_INT_EN),
+			(reg::INT_ENABLE, reg::MOT_EN),
+			(reg::PWR_MGMT_2, rate << 6 | reg::STBY_GYRO),
+			// Only sample the accelerometer every `period`
+			(reg::PWR_MGMT_1, reg::CYCLE | reg::TEMP_DIS),
+		];
+		for (register, value) in writes {
+			if let Err(err) = i2c.write(ADDR, &[register, value]) {
+				warn!(
+					"Failed to set up MPU6050 wake-on-motion: {}",
+					defmt::Debug2Format(&err)
+				);
+				return false;
+			}
+		}
+		// Clear anything that is already latched, or we would wake right away
+		let _ = i2c.write_read(ADDR, &[reg::INT_STATUS], &mut [0]);
+		true
+	}
+
+	fn sleep(self) {
+		match put_to_sleep(&mut self.mpu.release()) {
+			Ok(()) => debug!("MPU6050 is asleep"),
+			Err(err) => warn!(
+				"Failed to put the MPU6050 to sleep: {}",
+				defmt::Debug2Format(&err)
+			),
+		}
+	}
+}
+
+/// Stops the DMP and sets the SLEEP bit, giving up at the first write that fails.
+fn put_to_sleep<I: I2c>(i2c: &mut I) -> Result<(), <I as I2c>::Error> {
+	// Stop the DMP first, or it keeps the FIFO busy while we sleep
+	let writes = [
+		(reg::USER_CTRL, 0),
+		(reg::INT_ENABLE, 0),
+		(reg::PWR_MGMT_1, reg::SLEEP),
+	];
+	for (register, value) in writes {
+		i2c.write(ADDR, &[register, value])?;
+	}
+	Ok(())
 }
 
 #[allow(dead_code)]
 pub fn new_imu(
 	i2c: impl crate::aliases::I2c,
 	delay: &mut impl DelayMs<u32>,
-) -> impl crate::imu::FusedImu {
-	Mpu6050::new(i2c, delay).expect("Failed to create mpu6050")
+	rate: SampleRate,
+) -> Option<impl crate::imu::FusedImu> {
+	match Mpu6050::new(i2c, delay, rate) {
+		Ok(mpu) => Some(mpu),
+		Err(err) => {
+			error!(
+				"Failed to initialize MPU6050: {}",
+				defmt::Debug2Format(&err)
+			);
+			None
+		}
+	}
 }
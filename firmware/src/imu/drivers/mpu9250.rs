@@ -0,0 +1,394 @@
+//! Driver for the MPU-9250, an MPU-6500 with an AK8963 magnetometer in the same
+//! package. We read the raw accelerometer, gyroscope and magnetometer and fuse them
+//! ourselves with [`Madgwick`], which uses the magnetometer to keep the heading from
+//! drifting.
+//!
+//! The magnetometer only becomes useful once it is calibrated, see
+//! [`FusedImu::start_mag_calibration()`].
+
+use crate::aliases::I2c;
+use crate::imu::fusion::madgwick::Madgwick;
+use crate::imu::fusion::Fused;
+use crate::imu::{
+	divided_period, AccelRange, FusedImu, GyroRange, Imu, ImuConfig, ImuData,
+	SampleRate, Vec3,
+};
+use crate::utils;
+
+use defmt::{debug, error, info, trace, warn};
+use embassy_time::Duration;
+use embedded_hal::blocking::delay::DelayMs;
+use firmware_protocol::ImuType;
+
+/// I2C address of the MPU-9250 when AD0 is low.
+pub const ADDR: u8 = 0x68;
+/// I2C address of the MPU-9250 when AD0 is high, like on some breakout boards.
+pub const ADDR_ALTERNATE: u8 = 0x69;
+/// The I2C address of the AK8963 magnetometer, once the MPU is in bypass mode.
+const MAG_ADDR: u8 = 0x0C;
+
+/// The MPU-9250 identifies as `0x71`, and the otherwise identical MPU-9255 as `0x73`.
+const WHO_AM_I_VALUES: [u8; 2] = [0x71, 0x73];
+const MAG_WHO_AM_I_VALUE: u8 = 0x48;
+
+mod reg {
+	pub const SMPLRT_DIV: u8 = 0x19;
+	pub const CONFIG: u8 = 0x1A;
+	pub const GYRO_CONFIG: u8 = 0x1B;
+	pub const ACCEL_CONFIG: u8 = 0x1C;
+	pub const ACCEL_CONFIG_2: u8 = 0x1D;
+	pub const INT_PIN_CFG: u8 = 0x37;
+	pub const INT_ENABLE: u8 = 0x38;
+	/// Cleared when read.
+	pub const INT_STATUS: u8 = 0x3A;
+	/// Start of accel xyz, followed by temperature and gyro xyz. All big endian i16.
+	pub const ACCEL_XOUT_H: u8 = 0x3B;
+	pub const USER_CTRL: u8 = 0x6A;
+	pub const PWR_MGMT_1: u8 = 0x6B;
+	pub const PWR_MGMT_2: u8 = 0x6C;
+	pub const WHO_AM_I: u8 = 0x75;
+}
+
+/// Registers of the AK8963.
+mod mag_reg {
+	pub const WIA: u8 = 0x00;
+	pub const ST1: u8 = 0x02;
+	/// Start of mag xyz, little endian i16, followed by ST2.
+	pub const HXL: u8 = 0x03;
+	pub const CNTL1: u8 = 0x0A;
+	/// Factory sensitivity adjustment of each axis. Only readable in fuse ROM mode.
+	pub const ASAX: u8 = 0x10;
+}
+
+const PWR_MGMT_1_RESET: u8 = 0x80;
+/// Clear sleep bit and automatically pick the best clock source.
+const PWR_MGMT_1_AUTO_CLOCK: u8 = 0x01;
+const INT_PIN_CFG_BYPASS_EN: u8 = 1 << 1;
+/// Raw data ready, in both `INT_ENABLE` and `INT_STATUS`.
+const RAW_DATA_RDY_INT: u8 = 1 << 0;
+/// Low pass filter at 92Hz for the gyro, which also makes it sample at 1kHz.
+const CONFIG_DLPF_92HZ: u8 = 0x02;
+/// Low pass filter at 99Hz for the accel.
+const ACCEL_CONFIG_2_DLPF_99HZ: u8 = 0x02;
+const MAG_MODE_POWER_DOWN: u8 = 0x00;
+const MAG_MODE_FUSE_ROM: u8 = 0x0F;
+/// Continuous measurement at 100Hz, with 16 bit output.
+const MAG_MODE_CONTINUOUS_100HZ: u8 = 0x16;
+/// Set in ST2 when the magnetic field was too strong to measure.
+const MAG_ST2_HOFL: u8 = 1 << 3;
+
+/// The accel and gyro sample at 1kHz before the rate divider.
+const BASE_RATE_HZ: u32 = 1000;
+
+const TEMP_LSB_PER_C: f32 = 333.87;
+/// The temperature sensor reads 0 at this temperature, in °C.
+const TEMP_OFFSET_C: f32 = 21.;
+/// With 16 bit output, the AK8963 has a sensitivity of 0.15µT per LSB.
+const MAG_UT_PER_LSB: f32 = 0.15;
+
+pub struct InitError<I: I2c> {
+	pub i2c: I,
+	pub error: Error<I>,
+}
+impl<I> core::fmt::Debug for InitError<I>
+where
+	I: I2c,
+{
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		self.error.fmt(f)
+	}
+}
+
+pub enum Error<I: I2c> {
+	I2c(<I as I2c>::Error),
+	/// The chip at the address didn't identify as an MPU-9250.
+	WrongId(u8),
+}
+impl<I> core::fmt::Debug for Error<I>
+where
+	I: I2c,
+{
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		match self {
+			Self::I2c(err) => f.debug_tuple("I2c").field(err).finish(),
+			Self::WrongId(id) => f.debug_tuple("WrongId").field(id).finish(),
+		}
+	}
+}
+
+pub struct Mpu9250<I: I2c> {
+	i2c: I,
+	/// Either [`ADDR`] or [`ADDR_ALTERNATE`], depending on AD0.
+	addr: u8,
+	/// Factory sensitivity adjustment of each magnetometer axis, or `None` if the
+	/// magnetometer didn't respond.
+	mag_adjust: Option<Vec3>,
+	gyro_range: GyroRange,
+	accel_range: AccelRange,
+	/// Value of the sample rate divider register.
+	smplrt_div: u8,
+}
+impl<I: I2c> Mpu9250<I> {
+	/// Sets up the chip to sample at roughly `config.rate`, never slower than it, and
+	/// with the ranges of `config`. The chip may be at either [`ADDR`] or
+	/// [`ADDR_ALTERNATE`].
+	pub fn new(
+		i2c: I,
+		delay: &mut impl DelayMs<u32>,
+		config: ImuConfig,
+	) -> Result<Self, InitError<I>> {
+		debug!("Constructing MPU-9250...");
+		let smplrt_div = config.rate.divider(BASE_RATE_HZ);
+		debug!(
+			"Sample rate: {}Hz",
+			BASE_RATE_HZ / (1 + u32::from(smplrt_div))
+		);
+
+		utils::retry(
+			4,
+			i2c,
+			|mut i2c| {
+				let addr = find_address(&mut i2c).unwrap_or_else(|| {
+					warn!("No MPU-9250 answered, trying {:x} anyway", ADDR);
+					ADDR
+				});
+				let mut mpu = Self {
+					i2c,
+					addr,
+					mag_adjust: None,
+					smplrt_div,
+					gyro_range: config.gyro_range,
+					accel_range: config.accel_range,
+				};
+				match mpu.init(delay) {
+					Ok(()) => {
+						info!("Found MPU-9250 at address {:x}", addr);
+						Ok(mpu)
+					}
+					Err(err) => Err((mpu.i2c, err)),
+				}
+			},
+			|i| warn!("Retrying IMU connection (attempts so far: {})", i + 1),
+		)
+		// Map converts from tuple -> struct
+		.map_err(|(i2c, error)| InitError { i2c, error })
+	}
+
+	/// Resets the chip and configures the accel, gyro and magnetometer.
+	fn init(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Error<I>> {
+		delay.delay_ms(100);
+		trace!("Resetting MPU-9250");
+		self.write(reg::PWR_MGMT_1, PWR_MGMT_1_RESET)?;
+		delay.delay_ms(100);
+
+		let id = self.read_u8(reg::WHO_AM_I)?;
+		debug!("Constructed MPU with chip id: {:x}", id);
+		if !WHO_AM_I_VALUES.contains(&id) {
+			return Err(Error::WrongId(id));
+		}
+
+		self.write(reg::PWR_MGMT_1, PWR_MGMT_1_AUTO_CLOCK)?;
+		// Enable all axes of the accel and gyro
+		self.write(reg::PWR_MGMT_2, 0)?;
+		delay.delay_ms(50);
+
+		self.write(reg::CONFIG, CONFIG_DLPF_92HZ)?;
+		self.write(reg::GYRO_CONFIG, self.gyro_range.fs_sel() << 3)?;
+		self.write(reg::ACCEL_CONFIG, self.accel_range.fs_sel() << 3)?;
+		self.write(reg::ACCEL_CONFIG_2, ACCEL_CONFIG_2_DLPF_99HZ)?;
+		self.write(reg::SMPLRT_DIV, self.smplrt_div)?;
+		// The INT pin isn't connected, this only makes the status flag work
+		self.write(reg::INT_ENABLE, RAW_DATA_RDY_INT)?;
+		debug!("Configured accel and gyro");
+
+		// The magnetometer is optional, failing to set it up is not fatal.
+		self.mag_adjust = match self.init_mag(delay) {
+			Ok(adjust) => adjust,
+			Err(err) => {
+				warn!(
+					"Failed to set up magnetometer: {}",
+					defmt::Debug2Format(&err)
+				);
+				None
+			}
+		};
+		debug!("Magnetometer present: {}", self.mag_adjust.is_some());
+		Ok(())
+	}
+
+	/// Puts the MPU in bypass mode so that the AK8963 shows up on our I2C bus, reads
+	/// its sensitivity adjustment, and starts continuous measurements. Returns the
+	/// adjustment, or `None` if the magnetometer didn't respond.
+	fn init_mag(
+		&mut self,
+		delay: &mut impl DelayMs<u32>,
+	) -> Result<Option<Vec3>, Error<I>> {
+		// The internal I2C master has to be off for bypass mode
+		self.write(reg::USER_CTRL, 0)?;
+		self.write(reg::INT_PIN_CFG, INT_PIN_CFG_BYPASS_EN)?;
+		delay.delay_ms(10);
+
+		let mut id = [0];
+		if self.mag_read(mag_reg::WIA, &mut id).is_err() {
+			return Ok(None);
+		}
+		if id[0] != MAG_WHO_AM_I_VALUE {
+			warn!("Unexpected magnetometer id: {:x}", id[0]);
+			return Ok(None);
+		}
+
+		// The mode has to pass through power down between every change
+		self.mag_write(mag_reg::CNTL1, MAG_MODE_POWER_DOWN)?;
+		delay.delay_ms(10);
+		self.mag_write(mag_reg::CNTL1, MAG_MODE_FUSE_ROM)?;
+		delay.delay_ms(10);
+		let mut asa = [0; 3];
+		self.mag_read(mag_reg::ASAX, &mut asa)?;
+		self.mag_write(mag_reg::CNTL1, MAG_MODE_POWER_DOWN)?;
+		delay.delay_ms(10);
+		self.mag_write(mag_reg::CNTL1, MAG_MODE_CONTINUOUS_100HZ)?;
+		delay.delay_ms(10);
+
+		// From the datasheet: adjusted = raw * ((asa - 128) / 256 + 1)
+		let adjust = |asa: u8| (f32::from(asa) - 128.) / 256. + 1.;
+		Ok(Some(Vec3::new(
+			adjust(asa[0]),
+			adjust(asa[1]),
+			adjust(asa[2]),
+		)))
+	}
+
+	/// Reads the magnetic field in µT, in the axes of the accel and gyro. Returns
+	/// `None` if there is no magnetometer or no new reading.
+	fn mag(&mut self) -> Result<Option<Vec3>, Error<I>> {
+		let Some(adjust) = self.mag_adjust else {
+			return Ok(None);
+		};
+		let mut st1 = [0];
+		self.mag_read(mag_reg::ST1, &mut st1)?;
+		if st1[0] & 1 == 0 {
+			// Data not ready
+			return Ok(None);
+		}
+		// Reading all the way through ST2 is required to unlock the next sample.
+		let mut buf = [0; 7];
+		self.mag_read(mag_reg::HXL, &mut buf)?;
+		if buf[6] & MAG_ST2_HOFL != 0 {
+			trace!("Magnetometer overflowed");
+			return Ok(None);
+		}
+		let axis = |i: usize| i16::from_le_bytes([buf[i], buf[i + 1]]) as f32;
+		let mag = Vec3::new(axis(0), axis(2), axis(4)).component_mul(&adjust)
+			* MAG_UT_PER_LSB;
+		// The AK8963 is mounted with its x and y swapped relative to the MPU, and z
+		// pointing the other way
+		Ok(Some(Vec3::new(mag.y, mag.x, -mag.z)))
+	}
+
+	fn read(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), Error<I>> {
+		self.i2c
+			.write_read(self.addr, &[reg], buf)
+			.map_err(Error::I2c)
+	}
+
+	fn read_u8(&mut self, reg: u8) -> Result<u8, Error<I>> {
+		let mut buf = [0];
+		self.read(reg, &mut buf)?;
+		Ok(buf[0])
+	}
+
+	fn write(&mut self, reg: u8, value: u8) -> Result<(), Error<I>> {
+		self.i2c.write(self.addr, &[reg, value]).map_err(Error::I2c)
+	}
+
+	fn mag_read(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), Error<I>> {
+		self.i2c
+			.write_read(MAG_ADDR, &[reg], buf)
+			.map_err(Error::I2c)
+	}
+
+	fn mag_write(&mut self, reg: u8, value: u8) -> Result<(), Error<I>> {
+		self.i2c.write(MAG_ADDR, &[reg, value]).map_err(Error::I2c)
+	}
+}
+
+impl<I: I2c> Imu for Mpu9250<I> {
+	type Error = Error<I>;
+
+	const IMU_TYPE: ImuType = ImuType::Mpu9250;
+
+	fn data(&mut self) -> nb::Result<ImuData, Self::Error> {
+		// Without new data we would fuse the same reading twice
+		if self.read_u8(reg::INT_STATUS)? & RAW_DATA_RDY_INT == 0 {
+			return Err(nb::Error::WouldBlock);
+		}
+		let mut buf = [0; 14];
+		self.read(reg::ACCEL_XOUT_H, &mut buf)?;
+		let axis = |i: usize| i16::from_be_bytes([buf[i], buf[i + 1]]) as f32;
+
+		let accel = Vec3::new(axis(0), axis(2), axis(4)) / self.accel_range.lsb_per_g();
+		let temp = axis(6) / TEMP_LSB_PER_C + TEMP_OFFSET_C;
+		let gyro = Vec3::new(axis(8), axis(10), axis(12))
+			/ self.gyro_range.lsb_per_dps()
+			* (core::f32::consts::PI / 180.);
+		Ok(ImuData {
+			accel,
+			gyro,
+			temp: Some(temp),
+			mag: self.mag()?,
+		})
+	}
+
+	fn sample_period(&self) -> Duration {
+		divided_period(BASE_RATE_HZ, self.smplrt_div)
+	}
+
+	fn reinit(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		self.init(delay)
+	}
+
+	fn set_rate(
+		&mut self,
+		rate: SampleRate,
+		delay: &mut impl DelayMs<u32>,
+	) -> Result<(), Self::Error> {
+		self.smplrt_div = rate.divider(BASE_RATE_HZ);
+		self.init(delay)
+	}
+}
+
+/// Finds the address that the MPU-9250 is at, by reading its WHO_AM_I at both. An
+/// address where some other chip answers is only taken if the MPU isn't at the other
+/// one, so that initializing reports what that chip is. `None` if nothing answered.
+fn find_address(i2c: &mut impl I2c) -> Option<u8> {
+	let mut answered = None;
+	for addr in [ADDR, ADDR_ALTERNATE] {
+		let mut id = [0];
+		if i2c.write_read(addr, &[reg::WHO_AM_I], &mut id).is_ok() {
+			if WHO_AM_I_VALUES.contains(&id[0]) {
+				return Some(addr);
+			}
+			answered = answered.or(Some(addr));
+		}
+	}
+	answered
+}
+
+#[allow(dead_code)]
+pub fn new_imu(
+	i2c: impl crate::aliases::I2c,
+	delay: &mut impl DelayMs<u32>,
+	config: ImuConfig,
+) -> Option<impl FusedImu> {
+	match Mpu9250::new(i2c, delay, config) {
+		// Madgwick, because the default fusion can't use the magnetometer
+		Ok(mpu) => Some(Fused::<_, Madgwick>::new(mpu)),
+		Err(err) => {
+			error!(
+				"Failed to initialize MPU-9250: {}",
+				defmt::Debug2Format(&err)
+			);
+			None
+		}
+	}
+}
@@ -1,11 +1,40 @@
-use crate::imu::{FusedImu, Quat};
+use crate::imu::{FusedImu, Quat, SampleRate};
 
 use defmt::debug;
+use embassy_time::Instant;
 use embedded_hal::blocking::delay::DelayMs;
 use firmware_protocol::ImuType;
 
+pub use firmware_core::motion::Motion;
+
+/// What a [`FakeImu`] pretends to do, picked with the `FAKE_MOTION` env var.
+#[cfg(fake_motion = "identity")]
+pub const MOTION: Motion = Motion::Identity;
+#[cfg(fake_motion = "yaw-sweep")]
+pub const MOTION: Motion = Motion::YawSweep;
+#[cfg(fake_motion = "tilt")]
+pub const MOTION: Motion = Motion::Tilt;
+/// The recording in `FAKE_MOTION_FILE`, converted by the build script.
+#[cfg(fake_motion = "replay")]
+pub const MOTION: Motion =
+	Motion::Replay(&include!(concat!(env!("OUT_DIR"), "/fake_motion.rs")));
+
 /// Fakes an IMU for easier testing.
-struct FakeImu;
+pub struct FakeImu {
+	motion: Motion,
+	/// Time between the samples of a replay, in microseconds.
+	period_us: u32,
+	start: Instant,
+}
+impl FakeImu {
+	pub fn new(motion: Motion, rate: SampleRate) -> Self {
+		Self {
+			motion,
+			period_us: rate.period_us(),
+			start: Instant::now(),
+		}
+	}
+}
 
 impl FusedImu for FakeImu {
 	type Error = ();
@@ -13,7 +42,21 @@ impl FusedImu for FakeImu {
 	const IMU_TYPE: ImuType = ImuType::Unknown(0xFF);
 
 	fn quat(&mut self) -> nb::Result<Quat, Self::Error> {
-		Ok(Quat::identity())
+		let elapsed_us = (Instant::now() - self.start).as_micros();
+		Ok(self.motion.at(elapsed_us, self.period_us))
+	}
+
+	fn reinit(&mut self, _delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	fn set_rate(
+		&mut self,
+		rate: SampleRate,
+		_delay: &mut impl DelayMs<u32>,
+	) -> Result<(), Self::Error> {
+		self.period_us = rate.period_us();
+		Ok(())
 	}
 }
 
@@ -21,7 +64,12 @@ impl FusedImu for FakeImu {
 pub fn new_imu(
 	_i2c: impl crate::aliases::I2c,
 	_delay: &mut impl DelayMs<u32>,
-) -> impl crate::imu::FusedImu {
-	debug!("Created FakeImu");
-	FakeImu
+	rate: SampleRate,
+	motion: Motion,
+) -> Option<impl crate::imu::FusedImu> {
+	debug!(
+		"Created FakeImu with motion {}",
+		defmt::Debug2Format(&motion)
+	);
+	Some(FakeImu::new(motion, rate))
 }
@@ -0,0 +1,37 @@
+use crate::imu::fusion::Fusion;
+use crate::imu::{Quat, Vec3};
+
+use dcmimu::DCMIMU;
+
+/// Fusion using a direction cosine matrix, via the [`dcmimu`] crate.
+#[allow(dead_code)]
+pub struct Dcm {
+	dcm: DCMIMU,
+	/// The last estimate, in the frame of `dcm`
+	q: Quat,
+}
+impl Default for Dcm {
+	fn default() -> Self {
+		Self {
+			dcm: DCMIMU::new(),
+			q: Quat::identity(),
+		}
+	}
+}
+
+impl Fusion for Dcm {
+	fn update(&mut self, gyro: Vec3, accel: Vec3, dt: f32) -> Quat {
+		let (g, a) = (gyro, accel);
+		let (euler, _gyro_bias) = self.dcm.update((g.x, g.y, g.z), (a.x, a.y, a.z), dt);
+		self.q = Quat::from_euler_angles(euler.roll, euler.pitch, euler.yaw);
+		self.q
+	}
+
+	fn update_gyro(&mut self, gyro: Vec3, dt: f32) -> Quat {
+		// `DCMIMU` always corrects with the accelerometer. Its state is the direction
+		// of gravity in the sensor frame, which is where the last estimate puts the
+		// yaw axis of its euler angles, so feeding exactly that corrects nothing.
+		let expected = self.q.inverse_transform_vector(&Vec3::z());
+		self.update(gyro, expected, dt)
+	}
+}
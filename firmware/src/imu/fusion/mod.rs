@@ -1 +1,273 @@
-//! TODO: Add fusion
+//! Software sensor fusion, for IMUs that can't do it on-chip.
+
+pub mod dcm;
+
+pub use firmware_core::fusion::{madgwick, mag, mahony, temperature, Fusion};
+
+use crate::imu::fusion::mag::{MagCalibration, MagCollector};
+use crate::imu::fusion::temperature::TempCompensation;
+use crate::imu::{FusedImu, Imu, ImuData, Quat, SampleRate, Vec3};
+
+use defmt::{info, warn};
+use embassy_time::{Duration, Instant};
+use embedded_hal::blocking::delay::DelayMs;
+use firmware_core::fusion::step;
+use firmware_protocol::ImuType;
+
+/// How many gyroscope readings are averaged when calibrating.
+const CALIBRATION_SAMPLES: u32 = 200;
+/// Time to wait between polling for readings while calibrating.
+const CALIBRATION_POLL_MS: u32 = 5;
+/// Readings whose angular velocity (after removing the bias) is below this, in rad/s,
+/// count as being at rest.
+const REST_GYRO_THRESHOLD: f32 = 0.05;
+/// How many consecutive readings at rest are averaged into one temperature
+/// compensation sample. This is a few seconds at our usual data rates.
+const REST_SAMPLES: u32 = 500;
+/// How long magnetometer readings are collected for, once a calibration is started.
+const MAG_CALIBRATION_TIME: Duration = Duration::from_secs(20);
+/// When the accelerometer reads further than this from 1g, it is saturated or the
+/// tracker is accelerating hard, so it doesn't tell us where down is. Such readings
+/// are left out of the fusion, which then relies on the gyroscope alone. Picked with
+/// the `ACCEL_TOLERANCE_G` env variable.
+pub const ACCEL_TOLERANCE_G: f32 =
+	include!(concat!(env!("OUT_DIR"), "/accel_tolerance.rs"));
+
+/// The fusion algorithm used when none is specified, picked with the `fusion-*`
+/// features.
+#[cfg(not(any(feature = "fusion-madgwick", feature = "fusion-mahony")))]
+pub type DefaultFusion = dcm::Dcm;
+#[cfg(feature = "fusion-madgwick")]
+pub type DefaultFusion = madgwick::Madgwick;
+#[cfg(feature = "fusion-mahony")]
+pub type DefaultFusion = mahony::Mahony;
+
+/// The timestep to fuse a reading with, in seconds. That is the time `measured` since
+/// the last reading, or with `fusion-nominal-dt` the `nominal` sample period of the
+/// IMU, so that a late poll doesn't make it look like the IMU turned further than it
+/// did. That assumes no readings are missed, which the IMU task polling far more often
+/// than the data rate takes care of.
+fn timestep(measured: Duration, nominal: Duration) -> f32 {
+	let dt = if cfg!(feature = "fusion-nominal-dt") {
+		nominal
+	} else {
+		measured
+	};
+	dt.as_micros() as f32 / 1_000_000.
+}
+
+/// Wraps an [`Imu`] and fuses its raw readings into a [`Quat`] using `F`.
+pub struct Fused<I: Imu, F: Fusion = DefaultFusion> {
+	imu: I,
+	fusion: F,
+	/// Subtracted from the gyroscope readings, unless `temp_comp` knows better.
+	gyro_bias: Vec3,
+	temp_comp: TempCompensation,
+	/// Sum of the consecutive readings at rest so far, used to learn `temp_comp`.
+	rest: RestSum,
+	/// The magnetometer is only used once it is calibrated, since hard iron alone
+	/// can be stronger than the earth's field.
+	mag_cal: Option<MagCalibration>,
+	/// The magnetometer calibration in progress, and when it started.
+	mag_collector: Option<(Instant, MagCollector)>,
+	/// When we got the last reading, used to compute the timestep.
+	last: Instant,
+	/// The last reading that was fused, with the bias subtracted.
+	last_data: Option<ImuData>,
+}
+impl<I: Imu, F: Fusion + Default> Fused<I, F> {
+	pub fn new(imu: I) -> Self {
+		Self::with_fusion(imu, F::default())
+	}
+}
+impl<I: Imu, F: Fusion> Fused<I, F> {
+	pub fn with_fusion(imu: I, fusion: F) -> Self {
+		Self {
+			imu,
+			fusion,
+			gyro_bias: Vec3::zeros(),
+			temp_comp: TempCompensation::new(),
+			rest: RestSum::default(),
+			mag_cal: None,
+			mag_collector: None,
+			last: Instant::now(),
+			last_data: None,
+		}
+	}
+
+	#[allow(dead_code)]
+	pub fn inner(&self) -> &I {
+		&self.imu
+	}
+
+	#[allow(dead_code)]
+	pub fn inner_mut(&mut self) -> &mut I {
+		&mut self.imu
+	}
+
+	/// The bias to subtract from a reading taken at `temp` °C.
+	fn bias_at(&self, temp: Option<f32>) -> Vec3 {
+		temp.and_then(|t| self.temp_comp.bias_at(t))
+			.unwrap_or(self.gyro_bias)
+	}
+
+	/// Accumulates readings while the IMU is at rest, and turns every
+	/// [`REST_SAMPLES`] of them into a temperature compensation sample.
+	fn learn_temp_comp(&mut self, data: &ImuData, gyro: Vec3) {
+		let Some(temp) = data.temp else { return };
+		if gyro.amax() > REST_GYRO_THRESHOLD {
+			self.rest = RestSum::default();
+			return;
+		}
+		self.rest.add(data.gyro, temp);
+		if self.rest.count >= REST_SAMPLES {
+			let (bias, temp) = self.rest.mean();
+			self.temp_comp.add_sample(temp, bias);
+			self.rest = RestSum::default();
+		}
+	}
+}
+
+impl<I: Imu, F: Fusion> Fused<I, F> {
+	/// Feeds a raw magnetometer reading to the calibration in progress, if any, and
+	/// returns the calibrated reading.
+	fn correct_mag(&mut self, raw: Vec3) -> Option<Vec3> {
+		if let Some((started, collector)) = &mut self.mag_collector {
+			collector.add(raw);
+			if started.elapsed() >= MAG_CALIBRATION_TIME {
+				match collector.finish() {
+					Some(cal) => {
+						info!("Calibrated magnetometer");
+						self.mag_cal = Some(cal);
+					}
+					None => warn!("Magnetometer wasn't rotated enough to calibrate it"),
+				}
+				self.mag_collector = None;
+			}
+		}
+		self.mag_cal.map(|cal| cal.correct(raw))
+	}
+}
+
+/// Running sum of gyroscope readings and temperatures.
+#[derive(Debug, Clone, Default)]
+struct RestSum {
+	gyro: Vec3,
+	temp: f32,
+	count: u32,
+}
+impl RestSum {
+	fn add(&mut self, gyro: Vec3, temp: f32) {
+		self.gyro += gyro;
+		self.temp += temp;
+		self.count += 1;
+	}
+
+	/// The mean gyroscope reading and temperature.
+	fn mean(&self) -> (Vec3, f32) {
+		let n = self.count as f32;
+		(self.gyro / n, self.temp / n)
+	}
+}
+
+impl<I: Imu, F: Fusion> FusedImu for Fused<I, F> {
+	type Error = I::Error;
+
+	const IMU_TYPE: ImuType = I::IMU_TYPE;
+
+	fn imu_type(&self) -> ImuType {
+		self.imu.imu_type()
+	}
+
+	fn quat(&mut self) -> nb::Result<Quat, Self::Error> {
+		let data = self.imu.data()?;
+		let now = Instant::now();
+		let dt = timestep(now - self.last, self.imu.sample_period());
+		self.last = now;
+
+		let gyro = data.gyro - self.bias_at(data.temp);
+		self.learn_temp_comp(&data, gyro);
+		// Even while the accelerometer is ignored, so that calibrating isn't held up
+		let mag = data.mag.and_then(|raw| self.correct_mag(raw));
+		let q = step(
+			&mut self.fusion,
+			gyro,
+			data.accel,
+			mag,
+			dt,
+			ACCEL_TOLERANCE_G,
+		);
+		self.last_data = Some(ImuData { gyro, ..data });
+		Ok(q)
+	}
+
+	fn reinit(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		self.imu.reinit(delay)?;
+		// Don't count the time spent initializing as part of the next timestep
+		self.last = Instant::now();
+		Ok(())
+	}
+
+	fn set_rate(
+		&mut self,
+		rate: SampleRate,
+		delay: &mut impl DelayMs<u32>,
+	) -> Result<(), Self::Error> {
+		self.imu.set_rate(rate, delay)?;
+		self.last = Instant::now();
+		Ok(())
+	}
+
+	fn raw_data(&self) -> Option<ImuData> {
+		self.last_data
+	}
+
+	fn sleep(self) {
+		self.imu.sleep()
+	}
+
+	fn gyro_bias(&self) -> Option<Vec3> {
+		Some(self.gyro_bias)
+	}
+
+	fn set_gyro_bias(&mut self, bias: Vec3) {
+		self.gyro_bias = bias;
+	}
+
+	fn mag_calibration(&self) -> Option<MagCalibration> {
+		self.mag_cal
+	}
+
+	fn set_mag_calibration(&mut self, cal: MagCalibration) {
+		self.mag_cal = Some(cal);
+	}
+
+	fn start_mag_calibration(&mut self) {
+		self.mag_collector = Some((Instant::now(), MagCollector::new()));
+	}
+
+	fn calibrate(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		let mut sum = RestSum::default();
+		let mut has_temp = true;
+		while sum.count < CALIBRATION_SAMPLES {
+			match self.imu.data() {
+				Ok(data) => {
+					has_temp &= data.temp.is_some();
+					sum.add(data.gyro, data.temp.unwrap_or(0.));
+				}
+				Err(nb::Error::WouldBlock) => (),
+				Err(nb::Error::Other(err)) => return Err(err),
+			}
+			delay.delay_ms(CALIBRATION_POLL_MS);
+		}
+		let (bias, temp) = sum.mean();
+		self.gyro_bias = bias;
+		if has_temp {
+			self.temp_comp.add_sample(temp, bias);
+		}
+		self.rest = RestSum::default();
+		// Don't count the time spent calibrating as part of the next timestep
+		self.last = Instant::now();
+		Ok(())
+	}
+}
@@ -1,17 +1,132 @@
+mod calibration;
 mod drivers;
 mod fusion;
+mod prediction;
+#[cfg(feature = "fusion-relative")]
+mod relative;
+#[cfg(feature = "i2c-scan")]
+mod scan;
+mod smoothing;
+pub mod tuning;
 
-use defmt::{debug, info, trace, warn};
+use core::cell::Cell;
+use defmt::{debug, error, info, trace, warn};
 use embassy_executor::task;
 use embassy_futures::yield_now;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant};
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_storage::nor_flash::NorFlash;
 use firmware_protocol::ImuType;
 
+pub use self::fusion::mag::MagCalibration;
+use self::prediction::Prediction;
+use self::smoothing::Smoothing;
+use self::tuning::Tuning;
 use crate::{
-	aliases::ඞ::{DelayConcrete, I2cConcrete},
-	utils::{nb2a, Unreliable},
+	aliases::ඞ::{DelayConcrete, FlashConcrete, CONFIG_STORE_OFFSET},
+	peripherals::config::ConfigStore,
+	utils::Unreliable,
 };
 
-pub type Quat = nalgebra::UnitQuaternion<f32>;
+#[cfg(feature = "watchdog")]
+use crate::watchdog::{Task, WATCHDOG};
+
+// The bus that the IMUs are connected to
+#[cfg(not(feature = "transport-spi"))]
+use crate::aliases::{I2c as Bus, ඞ::I2cConcrete as BusConcrete};
+// The bus that the IMUs are connected to
+#[cfg(feature = "transport-spi")]
+use crate::aliases::{Spi as Bus, ඞ::SpiConcrete as BusConcrete};
+// The second I2C bus, which no driver uses yet
+#[cfg(feature = "i2c-secondary")]
+use crate::aliases::ඞ::I2c2Concrete as Bus2Concrete;
+#[cfg(not(feature = "i2c-secondary"))]
+type Bus2Concrete<'a> = ();
+
+pub use firmware_core::imu::{AccelRange, GyroRange, SampleRate};
+pub use firmware_core::{Quat, Vec3};
+
+/// A single reading from an [`Imu`], before any sensor fusion happened.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ImuData {
+	/// Acceleration, in g.
+	pub accel: Vec3,
+	/// Angular velocity, in radians per second.
+	pub gyro: Vec3,
+	/// Temperature of the chip, in °C. `None` if the IMU can't measure it.
+	pub temp: Option<f32>,
+	/// Magnetic field, in µT. `None` if the IMU has no magnetometer, or it has no new
+	/// reading yet.
+	pub mag: Option<Vec3>,
+}
+
+/// An IMU that only gives us raw readings. Use [`fusion::Fused`] to turn it into a
+/// [`FusedImu`].
+pub trait Imu {
+	type Error: core::fmt::Debug;
+
+	const IMU_TYPE: ImuType;
+	fn data(&mut self) -> nb::Result<ImuData, Self::Error>;
+
+	/// The time between readings at the data rate the IMU was configured for.
+	fn sample_period(&self) -> Duration;
+
+	/// The type of the IMU. Only differs from [`Self::IMU_TYPE`] for drivers that
+	/// support several chips.
+	fn imu_type(&self) -> ImuType {
+		Self::IMU_TYPE
+	}
+
+	/// Initializes the IMU again, to recover it after errors.
+	fn reinit(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error>;
+
+	/// Changes the rate that the IMU samples at, which [`Self::sample_period()`]
+	/// follows. Does nothing if the IMU only supports one rate.
+	fn set_rate(
+		&mut self,
+		_rate: SampleRate,
+		_delay: &mut impl DelayMs<u32>,
+	) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	/// Puts the IMU into its lowest power mode, so that it doesn't keep running when
+	/// the MCU resets. Does nothing if the IMU has no such mode.
+	fn sleep(self)
+	where
+		Self: Sized,
+	{
+	}
+}
+
+/// How confident an IMU is in its own calibration, from worst to best.
+#[derive(defmt::Format, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Accuracy {
+	Unreliable,
+	Low,
+	Medium,
+	High,
+}
+
+/// The orientation of an IMU, and how much it can be trusted.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rotation {
+	pub quat: Quat,
+	/// `None` if the IMU doesn't know.
+	pub accuracy: Option<Accuracy>,
+	/// The reading that `quat` was fused from. Only `Some` with the `raw-telemetry`
+	/// feature, and for IMUs that we do the fusion for.
+	pub raw: Option<ImuData>,
+	/// Temperature of the IMU in °C, for the IMUs that we do the fusion for and that
+	/// can measure it.
+	pub temp: Option<f32>,
+	/// When the rotation was read from the IMU. Counts from boot, like every
+	/// [`Instant`].
+	pub timestamp: Instant,
+}
 
 pub trait FusedImu {
 	type Error: core::fmt::Debug;
@@ -19,59 +134,571 @@ pub trait FusedImu {
 	const IMU_TYPE: ImuType;
 	// TODO: This should be async
 	fn quat(&mut self) -> nb::Result<Quat, Self::Error>;
+
+	/// Initializes the IMU again, to recover it after errors. The gyroscope bias is
+	/// kept.
+	fn reinit(&mut self, delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error>;
+
+	/// Changes the rate that the IMU gives us rotations at. Does nothing if the IMU
+	/// only supports one rate.
+	fn set_rate(
+		&mut self,
+		_rate: SampleRate,
+		_delay: &mut impl DelayMs<u32>,
+	) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	/// How accurate the most recent [`Self::quat()`] was. `None` if the IMU doesn't
+	/// know, which is the case unless it does its own sensor fusion.
+	fn accuracy(&self) -> Option<Accuracy> {
+		None
+	}
+
+	/// The reading that the most recent [`Self::quat()`] was fused from, with the
+	/// gyroscope bias already subtracted. `None` if the IMU does its own sensor fusion.
+	fn raw_data(&self) -> Option<ImuData> {
+		None
+	}
+
+	/// The type of the IMU. Only differs from [`Self::IMU_TYPE`] when the IMU is picked
+	/// at runtime.
+	fn imu_type(&self) -> ImuType {
+		Self::IMU_TYPE
+	}
+
+	/// The gyroscope bias that gets subtracted from the readings. `None` if the IMU
+	/// calibrates itself.
+	fn gyro_bias(&self) -> Option<Vec3> {
+		None
+	}
+
+	/// Sets the gyroscope bias. Does nothing if the IMU calibrates itself.
+	fn set_gyro_bias(&mut self, _bias: Vec3) {}
+
+	/// Measures the gyroscope bias. The IMU must be kept still while this runs. Does
+	/// nothing if the IMU calibrates itself.
+	fn calibrate(&mut self, _delay: &mut impl DelayMs<u32>) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	/// The hard and soft iron calibration of the magnetometer. `None` if there is no
+	/// magnetometer, or it isn't calibrated yet.
+	fn mag_calibration(&self) -> Option<MagCalibration> {
+		None
+	}
+
+	/// Sets the magnetometer calibration. Does nothing without a magnetometer.
+	fn set_mag_calibration(&mut self, _cal: MagCalibration) {}
+
+	/// Starts collecting magnetometer readings while the tracker is waved around in a
+	/// figure eight. Once enough time passed, [`Self::mag_calibration()`] returns the
+	/// new calibration. Does nothing without a magnetometer.
+	fn start_mag_calibration(&mut self) {}
+
+	/// Whether [`Self::wake_on_motion()`] is supported.
+	fn can_wake_on_motion(&self) -> bool {
+		false
+	}
+
+	/// Puts the IMU into a low power mode where it raises its interrupt line once it
+	/// accelerates more than `threshold_mg`, checking at least every `latency`. The
+	/// IMU can't be used afterwards, so this is only meant for right before the MCU
+	/// goes to sleep. Returns whether it succeeded.
+	fn wake_on_motion(self, _threshold_mg: u16, _latency: Duration) -> bool
+	where
+		Self: Sized,
+	{
+		false
+	}
+
+	/// Puts the IMU into its lowest power mode, so that it doesn't keep running when
+	/// the MCU resets. The IMU can't be used afterwards. Does nothing if the IMU has no
+	/// such mode.
+	fn sleep(self)
+	where
+		Self: Sized,
+	{
+	}
+}
+
+/// Signalled to put the IMUs to sleep, see [`shutdown()`].
+static SHUTDOWN: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Signalled by the IMU task once the IMUs are asleep.
+static ASLEEP: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// How long [`shutdown()`] waits for the IMU task, which may be stuck on a broken IMU.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Puts the IMUs to sleep, before the MCU resets. A reset doesn't cut the power of the
+/// IMUs, so they would keep running and draining the battery until they are
+/// initialized again.
+pub async fn shutdown() {
+	SHUTDOWN.signal(());
+	if with_timeout(SHUTDOWN_TIMEOUT, ASLEEP.wait()).await.is_err() {
+		warn!("Timed out putting the IMUs to sleep");
+	}
+}
+
+/// Time between the readings of an IMU that samples at `base_hz` and outputs
+/// `base_hz / (1 + div)`, see [`SampleRate::divider()`].
+pub const fn divided_period(base_hz: u32, div: u8) -> Duration {
+	Duration::from_micros(firmware_core::imu::divided_period_us(base_hz, div))
 }
 
-/// Gets data from the IMU
+/// The sample rate that the IMUs are configured with, picked with the `IMU_RATE` env
+/// variable.
+#[cfg(imu_rate = "50")]
+pub const SAMPLE_RATE: SampleRate = SampleRate::Hz50;
+#[cfg(imu_rate = "100")]
+pub const SAMPLE_RATE: SampleRate = SampleRate::Hz100;
+#[cfg(imu_rate = "200")]
+pub const SAMPLE_RATE: SampleRate = SampleRate::Hz200;
+#[cfg(imu_rate = "500")]
+pub const SAMPLE_RATE: SampleRate = SampleRate::Hz500;
+
+/// The range of the gyroscopes, picked with the `GYRO_RANGE` env variable.
+#[cfg(gyro_range = "250")]
+pub const GYRO_RANGE: GyroRange = GyroRange::Dps250;
+#[cfg(gyro_range = "500")]
+pub const GYRO_RANGE: GyroRange = GyroRange::Dps500;
+#[cfg(gyro_range = "1000")]
+pub const GYRO_RANGE: GyroRange = GyroRange::Dps1000;
+#[cfg(gyro_range = "2000")]
+pub const GYRO_RANGE: GyroRange = GyroRange::Dps2000;
+
+/// The range of the accelerometers, picked with the `ACCEL_RANGE` env variable.
+#[cfg(accel_range = "2")]
+pub const ACCEL_RANGE: AccelRange = AccelRange::G2;
+#[cfg(accel_range = "4")]
+pub const ACCEL_RANGE: AccelRange = AccelRange::G4;
+#[cfg(accel_range = "8")]
+pub const ACCEL_RANGE: AccelRange = AccelRange::G8;
+#[cfg(accel_range = "16")]
+pub const ACCEL_RANGE: AccelRange = AccelRange::G16;
+
+/// How the IMUs are set up. Drivers that fuse on the chip, or that don't support
+/// changing the ranges, only use the [`SampleRate`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ImuConfig {
+	pub rate: SampleRate,
+	pub gyro_range: GyroRange,
+	pub accel_range: AccelRange,
+}
+
+pub const IMU_CONFIG: ImuConfig = ImuConfig {
+	rate: SAMPLE_RATE,
+	gyro_range: GYRO_RANGE,
+	accel_range: ACCEL_RANGE,
+};
+
+/// How many IMUs we can drive at once.
+#[cfg(feature = "mux-tca9548a")]
+pub const MAX_IMUS: usize = crate::peripherals::tca9548a::NUM_CHANNELS;
+/// How many IMUs we can drive at once.
+#[cfg(not(feature = "mux-tca9548a"))]
+pub const MAX_IMUS: usize = 1;
+
+/// The latest orientation of each IMU, indexed by the sensor id.
+pub type QuatSignals = [Unreliable<Rotation>; MAX_IMUS];
+
+/// The type of each IMU that was initialized, indexed by the sensor id. `None` until
+/// the IMU task got to them, and for IMUs that were not found or that it gave up on.
+/// This lets the protocol tell the server what trackers we have.
+pub static IMU_TYPES: Mutex<
+	CriticalSectionRawMutex,
+	Cell<[Option<ImuType>; MAX_IMUS]>,
+> = Mutex::new(Cell::new([None; MAX_IMUS]));
+
+/// Consecutive read errors after which an IMU gets initialized again.
+const MAX_CONSECUTIVE_ERRORS: u8 = 10;
+/// How many times in a row we initialize a failing IMU again before giving up on it.
+const MAX_REINITS: u8 = 3;
+
+/// Keeps track of the errors of one IMU, to decide when to initialize it again.
+#[derive(Debug, Default, Copy, Clone)]
+struct Health {
+	/// Read errors since the last successful read.
+	errors: u8,
+	/// Times the IMU was initialized again since the last successful read.
+	reinits: u8,
+}
+
+/// Addresses that any of the supported IMUs could be on. Used to check if a mux
+/// channel has anything connected.
+#[cfg(feature = "mux-tca9548a")]
+const IMU_ADDRESSES: [u8; 6] = [0x68, 0x69, 0x4A, 0x4B, 0x6A, 0x6B];
+
+/// Gets data from the IMUs.
+///
+/// IMUs that keep erroring are initialized again, and if that doesn't help they are
+/// dropped so that the other IMUs and the network connection keep working.
 #[task]
 pub async fn imu_task(
-	quat_signal: &'static Unreliable<Quat>,
-	i2c: I2cConcrete<'static>,
+	quat_signals: &'static QuatSignals,
+	bus: BusConcrete<'static>,
+	bus2: Bus2Concrete<'static>,
 	delay: DelayConcrete,
+	flash: FlashConcrete<'static>,
 ) -> ! {
-	imu_task_inner(quat_signal, i2c, delay).await
+	// Drivers that need both buses would take it from here. Until then it only shows
+	// up in the scan, before the IMUs are initialized
+	#[cfg(all(feature = "i2c-secondary", feature = "i2c-scan"))]
+	let mut bus2 = bus2;
+	#[cfg(all(feature = "i2c-secondary", feature = "i2c-scan"))]
+	scan::scan(&mut bus2, scan::Location::Secondary);
+	let _ = bus2;
+	imu_task_inner(quat_signals, bus, delay, flash).await
 }
 
 /// Same as [`imu_task()`] but this version's arguments are type erased behind impl
 /// Trait to avoid accidentally accessing concrete behavior.
 async fn imu_task_inner(
-	quat_signal: &Unreliable<Quat>,
-	i2c: impl crate::aliases::I2c,
+	quat_signals: &QuatSignals,
+	bus: impl Bus,
 	mut delay: impl crate::aliases::Delay,
+	flash: impl NorFlash,
 ) -> ! {
 	debug!("Imu task");
-	let mut imu = new_imu(i2c, &mut delay);
-	info!("Initialized IMU!");
+	debug!("IMU sample rate: {}Hz", SAMPLE_RATE.hz());
+	debug!(
+		"IMU ranges: ±{}dps, ±{}g",
+		GYRO_RANGE.dps(),
+		ACCEL_RANGE.g()
+	);
+	#[cfg(not(feature = "transport-spi"))]
+	info!("I2C clock: {}kHz", crate::peripherals::I2C_KHZ);
+
+	// Before any driver touches the bus, so that we see it as it came up
+	#[cfg(feature = "i2c-scan")]
+	let mut bus = bus;
+	#[cfg(feature = "i2c-scan")]
+	scan::scan(&mut bus, scan::Location::Primary);
+
+	#[cfg(not(feature = "mux-tca9548a"))]
+	let mut imus = [new_imu(bus, &mut delay, IMU_CONFIG)];
+
+	#[cfg(feature = "mux-tca9548a")]
+	let mux = crate::peripherals::tca9548a::Tca9548a::new(bus);
+	#[cfg(feature = "mux-tca9548a")]
+	let mut imus: [_; MAX_IMUS] = core::array::from_fn(|channel| {
+		use embedded_hal::blocking::i2c::Read;
+
+		let mut i2c = mux.channel(channel as u8);
+		#[cfg(feature = "i2c-scan")]
+		scan::scan(&mut i2c, scan::Location::MuxChannel(channel as u8));
+		let present = IMU_ADDRESSES
+			.iter()
+			.any(|&addr| i2c.read(addr, &mut [0]).is_ok());
+		if !present {
+			info!("No IMU on mux channel {}, skipping it", channel);
+			return None;
+		}
+		new_imu(i2c, &mut delay, IMU_CONFIG)
+	});
+
+	for (i, imu) in imus.iter().enumerate() {
+		if let Some(imu) = imu {
+			info!(
+				"Initialized IMU {}: {}",
+				i,
+				defmt::Debug2Format(&imu.imu_type())
+			);
+		}
+	}
+	// Autodetection may have found fewer IMUs than there are channels
+	let types = core::array::from_fn(|i| imus[i].as_ref().map(|imu| imu.imu_type()));
+	IMU_TYPES.lock(|t| t.set(types));
+
+	// Only calibrate the IMUs that don't have a stored calibration yet
+	let mut store = ConfigStore::new(flash, CONFIG_STORE_OFFSET);
+	let mut stored = calibration::load(&mut store);
+	let mut biases = stored;
+	for (i, imu) in imus.iter_mut().enumerate() {
+		let Some(imu) = imu else { continue };
+		match stored[i] {
+			Some(bias) => imu.set_gyro_bias(bias),
+			None => calibration::calibrate(i, imu, &mut delay),
+		}
+		biases[i] = imu.gyro_bias();
+	}
+	calibration::save_if_changed(&mut store, &stored, &biases);
+	stored = biases;
+
+	let mut stored_mag = calibration::load_mag(&mut store);
+	for (imu, cal) in imus.iter_mut().zip(stored_mag) {
+		if let (Some(imu), Some(cal)) = (imu, cal) {
+			imu.set_mag_calibration(cal);
+		}
+	}
+
+	#[cfg(feature = "deep-sleep")]
+	let mut stillness: [_; MAX_IMUS] =
+		core::array::from_fn(|_| crate::power::Stillness::new());
+	#[cfg(feature = "deep-sleep")]
+	let can_sleep = imus.iter().flatten().all(|imu| imu.can_wake_on_motion());
+	#[cfg(feature = "deep-sleep")]
+	if !can_sleep {
+		warn!("Not every IMU supports wake-on-motion, so we will never sleep");
+	}
 
+	let mut tuning = Tuning::DEFAULT;
+	log_tuning(&tuning);
+	let mut smoothing = [Smoothing::new(tuning.smoothing); MAX_IMUS];
+	let mut prediction = [Prediction::new(tuning.prediction_lead); MAX_IMUS];
+	#[cfg(feature = "fusion-relative")]
+	let mut relative = [relative::Relative::new(); MAX_IMUS];
+
+	let mut imu_health = [Health::default(); MAX_IMUS];
 	loop {
-		let q = match nb2a(|| imu.quat()).await {
-			Ok(q) => q,
-			Err(err) => {
-				warn!("Error in IMU: {}", defmt::Debug2Format(&err));
-				continue;
+		#[cfg(feature = "watchdog")]
+		WATCHDOG.pet(Task::Imu);
+
+		if calibration::RECALIBRATE.try_take().is_some() {
+			for (i, imu) in imus.iter_mut().enumerate() {
+				let Some(imu) = imu else { continue };
+				calibration::calibrate(i, imu, &mut delay);
+				biases[i] = imu.gyro_bias();
+				// Calibrating every IMU in one go could take longer than the watchdog
+				// allows, so give it a chance to be fed in between
+				#[cfg(feature = "watchdog")]
+				{
+					WATCHDOG.pet(Task::Imu);
+					yield_now().await;
+				}
 			}
-		};
-		trace!(
-			"Quat values: x: {}, y: {}, z: {}, w: {}",
-			q.coords.x,
-			q.coords.y,
-			q.coords.z,
-			q.coords.w
-		);
-		quat_signal.signal(q);
+			calibration::save_if_changed(&mut store, &stored, &biases);
+			stored = biases;
+		}
+		if calibration::RECALIBRATE_MAG.try_take().is_some() {
+			info!(
+				"Calibrating magnetometers, wave the tracker around in a figure eight"
+			);
+			for imu in imus.iter_mut().flatten() {
+				imu.start_mag_calibration();
+			}
+		}
+		calibration::save_mag_if_changed(&mut store, &mut stored_mag, &imus);
+
+		// Only after the calibration above, so that settings that arrived while it ran
+		// don't take effect in the middle of it
+		if let Some(request) = tuning::REQUESTED.try_take() {
+			let old_rate = tuning.rate;
+			tuning.apply(request);
+			info!(
+				"Applied settings from the server: {}Hz, smoothing {}, prediction {}ms",
+				tuning.rate.hz(),
+				tuning.smoothing,
+				tuning.prediction_lead.as_millis()
+			);
+			if tuning.rate != old_rate {
+				for (i, imu) in imus.iter_mut().enumerate() {
+					let Some(imu) = imu else { continue };
+					// An IMU that is left broken gets initialized again by the error
+					// handling below, with the new rate
+					if let Err(err) = imu.set_rate(tuning.rate, &mut delay) {
+						warn!(
+							"Failed to change the rate of IMU {}: {}",
+							i,
+							defmt::Debug2Format(&err)
+						);
+					}
+					// Some IMUs are initialized again for this, which could take
+					// longer than the watchdog allows
+					#[cfg(feature = "watchdog")]
+					{
+						WATCHDOG.pet(Task::Imu);
+						yield_now().await;
+					}
+				}
+			}
+			for s in &mut smoothing {
+				s.set_factor(tuning.smoothing);
+			}
+			for p in &mut prediction {
+				p.set_lead(tuning.prediction_lead);
+			}
+			tuning::APPLIED.signal(tuning);
+		}
+
+		#[cfg(feature = "fusion-relative")]
+		if relative::REZERO.try_take().is_some() {
+			info!("Taking the next rotations as the new reference");
+			for r in &mut relative {
+				r.rezero();
+			}
+			// Blending the old frame into the new one would be meaningless
+			for s in &mut smoothing {
+				s.reset();
+			}
+			for p in &mut prediction {
+				p.reset();
+			}
+		}
+
+		if SHUTDOWN.try_take().is_some() {
+			debug!("Putting the IMUs to sleep");
+			for imu in imus.iter_mut().filter_map(Option::take) {
+				imu.sleep();
+			}
+			ASLEEP.signal(());
+			// The IMUs are gone now, and the MCU is about to reset
+			core::future::pending::<()>().await;
+		}
+
+		for (i, slot) in imus.iter_mut().enumerate() {
+			// Absent IMUs are skipped, so they can't stall the others
+			let Some(imu) = slot else { continue };
+			let health = &mut imu_health[i];
+			let q = match imu.quat() {
+				Ok(q) => q,
+				Err(nb::Error::WouldBlock) => continue,
+				Err(nb::Error::Other(err)) => {
+					warn!("Error in IMU {}: {}", i, defmt::Debug2Format(&err));
+					health.errors += 1;
+					if health.errors < MAX_CONSECUTIVE_ERRORS {
+						continue;
+					}
+					health.errors = 0;
+					if health.reinits >= MAX_REINITS {
+						error!("IMU {} keeps failing, giving up on it", i);
+						*slot = None;
+						IMU_TYPES.lock(|t| {
+							let mut types = t.get();
+							types[i] = None;
+							t.set(types);
+						});
+						continue;
+					}
+					health.reinits += 1;
+					warn!("Initializing IMU {} again (attempt {})", i, health.reinits);
+					// Don't blend the rotations from before the IMU broke into new ones
+					smoothing[i].reset();
+					prediction[i].reset();
+					if let Err(err) = imu.reinit(&mut delay) {
+						warn!(
+							"Failed to initialize IMU {}: {}",
+							i,
+							defmt::Debug2Format(&err)
+						);
+					}
+					continue;
+				}
+			};
+			let timestamp = Instant::now();
+			*health = Health::default();
+			#[cfg(feature = "fusion-relative")]
+			let q = relative[i].update(q);
+			let q = smoothing[i].update(q);
+			#[cfg(feature = "deep-sleep")]
+			stillness[i].update(q);
+			// Last, to also make up for the latency of the smoothing
+			let q = prediction[i].update(q, timestamp);
+			trace!(
+				"Quat values of IMU {}: x: {}, y: {}, z: {}, w: {}",
+				i,
+				q.coords.x,
+				q.coords.y,
+				q.coords.z,
+				q.coords.w
+			);
+			quat_signals[i].signal(Rotation {
+				quat: q,
+				accuracy: imu.accuracy(),
+				raw: if cfg!(feature = "raw-telemetry") {
+					imu.raw_data()
+				} else {
+					None
+				},
+				temp: imu.raw_data().and_then(|raw| raw.temp),
+				timestamp,
+			});
+		}
+
+		#[cfg(feature = "deep-sleep")]
+		if can_sleep && is_sleepy(&imus, &stillness) {
+			use crate::power::{MOTION_THRESHOLD_MG, SLEEP, WAKE_LATENCY};
+
+			info!("IMUs have been still for a while, arming wake-on-motion");
+			let mut armed = true;
+			for imu in imus.iter_mut().filter_map(Option::take) {
+				armed &= imu.wake_on_motion(MOTION_THRESHOLD_MG, WAKE_LATENCY);
+			}
+			SLEEP.signal(armed);
+			// The IMUs are gone now, so leave the rest to the power task
+			core::future::pending::<()>().await;
+		}
 		yield_now().await // Yield to ensure fairness
 	}
 }
 
+/// Logs the settings that change the rotations, if they are on.
+fn log_tuning(tuning: &Tuning) {
+	if tuning.smoothing > 0. {
+		info!(
+			"Smoothing the rotations with a factor of {}",
+			tuning.smoothing
+		);
+	}
+	if tuning.prediction_lead.as_ticks() > 0 {
+		info!(
+			"Predicting the rotations {}ms ahead",
+			tuning.prediction_lead.as_millis()
+		);
+	}
+}
+
+/// Whether every IMU that is still working has been still for long enough to sleep.
+#[cfg(feature = "deep-sleep")]
+fn is_sleepy<I>(imus: &[Option<I>], stillness: &[crate::power::Stillness]) -> bool {
+	let mut any = false;
+	for (imu, stillness) in imus.iter().zip(stillness) {
+		if imu.is_some() {
+			any = true;
+			if !stillness.is_sleepy() {
+				return false;
+			}
+		}
+	}
+	// Without IMUs nothing could wake us up again
+	any
+}
+
+#[cfg(not(feature = "transport-spi"))]
 fn new_imu(
 	i2c: impl crate::aliases::I2c,
 	delay: &mut impl crate::aliases::Delay,
-) -> impl FusedImu {
+	config: ImuConfig,
+) -> Option<impl FusedImu> {
 	use crate::imu::drivers as d;
 
+	#[cfg(feature = "imu-autodetect")]
+	return d::autodetect::new_imu(i2c, delay, config);
 	#[cfg(feature = "imu-bmi160")]
-	return d::bmi160::new_imu(i2c, delay);
+	return d::bmi160::new_imu(i2c, delay, config.rate);
+	#[cfg(feature = "imu-bno085")]
+	return d::bno085::new_imu(i2c, delay, config.rate);
+	#[cfg(feature = "imu-icm20948")]
+	return d::icm20948::new_imu(i2c, delay, config);
+	#[cfg(feature = "imu-lsm6ds3")]
+	return d::lsm6ds3::new_imu(i2c, delay, config);
+	#[cfg(feature = "imu-lsm6dsv")]
+	return d::lsm6ds3::new_imu_sflp(i2c, delay, config);
 	#[cfg(feature = "imu-mpu6050")]
-	return d::mpu6050::new_imu(i2c, delay);
+	return d::mpu6050::new_imu(i2c, delay, config.rate);
+	#[cfg(feature = "imu-mpu9250")]
+	return d::mpu9250::new_imu(i2c, delay, config);
 	#[cfg(feature = "imu-stubbed")]
-	return d::stubbed::new_imu(i2c, delay);
+	return d::stubbed::new_imu(i2c, delay, config.rate, d::stubbed::MOTION);
+}
+
+#[cfg(feature = "transport-spi")]
+fn new_imu(
+	spi: impl crate::aliases::Spi,
+	delay: &mut impl crate::aliases::Delay,
+	config: ImuConfig,
+) -> Option<impl FusedImu> {
+	use crate::imu::drivers as d;
+
+	#[cfg(feature = "imu-bmi160")]
+	return d::bmi160::new_imu_spi(spi, delay, config.rate);
 }
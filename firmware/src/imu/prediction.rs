@@ -0,0 +1,72 @@
+//! Optional prediction of the rotations that we send, to make up for the latency of
+//! the fusion and the network during fast movements.
+//!
+//! The angular velocity is estimated from the last two rotations of an IMU, and the
+//! newest one is turned ahead at that velocity by the lead time. The further ahead we
+//! look, the more a change of direction overshoots, so the lead is capped at
+//! [`MAX_PREDICTION_LEAD`].
+
+use embassy_time::{Duration, Instant};
+use skeletal_model::velocity::{angular_velocity, extrapolate};
+
+use crate::imu::Quat;
+
+/// The longest lead that [`Prediction`] looks ahead by.
+pub const MAX_PREDICTION_LEAD: Duration = Duration::from_millis(10);
+/// How far ahead the rotations are predicted, picked with the `PREDICTION_LEAD_MS`
+/// env variable. Zero turns prediction off.
+pub const PREDICTION_LEAD: Duration =
+	Duration::from_millis(include!(concat!(env!("OUT_DIR"), "/prediction_lead.rs")));
+
+/// Readings further apart than this tell little about how fast the IMU turns now,
+/// such as when it was initialized again in between.
+const MAX_GAP: Duration = Duration::from_millis(100);
+
+/// Predicts the rotations of one IMU. See the [module](self) documentation.
+#[derive(Debug, Copy, Clone)]
+pub struct Prediction {
+	lead: Duration,
+	prev: Option<(Quat, Instant)>,
+}
+impl Prediction {
+	/// Looks `lead` ahead, but never more than [`MAX_PREDICTION_LEAD`].
+	pub fn new(lead: Duration) -> Self {
+		Self {
+			lead: lead.min(MAX_PREDICTION_LEAD),
+			prev: None,
+		}
+	}
+
+	/// Changes the lead, which is capped like in [`Self::new()`].
+	pub fn set_lead(&mut self, lead: Duration) {
+		self.lead = lead.min(MAX_PREDICTION_LEAD);
+	}
+
+	/// Returns where `q`, read at `timestamp`, will be after the lead time. `q` is
+	/// returned as it is when the lead is zero, and when there is no recent rotation
+	/// to tell the velocity from.
+	pub fn update(&mut self, q: Quat, timestamp: Instant) -> Quat {
+		if self.lead.as_ticks() == 0 {
+			return q;
+		}
+		let Some((prev, then)) = self.prev.replace((q, timestamp)) else {
+			return q;
+		};
+		let dt = timestamp - then;
+		if dt > MAX_GAP {
+			return q;
+		}
+		// Zero if `dt` is, which leaves `q` as it is
+		let velocity = angular_velocity(prev, q, secs(dt));
+		extrapolate(q, velocity, secs(self.lead))
+	}
+
+	/// Forgets the previous rotation, so that the next one isn't predicted from it.
+	pub fn reset(&mut self) {
+		self.prev = None;
+	}
+}
+
+fn secs(duration: Duration) -> f32 {
+	duration.as_micros() as f32 / 1_000_000.
+}
@@ -0,0 +1,11 @@
+//! Optional "relative" output, see [`Relative`].
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+pub use firmware_core::relative::Relative;
+
+/// Signal this to make the IMU task take the next reading of every IMU as its new
+/// reference.
+#[allow(dead_code)]
+pub static REZERO: Signal<CriticalSectionRawMutex, ()> = Signal::new();
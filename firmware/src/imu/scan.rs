@@ -0,0 +1,54 @@
+//! Lists the devices on the I2C bus at boot, to tell a missing IMU apart from one that
+//! doesn't work. Enabled with the `i2c-scan` feature.
+
+use core::ops::RangeInclusive;
+use defmt::{info, warn};
+
+use crate::aliases::I2c;
+
+/// Every address that the I2C specification doesn't reserve
+const ADDRESSES: RangeInclusive<u8> = 0x08..=0x77;
+const NUM_ADDRESSES: usize = 0x77 - 0x08 + 1;
+
+/// Which bus is being scanned, for the log
+#[derive(Debug, Clone, Copy)]
+pub enum Location {
+	/// The bus that the IMU is on
+	Primary,
+	/// The second bus of the `i2c-secondary` feature
+	#[allow(dead_code)]
+	Secondary,
+	/// A downstream bus of the TCA9548A mux
+	#[allow(dead_code)]
+	MuxChannel(u8),
+}
+
+/// Logs the address of every device that acknowledges a read on `bus`.
+pub fn scan(bus: &mut impl I2c, location: Location) {
+	let mut found = heapless::Vec::<u8, NUM_ADDRESSES>::new();
+	for addr in ADDRESSES {
+		if bus.read(addr, &mut [0]).is_ok() {
+			// Can't be full, it has room for every address
+			let _ = found.push(addr);
+		}
+	}
+	match (location, found.is_empty()) {
+		(Location::Primary, true) => warn!("I2C scan: no devices answered"),
+		(Location::Primary, false) => info!("I2C scan: devices at {=[u8]:#x}", &found),
+		(Location::Secondary, true) => {
+			warn!("I2C scan of the second bus: no devices answered")
+		}
+		(Location::Secondary, false) => {
+			info!("I2C scan of the second bus: devices at {=[u8]:#x}", &found)
+		}
+		(Location::MuxChannel(c), true) => {
+			warn!("I2C scan of mux channel {}: no devices answered", c)
+		}
+		(Location::MuxChannel(c), false) => {
+			info!(
+				"I2C scan of mux channel {}: devices at {=[u8]:#x}",
+				c, &found
+			)
+		}
+	}
+}
@@ -0,0 +1,8 @@
+//! Optional low-pass filter on the rotations that we send, see [`Smoothing`].
+
+pub use firmware_core::smoothing::Smoothing;
+
+/// How much of the previous output is kept for each sample, from 0 (no smoothing) up
+/// to but not including 1. Picked with the `OUTPUT_SMOOTHING` env variable.
+pub const OUTPUT_SMOOTHING: f32 =
+	include!(concat!(env!("OUT_DIR"), "/output_smoothing.rs"));
@@ -0,0 +1,114 @@
+//! The settings of the IMU task that the server can change while we run, with
+//! [`CbPacket::Config`](firmware_protocol::CbPacket::Config).
+//!
+//! The protocol checks the settings when they arrive, and hands the valid ones to the
+//! IMU task with [`REQUESTED`]. The IMU task applies them between readings, and never
+//! in the middle of a calibration, so settings that arrive while it calibrates wait
+//! until it is done. It then reports what it uses with [`APPLIED`], which the
+//! protocol echoes back to the server. The settings aren't stored, so a restart goes
+//! back to the ones the firmware was built with.
+//!
+//! A new sample rate is set on every IMU, which for most of them means initializing
+//! them again. IMUs that only support one rate keep it.
+
+use defmt::warn;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Duration;
+use firmware_protocol::TrackerConfig;
+
+use crate::imu::prediction::{MAX_PREDICTION_LEAD, PREDICTION_LEAD};
+use crate::imu::smoothing::OUTPUT_SMOOTHING;
+use crate::imu::{SampleRate, SAMPLE_RATE};
+
+/// Signalled by the protocol with the valid settings from the server.
+pub static REQUESTED: Signal<CriticalSectionRawMutex, Request> = Signal::new();
+/// Signalled by the IMU task with the settings that it uses, once it applied a
+/// [`Request`].
+pub static APPLIED: Signal<CriticalSectionRawMutex, Tuning> = Signal::new();
+
+/// The settings that the IMU task uses.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tuning {
+	/// See [`SAMPLE_RATE`]
+	pub rate: SampleRate,
+	/// See [`Smoothing`](crate::imu::smoothing::Smoothing)
+	pub smoothing: f32,
+	/// See [`Prediction`](crate::imu::prediction::Prediction)
+	pub prediction_lead: Duration,
+}
+impl Tuning {
+	/// What the firmware was built with.
+	pub const DEFAULT: Self = Self {
+		rate: SAMPLE_RATE,
+		smoothing: OUTPUT_SMOOTHING,
+		prediction_lead: PREDICTION_LEAD,
+	};
+
+	/// Changes the settings that `request` has.
+	pub fn apply(&mut self, request: Request) {
+		if let Some(rate) = request.rate {
+			self.rate = rate;
+		}
+		if let Some(smoothing) = request.smoothing {
+			self.smoothing = smoothing;
+		}
+		if let Some(lead) = request.prediction_lead {
+			self.prediction_lead = lead;
+		}
+	}
+
+	/// The settings as the server sees them.
+	pub fn to_config(self) -> TrackerConfig {
+		TrackerConfig {
+			sample_rate_hz: self.rate.hz() as u16,
+			smoothing_permille: (self.smoothing * 1000.) as u16,
+			prediction_lead_ms: self.prediction_lead.as_millis() as u16,
+		}
+	}
+}
+
+/// The settings of a [`TrackerConfig`] that are valid. The others are `None`, and stay
+/// as they were.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Request {
+	pub rate: Option<SampleRate>,
+	pub smoothing: Option<f32>,
+	pub prediction_lead: Option<Duration>,
+}
+impl Request {
+	/// Checks every setting of `config` against its range.
+	pub fn new(config: &TrackerConfig) -> Self {
+		let rate = SampleRate::from_hz(config.sample_rate_hz.into());
+		if rate.is_none() {
+			warn!(
+				"Sample rate of {}Hz isn't supported, keeping it",
+				config.sample_rate_hz
+			);
+		}
+		// Smoothing everything away would freeze the rotation
+		let smoothing = match config.smoothing_permille {
+			permille @ 0..=999 => Some(f32::from(permille) / 1000.),
+			permille => {
+				warn!("Smoothing of {}/1000 is out of range, keeping it", permille);
+				None
+			}
+		};
+		let lead = Duration::from_millis(config.prediction_lead_ms.into());
+		let prediction_lead = if lead <= MAX_PREDICTION_LEAD {
+			Some(lead)
+		} else {
+			warn!(
+				"Prediction lead of {}ms is over the limit of {}ms, keeping it",
+				lead.as_millis(),
+				MAX_PREDICTION_LEAD.as_millis()
+			);
+			None
+		};
+		Self {
+			rate,
+			smoothing,
+			prediction_lead,
+		}
+	}
+}
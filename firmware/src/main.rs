@@ -13,14 +13,25 @@
 load_dotenv::try_load_dotenv!();
 
 mod aliases;
+#[cfg(feature = "battery-adc")]
+mod battery;
+#[cfg(feature = "button")]
+mod button;
 mod globals;
 mod imu;
 mod networking;
 mod peripherals;
+#[cfg(feature = "deep-sleep")]
+mod power;
+mod status;
 mod utils;
+#[cfg(feature = "watchdog")]
+mod watchdog;
 
 #[cfg(bbq)]
 mod bbq_logger;
+#[cfg(usb_log)]
+mod usb_logger;
 
 use defmt::debug;
 use embassy_executor::Executor;
@@ -35,7 +46,7 @@ use xtensa_lx_rt::entry;
 
 #[entry]
 fn main() -> ! {
-	use crate::imu::Quat;
+	use crate::imu::QuatSignals;
 	use crate::networking::protocol::Packets;
 	use crate::utils::Unreliable;
 	use embedded_hal::blocking::delay::DelayMs;
@@ -57,17 +68,42 @@ fn main() -> ! {
 	static PACKETS: StaticCell<Packets> = StaticCell::new();
 	let packets: &'static Packets = PACKETS.init(Packets::new());
 
-	static QUAT: StaticCell<Unreliable<Quat>> = StaticCell::new();
-	let quat: &'static Unreliable<Quat> = QUAT.init(Unreliable::new());
+	#[cfg(feature = "battery-adc")]
+	let battery: &'static Unreliable<crate::battery::BatteryLevel> = &packets.battery;
+
+	static QUATS: StaticCell<QuatSignals> = StaticCell::new();
+	let quats: &'static QuatSignals =
+		QUATS.init(core::array::from_fn(|_| Unreliable::new()));
 
 	static EXECUTOR: StaticCell<Executor> = StaticCell::new();
 	EXECUTOR.init(Executor::new()).run(move |s| {
-		s.spawn(crate::networking::protocol::control_task(packets, quat))
+		s.spawn(crate::networking::protocol::control_task(packets, quats))
 			.unwrap();
 		s.spawn(crate::networking::network_task(packets)).unwrap();
-		s.spawn(crate::imu::imu_task(quat, p.i2c, p.delay)).unwrap();
+		#[cfg(feature = "net-wifi")]
+		s.spawn(crate::networking::provisioning::provisioning_task(p.uart))
+			.unwrap();
+		#[cfg(not(feature = "transport-spi"))]
+		s.spawn(crate::imu::imu_task(quats, p.i2c, p.i2c2, p.delay, p.flash))
+			.unwrap();
+		#[cfg(feature = "transport-spi")]
+		s.spawn(crate::imu::imu_task(quats, p.spi, p.i2c2, p.delay, p.flash))
+			.unwrap();
+		#[cfg(feature = "battery-adc")]
+		s.spawn(crate::battery::battery_task(battery, p.battery))
+			.unwrap();
+		#[cfg(feature = "status-led")]
+		s.spawn(crate::status::status_task(p.led)).unwrap();
+		#[cfg(feature = "button")]
+		s.spawn(crate::button::button_task(p.button)).unwrap();
+		#[cfg(feature = "deep-sleep")]
+		s.spawn(crate::power::power_task(p.power)).unwrap();
+		#[cfg(feature = "watchdog")]
+		s.spawn(crate::watchdog::watchdog_task(p.watchdog)).unwrap();
 		#[cfg(bbq)]
 		s.spawn(logger_task(bbq, bbq_peripheral)).unwrap();
+		#[cfg(usb_log)]
+		s.spawn(usb_logger_task(p.usb_driver)).unwrap();
 	});
 }
 
@@ -79,3 +115,9 @@ async fn logger_task(
 ) {
 	crate::bbq_logger::ඞ::logger_task(bbq, logger_peripheral).await;
 }
+
+#[cfg(usb_log)]
+#[embassy_executor::task]
+async fn usb_logger_task(driver: crate::aliases::ඞ::UsbDriverConcrete<'static>) {
+	crate::usb_logger::logger_task(driver).await;
+}
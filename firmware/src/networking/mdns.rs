@@ -0,0 +1,162 @@
+//! Finds the SlimeVR server on the local network with mDNS, so that its address
+//! doesn't have to be known ahead of time.
+//!
+//! We send a one-shot query for [`SERVICE`] from a regular port, which makes
+//! responders answer us directly (RFC 6762 section 6.7). The address of the first
+//! valid responder is cached in flash, and used as a fallback when nobody answers.
+
+use defmt::{debug, warn};
+use embassy_time::Duration;
+
+use crate::aliases::ඞ::{FlashConcrete, CONFIG_STORE_OFFSET};
+use crate::peripherals::config::ConfigStore;
+
+/// The service that the SlimeVR server advertises.
+pub const SERVICE: [&str; 3] = ["_slimevr", "_udp", "local"];
+/// Multicast group and port that mDNS queries are sent to.
+pub const MDNS_ADDR: [u8; 4] = [224, 0, 0, 251];
+pub const MDNS_PORT: u16 = 5353;
+/// How long we wait for an answer before giving up on discovery.
+pub const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+/// How many queries we send before giving up on discovery.
+pub const QUERY_ATTEMPTS: usize = 3;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const CLASS_IN: u16 = 1;
+/// Set in the class of a question to ask for a unicast response.
+const UNICAST_RESPONSE: u16 = 0x8000;
+/// Set in the flags of a response.
+const FLAG_RESPONSE: u16 = 0x8000;
+const HEADER_LEN: usize = 12;
+/// Longest a name can be when written out, per RFC 1035.
+const MAX_NAME_LEN: usize = 255;
+/// Bounds how many compression pointers we follow, so a malicious packet can't loop.
+const MAX_POINTERS: usize = 16;
+
+/// Writes a PTR query for [`SERVICE`] into `buf`, returning its length.
+pub fn write_query(buf: &mut [u8]) -> Option<usize> {
+	let mut len = 0;
+	let mut put = |bytes: &[u8]| {
+		buf.get_mut(len..len + bytes.len())?.copy_from_slice(bytes);
+		len += bytes.len();
+		Some(())
+	};
+	// ID 0, no flags, one question
+	put(&[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+	for label in SERVICE {
+		put(&[label.len() as u8])?;
+		put(label.as_bytes())?;
+	}
+	put(&[0])?;
+	put(&TYPE_PTR.to_be_bytes())?;
+	put(&(CLASS_IN | UNICAST_RESPONSE).to_be_bytes())?;
+	Some(len)
+}
+
+/// Checks that `packet`, received from `source`, answers our query and returns the
+/// address of the server. Prefers an A record in the response over `source`, in case
+/// the response was relayed.
+pub fn parse_response(packet: &[u8], source: [u8; 4]) -> Option<[u8; 4]> {
+	let header = packet.get(..HEADER_LEN)?;
+	let field = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]);
+	if field(2) & FLAG_RESPONSE == 0 {
+		return None;
+	}
+	let questions = field(4);
+	let records =
+		usize::from(field(6)) + usize::from(field(8)) + usize::from(field(10));
+
+	let mut pos = HEADER_LEN;
+	for _ in 0..questions {
+		pos = skip_name(packet, pos)? + 4;
+	}
+
+	let mut answered = false;
+	let mut address = None;
+	for _ in 0..records {
+		let is_service = name_is_service(packet, pos)?;
+		pos = skip_name(packet, pos)?;
+		let fixed = packet.get(pos..pos + 10)?;
+		let ty = u16::from_be_bytes([fixed[0], fixed[1]]);
+		let data_len = usize::from(u16::from_be_bytes([fixed[8], fixed[9]]));
+		pos += 10;
+		let data = packet.get(pos..pos + data_len)?;
+		pos += data_len;
+
+		match ty {
+			TYPE_PTR if is_service => answered = true,
+			TYPE_A if data.len() == 4 && address.is_none() => {
+				address = Some([data[0], data[1], data[2], data[3]])
+			}
+			_ => (),
+		}
+	}
+	answered.then(|| address.unwrap_or(source))
+}
+
+/// Returns the position right after the name starting at `pos`.
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+	loop {
+		let len = *packet.get(pos)?;
+		match len {
+			0 => return Some(pos + 1),
+			// A compression pointer always ends the name
+			l if l & 0xC0 == 0xC0 => return Some(pos + 2),
+			l => pos += 1 + usize::from(l),
+		}
+	}
+}
+
+/// Compares the (possibly compressed) name at `pos` with [`SERVICE`].
+fn name_is_service(packet: &[u8], mut pos: usize) -> Option<bool> {
+	let mut labels = SERVICE.iter();
+	let mut pointers = 0;
+	let mut read = 0;
+	loop {
+		let len = *packet.get(pos)?;
+		if len & 0xC0 == 0xC0 {
+			pointers += 1;
+			if pointers > MAX_POINTERS {
+				return None;
+			}
+			let low = *packet.get(pos + 1)?;
+			pos = usize::from(u16::from_be_bytes([len & 0x3F, low]));
+			continue;
+		}
+		let len = usize::from(len);
+		read += len + 1;
+		if read > MAX_NAME_LEN {
+			return None;
+		}
+		if len == 0 {
+			return Some(labels.next().is_none());
+		}
+		let label = packet.get(pos + 1..pos + 1 + len)?;
+		match labels.next() {
+			Some(expected) if expected.as_bytes().eq_ignore_ascii_case(label) => (),
+			_ => return Some(false),
+		}
+		pos += 1 + len;
+	}
+}
+
+fn store() -> ConfigStore<FlashConcrete<'static>> {
+	ConfigStore::new(FlashConcrete::new(), CONFIG_STORE_OFFSET)
+}
+
+/// Loads the address of the last server we found.
+pub fn load_cached() -> Option<[u8; 4]> {
+	store().load().server_address
+}
+
+/// Remembers `address` as the server's, unless it is already cached.
+pub fn cache(address: [u8; 4]) {
+	debug!("Caching server address {}", address);
+	if let Err(err) = store().update(|config| config.server_address = Some(address)) {
+		warn!(
+			"Failed to cache server address: {}",
+			defmt::Debug2Format(&err)
+		);
+	}
+}
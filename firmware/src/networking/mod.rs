@@ -1,5 +1,11 @@
+#[cfg(feature = "net-wifi")]
+pub mod mdns;
+#[cfg(feature = "ota")]
+pub mod ota;
 pub mod protocol;
 #[cfg(feature = "net-wifi")]
+pub mod provisioning;
+#[cfg(feature = "net-wifi")]
 pub mod wifi;
 
 #[cfg(feature = "net-ble")]
@@ -27,7 +33,11 @@ pub async fn network_task(msg_signals: &'static Packets) {
 async fn stubbed_network_task(packets: &Packets) -> ! {
 	loop {
 		// Dump network messages
-		let _ = packets.serverbound.recv().await;
+		let _ = embassy_futures::select::select(
+			packets.serverbound.recv(),
+			packets.bundles.recv(),
+		)
+		.await;
 		defmt::trace!("pretending to do networking..");
 	}
 }
@@ -0,0 +1,109 @@
+//! Receives firmware updates from the server, and writes them to the inactive slot.
+//!
+//! The server sends `OtaBegin`, then the image in `OtaChunk`s, then `OtaEnd`. We
+//! answer each with `OtaProgress`, whose `received` tells the server where to continue
+//! from, so lost or repeated chunks are simply sent again.
+
+use defmt::{info, warn};
+use embassy_time::{Duration, Timer};
+use firmware_protocol::{CbPacket, OtaStatus, SbPacket};
+
+use crate::aliases::ඞ::FlashConcrete;
+use crate::peripherals::ota::{OtaError, OtaWriter, IMAGE_MAGIC};
+
+/// Gives the final `OtaProgress` time to reach the server before we reset.
+const RESET_DELAY: Duration = Duration::from_secs(1);
+
+/// An update in progress.
+struct Transfer {
+	writer: OtaWriter<FlashConcrete<'static>>,
+	crc32: u32,
+}
+
+#[derive(Default)]
+pub struct Ota {
+	transfer: Option<Transfer>,
+	/// Set once an image has been verified, and we should reset into it.
+	complete: bool,
+}
+impl Ota {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Handles an OTA packet, returning the response to send. Other packets are
+	/// ignored.
+	pub fn handle(&mut self, packet: &CbPacket) -> Option<SbPacket> {
+		let status = match packet {
+			CbPacket::OtaBegin { size, crc32 } => {
+				info!("Starting firmware update of {} bytes", size);
+				match OtaWriter::new(FlashConcrete::new(), *size) {
+					Ok(writer) => {
+						self.transfer = Some(Transfer {
+							writer,
+							crc32: *crc32,
+						});
+						OtaStatus::Receiving
+					}
+					Err(e) => self.fail(e),
+				}
+			}
+			CbPacket::OtaChunk { offset, data, .. } => {
+				let Some(transfer) = &mut self.transfer else { return Some(failed(0)) };
+				if *offset != transfer.writer.written() {
+					// A chunk was lost or repeated, tell the server where we are
+					OtaStatus::Receiving
+				} else if *offset == 0 && data.first() != Some(&IMAGE_MAGIC) {
+					self.fail(OtaError::Corrupted)
+				} else {
+					match transfer.writer.write(data) {
+						Ok(()) => OtaStatus::Receiving,
+						Err(e) => self.fail(e),
+					}
+				}
+			}
+			CbPacket::OtaEnd => {
+				let Some(Transfer { writer, crc32 }) = self.transfer.take() else { return Some(failed(0)) };
+				let received = writer.written();
+				if let Err(e) = writer.finish(crc32) {
+					warn!("Firmware update failed: {}", defmt::Debug2Format(&e));
+					return Some(failed(received));
+				}
+				info!("Firmware update complete");
+				self.complete = true;
+				return Some(SbPacket::OtaProgress {
+					status: OtaStatus::Complete,
+					received,
+				});
+			}
+			_ => return None,
+		};
+		let received = self.transfer.as_ref().map_or(0, |t| t.writer.written());
+		Some(SbPacket::OtaProgress { status, received })
+	}
+
+	/// Resets into the new firmware, if an update has completed. Call this after the
+	/// response to the last packet has been sent.
+	pub async fn reset_if_complete(&self) {
+		if self.complete {
+			info!("Resetting into the new firmware");
+			Timer::after(RESET_DELAY).await;
+			crate::imu::shutdown().await;
+			crate::peripherals::ඞ::reset();
+		}
+	}
+
+	/// Abandons the transfer. The running firmware stays the one that gets booted.
+	fn fail<E: core::fmt::Debug>(&mut self, e: OtaError<E>) -> OtaStatus {
+		warn!("Firmware update failed: {}", defmt::Debug2Format(&e));
+		self.transfer = None;
+		OtaStatus::Failed
+	}
+}
+
+fn failed(received: u32) -> SbPacket {
+	SbPacket::OtaProgress {
+		status: OtaStatus::Failed,
+		received,
+	}
+}
@@ -1,72 +1,255 @@
 //! The protocol implementation to communicate with the SlimeVR Server.
 
+extern crate alloc;
+
 mod packets;
 pub use self::packets::Packets;
 
-use defmt::{debug, trace};
+use alloc::vec::Vec;
+use core::cell::Cell;
+use defmt::{debug, trace, warn};
 use embassy_executor::task;
-use embassy_futures::select::{select, Either};
+use embassy_futures::select::{select4, select_array, Either4};
+use embassy_time::{Duration, Instant, Timer};
 
+use firmware_core::protocol::advertised_sensors;
 use firmware_protocol::{
-	BoardType, CbPacket, ImuType, McuType, SbPacket, SensorDataType, SensorStatus,
+	pack, Batcher, BoardType, Bundle, Capabilities, CbPacket, FlushPolicy, ImuType,
+	McuType, Negotiation, SbPacket, SensorDataType, SensorStatus,
 };
+use git_version::git_version;
 
-use crate::imu::Quat;
-use crate::utils::{Reliable, Unreliable};
+#[cfg(feature = "battery-adc")]
+use crate::battery::BatteryLevel;
+use crate::imu::tuning::{self, Request};
+use crate::imu::{QuatSignals, Rotation, SampleRate, IMU_TYPES, MAX_IMUS, SAMPLE_RATE};
+use crate::peripherals::config::BODY_PARTS;
+use crate::utils::Reliable;
 
 #[allow(dead_code)]
 mod v2;
 
+/// How often we send a heartbeat to the server, so that it knows we are alive even
+/// when there is nothing else to send.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// If nothing is heard from the server for this long, the connection is considered
+/// lost. The server sends us a heartbeat every second, so this tolerates a few of them
+/// getting lost.
+pub const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the rotation of one tracker may wait for the other trackers, to be sent
+/// in one datagram with theirs. Half a sample period at `rate`, so that the trackers
+/// can drift apart without any reading getting replaced by the next one before it is
+/// sent.
+const fn max_batch_latency(rate: SampleRate) -> Duration {
+	Duration::from_micros(rate.period_us() as u64 / 2)
+}
+
+/// How often the temperature of each IMU is sent, picked with the
+/// `TEMPERATURE_INTERVAL_S` env variable. Zero never sends it.
+const TEMPERATURE_INTERVAL: Duration = Duration::from_secs(include!(concat!(
+	env!("OUT_DIR"),
+	"/temperature_interval.rs"
+)));
+
+/// The most bytes that we hand to the network in one datagram, picked with the
+/// `MAX_PAYLOAD` env variable. Longer datagrams could get lost on links with a smaller
+/// MTU, so bundles are split to stay below it and longer packets are not sent at all.
+pub const MAX_PAYLOAD: usize = include!(concat!(env!("OUT_DIR"), "/max_payload.rs"));
+
+/// The family of the MCU we are running on, reported in the handshake.
+#[cfg(feature = "mcu-esp32")]
+const MCU_TYPE: McuType = McuType::Esp32;
+#[cfg(feature = "mcu-esp32c3")]
+const MCU_TYPE: McuType = McuType::Esp32C3;
+#[cfg(not(mcu_f_esp32))]
+const MCU_TYPE: McuType = McuType::Unknown(0);
+
+/// Reported for sensors whose type we don't know, and in the handshake if there are
+/// no IMUs at all.
+const UNKNOWN_IMU: ImuType = ImuType::Unknown(0xFF);
+
+/// Reported in the handshake, so that the server can tell which build we are.
+const FIRMWARE_VERSION: &str =
+	git_version!(prefix = "SlimeVR-Rust ", fallback = "SlimeVR-Rust");
+
+/// What we can do beyond the official protocol. Servers that support less still work,
+/// they just don't get the extras.
+const CAPABILITIES: Capabilities = {
+	let caps = Capabilities::RAW_IMU_DATA
+		.union(Capabilities::ROTATION_TIMESTAMP)
+		.union(Capabilities::TRACKER_POSITION)
+		.union(Capabilities::CONFIG);
+	#[cfg(feature = "battery-adc")]
+	let caps = caps.union(Capabilities::BATTERY);
+	#[cfg(feature = "ota")]
+	let caps = caps.union(Capabilities::OTA);
+	caps
+};
+
 #[task]
-pub async fn control_task(
-	packets: &'static Packets,
-	quat: &'static Unreliable<Quat>,
-) -> ! {
+pub async fn control_task(packets: &'static Packets, quats: &'static QuatSignals) -> ! {
 	debug!("Control task!");
-	async {
+	// What the server accepted, for the tasks other than `control`
+	let accepted = Cell::new(Capabilities::BASELINE);
+	// The rate the IMUs sample at, which the server can change
+	let rate = Cell::new(SAMPLE_RATE);
+	let control = async {
+		// Which sensors the server has been told about with `SensorInfo`
+		let mut announced = [false; MAX_IMUS];
+		// Heartbeats share the channel with everything else, so they go out in between
+		// rotations instead of holding them up
+		let mut next_heartbeat = Instant::now() + HEARTBEAT_INTERVAL;
+		// Temperature changes slowly, so it is sent much less often than rotations
+		let mut next_temperature = [Instant::from_ticks(0); MAX_IMUS];
+		// Rotations wait here for the other trackers, so they can be sent together
+		let mut batcher = Batcher::new(FlushPolicy {
+			trackers: 1,
+			max_latency_us: max_batch_latency(SAMPLE_RATE).as_micros(),
+		});
+		// Never waited on, so that servers that don't know about capabilities still
+		// get through the handshake
+		let mut negotiation = Negotiation::new(CAPABILITIES);
+		#[cfg(feature = "ota")]
+		let mut ota = crate::networking::ota::Ota::new();
 		loop {
-			match select(packets.clientbound.recv(), quat.wait()).await {
-				Either::First(cb_msg) => {
-					handle_cb_msg(cb_msg, &packets.serverbound).await
+			accepted.set(negotiation.accepted());
+			batcher.policy.max_latency_us = max_batch_latency(rate.get()).as_micros();
+			let quat_futs = core::array::from_fn(|i| quats[i].wait());
+			let wake_at = match batcher.deadline() {
+				Some(deadline) => next_heartbeat.min(Instant::from_micros(deadline)),
+				None => next_heartbeat,
+			};
+			match select4(
+				packets.clientbound.recv(),
+				packets.connected.wait(),
+				select_array(quat_futs),
+				Timer::at(wake_at),
+			)
+			.await
+			{
+				Either4::First(cb_msg) => {
+					handle_capabilities(&cb_msg, &mut negotiation, &mut announced);
+					#[cfg(feature = "ota")]
+					if let Some(response) = ota.handle(&cb_msg) {
+						packets.serverbound.send(response).await;
+						ota.reset_if_complete().await;
+						continue;
+					}
+					handle_cb_msg(
+						cb_msg,
+						&packets.serverbound,
+						&mut announced,
+						&mut negotiation,
+					)
+					.await
+				}
+				Either4::Second(()) => {
+					debug!("protocol: (re)connected, sending Handshake");
+					send_handshake(
+						&packets.serverbound,
+						&mut announced,
+						&mut negotiation,
+					)
+					.await
 				}
-				Either::Second(quat_msg) => {
-					handle_quat(quat_msg, &packets.serverbound).await
+				Either4::Fourth(()) => {
+					let now = Instant::now();
+					if batcher.is_due(now.as_micros()) {
+						send_batch(&mut batcher, &packets.bundles).await;
+					}
+					if now >= next_heartbeat {
+						trace!("protocol: sending Heartbeat");
+						packets.serverbound.send(SbPacket::Heartbeat).await;
+						// Don't catch up on missed heartbeats with a burst of them
+						next_heartbeat = Instant::now() + HEARTBEAT_INTERVAL;
+					}
+				}
+				Either4::Third((quat_msg, sensor_id)) => {
+					let temp = quat_msg.temp;
+					let rotation = handle_quat(
+						quat_msg,
+						sensor_id as u8,
+						&packets.serverbound,
+						&mut announced[sensor_id],
+						negotiation.accepted(),
+					)
+					.await;
+					if let Some(temp) = temp {
+						handle_temperature(
+							temp,
+							sensor_id as u8,
+							&packets.serverbound,
+							&mut next_temperature[sensor_id],
+						)
+						.await;
+					}
+					// Only wait for the trackers that are actually there
+					batcher.policy.trackers = announced.iter().filter(|&&a| a).count();
+					let now = Instant::now().as_micros();
+					if batcher.add(now, sensor_id as u8, rotation) {
+						send_batch(&mut batcher, &packets.bundles).await;
+					}
 				}
 			}
 		}
+	};
+
+	// The IMU task applies new settings when it gets to them, so confirm them from here
+	let config = async {
+		loop {
+			let applied = tuning::APPLIED.wait().await;
+			rate.set(applied.rate);
+			let config = applied.to_config();
+			debug!("protocol: sending Config {}", defmt::Debug2Format(&config));
+			packets.serverbound.send(SbPacket::Config { config }).await;
+		}
+	};
+
+	#[cfg(feature = "battery-adc")]
+	let battery = async {
+		loop {
+			let battery = packets.battery.wait().await;
+			if accepted.get().contains(Capabilities::BATTERY) {
+				handle_battery(battery, &packets.serverbound).await;
+			}
+		}
+	};
+	#[cfg(feature = "battery-adc")]
+	embassy_futures::select::select3(control, config, battery).await;
+	#[cfg(not(feature = "battery-adc"))]
+	embassy_futures::select::select(control, config).await;
+	unreachable!("all futures loop forever")
+}
+
+fn handle_capabilities(
+	cb_msg: &CbPacket,
+	negotiation: &mut Negotiation,
+	announced: &mut [bool; MAX_IMUS],
+) {
+	negotiation.handle(cb_msg);
+	if let CbPacket::Capabilities { capabilities } = cb_msg {
+		debug!(
+			"protocol: server supports capabilities {=u32:b}, using {=u32:b}",
+			capabilities.bits(),
+			negotiation.accepted().bits()
+		);
+		// Sensors may have been announced before the answer arrived, without the
+		// packets that the server turned out to support
+		*announced = [false; MAX_IMUS];
 	}
-	.await
 }
 
-async fn handle_cb_msg(cb_msg: CbPacket, sb_chan: &Reliable<SbPacket>) {
+async fn handle_cb_msg(
+	cb_msg: CbPacket,
+	sb_chan: &Reliable<SbPacket>,
+	announced: &mut [bool; MAX_IMUS],
+	negotiation: &mut Negotiation,
+) {
 	match cb_msg {
 		// Identify ourself when discovery packet is received
 		CbPacket::Discovery => {
 			trace!("protocol: received Discovery");
-			sb_chan
-				.send(SbPacket::Handshake {
-					// TODO: Compile time constants for board and MCU
-					board: BoardType::Custom,
-					// Should this IMU type be whatever the first IMU of the system is?
-					imu: ImuType::Unknown(0xFF),
-					mcu: McuType::Esp32,
-					imu_info: (0, 0, 0), // These appear to be inert
-					// Needs to be >=9 to use newer protocol, this is hard-coded in
-					// the java server :(
-					build: 10,
-					firmware: "SlimeVR-Rust".into(),
-					mac_address: [0; 6],
-				})
-				.await;
-
-			// After handshake, we are supposed to send `SensorInfo` only once.
-			sb_chan
-				.send(SbPacket::SensorInfo {
-					sensor_id: 0, // First sensor (of two)
-					sensor_status: SensorStatus::Ok,
-					sensor_type: ImuType::Unknown(0xFF),
-				})
-				.await;
+			send_handshake(sb_chan, announced, negotiation).await;
 		}
 		// When heartbeat is received, we should reply with heartbeat 0 aka Discovery
 		// The protocol is asymmetric so its a bit unintuitive.
@@ -79,17 +262,206 @@ async fn handle_cb_msg(cb_msg: CbPacket, sb_chan: &Reliable<SbPacket>) {
 			trace!("protocol: received Ping");
 			sb_chan.send(SbPacket::Ping { challenge }).await;
 		}
+		// Answered once the IMU task applied it
+		CbPacket::Config { config } => {
+			debug!("protocol: received Config {}", defmt::Debug2Format(&config));
+			tuning::REQUESTED.signal(Request::new(&config));
+		}
 		_ => (),
 	}
 }
 
-async fn handle_quat(quat: Quat, sb_chan: &Reliable<SbPacket>) {
+async fn send_handshake(
+	sb_chan: &Reliable<SbPacket>,
+	announced: &mut [bool; MAX_IMUS],
+	negotiation: &mut Negotiation,
+) {
+	// Only the esp32s have a MAC address that we read so far
+	#[cfg(mcu_f_esp32)]
+	let mac_address = crate::peripherals::ඞ::mac_address();
+	#[cfg(not(mcu_f_esp32))]
+	let mac_address = [0; 6];
+
+	let types = IMU_TYPES.lock(|t| t.get());
+	let sensors: Vec<_> = advertised_sensors(types).collect();
+	sb_chan
+		.send(SbPacket::Handshake {
+			// TODO: Compile time constant for the board
+			board: BoardType::Custom,
+			// There is only room for one, the sensors tell their own type below
+			imu: sensors
+				.first()
+				.map_or(UNKNOWN_IMU, |&(_, imu_type)| imu_type),
+			mcu: MCU_TYPE,
+			imu_info: (0, 0, 0), // These appear to be inert
+			// Needs to be >=9 to use newer protocol, this is hard-coded in
+			// the java server :(
+			build: 10,
+			firmware: FIRMWARE_VERSION.into(),
+			mac_address,
+		})
+		.await;
+
+	// The server may be a different one now, so until it tells us otherwise we only
+	// use what any server supports. We don't wait for its answer, old servers never
+	// send one.
+	negotiation.reset();
 	sb_chan
-		.send(SbPacket::RotationData {
-			sensor_id: 0,                      // First sensor
-			data_type: SensorDataType::Normal, // Rotation data without magnetometer correction.
-			quat: quat.into_inner().into(),
-			calibration_info: 0,
+		.send(SbPacket::Capabilities {
+			capabilities: negotiation.ours(),
+		})
+		.await;
+
+	// After handshake, we are supposed to send `SensorInfo` only once per sensor.
+	// The IMUs that were initialized are announced right away, so that the server
+	// knows how many trackers we have. Any that the IMU task hasn't gotten to yet are
+	// announced with their first rotation instead, and absent ones never are.
+	*announced = [false; MAX_IMUS];
+	for (sensor_id, imu_type) in sensors {
+		announce(sensor_id, imu_type, sb_chan, negotiation.accepted()).await;
+		announced[usize::from(sensor_id)] = true;
+	}
+}
+
+/// Tells the server about a sensor with `SensorInfo`, and where it is on the body if
+/// the server `accepted` that.
+async fn announce(
+	sensor_id: u8,
+	sensor_type: ImuType,
+	sb_chan: &Reliable<SbPacket>,
+	accepted: Capabilities,
+) {
+	debug!(
+		"protocol: announcing sensor {} as {}",
+		sensor_id,
+		defmt::Debug2Format(&sensor_type)
+	);
+	sb_chan
+		.send(SbPacket::SensorInfo {
+			sensor_id,
+			sensor_status: SensorStatus::Ok,
+			sensor_type,
+		})
+		.await;
+	// Unassigned sensors are reported too, so that the server asks the user instead of
+	// guessing
+	if accepted.contains(Capabilities::TRACKER_POSITION) {
+		let body_part = BODY_PARTS.lock(|parts| parts.get()[usize::from(sensor_id)]);
+		sb_chan
+			.send(SbPacket::TrackerPosition {
+				sensor_id,
+				body_part,
+			})
+			.await;
+	}
+}
+
+#[cfg(feature = "battery-adc")]
+async fn handle_battery(battery: BatteryLevel, sb_chan: &Reliable<SbPacket>) {
+	trace!("protocol: sending battery level {}", battery);
+	sb_chan
+		.send(SbPacket::BatteryLevel {
+			voltage: battery.voltage,
+			level: battery.level,
+		})
+		.await
+}
+
+/// Announces the sensor if needed, and returns the packets that describe `rotation`.
+/// Packets outside of the `accepted` capabilities are left out.
+async fn handle_quat(
+	rotation: Rotation,
+	sensor_id: u8,
+	sb_chan: &Reliable<SbPacket>,
+	announced: &mut bool,
+	accepted: Capabilities,
+) -> Vec<SbPacket> {
+	if !*announced {
+		let imu_type = IMU_TYPES.lock(|t| t.get()[usize::from(sensor_id)]);
+		announce(
+			sensor_id,
+			imu_type.unwrap_or(UNKNOWN_IMU),
+			sb_chan,
+			accepted,
+		)
+		.await;
+		*announced = true;
+	}
+	let mut packets = Vec::with_capacity(3);
+	packets.push(SbPacket::RotationData {
+		sensor_id,
+		data_type: SensorDataType::Normal, // Rotation data without magnetometer correction.
+		quat: rotation.quat.into_inner().into(),
+		// Like the official firmware, 0 is sent for both unreliable and unknown
+		calibration_info: rotation.accuracy.map_or(0, |a| a as u8),
+	});
+	if accepted.contains(Capabilities::ROTATION_TIMESTAMP) {
+		packets.push(SbPacket::RotationTimestamp {
+			sensor_id,
+			micros: rotation.timestamp.as_micros(),
+		});
+	}
+	let raw = rotation
+		.raw
+		.filter(|_| accepted.contains(Capabilities::RAW_IMU_DATA));
+	if let Some(raw) = raw {
+		packets.push(SbPacket::RawImuData {
+			sensor_id,
+			accel: (raw.accel.x, raw.accel.y, raw.accel.z),
+			gyro: (raw.gyro.x, raw.gyro.y, raw.gyro.z),
+		});
+	}
+	packets
+}
+
+/// Sends the temperature of a sensor, unless it was sent less than
+/// [`TEMPERATURE_INTERVAL`] ago. `next` is when it may be sent again.
+async fn handle_temperature(
+	temp: f32,
+	sensor_id: u8,
+	sb_chan: &Reliable<SbPacket>,
+	next: &mut Instant,
+) {
+	let now = Instant::now();
+	if TEMPERATURE_INTERVAL.as_ticks() == 0 || now < *next {
+		return;
+	}
+	*next = now + TEMPERATURE_INTERVAL;
+	trace!(
+		"protocol: sending temperature {} of sensor {}",
+		temp,
+		sensor_id
+	);
+	sb_chan
+		.send(SbPacket::Temperature {
+			sensor_id,
+			temperature: temp,
 		})
 		.await
 }
+
+/// Sends the rotations waiting in `batcher`, in as few bundles as fit into
+/// [`MAX_PAYLOAD`].
+async fn send_batch(batcher: &mut Batcher, bundles: &Reliable<Bundle>) {
+	let packed = match pack(batcher.take(), MAX_PAYLOAD) {
+		Ok(packed) => packed,
+		Err(e) => {
+			warn!(
+				"protocol: failed to bundle packets: {}",
+				defmt::Debug2Format(&e)
+			);
+			return;
+		}
+	};
+	for packet in &packed.oversized {
+		warn!(
+			"protocol: not sending packet longer than MAX_PAYLOAD ({=usize} bytes): {}",
+			MAX_PAYLOAD,
+			defmt::Debug2Format(packet)
+		);
+	}
+	for bundle in packed.bundles {
+		trace!("protocol: sending bundle of {} packets", bundle.len());
+		bundles.send(bundle).await
+	}
+}
@@ -1,19 +1,34 @@
+#[cfg(feature = "battery-adc")]
+use crate::battery::BatteryLevel;
 use crate::utils::Reliable;
-use firmware_protocol::{CbPacket, SbPacket};
+use crate::utils::Unreliable;
+use firmware_protocol::{Bundle, CbPacket, SbPacket};
 
 /// Packets is an accessor to internal logic <-> network messaging system
 pub struct Packets {
 	/// The latest `Message` that should be sent
 	pub serverbound: Reliable<SbPacket>,
+	/// Rotations of several trackers that should be sent together
+	pub bundles: Reliable<Bundle>,
 	/// The latest `Message` that could be received
 	pub clientbound: Reliable<CbPacket>,
+	/// Signalled by the network task whenever it (re)connects to a server, so that
+	/// we introduce ourselves again
+	pub connected: Unreliable<()>,
+	/// The latest battery reading, which should be reported to the server
+	#[cfg(feature = "battery-adc")]
+	pub battery: Unreliable<BatteryLevel>,
 }
 
 impl Packets {
 	pub const fn new() -> Packets {
 		Packets {
 			serverbound: Reliable::new(),
+			bundles: Reliable::new(),
 			clientbound: Reliable::new(),
+			connected: Unreliable::new(),
+			#[cfg(feature = "battery-adc")]
+			battery: Unreliable::new(),
 		}
 	}
 }
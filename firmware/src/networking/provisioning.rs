@@ -0,0 +1,338 @@
+//! Lets the WiFi credentials be set over serial instead of compiling them in.
+//!
+//! Send `SET WIFI "<ssid>" "<password>"` followed by a newline. Valid credentials are
+//! stored in flash and used from the next boot on, or right away if we were still
+//! waiting for some.
+//!
+//! `SET BODYPART <sensor id> <body part>` assigns a sensor to a body part, such as
+//! `left-foot`, or `none` to unassign it. The server learns about it once it connects
+//! again.
+//!
+//! `SET TXPOWER <dBm>` limits the WiFi transmit power from the next boot on, to save
+//! battery and keep the radio from warming up the IMU. `SET TXPOWER max` removes the
+//! limit.
+
+use defmt::{debug, info, warn};
+use embassy_executor::task;
+use embassy_futures::select::{select, Either};
+use embassy_futures::yield_now;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use embedded_storage::nor_flash::NorFlash;
+use firmware_protocol::BodyPart;
+use heapless::{String, Vec};
+
+use crate::aliases::ඞ::{FlashConcrete, UartConcrete, CONFIG_STORE_OFFSET};
+use crate::imu::MAX_IMUS;
+use crate::peripherals::config::{
+	ConfigStore, MAX_PASSWORD_LEN, MAX_SSID_LEN, MAX_TX_POWER, MIN_TX_POWER,
+};
+use crate::status::{Flag, STATUS};
+
+/// Longest line we accept. Anything longer can't be a valid command.
+const MAX_LINE_LEN: usize = 128;
+/// How often we remind the user that we are waiting for credentials.
+const REMINDER_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Credentials that were compiled in, used when none are stored in flash.
+const DEFAULT_SSID: Option<&str> = option_env!("SSID");
+const DEFAULT_PASSWORD: Option<&str> = option_env!("PASSWORD");
+
+/// Signalled when valid credentials are received over serial.
+static NEW_CREDENTIALS: Signal<CriticalSectionRawMutex, Credentials> = Signal::new();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+	pub ssid: String<MAX_SSID_LEN>,
+	pub password: String<MAX_PASSWORD_LEN>,
+}
+impl Credentials {
+	fn new(ssid: &str, password: &str) -> Option<Self> {
+		if ssid.is_empty() {
+			return None;
+		}
+		Some(Self {
+			ssid: to_heapless(ssid)?,
+			password: to_heapless(password)?,
+		})
+	}
+}
+
+/// Copies `s` into a `heapless::String`, if it fits.
+fn to_heapless<const N: usize>(s: &str) -> Option<String<N>> {
+	let mut string = String::new();
+	string.push_str(s).ok()?;
+	Some(string)
+}
+
+/// The names of the body parts in `SET BODYPART`.
+const BODY_PART_NAMES: [(&str, BodyPart); 20] = [
+	("none", BodyPart::Unassigned),
+	("head", BodyPart::Head),
+	("neck", BodyPart::Neck),
+	("chest", BodyPart::Chest),
+	("waist", BodyPart::Waist),
+	("hip", BodyPart::Hip),
+	("left-upper-leg", BodyPart::LeftUpperLeg),
+	("right-upper-leg", BodyPart::RightUpperLeg),
+	("left-lower-leg", BodyPart::LeftLowerLeg),
+	("right-lower-leg", BodyPart::RightLowerLeg),
+	("left-foot", BodyPart::LeftFoot),
+	("right-foot", BodyPart::RightFoot),
+	("left-lower-arm", BodyPart::LeftLowerArm),
+	("right-lower-arm", BodyPart::RightLowerArm),
+	("left-upper-arm", BodyPart::LeftUpperArm),
+	("right-upper-arm", BodyPart::RightUpperArm),
+	("left-hand", BodyPart::LeftHand),
+	("right-hand", BodyPart::RightHand),
+	("left-shoulder", BodyPart::LeftShoulder),
+	("right-shoulder", BodyPart::RightShoulder),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+	SetWifi(Credentials),
+	SetBodyPart {
+		sensor_id: u8,
+		part: BodyPart,
+	},
+	/// In dBm, `None` for the maximum
+	SetTxPower(Option<u8>),
+}
+
+#[derive(defmt::Format, Debug, Copy, Clone, Eq, PartialEq)]
+enum ParseError {
+	/// Not a command that we know about.
+	UnknownCommand,
+	/// The arguments aren't what the command takes.
+	BadArguments,
+	/// The SSID is empty, or either argument is too long.
+	InvalidCredentials,
+	/// There is no sensor with that id.
+	InvalidSensor,
+	/// Not one of [`BODY_PART_NAMES`].
+	UnknownBodyPart,
+	/// Outside of [`MIN_TX_POWER`] and [`MAX_TX_POWER`].
+	InvalidTxPower,
+}
+
+/// Parses a line with one of the commands.
+fn parse_line(line: &str) -> Result<Command, ParseError> {
+	let line = line.trim();
+	if let Some(args) = line.strip_prefix("SET WIFI ") {
+		parse_wifi(args).map(Command::SetWifi)
+	} else if let Some(args) = line.strip_prefix("SET BODYPART ") {
+		parse_body_part(args)
+	} else if let Some(args) = line.strip_prefix("SET TXPOWER ") {
+		parse_tx_power(args).map(Command::SetTxPower)
+	} else {
+		Err(ParseError::UnknownCommand)
+	}
+}
+
+/// Parses the `<sensor id> <body part>` arguments of `SET BODYPART`.
+fn parse_body_part(args: &str) -> Result<Command, ParseError> {
+	let mut args = args.split_whitespace();
+	let (Some(sensor_id), Some(name), None) = (args.next(), args.next(), args.next())
+	else {
+		return Err(ParseError::BadArguments);
+	};
+	let sensor_id: u8 = sensor_id.parse().map_err(|_| ParseError::BadArguments)?;
+	if usize::from(sensor_id) >= MAX_IMUS {
+		return Err(ParseError::InvalidSensor);
+	}
+	let (_, part) = BODY_PART_NAMES
+		.into_iter()
+		.find(|(n, _)| n.eq_ignore_ascii_case(name))
+		.ok_or(ParseError::UnknownBodyPart)?;
+	Ok(Command::SetBodyPart { sensor_id, part })
+}
+
+/// Parses the `<dBm>` or `max` argument of `SET TXPOWER`.
+fn parse_tx_power(args: &str) -> Result<Option<u8>, ParseError> {
+	let args = args.trim();
+	if args.eq_ignore_ascii_case("max") {
+		return Ok(None);
+	}
+	let dbm: u8 = args.parse().map_err(|_| ParseError::BadArguments)?;
+	if !(MIN_TX_POWER..=MAX_TX_POWER).contains(&dbm) {
+		return Err(ParseError::InvalidTxPower);
+	}
+	Ok(Some(dbm))
+}
+
+/// Parses the `"<ssid>" "<password>"` arguments of `SET WIFI`.
+fn parse_wifi(args: &str) -> Result<Credentials, ParseError> {
+	/// Splits a quoted string off the start of `s`, returning it and the rest.
+	fn quoted(s: &str) -> Option<(&str, &str)> {
+		let s = s.trim_start().strip_prefix('"')?;
+		let end = s.find('"')?;
+		Some((&s[..end], &s[end + 1..]))
+	}
+	let (ssid, rest) = quoted(args).ok_or(ParseError::BadArguments)?;
+	let (password, rest) = quoted(rest).ok_or(ParseError::BadArguments)?;
+	if !rest.trim().is_empty() {
+		return Err(ParseError::BadArguments);
+	}
+	Credentials::new(ssid, password).ok_or(ParseError::InvalidCredentials)
+}
+
+fn store() -> ConfigStore<FlashConcrete<'static>> {
+	ConfigStore::new(FlashConcrete::new(), CONFIG_STORE_OFFSET)
+}
+
+fn load<F: NorFlash>(store: &mut ConfigStore<F>) -> Option<Credentials> {
+	let config = store.load();
+	Credentials::new(&config.wifi_ssid, &config.wifi_password)
+}
+
+/// The limit on the WiFi transmit power in dBm, if there is one.
+pub fn tx_power() -> Option<u8> {
+	store().load().wifi_tx_power
+}
+
+/// Forgets the WiFi credentials, the calibration and the server we were talking to,
+/// and restarts. The tracker then waits for new credentials, unless some were compiled
+/// in.
+#[cfg(feature = "button")]
+pub async fn factory_reset() -> ! {
+	warn!("Forgetting stored settings and restarting");
+	if let Err(err) = store().save(&Default::default()) {
+		warn!("Failed to reset config: {}", defmt::Debug2Format(&err));
+	}
+	crate::imu::shutdown().await;
+	crate::peripherals::ඞ::reset()
+}
+
+/// Gets the credentials to connect with. Uses the ones stored in flash, then the
+/// compiled in ones, and if there are neither waits until some are sent over serial.
+pub async fn credentials() -> Credentials {
+	if let Some(credentials) = load(&mut store()) {
+		debug!("Using WiFi credentials from flash");
+		return credentials;
+	}
+	let default = DEFAULT_SSID.zip(DEFAULT_PASSWORD);
+	if let Some(credentials) = default.and_then(|(s, p)| Credentials::new(s, p)) {
+		debug!("Using compiled in WiFi credentials");
+		return credentials;
+	}
+
+	STATUS.set(Flag::WaitingForCredentials, true);
+	let credentials = loop {
+		info!("No WiFi credentials, send `SET WIFI \"<ssid>\" \"<password>\"` over serial");
+		match select(NEW_CREDENTIALS.wait(), Timer::after(REMINDER_INTERVAL)).await {
+			Either::First(credentials) => break credentials,
+			Either::Second(()) => (),
+		}
+	};
+	STATUS.set(Flag::WaitingForCredentials, false);
+	credentials
+}
+
+/// Reads commands from the serial port.
+#[task]
+pub async fn provisioning_task(uart: UartConcrete<'static>) -> ! {
+	provisioning_task_inner(uart, store()).await
+}
+
+/// Same as [`provisioning_task()`] but this version's arguments are type erased behind
+/// impl Trait to avoid accidentally accessing concrete behavior.
+async fn provisioning_task_inner(
+	mut uart: impl embedded_hal::serial::Read<u8>,
+	mut store: ConfigStore<impl NorFlash>,
+) -> ! {
+	debug!("Provisioning task");
+	let mut line = Vec::<u8, MAX_LINE_LEN>::new();
+	// Set when the line didn't fit, so that the rest of it gets discarded too
+	let mut overflowed = false;
+	loop {
+		let byte = match uart.read() {
+			Ok(byte) => byte,
+			Err(nb::Error::WouldBlock) => {
+				yield_now().await;
+				continue;
+			}
+			Err(nb::Error::Other(_)) => {
+				warn!("Serial read error, discarding line");
+				line.clear();
+				overflowed = true;
+				continue;
+			}
+		};
+		if byte != b'\n' {
+			if line.push(byte).is_err() {
+				overflowed = true;
+			}
+			continue;
+		}
+
+		if overflowed {
+			warn!("Rejected serial line: too long or corrupted");
+		} else {
+			handle_line(&line, &mut store);
+		}
+		line.clear();
+		overflowed = false;
+	}
+}
+
+/// Parses a complete line and stores the settings in it. Malformed lines never touch
+/// the stored settings.
+fn handle_line<F: NorFlash>(line: &[u8], store: &mut ConfigStore<F>) {
+	let Ok(line) = core::str::from_utf8(line) else {
+		warn!("Rejected serial line: not UTF-8");
+		return;
+	};
+	if line.trim().is_empty() {
+		return;
+	}
+	match parse_line(line) {
+		Ok(Command::SetWifi(credentials)) => set_wifi(credentials, store),
+		Ok(Command::SetBodyPart { sensor_id, part }) => {
+			let stored = store.update(|config| {
+				config.body_parts[usize::from(sensor_id)] = part;
+			});
+			match stored {
+				Ok(()) => info!(
+					"Assigned sensor {} to {}",
+					sensor_id,
+					defmt::Debug2Format(&part)
+				),
+				Err(err) => {
+					warn!("Failed to store body part: {}", defmt::Debug2Format(&err))
+				}
+			}
+		}
+		Ok(Command::SetTxPower(dbm)) => {
+			match store.update(|config| config.wifi_tx_power = dbm) {
+				Ok(()) => match dbm {
+					Some(dbm) => info!("Limited TX power to {}dBm after restart", dbm),
+					None => info!("Using the maximum TX power after restart"),
+				},
+				Err(err) => {
+					warn!("Failed to store TX power: {}", defmt::Debug2Format(&err))
+				}
+			}
+		}
+		Err(err) => warn!("Rejected serial line: {}", err),
+	}
+}
+
+fn set_wifi<F: NorFlash>(credentials: Credentials, store: &mut ConfigStore<F>) {
+	let stored = store.update(|config| {
+		config.wifi_ssid = credentials.ssid.clone();
+		config.wifi_password = credentials.password.clone();
+	});
+	match stored {
+		Ok(()) => info!("Stored WiFi credentials for {}", credentials.ssid.as_str()),
+		Err(err) => {
+			warn!(
+				"Failed to store WiFi credentials: {}",
+				defmt::Debug2Format(&err)
+			);
+			return;
+		}
+	}
+	NEW_CREDENTIALS.signal(credentials);
+}
@@ -1,10 +1,11 @@
 extern crate alloc;
 
-use defmt::{error, info, trace, warn};
+use defmt::{debug, error, info, trace, warn};
 use embassy_futures::{
-	select::{select, Either},
+	select::{select, select4, Either, Either4},
 	yield_now,
 };
+use embassy_time::{Duration, Instant, Timer};
 use embedded_svc::ipv4::Interface;
 use esp_wifi::{
 	create_network_stack_storage, current_millis, network_stack_storage,
@@ -13,11 +14,22 @@ use esp_wifi::{
 };
 use smoltcp::{socket::UdpPacketMetadata, wire::Ipv4Address};
 
-use crate::networking::protocol::Packets;
-use firmware_protocol::Packet;
+use super::panic_report;
+#[cfg(feature = "button")]
+use crate::button::{ButtonEvent, BUTTON_EVENTS};
+use crate::networking::mdns;
+use crate::networking::protocol::{Packets, MAX_PAYLOAD, SERVER_TIMEOUT};
+use crate::status::{Flag, STATUS};
+use crate::utils::Backoff;
+use firmware_protocol::{CbPacket, Packet, SeqCounter, SeqStatus, SeqTracker};
 
 // SlimeVR default UDP port on both sides of connection
 const PORT: u16 = 6969;
+/// Port that mDNS queries are sent from. Anything but 5353 gets a direct response.
+const QUERY_PORT: u16 = 6970;
+/// Bounds of how long we wait before reconnecting to the server.
+const MIN_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(4);
 
 pub async fn network_task(packets: &Packets) -> ! {
 	// TODO: Maybe we should look at the macros in the future for better config
@@ -25,7 +37,14 @@ pub async fn network_task(packets: &Packets) -> ! {
 	let mut storage = create_network_stack_storage!(3, 8, 1, 1);
 	let ethernet = create_network_interface(network_stack_storage!(storage));
 	let mut wifi = esp_wifi::wifi_interface::Wifi::new(ethernet);
-	super::connect_wifi(&mut wifi)
+	let credentials = crate::networking::provisioning::credentials().await;
+	let tx_power = crate::networking::provisioning::tx_power();
+	if let Some(dbm) = tx_power {
+		// The driver only accepts the limit once it has started
+		wifi.start().expect("Couldn't start wifi");
+		set_tx_power(dbm);
+	}
+	super::connect_wifi(&mut wifi, &credentials, tx_power)
 		.await
 		.expect("Couldn't connect to wifi");
 
@@ -41,62 +60,205 @@ pub async fn network_task(packets: &Packets) -> ! {
 
 	info!("DHCP IP: {}", client_ip);
 
-	let mut server_ip = None; // We don't know the server ip yet.
+	let mut backoff = Backoff::new(MIN_BACKOFF, MAX_BACKOFF);
+	loop {
+		// Look for the server again on every reconnect, in case it moved. If discovery
+		// fails we still learn the server from the packets it broadcasts
+		let server_ip = {
+			let mut rx_buffer = [0u8; 512];
+			let mut tx_buffer = [0u8; 512];
+			let mut rx_meta = [UdpPacketMetadata::EMPTY];
+			let mut tx_meta = [UdpPacketMetadata::EMPTY];
+			let mut socket = network.get_udp_socket(
+				&mut rx_meta,
+				&mut rx_buffer,
+				&mut tx_meta,
+				&mut tx_buffer,
+			);
+			socket.bind(QUERY_PORT).unwrap();
+			find_server(&mut socket).await
+		};
+
+		// Buffer size of 1536 matches modern MTU sizes and is more than enough for the SlimeVR protocol
+		let mut rx_buffer = [0u8; 1536];
+		let mut tx_buffer = [0u8; 1536];
+		let mut rx_meta = [UdpPacketMetadata::EMPTY];
+		let mut tx_meta = [UdpPacketMetadata::EMPTY];
+		let mut socket = network.get_udp_socket(
+			&mut rx_meta,
+			&mut rx_buffer,
+			&mut tx_meta,
+			&mut tx_buffer,
+		);
+
+		// Server will send broadcasts to this port
+		socket.bind(PORT).unwrap();
+
+		#[cfg(not(feature = "button"))]
+		let lost = session(&mut socket, server_ip, packets, &mut backoff).await;
+		#[cfg(feature = "button")]
+		let lost = match select(
+			session(&mut socket, server_ip, packets, &mut backoff),
+			BUTTON_EVENTS.wait(),
+		)
+		.await
+		{
+			Either::First(lost) => lost,
+			Either::Second(ButtonEvent::ShortPress) => ConnectionLost::Rediscover,
+			Either::Second(ButtonEvent::LongPress) => {
+				crate::networking::provisioning::factory_reset().await
+			}
+		};
+		STATUS.set(Flag::Connected, false);
+
+		#[cfg(feature = "button")]
+		if lost == ConnectionLost::Rediscover {
+			info!("Looking for the server again");
+			backoff.reset();
+			continue;
+		}
+		let delay = backoff.next_delay();
+		warn!(
+			"Connection lost ({}), reconnecting in {}ms",
+			lost,
+			delay.as_millis()
+		);
+		// Keep draining outbound packets while we wait, so that nothing stalls behind
+		// them and we don't send stale data once we are back
+		select(Timer::after(delay), async {
+			loop {
+				select(packets.serverbound.recv(), packets.bundles.recv()).await;
+			}
+		})
+		.await;
+	}
+}
+
+/// Limits the transmit power of the radio to `dbm`, and reads back what the driver
+/// settled on, since it rounds to the levels that the radio supports.
+fn set_tx_power(dbm: u8) {
+	use esp_wifi::binary::include::{
+		esp_wifi_get_max_tx_power, esp_wifi_set_max_tx_power,
+	};
+
+	// The driver counts in units of 0.25dBm
+	let requested = (dbm * 4) as i8;
+	// SAFETY: The driver has started, and nothing else changes the power concurrently
+	let err = unsafe { esp_wifi_set_max_tx_power(requested) };
+	if err != 0 {
+		warn!("Failed to limit TX power to {}dBm: error {}", dbm, err);
+		return;
+	}
+	let mut applied = 0;
+	// SAFETY: `applied` outlives the call
+	let err = unsafe { esp_wifi_get_max_tx_power(&mut applied) };
+	if err != 0 {
+		warn!("Couldn't read back the TX power: error {}", err);
+		return;
+	}
+	let applied_dbm = f32::from(applied) / 4.;
+	if applied > requested {
+		warn!(
+			"Asked for {}dBm of TX power, driver uses {=f32}dBm",
+			dbm, applied_dbm
+		);
+	} else {
+		info!("TX power limited to {=f32}dBm", applied_dbm);
+	}
+}
+
+/// Why a [`session()`] ended.
+#[derive(defmt::Format, Debug, Copy, Clone, Eq, PartialEq)]
+enum ConnectionLost {
+	SendFailed,
+	RecvFailed,
+	/// The server went quiet for longer than [`SERVER_TIMEOUT`].
+	TimedOut,
+	/// The pairing button was pressed.
+	#[cfg(feature = "button")]
+	Rediscover,
+}
+
+/// Exchanges packets with the server until the connection fails.
+async fn session<'s, 'n>(
+	socket: &mut UdpSocket<'s, 'n>,
+	mut server_ip: Option<[u8; 4]>,
+	packets: &Packets,
+	backoff: &mut Backoff,
+) -> ConnectionLost {
+	// We already know the server, so introduce ourselves instead of waiting for its
+	// discovery broadcast
+	if server_ip.is_some() {
+		packets.connected.signal(());
+	}
+	let _report = panic_report::register(socket, PORT);
+	panic_report::set_server(server_ip);
 
-	// Buffer size of 1536 matches modern MTU sizes and is more than enough for the SlimeVR protocol
 	// Unfortunately esp-wifi won't let us access the underlying tx/rx buffer. Unecessary copy here
 	let mut buffer = [0; 1536];
-	let mut rx_buffer = [0u8; 1536];
-	let mut tx_buffer = [0u8; 1536];
-	let mut rx_meta = [UdpPacketMetadata::EMPTY];
-	let mut tx_meta = [UdpPacketMetadata::EMPTY];
-	let mut socket = network.get_udp_socket(
-		&mut rx_meta,
-		&mut rx_buffer,
-		&mut tx_meta,
-		&mut tx_buffer,
-	);
-
-	// Server will send broadcasts to this port
-	socket.bind(PORT).unwrap();
 
 	// Sequence numbers are monotonically increasing. This is done to reject out-of-order packets
 	// This along with serialization should maybe be done in Packets
-	let mut tx_seq = 0;
-	let mut rx_seq = 0;
+	let mut tx_seq = SeqCounter::new();
+	let mut rx_seq = SeqTracker::new();
+	let mut last_heard = Instant::now();
 
 	// TODO: Implement with proper async select. So far there is no async counterpart of recv
 	loop {
 		// Either start sending or receive, if either is available
-		let net = select(
-			recv_bytes(&mut socket, &mut buffer),
+		let net = select4(
+			recv_bytes(socket, &mut buffer),
 			packets.serverbound.recv(),
+			packets.bundles.recv(),
+			async {
+				// Until we know the server there is nothing to time out
+				match server_ip {
+					Some(_) => Timer::at(last_heard + SERVER_TIMEOUT).await,
+					None => core::future::pending().await,
+				}
+			},
 		)
 		.await;
 
 		match (net, server_ip) {
+			(Either4::First(Err(e)), _) => {
+				error!("Receive failed: {}", defmt::Debug2Format(&e));
+				return ConnectionLost::RecvFailed;
+			}
 			// There is inbound bytes that should be parsed and processed
-			(Either::First((len, addr, _port)), _) => {
+			(Either4::First(Ok((len, addr, _port))), _) => {
 				// Try to optimistically parse all packets that come off the network
 				let Ok(packet) = Packet::deserialize_from(&buffer[..len]) else { trace!("Discarding {}", &buffer[..len]); continue };
 				let (seq, msg) = packet.split();
 
-				// Cancel if sequence number is less than last seen. As of writing, SlimeVR server does not properly
-				// count sequence numbers for clientbound packets, so it always sends 0. This still works, because we
-				// only discard packets that were _less_ than previous
-				if seq < rx_seq {
-					warn!(
-						"Out of order packet received: {}, we are at {} ({})",
-						seq,
-						rx_seq,
-						defmt::Debug2Format(&msg)
-					);
-					continue;
+				// Cancel if sequence number is older than last seen. As of writing, SlimeVR server does not properly
+				// count sequence numbers for clientbound packets, so it always sends 0. This still works, because 0
+				// means the packet isn't numbered
+				if msg == CbPacket::Discovery {
+					// A restarted server numbers its packets from the start again
+					rx_seq.reset();
+				}
+				match rx_seq.receive(seq) {
+					SeqStatus::InOrder => (),
+					SeqStatus::Skipped(lost) => {
+						debug!("Lost {} packets before #{}", lost, seq)
+					}
+					SeqStatus::Stale => {
+						warn!(
+							"Out of order packet received: {} ({})",
+							seq,
+							defmt::Debug2Format(&msg)
+						);
+						continue;
+					}
 				}
 
 				// Hand the packet to rest of the system
 				packets.clientbound.send(msg).await;
-				rx_seq = seq;
+				// The server is talking to us, so the connection works
+				backoff.reset();
+				last_heard = Instant::now();
+				STATUS.set(Flag::Connected, true);
 
 				// If we received a valid packet, assume they are our real host
 				if server_ip != Some(addr) {
@@ -105,37 +267,110 @@ pub async fn network_task(packets: &Packets) -> ! {
 						addr, server_ip
 					);
 					server_ip = Some(addr);
+					mdns::cache(addr);
+					panic_report::set_server(server_ip);
 				}
 			}
 			// There is pending outbound packet that should be sent
-			(Either::Second(msg), Some(server_ip)) => {
+			(Either4::Second(msg), Some(server_ip)) => {
 				// Serialize the packet based on our send sequence number
-				let Ok(len) = Packet::new(tx_seq, msg).serialize_into(&mut buffer) else { warn!("Failed to serialize outgoing packet"); continue };
-				tx_seq += 1;
+				let seq = tx_seq.next_seq();
+				// Refused instead of cut off if it is longer
+				let buffer = &mut buffer[..MAX_PAYLOAD];
+				let Ok(len) = Packet::new(seq, msg).serialize_into(buffer) else { warn!("Failed to serialize outgoing packet, or it is longer than {} bytes", MAX_PAYLOAD); continue };
+
+				if let Err(e) =
+					socket.send(Ipv4Address(server_ip), PORT, &buffer[..len])
+				{
+					warn!("Failed to send #{}: {}", seq, defmt::Debug2Format(&e));
+					return ConnectionLost::SendFailed;
+				}
+			}
+			(Either4::Third(bundle), Some(server_ip)) => {
+				let seq = tx_seq.next_seq();
+				// The protocol task already split the bundles to fit
+				let buffer = &mut buffer[..MAX_PAYLOAD];
+				let Ok(len) = bundle.serialize_into(seq, buffer) else { warn!("Failed to serialize outgoing bundle, or it is longer than {} bytes", MAX_PAYLOAD); continue };
 
 				if let Err(e) =
 					socket.send(Ipv4Address(server_ip), PORT, &buffer[..len])
 				{
-					warn!("Failed to send #{}: {}", tx_seq, defmt::Debug2Format(&e));
+					warn!("Failed to send #{}: {}", seq, defmt::Debug2Format(&e));
+					return ConnectionLost::SendFailed;
 				}
 			}
+			(Either4::Fourth(()), _) => {
+				warn!(
+					"Nothing heard from the server in {}s",
+					SERVER_TIMEOUT.as_secs()
+				);
+				return ConnectionLost::TimedOut;
+			}
 			_ => (),
 		}
 	}
 }
 
+/// Looks for the server with mDNS, and caches its address if it is found. Falls back
+/// to the cached address if nobody answers.
+async fn find_server<'s, 'n>(socket: &mut UdpSocket<'s, 'n>) -> Option<[u8; 4]> {
+	let mut query = [0; 64];
+	let query_len = mdns::write_query(&mut query).expect("mDNS query doesn't fit");
+	let query = &query[..query_len];
+	let mut buffer = [0; 512];
+	for attempt in 1..=mdns::QUERY_ATTEMPTS {
+		debug!("Querying for SlimeVR server, attempt {}", attempt);
+		if let Err(e) =
+			socket.send(Ipv4Address(mdns::MDNS_ADDR), mdns::MDNS_PORT, query)
+		{
+			warn!("Failed to send mDNS query: {}", defmt::Debug2Format(&e));
+		}
+
+		let found = select(
+			async {
+				loop {
+					let Ok((len, addr, _port)) = recv_bytes(socket, &mut buffer).await else {
+						// Wait for the timeout instead of spinning on the error
+						yield_now().await;
+						continue;
+					};
+					if let Some(server) = mdns::parse_response(&buffer[..len], addr) {
+						break server;
+					}
+					trace!("Ignoring unrelated mDNS packet from {}", addr);
+				}
+			},
+			Timer::after(mdns::QUERY_TIMEOUT),
+		)
+		.await;
+		if let Either::First(server) = found {
+			info!("Discovered SlimeVR server at {}", server);
+			mdns::cache(server);
+			return Some(server);
+		}
+	}
+
+	let cached = mdns::load_cached();
+	match cached {
+		Some(server) => info!("No mDNS answer, using cached server {}", server),
+		None => info!("No mDNS answer and no cached server, waiting for broadcasts"),
+	}
+	cached
+}
+
 /// Asynchronously receive bytes from the network. This is a wrapper around UdpSocket::receive
-/// Returns number of bytes read, receiving Ipv4 address and receiving port
+/// Returns number of bytes read, receiving Ipv4 address and receiving port. Errors with
+/// a single packet are skipped, but errors of the interface itself are returned.
 async fn recv_bytes<'s, 'n>(
 	socket: &mut UdpSocket<'s, 'n>,
 	buffer: &mut [u8],
-) -> (usize, [u8; 4], u16) {
+) -> Result<(usize, [u8; 4], u16), WifiError> {
 	loop {
 		match socket.receive(buffer) {
-			Ok(v) => return v,
+			Ok(v) => return Ok(v),
 			Err(WifiError::Other(smoltcp::Error::Exhausted)) => {}
 			Err(WifiError::Other(e)) => error!("smoltcp error {}", e),
-			Err(e) => error!("esp-wifi error {}", defmt::Debug2Format(&e)),
+			Err(e) => return Err(e),
 		}
 		yield_now().await
 	}
@@ -1,17 +1,29 @@
-use defmt::{debug, info};
+use defmt::{debug, error, info, warn};
 use embassy_futures::yield_now;
+use embassy_time::{Duration, Instant};
 use embedded_svc::wifi::{ClientConfiguration, Configuration, Wifi};
 
+use crate::networking::provisioning::Credentials;
+
+#[cfg(feature = "net-wifi")]
+mod panic_report;
 #[cfg(feature = "net-wifi")]
 #[path = "esp.rs"]
 pub mod ඞ;
 
-const SSID: &str = env!("SSID");
-const PASSWORD: &str = env!("PASSWORD");
 const EXPECTED_NEIGHBOURS: usize = 10;
 const WIFI_FIND_RETRIES: usize = 10;
+/// How long associating may take before we suspect that something is wrong.
+const ASSOCIATION_TIMEOUT: Duration = Duration::from_secs(10);
 
-pub async fn connect_wifi<W: Wifi>(wifi: &mut W) -> Result<(), W::Error> {
+/// Connects to the network in `credentials`. `tx_power` is the limit on the transmit
+/// power in dBm, if there is one, so that failures caused by it can be pointed out.
+pub async fn connect_wifi<W: Wifi>(
+	wifi: &mut W,
+	credentials: &Credentials,
+	tx_power: Option<u8>,
+) -> Result<(), W::Error> {
+	let ssid = credentials.ssid.as_str();
 	if !wifi.is_started()? {
 		wifi.start()?
 	}
@@ -23,20 +35,23 @@ pub async fn connect_wifi<W: Wifi>(wifi: &mut W) -> Result<(), W::Error> {
 		let (mut scan_list, count) = wifi.scan_n::<EXPECTED_NEIGHBOURS>()?;
 		debug!("found {} APs", count);
 
-		let pos = scan_list.iter().position(|ap| ap.ssid == SSID);
+		let pos = scan_list.iter().position(|ap| ap.ssid == ssid);
 
 		if let Some(ap) = pos {
 			break scan_list.swap_remove(ap);
 		} else if i == WIFI_FIND_RETRIES {
-			panic!("Couldn't find SSID {}", SSID);
+			if let Some(dbm) = tx_power {
+				error!("TX power is limited to {}dBm, which may be too low", dbm);
+			}
+			panic!("Couldn't find SSID {}", ssid);
 		}
 		// TODO: this also should require a ticker
 		yield_now().await;
 	};
-	info!("found SSID {}", SSID);
+	info!("found SSID {}", ssid);
 	let client_config = Configuration::Client(ClientConfiguration {
-		ssid: SSID.into(),
-		password: PASSWORD.into(),
+		ssid: ssid.into(),
+		password: credentials.password.as_str().into(),
 		bssid: Some(ap.bssid),
 		auth_method: ap.auth_method,
 		channel: Some(ap.channel),
@@ -46,11 +61,27 @@ pub async fn connect_wifi<W: Wifi>(wifi: &mut W) -> Result<(), W::Error> {
 	debug!("{:?}", defmt::Debug2Format(&wifi.get_capabilities()?));
 	wifi.connect()?;
 
+	let started = Instant::now();
+	let mut warned = false;
 	loop {
 		let res = wifi.is_connected();
 		if matches!(res, Ok(true)) {
 			break; // connected successfully
 		}
+		if !warned && started.elapsed() > ASSOCIATION_TIMEOUT {
+			warned = true;
+			warn!(
+				"Not associated with {} after {}s",
+				ssid,
+				ASSOCIATION_TIMEOUT.as_secs()
+			);
+			if let Some(dbm) = tx_power {
+				warn!(
+					"TX power is limited to {}dBm, try `SET TXPOWER max` if this persists",
+					dbm
+				);
+			}
+		}
 		yield_now().await;
 	}
 
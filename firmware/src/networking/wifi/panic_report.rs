@@ -0,0 +1,141 @@
+//! Reports panics to the server, so that crashes can be noticed without a debug probe
+//! attached.
+//!
+//! While a session with the server is running, it registers its socket here. The panic
+//! handler then sends the panic message to the server as a serial log packet, which the
+//! server shows in its log. If there is no session, or the server isn't known yet,
+//! nothing is sent and the panic handler halts as usual.
+
+use core::cell::Cell;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use esp_wifi::{current_millis, wifi_interface::UdpSocket};
+use smoltcp::wire::Ipv4Address;
+
+/// Id of the serial log packet in the SlimeVR protocol.
+const PACKET_SERIAL: u32 = 11;
+/// The packet id, the sequence number and the length of the message.
+const HEADER_LEN: usize = 4 + 8 + 4;
+/// Longest message that is sent, the rest is cut off.
+const MAX_MESSAGE_LEN: usize = 256;
+/// How long the network stack gets to send the report before we halt, in ms.
+const FLUSH_TIME_MS: u64 = 50;
+
+/// Where panics are reported to.
+#[derive(Copy, Clone)]
+struct Target {
+	/// The socket of the running session, with its lifetimes erased. It is only
+	/// dereferenced while the session holds the [`Registration`].
+	socket: *mut UdpSocket<'static, 'static>,
+	server: Option<[u8; 4]>,
+	port: u16,
+}
+// SAFETY: There is a single core and a single executor, so the socket is never
+// accessed from two places at the same time. The panic handler interrupting the
+// session is the one exception, which is why the report is only best effort.
+unsafe impl Send for Target {}
+
+static TARGET: Mutex<CriticalSectionRawMutex, Cell<Option<Target>>> =
+	Mutex::new(Cell::new(None));
+
+/// Unregisters the socket when dropped, so that it is not used after the session.
+pub struct Registration(());
+impl Drop for Registration {
+	fn drop(&mut self) {
+		panic_defmt::set_hook(None);
+		TARGET.lock(|target| target.set(None));
+	}
+}
+
+/// Reports panics through `socket` to `port` on the server, until the returned
+/// [`Registration`] is dropped. The server address is set with [`set_server()`].
+pub fn register(socket: &mut UdpSocket<'_, '_>, port: u16) -> Registration {
+	let socket = socket as *mut UdpSocket<'_, '_> as *mut UdpSocket<'static, 'static>;
+	TARGET.lock(|target| {
+		target.set(Some(Target {
+			socket,
+			server: None,
+			port,
+		}))
+	});
+	panic_defmt::set_hook(Some(report));
+	Registration(())
+}
+
+/// Updates the address of the server that panics are reported to.
+pub fn set_server(server: Option<[u8; 4]>) {
+	TARGET.lock(|target| {
+		if let Some(mut t) = target.get() {
+			t.server = server;
+			target.set(Some(t));
+		}
+	});
+}
+
+/// Called by the panic handler.
+fn report(panic: &PanicInfo) {
+	let Some(target) = TARGET.lock(|target| target.get()) else { return };
+	let Some(server) = target.server else { return };
+
+	// Format on the stack, as the heap may be what failed
+	let mut packet = [0; HEADER_LEN + MAX_MESSAGE_LEN];
+	let (header, message) = packet.split_at_mut(HEADER_LEN);
+	let mut message = Truncating {
+		buf: message,
+		len: 0,
+	};
+	// Truncation is the only error, and a partial message is still useful
+	let _ = write!(message, "{}", panic);
+	let len = message.len;
+
+	header[0..4].copy_from_slice(&PACKET_SERIAL.to_be_bytes());
+	// The sequence number is left at 0, which the server accepts regardless of the
+	// packets it has seen before
+	header[12..16].copy_from_slice(&(len as u32).to_be_bytes());
+
+	info!("Reporting the panic to the server at {}", server);
+	// SAFETY: The session registered the socket and hasn't dropped the registration,
+	// so it is still alive. See `Target` for why nothing else is using it.
+	let socket = unsafe { &mut *target.socket };
+	if socket
+		.send(
+			Ipv4Address(server),
+			target.port,
+			&packet[..HEADER_LEN + len],
+		)
+		.is_err()
+	{
+		return;
+	}
+	// Keep the network stack going until the packet has had time to leave
+	let start = current_millis();
+	while current_millis() - start < FLUSH_TIME_MS {
+		socket.work();
+	}
+}
+
+/// Writes into a fixed buffer, and fails once it is full.
+struct Truncating<'a> {
+	buf: &'a mut [u8],
+	len: usize,
+}
+impl Write for Truncating<'_> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		let free = self.buf.len() - self.len;
+		let mut n = s.len().min(free);
+		// Don't cut a character in half
+		while !s.is_char_boundary(n) {
+			n -= 1;
+		}
+		self.buf[self.len..][..n].copy_from_slice(&s.as_bytes()[..n]);
+		self.len += n;
+		if n < s.len() {
+			return Err(fmt::Error);
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,100 @@
+//! The settings of the tracker that survive reboots, kept together in a single flash
+//! record.
+//!
+//! How the record is encoded is up to [`firmware_core::config`]. A record of an
+//! unknown version, or that fails the CRC, is replaced with [`Config::default()`].
+
+use core::cell::Cell;
+
+use defmt::{debug, warn};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embedded_storage::nor_flash::NorFlash;
+use firmware_core::config::{record_len, Invalid, VERSION};
+use firmware_protocol::BodyPart;
+
+use crate::imu::MAX_IMUS;
+use crate::peripherals::flash::{FlashStore, MAX_RECORD_LEN};
+
+pub use firmware_core::config::{
+	MAX_PASSWORD_LEN, MAX_SSID_LEN, MAX_TX_POWER, MIN_TX_POWER,
+};
+
+const RECORD_LEN: usize = record_len(MAX_IMUS);
+const _: () = assert!(RECORD_LEN <= MAX_RECORD_LEN, "config doesn't fit in flash");
+
+/// The body parts of the stored config, updated whenever it is loaded or saved. This
+/// lets the protocol tell the server about them without access to the flash.
+pub static BODY_PARTS: Mutex<CriticalSectionRawMutex, Cell<[BodyPart; MAX_IMUS]>> =
+	Mutex::new(Cell::new([BodyPart::Unassigned; MAX_IMUS]));
+
+pub type Config = firmware_core::config::Config<MAX_IMUS>;
+
+/// Loads and stores the [`Config`] in a reserved flash sector.
+pub struct ConfigStore<F: NorFlash> {
+	store: FlashStore<F>,
+}
+impl<F: NorFlash> ConfigStore<F> {
+	/// `offset` must be the start of an erase sector that nothing else uses.
+	pub fn new(flash: F, offset: u32) -> Self {
+		Self {
+			store: FlashStore::new(flash, offset),
+		}
+	}
+
+	/// Loads the stored config. If it is missing, outdated or corrupted, the defaults
+	/// are stored in its place and returned.
+	pub fn load(&mut self) -> Config {
+		let invalid = match self.read() {
+			Ok(Ok(config)) => {
+				BODY_PARTS.lock(|parts| parts.set(config.body_parts));
+				return config;
+			}
+			Ok(Err(invalid)) => invalid,
+			Err(err) => {
+				// Might be temporary, so leave the record alone
+				warn!("Failed to read config: {}", defmt::Debug2Format(&err));
+				return Config::default();
+			}
+		};
+		warn!(
+			"Stored config can't be used ({}), using the defaults",
+			defmt::Debug2Format(&invalid)
+		);
+		let config = Config::default();
+		if let Err(err) = self.save(&config) {
+			warn!("Failed to store config: {}", defmt::Debug2Format(&err));
+		}
+		config
+	}
+
+	fn read(&mut self) -> Result<Result<Config, Invalid>, F::Error> {
+		let mut record = [0; RECORD_LEN];
+		let Some(len) = self.store.load(&mut record)? else {
+			return Ok(Err(Invalid::Missing));
+		};
+		Ok(Config::from_record(&record[..len]))
+	}
+
+	/// Replaces the stored config with `config`.
+	pub fn save(&mut self, config: &Config) -> Result<(), F::Error> {
+		let mut record = [0; RECORD_LEN];
+		let len = config.to_record(&mut record);
+		debug!("Storing config version {}", VERSION);
+		self.store.store(&record[..len])?;
+		BODY_PARTS.lock(|parts| parts.set(config.body_parts));
+		Ok(())
+	}
+
+	/// Changes the stored config with `f`. Nothing is written if `f` leaves it as it
+	/// was, to avoid wearing out the flash.
+	pub fn update(&mut self, f: impl FnOnce(&mut Config)) -> Result<(), F::Error> {
+		let old = self.load();
+		let mut config = old.clone();
+		f(&mut config);
+		if config == old {
+			return Ok(());
+		}
+		self.save(&config)
+	}
+}
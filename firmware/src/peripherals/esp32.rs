@@ -1,6 +1,10 @@
 use super::Peripherals;
 use crate::aliases::ඞ::DelayConcrete;
+use crate::aliases::ඞ::FlashConcrete;
+#[cfg(feature = "i2c-secondary")]
+use crate::aliases::ඞ::I2c2Concrete;
 use crate::aliases::ඞ::I2cConcrete;
+use crate::aliases::ඞ::UartConcrete;
 
 use fugit::RateExtU32;
 use paste::paste;
@@ -21,7 +25,38 @@ macro_rules! map_pin {
 	};
 }
 
-pub fn get_peripherals() -> Peripherals<I2cConcrete<'static>, DelayConcrete> {
+/// Restarts the chip, as if the reset button was pressed.
+#[cfg(feature = "ota")]
+pub fn reset() -> ! {
+	esp32_hal::reset::software_reset();
+	unreachable!("the chip is resetting")
+}
+
+/// The factory programmed MAC address, which the wifi station also uses. The server
+/// tells trackers apart by it.
+pub fn mac_address() -> [u8; 6] {
+	esp32_hal::efuse::Efuse::get_mac_address()
+}
+
+#[cfg(not(feature = "i2c-secondary"))]
+type I2c2 = ();
+#[cfg(feature = "i2c-secondary")]
+type I2c2 = I2c2Concrete<'static>;
+
+pub fn get_peripherals() -> Peripherals<
+	I2cConcrete<'static>,
+	DelayConcrete,
+	UartConcrete<'static>,
+	(),
+	(),
+	FlashConcrete<'static>,
+	(),
+	(),
+	(),
+	(),
+	(),
+	I2c2,
+> {
 	let p = pac::Peripherals::take().unwrap();
 
 	let mut system = p.DPORT.split();
@@ -59,16 +94,34 @@ pub fn get_peripherals() -> Peripherals<I2cConcrete<'static>, DelayConcrete> {
 	}
 
 	let io = esp32_hal::IO::new(p.GPIO, p.IO_MUX);
-	// let hz =
 	let i2c = esp32_hal::i2c::I2C::new(
 		p.I2C0,
 		map_pin!(io, env!("PIN_SDA")),
 		map_pin!(io, env!("PIN_SCL")),
-		400u32.kHz(),
+		super::I2C_KHZ.kHz(),
+		&mut system.peripheral_clock_control,
+		&clocks,
+	);
+	#[cfg(not(feature = "i2c-secondary"))]
+	let i2c2 = ();
+	#[cfg(feature = "i2c-secondary")]
+	let i2c2 = esp32_hal::i2c::I2C::new(
+		p.I2C1,
+		map_pin!(io, env!("PIN_SDA2")),
+		map_pin!(io, env!("PIN_SCL2")),
+		super::I2C_KHZ.kHz(),
 		&mut system.peripheral_clock_control,
 		&clocks,
 	);
 
 	let delay = esp32_hal::Delay::new(&clocks);
-	Peripherals::new().i2c(i2c).delay(delay)
+	let flash = esp_storage::FlashStorage::new();
+	// The default pins of UART0 are connected to the USB serial bridge of most boards
+	let uart = esp32_hal::Uart::new(p.UART0, &mut system.peripheral_clock_control);
+	Peripherals::new()
+		.i2c(i2c)
+		.delay(delay)
+		.uart(uart)
+		.flash(flash)
+		.i2c2(i2c2)
 }
@@ -1,6 +1,11 @@
 use super::Peripherals;
 use crate::aliases::ඞ::DelayConcrete;
+use crate::aliases::ඞ::FlashConcrete;
+#[cfg(not(feature = "transport-spi"))]
 use crate::aliases::ඞ::I2cConcrete;
+#[cfg(feature = "transport-spi")]
+use crate::aliases::ඞ::SpiConcrete;
+use crate::aliases::ඞ::UartConcrete;
 
 use fugit::RateExtU32;
 use paste::paste;
@@ -20,7 +25,107 @@ macro_rules! map_pin {
 	};
 }
 
-pub fn get_peripherals() -> Peripherals<I2cConcrete<'static>, DelayConcrete> {
+#[cfg(not(feature = "transport-spi"))]
+type ImuBus = I2cConcrete<'static>;
+#[cfg(feature = "transport-spi")]
+type ImuBus = ();
+#[cfg(not(feature = "transport-spi"))]
+type Spi = ();
+#[cfg(feature = "transport-spi")]
+type Spi = SpiConcrete<'static>;
+#[cfg(not(feature = "battery-adc"))]
+type Battery = ();
+#[cfg(feature = "battery-adc")]
+type Battery = Adc;
+#[cfg(not(feature = "button"))]
+type Button = ();
+#[cfg(feature = "button")]
+pub type Button = esp32c3_hal::gpio::GpioPin<
+	esp32c3_hal::gpio::Input<esp32c3_hal::gpio::PullUp>,
+	BUTTON_PIN,
+>;
+#[cfg(not(feature = "status-led"))]
+type Led = ();
+#[cfg(feature = "status-led")]
+pub type Led = esp32c3_hal::gpio::GpioPin<
+	esp32c3_hal::gpio::Output<esp32c3_hal::gpio::PushPull>,
+	LED_PIN,
+>;
+
+#[cfg(not(feature = "watchdog"))]
+type Watchdog = ();
+#[cfg(feature = "watchdog")]
+pub type Watchdog = esp32c3_hal::timer::Wdt<esp32c3_hal::pac::TIMG1>;
+
+/// The ADC and the pin that the battery is connected to.
+#[cfg(feature = "battery-adc")]
+pub struct Adc {
+	adc: esp32c3_hal::adc::ADC<esp32c3_hal::adc::ADC1>,
+	pin: esp32c3_hal::adc::AdcPin<
+		esp32c3_hal::gpio::GpioPin<esp32c3_hal::gpio::Analog, BATTERY_PIN>,
+		esp32c3_hal::adc::ADC1,
+	>,
+}
+#[cfg(feature = "battery-adc")]
+impl crate::battery::BatteryAdc for Adc {
+	fn read_mv(&mut self) -> nb::Result<u32, ()> {
+		use embedded_hal::adc::OneShot;
+
+		let raw: u16 = self.adc.read(&mut self.pin)?;
+		// With 11dB of attenuation the full 12 bit range is roughly 0-2.5V
+		Ok(u32::from(raw) * 2500 / 4095)
+	}
+}
+#[cfg(feature = "watchdog")]
+impl crate::peripherals::watchdog::Watchdog for Watchdog {
+	fn start(&mut self, timeout: embassy_time::Duration) {
+		use embedded_hal::watchdog::WatchdogEnable;
+		use fugit::ExtU64;
+
+		WatchdogEnable::start(self, timeout.as_micros().micros());
+	}
+
+	fn feed(&mut self) {
+		embedded_hal::watchdog::Watchdog::feed(self)
+	}
+}
+
+/// GPIO number of the battery pin, parsed from the board config.
+#[cfg(feature = "battery-adc")]
+const BATTERY_PIN: u8 = crate::utils::parse_u8(env!("PIN_BATTERY"));
+/// GPIO number of the status LED, parsed from the board config.
+#[cfg(feature = "status-led")]
+const LED_PIN: u8 = crate::utils::parse_u8(env!("PIN_LED"));
+/// GPIO number of the pairing button, parsed from the board config.
+#[cfg(feature = "button")]
+const BUTTON_PIN: u8 = crate::utils::parse_u8(env!("PIN_BUTTON"));
+
+/// Restarts the chip, as if the reset button was pressed.
+#[cfg(any(feature = "ota", feature = "button"))]
+pub fn reset() -> ! {
+	esp32c3_hal::reset::software_reset();
+	unreachable!("the chip is resetting")
+}
+
+/// The factory programmed MAC address, which the wifi station also uses. The server
+/// tells trackers apart by it.
+pub fn mac_address() -> [u8; 6] {
+	esp32c3_hal::efuse::Efuse::get_mac_address()
+}
+
+pub fn get_peripherals() -> Peripherals<
+	ImuBus,
+	DelayConcrete,
+	UartConcrete<'static>,
+	(),
+	Spi,
+	FlashConcrete<'static>,
+	Battery,
+	Led,
+	Button,
+	(),
+	Watchdog,
+> {
 	let p = esp32c3_hal::pac::Peripherals::take().unwrap();
 
 	let mut system = p.SYSTEM.split();
@@ -30,8 +135,9 @@ pub fn get_peripherals() -> Peripherals<I2cConcrete<'static>, DelayConcrete> {
 	// Initialize embassy stuff
 	// embassy::init(&clocks);
 
-	// Disable the RTC and TIMG watchdog timers
-	let timer0 = {
+	// Disable the RTC and TIMG watchdog timers. With the `watchdog` feature, the watchdog
+	// task starts the TIMG1 one again once we are up and running
+	let (timer0, watchdog) = {
 		let mut rtc = Rtc::new(p.RTC_CNTL);
 		let timer_group0 = TimerGroup::new(p.TIMG0, &clocks);
 		let mut wdt0 = timer_group0.wdt;
@@ -43,7 +149,9 @@ pub fn get_peripherals() -> Peripherals<I2cConcrete<'static>, DelayConcrete> {
 		wdt0.disable();
 		wdt1.disable();
 
-		timer_group0.timer0
+		#[cfg(not(feature = "watchdog"))]
+		let wdt1 = ();
+		(timer_group0.timer0, wdt1)
 	};
 
 	// Initialize embassy
@@ -63,15 +171,82 @@ pub fn get_peripherals() -> Peripherals<I2cConcrete<'static>, DelayConcrete> {
 	}
 
 	let io = esp32c3_hal::IO::new(p.GPIO, p.IO_MUX);
-	let i2c = esp32c3_hal::i2c::I2C::new(
-		p.I2C0,
-		map_pin!(io, env!("PIN_SDA")),
-		map_pin!(io, env!("PIN_SCL")),
-		400u32.kHz(),
-		&mut system.peripheral_clock_control,
-		&clocks,
-	);
-
 	let delay = esp32c3_hal::Delay::new(&clocks);
-	Peripherals::new().i2c(i2c).delay(delay)
+	let flash = esp_storage::FlashStorage::new();
+	// The default pins of UART0 are connected to the USB serial bridge of most boards
+	let uart = esp32c3_hal::Uart::new(p.UART0, &mut system.peripheral_clock_control);
+
+	#[cfg(not(feature = "battery-adc"))]
+	let battery = ();
+	#[cfg(feature = "battery-adc")]
+	let battery = {
+		use esp32c3_hal::adc::{AdcConfig, Attenuation, ADC, ADC1};
+
+		let analog = p.APB_SARADC.split();
+		let mut config = AdcConfig::new();
+		let pin = config.enable_pin(
+			map_pin!(io, env!("PIN_BATTERY")).into_analog(),
+			Attenuation::Attenuation11dB,
+		);
+		let adc =
+			ADC::<ADC1>::adc(&mut system.peripheral_clock_control, analog.adc1, config)
+				.unwrap();
+		Adc { adc, pin }
+	};
+
+	#[cfg(not(feature = "status-led"))]
+	let led = ();
+	#[cfg(feature = "status-led")]
+	let led = map_pin!(io, env!("PIN_LED")).into_push_pull_output();
+
+	#[cfg(not(feature = "button"))]
+	let button = ();
+	#[cfg(feature = "button")]
+	let button = map_pin!(io, env!("PIN_BUTTON")).into_pull_up_input();
+
+	#[cfg(not(feature = "transport-spi"))]
+	{
+		let i2c = esp32c3_hal::i2c::I2C::new(
+			p.I2C0,
+			map_pin!(io, env!("PIN_SDA")),
+			map_pin!(io, env!("PIN_SCL")),
+			super::I2C_KHZ.kHz(),
+			&mut system.peripheral_clock_control,
+			&clocks,
+		);
+		Peripherals::new()
+			.i2c(i2c)
+			.delay(delay)
+			.uart(uart)
+			.flash(flash)
+			.battery(battery)
+			.led(led)
+			.button(button)
+			.watchdog(watchdog)
+	}
+
+	// The chip select is driven by the SPI peripheral itself
+	#[cfg(feature = "transport-spi")]
+	{
+		let spi = esp32c3_hal::spi::Spi::new(
+			p.SPI2,
+			map_pin!(io, env!("PIN_SCK")),
+			map_pin!(io, env!("PIN_MOSI")),
+			map_pin!(io, env!("PIN_MISO")),
+			map_pin!(io, env!("PIN_CS")),
+			4u32.MHz(),
+			esp32c3_hal::spi::SpiMode::Mode0,
+			&mut system.peripheral_clock_control,
+			&clocks,
+		);
+		Peripherals::new()
+			.spi(spi)
+			.delay(delay)
+			.uart(uart)
+			.flash(flash)
+			.battery(battery)
+			.led(led)
+			.button(button)
+			.watchdog(watchdog)
+	}
 }
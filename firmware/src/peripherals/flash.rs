@@ -0,0 +1,75 @@
+//! Stores a single small record in a reserved sector of the on-chip flash, so that
+//! it survives reboots.
+
+use defmt::{debug, warn};
+use embedded_storage::nor_flash::NorFlash;
+
+/// Marks that the sector holds a record written by us, instead of leftover data.
+const MAGIC: u32 = u32::from_le_bytes(*b"SLME");
+/// Size of the magic and the length that precede the record.
+const HEADER_LEN: usize = 8;
+/// Largest record that can be stored.
+pub const MAX_RECORD_LEN: usize = 504;
+const BUF_LEN: usize = HEADER_LEN + MAX_RECORD_LEN;
+
+pub struct FlashStore<F: NorFlash> {
+	flash: F,
+	/// Where in the flash the reserved sector starts.
+	offset: u32,
+}
+impl<F: NorFlash> FlashStore<F> {
+	/// `offset` must be the start of an erase sector that nothing else uses.
+	pub fn new(flash: F, offset: u32) -> Self {
+		debug_assert_eq!(offset % F::ERASE_SIZE as u32, 0, "offset is not aligned");
+		Self { flash, offset }
+	}
+
+	/// Reads the stored record into `record`, returning its length. Returns `None` if
+	/// nothing has been stored yet.
+	pub fn load(&mut self, record: &mut [u8]) -> Result<Option<usize>, F::Error> {
+		let mut buf = [0; BUF_LEN];
+		self.flash.read(self.offset, &mut buf)?;
+
+		let magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+		let len = u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize;
+		if magic != MAGIC || len > MAX_RECORD_LEN {
+			debug!("No record stored in flash");
+			return Ok(None);
+		}
+		if len > record.len() {
+			warn!("Record in flash is larger than expected, ignoring it");
+			return Ok(None);
+		}
+		record[..len].copy_from_slice(&buf[HEADER_LEN..][..len]);
+		Ok(Some(len))
+	}
+
+	/// Replaces the stored record with `record`.
+	///
+	/// This erases the sector every time, so avoid calling it more often than needed.
+	///
+	/// # Panics
+	/// Panics if `record` is longer than [`MAX_RECORD_LEN`].
+	pub fn store(&mut self, record: &[u8]) -> Result<(), F::Error> {
+		assert!(record.len() <= MAX_RECORD_LEN, "record too large for flash");
+		let mut buf = [0xFF; BUF_LEN];
+		buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+		buf[4..8].copy_from_slice(&(record.len() as u32).to_le_bytes());
+		buf[HEADER_LEN..][..record.len()].copy_from_slice(record);
+
+		// Writes have to be a multiple of the write size
+		let len = HEADER_LEN + record.len();
+		let len = (len + F::WRITE_SIZE - 1) / F::WRITE_SIZE * F::WRITE_SIZE;
+
+		debug!("Writing {} bytes to flash", len);
+		self.erase()?;
+		self.flash.write(self.offset, &buf[..len])
+	}
+
+	/// Erases the stored record, so that [`Self::load()`] returns `None` until something
+	/// is stored again.
+	pub fn erase(&mut self) -> Result<(), F::Error> {
+		self.flash
+			.erase(self.offset, self.offset + F::ERASE_SIZE as u32)
+	}
+}
@@ -12,13 +12,57 @@ pub mod ඞ;
 #[path = "nrf52.rs"]
 pub mod ඞ;
 
+#[cfg(feature = "mcu-rp2040")]
+#[path = "rp2040.rs"]
+pub mod ඞ;
+
+pub mod config;
+pub mod flash;
+#[cfg(feature = "ota")]
+pub mod ota;
+#[cfg(feature = "deep-sleep")]
+pub mod power;
+#[cfg(feature = "status-led")]
+pub mod status_led;
+#[cfg(feature = "mux-tca9548a")]
+pub mod tca9548a;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
+
+/// The clock speed of the I2C bus, in kHz. Set with `i2c_khz` in the board toml, and
+/// 400kHz by default.
+pub const I2C_KHZ: u32 = crate::utils::parse_u32(env!("I2C_KHZ"));
+
 /// Holds the peripherals. This merely exists to allow a way to pass around platform
 /// specific peripherals, some of which may not even exist, in a platform-agnostic way.
-pub struct Peripherals<I2c = (), Delay = (), Uart = (), UsbDriver = ()> {
+pub struct Peripherals<
+	I2c = (),
+	Delay = (),
+	Uart = (),
+	UsbDriver = (),
+	Spi = (),
+	Flash = (),
+	Battery = (),
+	Led = (),
+	Button = (),
+	Power = (),
+	Watchdog = (),
+	I2c2 = (),
+> {
 	pub i2c: I2c,
 	pub delay: Delay,
 	pub uart: Uart,
 	pub usb_driver: UsbDriver,
+	pub spi: Spi,
+	pub flash: Flash,
+	pub battery: Battery,
+	pub led: Led,
+	pub button: Button,
+	pub power: Power,
+	pub watchdog: Watchdog,
+	/// A second I2C bus, for boards that split their sensors across two. Only with the
+	/// `i2c-secondary` feature.
+	pub i2c2: I2c2,
 }
 impl Peripherals {
 	pub fn new() -> Self {
@@ -27,54 +71,496 @@ impl Peripherals {
 			delay: (),
 			uart: (),
 			usb_driver: (),
+			spi: (),
+			flash: (),
+			battery: (),
+			led: (),
+			button: (),
+			power: (),
+			watchdog: (),
+			i2c2: (),
 		}
 	}
 }
 /// Type-level builder for `Peripherals`, which transforms each field from () to the
 /// peripheral type.
-impl<I2c, Delay, Uart, UsbDriver> Peripherals<I2c, Delay, Uart, UsbDriver> {
+impl<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	>
+	Peripherals<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	>
+{
 	#[allow(dead_code)]
-	pub fn i2c<T>(self, p: T) -> Peripherals<T, Delay, Uart, UsbDriver> {
+	pub fn i2c<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		T,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	> {
 		Peripherals {
 			i2c: p,
 			delay: self.delay,
 			uart: self.uart,
 			usb_driver: self.usb_driver,
+			spi: self.spi,
+			flash: self.flash,
+			battery: self.battery,
+			led: self.led,
+			button: self.button,
+			power: self.power,
+			watchdog: self.watchdog,
+			i2c2: self.i2c2,
 		}
 	}
 	#[allow(dead_code)]
-	pub fn delay<T>(self, p: T) -> Peripherals<I2c, T, Uart, UsbDriver> {
+	pub fn delay<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		I2c,
+		T,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	> {
 		Peripherals {
 			i2c: self.i2c,
 			delay: p,
 			uart: self.uart,
 			usb_driver: self.usb_driver,
+			spi: self.spi,
+			flash: self.flash,
+			battery: self.battery,
+			led: self.led,
+			button: self.button,
+			power: self.power,
+			watchdog: self.watchdog,
+			i2c2: self.i2c2,
 		}
 	}
 	#[allow(dead_code)]
-	pub fn uart<T>(self, p: T) -> Peripherals<I2c, Delay, T, UsbDriver> {
+	pub fn uart<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		I2c,
+		Delay,
+		T,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	> {
 		Peripherals {
 			i2c: self.i2c,
 			delay: self.delay,
 			uart: p,
 			usb_driver: self.usb_driver,
+			spi: self.spi,
+			flash: self.flash,
+			battery: self.battery,
+			led: self.led,
+			button: self.button,
+			power: self.power,
+			watchdog: self.watchdog,
+			i2c2: self.i2c2,
 		}
 	}
 	#[allow(dead_code)]
-	pub fn usb_driver<T>(self, p: T) -> Peripherals<I2c, Delay, Uart, T> {
+	pub fn usb_driver<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		I2c,
+		Delay,
+		Uart,
+		T,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	> {
 		Peripherals {
 			i2c: self.i2c,
 			delay: self.delay,
 			uart: self.uart,
 			usb_driver: p,
+			spi: self.spi,
+			flash: self.flash,
+			battery: self.battery,
+			led: self.led,
+			button: self.button,
+			power: self.power,
+			watchdog: self.watchdog,
+			i2c2: self.i2c2,
+		}
+	}
+	#[allow(dead_code)]
+	pub fn spi<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		T,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	> {
+		Peripherals {
+			i2c: self.i2c,
+			delay: self.delay,
+			uart: self.uart,
+			usb_driver: self.usb_driver,
+			spi: p,
+			flash: self.flash,
+			battery: self.battery,
+			led: self.led,
+			button: self.button,
+			power: self.power,
+			watchdog: self.watchdog,
+			i2c2: self.i2c2,
+		}
+	}
+	#[allow(dead_code)]
+	pub fn flash<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		T,
+		Battery,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	> {
+		Peripherals {
+			i2c: self.i2c,
+			delay: self.delay,
+			uart: self.uart,
+			usb_driver: self.usb_driver,
+			spi: self.spi,
+			flash: p,
+			battery: self.battery,
+			led: self.led,
+			button: self.button,
+			power: self.power,
+			watchdog: self.watchdog,
+			i2c2: self.i2c2,
+		}
+	}
+	#[allow(dead_code)]
+	pub fn battery<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		T,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	> {
+		Peripherals {
+			i2c: self.i2c,
+			delay: self.delay,
+			uart: self.uart,
+			usb_driver: self.usb_driver,
+			spi: self.spi,
+			flash: self.flash,
+			battery: p,
+			led: self.led,
+			button: self.button,
+			power: self.power,
+			watchdog: self.watchdog,
+			i2c2: self.i2c2,
+		}
+	}
+	#[allow(dead_code)]
+	pub fn led<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		T,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	> {
+		Peripherals {
+			i2c: self.i2c,
+			delay: self.delay,
+			uart: self.uart,
+			usb_driver: self.usb_driver,
+			spi: self.spi,
+			flash: self.flash,
+			battery: self.battery,
+			led: p,
+			button: self.button,
+			power: self.power,
+			watchdog: self.watchdog,
+			i2c2: self.i2c2,
+		}
+	}
+	#[allow(dead_code)]
+	pub fn button<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		T,
+		Power,
+		Watchdog,
+		I2c2,
+	> {
+		Peripherals {
+			i2c: self.i2c,
+			delay: self.delay,
+			uart: self.uart,
+			usb_driver: self.usb_driver,
+			spi: self.spi,
+			flash: self.flash,
+			battery: self.battery,
+			led: self.led,
+			button: p,
+			power: self.power,
+			watchdog: self.watchdog,
+			i2c2: self.i2c2,
+		}
+	}
+	#[allow(dead_code)]
+	pub fn power<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		T,
+		Watchdog,
+		I2c2,
+	> {
+		Peripherals {
+			i2c: self.i2c,
+			delay: self.delay,
+			uart: self.uart,
+			usb_driver: self.usb_driver,
+			spi: self.spi,
+			flash: self.flash,
+			battery: self.battery,
+			led: self.led,
+			button: self.button,
+			power: p,
+			watchdog: self.watchdog,
+			i2c2: self.i2c2,
+		}
+	}
+	#[allow(dead_code)]
+	pub fn watchdog<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		Power,
+		T,
+		I2c2,
+	> {
+		Peripherals {
+			i2c: self.i2c,
+			delay: self.delay,
+			uart: self.uart,
+			usb_driver: self.usb_driver,
+			spi: self.spi,
+			flash: self.flash,
+			battery: self.battery,
+			led: self.led,
+			button: self.button,
+			power: self.power,
+			watchdog: p,
+			i2c2: self.i2c2,
+		}
+	}
+	#[allow(dead_code)]
+	pub fn i2c2<T>(
+		self,
+		p: T,
+	) -> Peripherals<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		T,
+	> {
+		Peripherals {
+			i2c: self.i2c,
+			delay: self.delay,
+			uart: self.uart,
+			usb_driver: self.usb_driver,
+			spi: self.spi,
+			flash: self.flash,
+			battery: self.battery,
+			led: self.led,
+			button: self.button,
+			power: self.power,
+			watchdog: self.watchdog,
+			i2c2: p,
 		}
 	}
 }
 
 /// Type-level destructors for `Peripherals` which turn peripheral type into ().
-impl<I2c, Delay, Uart, UsbDriver> Peripherals<I2c, Delay, Uart, UsbDriver> {
+impl<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	>
+	Peripherals<
+		I2c,
+		Delay,
+		Uart,
+		UsbDriver,
+		Spi,
+		Flash,
+		Battery,
+		Led,
+		Button,
+		Power,
+		Watchdog,
+		I2c2,
+	>
+{
 	#[cfg(all(bbq, feature = "log-usb-serial"))]
-	pub fn bbq_peripheral(self) -> (UsbDriver, Peripherals<I2c, Delay, Uart, ()>) {
+	pub fn bbq_peripheral(
+		self,
+	) -> (
+		UsbDriver,
+		Peripherals<
+			I2c,
+			Delay,
+			Uart,
+			(),
+			Spi,
+			Flash,
+			Battery,
+			Led,
+			Button,
+			Power,
+			Watchdog,
+			I2c2,
+		>,
+	) {
 		(
 			self.usb_driver,
 			Peripherals {
@@ -82,11 +568,37 @@ impl<I2c, Delay, Uart, UsbDriver> Peripherals<I2c, Delay, Uart, UsbDriver> {
 				delay: self.delay,
 				uart: self.uart,
 				usb_driver: (),
+				spi: self.spi,
+				flash: self.flash,
+				battery: self.battery,
+				led: self.led,
+				button: self.button,
+				power: self.power,
+				watchdog: self.watchdog,
+				i2c2: self.i2c2,
 			},
 		)
 	}
 	#[cfg(all(bbq, feature = "log-uart"))]
-	pub fn bbq_peripheral(self) -> (Uart, Peripherals<I2c, Delay, (), UsbDriver>) {
+	pub fn bbq_peripheral(
+		self,
+	) -> (
+		Uart,
+		Peripherals<
+			I2c,
+			Delay,
+			(),
+			UsbDriver,
+			Spi,
+			Flash,
+			Battery,
+			Led,
+			Button,
+			Power,
+			Watchdog,
+			I2c2,
+		>,
+	) {
 		(
 			self.uart,
 			Peripherals {
@@ -94,11 +606,37 @@ impl<I2c, Delay, Uart, UsbDriver> Peripherals<I2c, Delay, Uart, UsbDriver> {
 				delay: self.delay,
 				uart: (),
 				usb_driver: self.usb_driver,
+				spi: self.spi,
+				flash: self.flash,
+				battery: self.battery,
+				led: self.led,
+				button: self.button,
+				power: self.power,
+				watchdog: self.watchdog,
+				i2c2: self.i2c2,
 			},
 		)
 	}
 	#[cfg(not(bbq))]
-	pub fn bbq_peripheral(self) -> ((), Peripherals<I2c, Delay, Uart, UsbDriver>) {
+	pub fn bbq_peripheral(
+		self,
+	) -> (
+		(),
+		Peripherals<
+			I2c,
+			Delay,
+			Uart,
+			UsbDriver,
+			Spi,
+			Flash,
+			Battery,
+			Led,
+			Button,
+			Power,
+			Watchdog,
+			I2c2,
+		>,
+	) {
 		((), self)
 	}
 }
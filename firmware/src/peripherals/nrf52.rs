@@ -1,5 +1,6 @@
 use super::Peripherals;
 use crate::aliases::ඞ::DelayConcrete;
+use crate::aliases::ඞ::FlashConcrete;
 use crate::aliases::ඞ::I2cConcrete;
 use crate::aliases::ඞ::UartConcrete;
 use crate::aliases::ඞ::UsbDriverConcrete;
@@ -8,8 +9,29 @@ use defmt::debug;
 use embassy_nrf::interrupt;
 use embassy_nrf::twim::{self, Twim};
 use embassy_nrf::uarte::{self, Uarte};
+#[cfg(all(feature = "mcu-nrf52832", feature = "deep-sleep"))]
+use nrf52832_pac as pac;
+#[cfg(feature = "mcu-nrf52840")]
+use nrf52840_pac as pac;
 use paste::paste;
 
+#[cfg(not(feature = "status-led"))]
+type Led = ();
+#[cfg(feature = "status-led")]
+pub type Led = embassy_nrf::gpio::Output<'static, embassy_nrf::gpio::AnyPin>;
+#[cfg(not(feature = "deep-sleep"))]
+type Power = ();
+#[cfg(not(feature = "watchdog"))]
+type Watchdog = ();
+
+/// The TWIM only supports a few clock speeds, so anything else fails to compile.
+const TWIM_FREQUENCY: twim::Frequency = match super::I2C_KHZ {
+	100 => twim::Frequency::K100,
+	250 => twim::Frequency::K250,
+	400 => twim::Frequency::K400,
+	_ => panic!("the nrf52 only supports an I2C clock of 100, 250 or 400kHz"),
+};
+
 macro_rules! map_pin {
 	($io: ident, $pin: expr) => {
 		paste! {
@@ -23,6 +45,13 @@ pub fn get_peripherals() -> Peripherals<
 	DelayConcrete,
 	UartConcrete<'static>,
 	UsbDriverConcrete<'static>,
+	(),
+	FlashConcrete<'static>,
+	(),
+	Led,
+	(),
+	Power,
+	Watchdog,
 > {
 	let p = embassy_nrf::init(Default::default());
 
@@ -30,7 +59,6 @@ pub fn get_peripherals() -> Peripherals<
 	#[cfg(feature = "mcu-nrf52840")] // TODO: Add nrf52832 support
 	{
 		use defmt::{error, info};
-		use nrf52840_pac as pac;
 
 		let code = {
 			// Safety: should not have any references held elsewhere
@@ -104,16 +132,18 @@ pub fn get_peripherals() -> Peripherals<
 		}
 	}
 
+	let irqs = Irqs::take();
+
 	debug!("Initializing TWIM (I2C controller)");
 
 	// IDK how this works, code is from here:
 	// https://github.com/embassy-rs/embassy/blob/f109e73c6d7ef2ad93102b7c8223f5cef30ef36f/examples/nrf/src/bin/twim.rs
 	let twim = {
-		let config = twim::Config::default();
-		let irq = interrupt::take!(SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0);
+		let mut config = twim::Config::default();
+		config.frequency = TWIM_FREQUENCY;
 		Twim::new(
 			p.TWISPI0,
-			irq,
+			irqs.twim,
 			map_pin!(p, env!("PIN_SDA")),
 			map_pin!(p, env!("PIN_SCL")),
 			config,
@@ -125,14 +155,13 @@ pub fn get_peripherals() -> Peripherals<
 	debug!("Initialized delay");
 
 	let uarte = {
-		let irq = interrupt::take!(UARTE0_UART0);
 		let mut config = uarte::Config::default();
 		config.parity = uarte::Parity::EXCLUDED;
 		config.baudrate = uarte::Baudrate::BAUD115200;
 		let tx = map_pin!(p, env!("PIN_TX"));
 		let rx = map_pin!(p, env!("PIN_RX"));
 
-		Uarte::new(p.UARTE0, irq, rx, tx, config)
+		Uarte::new(p.UARTE0, irqs.uarte, rx, tx, config)
 	};
 	debug!("Initialized uarte");
 
@@ -141,13 +170,161 @@ pub fn get_peripherals() -> Peripherals<
 	#[cfg(feature = "mcu-nrf52840")]
 	let usb_driver = {
 		use embassy_nrf::usb::{self, Driver};
-		let irq = interrupt::take!(USBD);
-		let power_irq = interrupt::take!(POWER_CLOCK);
-		let d = Driver::new(p.USBD, irq, usb::PowerUsb::new(power_irq));
+		let d = Driver::new(p.USBD, irqs.usbd, usb::PowerUsb::new(irqs.power));
 		debug!("Initialized usb_driver");
 		d
 	};
 
+	let flash = embassy_nrf::nvmc::Nvmc::new(p.NVMC);
+	debug!("Initialized nvmc");
+
+	#[cfg(not(feature = "status-led"))]
+	let led = ();
+	#[cfg(feature = "status-led")]
+	let led = {
+		use embassy_nrf::gpio::{Level, Output, OutputDrive, Pin};
+		let pin = map_pin!(p, env!("PIN_LED")).degrade();
+		Output::new(pin, Level::Low, OutputDrive::Standard)
+	};
+	debug!("Initialized led");
+
+	#[cfg(not(feature = "deep-sleep"))]
+	let power = ();
+	#[cfg(feature = "deep-sleep")]
+	let power = {
+		use embassy_nrf::gpio::Pin;
+		Power {
+			wake_pin: map_pin!(p, env!("PIN_INT0")).degrade(),
+		}
+	};
+
+	#[cfg(not(feature = "watchdog"))]
+	let watchdog = ();
+	#[cfg(feature = "watchdog")]
+	let watchdog = Watchdog {
+		wdt: Some(p.WDT),
+		handle: None,
+	};
+
 	let p = Peripherals::new();
-	p.i2c(twim).delay(delay).uart(uarte).usb_driver(usb_driver)
+	p.i2c(twim)
+		.delay(delay)
+		.uart(uarte)
+		.usb_driver(usb_driver)
+		.flash(flash)
+		.led(led)
+		.power(power)
+		.watchdog(watchdog)
+}
+
+/// Sleeps in System OFF, the lowest power mode, with the IMU interrupt line set up to
+/// wake us.
+#[cfg(feature = "deep-sleep")]
+pub struct Power {
+	wake_pin: embassy_nrf::gpio::AnyPin,
+}
+#[cfg(feature = "deep-sleep")]
+impl crate::peripherals::power::PowerManager for Power {
+	fn woke_from_sleep(&mut self) -> bool {
+		/// RESETREAS bit that is set when waking from System OFF by a GPIO.
+		const RESETREAS_OFF: u32 = 1 << 16;
+
+		// Safety: only read and cleared here, before anything else cares
+		let power = unsafe { &*pac::POWER::ptr() };
+		let woke = power.resetreas.read().bits() & RESETREAS_OFF != 0;
+		// The bits are sticky, so clear it to tell apart the next reset
+		power.resetreas.write(|w| unsafe { w.bits(RESETREAS_OFF) });
+		woke
+	}
+
+	fn deep_sleep(&mut self) -> ! {
+		use embassy_nrf::gpio::{Pin, Port};
+
+		let port = match self.wake_pin.port() {
+			Port::Port0 => pac::P0::ptr(),
+			#[cfg(feature = "mcu-nrf52840")]
+			Port::Port1 => pac::P1::ptr(),
+		};
+		// Safety: we own the pin, and nothing runs after this
+		unsafe {
+			// The IMU drives the line high on motion, and SENSE turns that into a
+			// wakeup from System OFF
+			(*port).pin_cnf[self.wake_pin.pin() as usize].write(|w| {
+				w.dir().input();
+				w.input().connect();
+				w.pull().disabled();
+				w.sense().high()
+			});
+			(*pac::POWER::ptr())
+				.systemoff
+				.write(|w| w.systemoff().enter());
+		}
+		// Entering System OFF can take a moment, and wakes up through a reset
+		loop {
+			cortex_m::asm::wfe();
+		}
+	}
+
+	fn reset(&mut self) -> ! {
+		cortex_m::peripheral::SCB::sys_reset()
+	}
+}
+
+/// The WDT, which can't be configured anymore once it runs, so it is only set up when
+/// it is started.
+#[cfg(feature = "watchdog")]
+pub struct Watchdog {
+	/// The peripheral, until the watchdog is started.
+	wdt: Option<embassy_nrf::peripherals::WDT>,
+	handle: Option<embassy_nrf::wdt::WatchdogHandle>,
+}
+#[cfg(feature = "watchdog")]
+impl crate::peripherals::watchdog::Watchdog for Watchdog {
+	fn start(&mut self, timeout: embassy_time::Duration) {
+		use embassy_nrf::wdt;
+
+		let Some(peripheral) = self.wdt.take() else { return };
+		let mut config = wdt::Config::default();
+		// The WDT counts at 32768Hz
+		config.timeout_ticks = (timeout.as_micros() * 32768 / 1_000_000) as u32;
+		// Waiting for an interrupt is not an excuse to stop feeding it
+		config.run_during_sleep = true;
+		// Don't reset while halted by a debugger
+		config.run_during_debug_halt = false;
+		match wdt::Watchdog::try_new(peripheral, config) {
+			Ok((_wdt, [handle])) => self.handle = Some(handle),
+			// The bootloader may have started it already, which we can't undo
+			Err(_) => defmt::warn!("The WDT is already running, can't start it"),
+		}
+	}
+
+	fn feed(&mut self) {
+		if let Some(handle) = &mut self.handle {
+			handle.pet();
+		}
+	}
+}
+
+/// The interrupts used by the peripherals. embassy-nrf wants each interrupt taken as a
+/// token and handed to its driver, and each can only be taken once, so they are all
+/// taken here where it is easy to see which ones are in use.
+struct Irqs {
+	twim: interrupt::SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0,
+	uarte: interrupt::UARTE0_UART0,
+	#[cfg(feature = "mcu-nrf52840")]
+	usbd: interrupt::USBD,
+	#[cfg(feature = "mcu-nrf52840")]
+	power: interrupt::POWER_CLOCK,
+}
+impl Irqs {
+	fn take() -> Self {
+		Self {
+			twim: interrupt::take!(SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0),
+			uarte: interrupt::take!(UARTE0_UART0),
+			#[cfg(feature = "mcu-nrf52840")]
+			usbd: interrupt::take!(USBD),
+			#[cfg(feature = "mcu-nrf52840")]
+			power: interrupt::take!(POWER_CLOCK),
+		}
+	}
 }
@@ -0,0 +1,192 @@
+//! A/B firmware slots, as understood by the ESP-IDF second stage bootloader.
+//!
+//! The bootloader boots the slot named by the newest valid entry in the `otadata`
+//! partition. A new image is written to the slot that isn't running, and only once it
+//! has been verified do we add an entry for it. Until then, and if anything goes wrong
+//! on the way, the running slot stays the one that gets booted.
+
+use defmt::{debug, info};
+use embedded_storage::nor_flash::NorFlash;
+use firmware_core::crc::Crc32;
+
+use crate::aliases::ඞ::{OTA_DATA_OFFSET, OTA_SLOTS, OTA_SLOT_SIZE};
+
+/// Size of a sector of the `otadata` partition, which holds one entry at its start.
+const SECTOR_SIZE: u32 = 0x1000;
+/// `ota_seq`, `seq_label`, `ota_state` and `crc` of an `esp_ota_select_entry_t`.
+const ENTRY_LEN: usize = 32;
+/// `ESP_OTA_IMG_NEW`. The bootloader ignores the state unless rollback is enabled.
+const STATE_NEW: u32 = 0;
+/// First byte of every ESP app image.
+pub const IMAGE_MAGIC: u8 = 0xE9;
+
+#[derive(defmt::Format, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OtaError<E> {
+	Flash(E),
+	/// The image doesn't fit in a slot.
+	TooLarge,
+	/// Data was written out of order, or not aligned to the flash's write size.
+	BadWrite,
+	/// The image in flash doesn't match the expected CRC or isn't an app image.
+	Corrupted,
+}
+impl<E> From<E> for OtaError<E> {
+	fn from(e: E) -> Self {
+		Self::Flash(e)
+	}
+}
+
+/// A valid entry of the `otadata` partition.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Entry {
+	seq: u32,
+	/// Which of the two sectors the entry is in.
+	sector: u32,
+}
+impl Entry {
+	/// The slot that the bootloader boots because of this entry.
+	fn slot(&self) -> usize {
+		(self.seq as usize - 1) % OTA_SLOTS.len()
+	}
+}
+
+/// Writes a new image to the inactive slot.
+pub struct OtaWriter<F: NorFlash> {
+	flash: F,
+	/// Which of [`OTA_SLOTS`] we are writing to.
+	slot: usize,
+	/// Size of the whole image.
+	size: u32,
+	/// How much of the image has been written so far.
+	written: u32,
+	/// How much of the slot has been erased so far.
+	erased: u32,
+}
+impl<F: NorFlash> OtaWriter<F> {
+	/// Prepares to write an image of `size` bytes to the slot that isn't running.
+	pub fn new(mut flash: F, size: u32) -> Result<Self, OtaError<F::Error>> {
+		if size == 0 || size > OTA_SLOT_SIZE {
+			return Err(OtaError::TooLarge);
+		}
+		let running = newest_entry(&mut flash)?.map_or(0, |e| e.slot());
+		let slot = (running + 1) % OTA_SLOTS.len();
+		info!("Running from slot {}, updating slot {}", running, slot);
+		Ok(Self {
+			flash,
+			slot,
+			size,
+			written: 0,
+			erased: 0,
+		})
+	}
+
+	/// How much of the image has been written so far.
+	pub fn written(&self) -> u32 {
+		self.written
+	}
+
+	/// Appends `data` to the image. All writes except the last one must be a multiple
+	/// of the flash's write size.
+	pub fn write(&mut self, data: &[u8]) -> Result<(), OtaError<F::Error>> {
+		let end = self.written + data.len() as u32;
+		if end > self.size || self.written % F::WRITE_SIZE as u32 != 0 {
+			return Err(OtaError::BadWrite);
+		}
+		let base = OTA_SLOTS[self.slot];
+
+		// Erase sectors just before we need them, instead of the whole slot up front
+		while self.erased < end {
+			let sector = base + self.erased;
+			self.flash.erase(sector, sector + F::ERASE_SIZE as u32)?;
+			self.erased += F::ERASE_SIZE as u32;
+		}
+
+		let aligned = data.len() / F::WRITE_SIZE * F::WRITE_SIZE;
+		self.flash.write(base + self.written, &data[..aligned])?;
+		// Pad the tail of the last write, erased flash reads as 0xFF anyway
+		let tail = &data[aligned..];
+		if !tail.is_empty() {
+			let mut padded = [0xFF; 16];
+			let padded = &mut padded[..F::WRITE_SIZE];
+			padded[..tail.len()].copy_from_slice(tail);
+			self.flash
+				.write(base + self.written + aligned as u32, padded)?;
+		}
+		self.written = end;
+		Ok(())
+	}
+
+	/// Checks the written image against `crc32`, and makes the bootloader boot it on
+	/// the next reset.
+	pub fn finish(mut self, crc32: u32) -> Result<(), OtaError<F::Error>> {
+		if self.written != self.size {
+			return Err(OtaError::BadWrite);
+		}
+		let base = OTA_SLOTS[self.slot];
+		let mut buf = [0; 256];
+		let mut crc = Crc32::new();
+		let mut pos = 0;
+		while pos < self.size {
+			let len = (self.size - pos).min(buf.len() as u32) as usize;
+			self.flash.read(base + pos, &mut buf[..len])?;
+			if pos == 0 && buf[0] != IMAGE_MAGIC {
+				return Err(OtaError::Corrupted);
+			}
+			crc.update(&buf[..len]);
+			pos += len as u32;
+		}
+		if crc.finish() != crc32 {
+			return Err(OtaError::Corrupted);
+		}
+		debug!("Image in slot {} verified", self.slot);
+
+		// The new entry goes in the sector that doesn't hold the newest one, so that a
+		// power loss while writing it leaves the old entry intact
+		let newest = newest_entry(&mut self.flash)?;
+		let mut seq = newest.map_or(1, |e| e.seq + 1);
+		while (seq as usize - 1) % OTA_SLOTS.len() != self.slot {
+			seq += 1;
+		}
+		let sector = newest.map_or(0, |e| 1 - e.sector);
+		let offset = OTA_DATA_OFFSET + sector * SECTOR_SIZE;
+		self.flash.erase(offset, offset + SECTOR_SIZE)?;
+		self.flash.write(offset, &encode_entry(seq))?;
+		info!("Slot {} will be booted next (seq {})", self.slot, seq);
+		Ok(())
+	}
+}
+
+/// Finds the entry of `otadata` that the bootloader would use.
+fn newest_entry<F: NorFlash>(flash: &mut F) -> Result<Option<Entry>, F::Error> {
+	let mut newest: Option<Entry> = None;
+	for sector in 0..2 {
+		let mut buf = [0; ENTRY_LEN];
+		flash.read(OTA_DATA_OFFSET + sector * SECTOR_SIZE, &mut buf)?;
+		let seq = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+		let crc = u32::from_le_bytes(buf[28..32].try_into().unwrap());
+		if seq == u32::MAX || seq == 0 || crc != entry_crc(seq) {
+			continue;
+		}
+		if newest.map_or(true, |n| seq > n.seq) {
+			newest = Some(Entry { seq, sector });
+		}
+	}
+	Ok(newest)
+}
+
+fn encode_entry(seq: u32) -> [u8; ENTRY_LEN] {
+	// The label is unused, and left erased
+	let mut entry = [0xFF; ENTRY_LEN];
+	entry[0..4].copy_from_slice(&seq.to_le_bytes());
+	entry[24..28].copy_from_slice(&STATE_NEW.to_le_bytes());
+	entry[28..32].copy_from_slice(&entry_crc(seq).to_le_bytes());
+	entry
+}
+
+/// The bootloader checks the sequence number with `crc32_le(UINT32_MAX, seq, 4)` from
+/// the ROM, which doesn't invert the initial value like zlib does.
+fn entry_crc(seq: u32) -> u32 {
+	let mut crc = Crc32::with_state(0);
+	crc.update(&seq.to_le_bytes());
+	crc.finish()
+}
@@ -0,0 +1,15 @@
+//! Puts the MCU into its lowest power state.
+
+/// Controls the power state of the MCU.
+pub trait PowerManager {
+	/// Whether the MCU was started by waking up from [`Self::deep_sleep()`], instead of
+	/// being powered on or reset.
+	fn woke_from_sleep(&mut self) -> bool;
+
+	/// Turns off everything but the wakeup logic. The MCU wakes up when the IMU
+	/// interrupt line goes high, and starts over as if it was reset.
+	fn deep_sleep(&mut self) -> !;
+
+	/// Restarts the MCU.
+	fn reset(&mut self) -> !;
+}
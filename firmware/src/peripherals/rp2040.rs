@@ -0,0 +1,103 @@
+use super::Peripherals;
+use crate::aliases::ඞ::DelayConcrete;
+use crate::aliases::ඞ::FlashConcrete;
+#[cfg(feature = "i2c-secondary")]
+use crate::aliases::ඞ::I2c2Concrete;
+use crate::aliases::ඞ::I2cConcrete;
+use crate::aliases::ඞ::UartConcrete;
+use crate::aliases::ඞ::UsbDriverConcrete;
+
+use defmt::debug;
+use embassy_rp::i2c::{self, I2c};
+use embassy_rp::interrupt;
+use embassy_rp::uart::{self, Uart};
+use paste::paste;
+
+macro_rules! map_pin {
+	($io: ident, $pin: expr) => {
+		paste! {
+			$io.[<PIN_ $pin>]
+		}
+	};
+}
+
+#[cfg(not(feature = "i2c-secondary"))]
+type I2c2 = ();
+#[cfg(feature = "i2c-secondary")]
+type I2c2 = I2c2Concrete<'static>;
+
+pub fn get_peripherals() -> Peripherals<
+	I2cConcrete<'static>,
+	DelayConcrete,
+	UartConcrete<'static>,
+	UsbDriverConcrete<'static>,
+	(),
+	FlashConcrete<'static>,
+	(),
+	(),
+	(),
+	(),
+	(),
+	I2c2,
+> {
+	let p = embassy_rp::init(Default::default());
+
+	debug!("Initializing I2C");
+	let i2c = {
+		let mut config = i2c::Config::default();
+		config.frequency = super::I2C_KHZ * 1000;
+		I2c::new_blocking(
+			p.I2C0,
+			map_pin!(p, env!("PIN_SCL")),
+			map_pin!(p, env!("PIN_SDA")),
+			config,
+		)
+	};
+	debug!("Initialized i2c");
+
+	#[cfg(not(feature = "i2c-secondary"))]
+	let i2c2 = ();
+	#[cfg(feature = "i2c-secondary")]
+	let i2c2 = {
+		let mut config = i2c::Config::default();
+		config.frequency = super::I2C_KHZ * 1000;
+		I2c::new_blocking(
+			p.I2C1,
+			map_pin!(p, env!("PIN_SCL2")),
+			map_pin!(p, env!("PIN_SDA2")),
+			config,
+		)
+	};
+	#[cfg(feature = "i2c-secondary")]
+	debug!("Initialized i2c2");
+
+	let delay = embassy_time::Delay;
+	debug!("Initialized delay");
+
+	let uart = {
+		let mut config = uart::Config::default();
+		config.baudrate = 115200;
+		let tx = map_pin!(p, env!("PIN_TX"));
+		let rx = map_pin!(p, env!("PIN_RX"));
+
+		Uart::new_blocking(p.UART0, tx, rx, config)
+	};
+	debug!("Initialized uart");
+
+	let usb_driver = {
+		let irq = interrupt::take!(USBCTRL_IRQ);
+		embassy_rp::usb::Driver::new(p.USB, irq)
+	};
+	debug!("Initialized usb_driver");
+
+	let flash = FlashConcrete::new(p.FLASH);
+	debug!("Initialized flash");
+
+	let p = Peripherals::new();
+	p.i2c(i2c)
+		.delay(delay)
+		.uart(uart)
+		.usb_driver(usb_driver)
+		.flash(flash)
+		.i2c2(i2c2)
+}
@@ -0,0 +1,18 @@
+//! An LED that shows the state of the tracker.
+
+use embedded_hal::digital::v2::OutputPin;
+
+/// Anything that can be turned on and off to show a blink pattern. A plain GPIO works
+/// out of the box; an addressable LED like a WS2812 can implement this by picking a
+/// color for "on".
+pub trait StatusLed {
+	fn set(&mut self, on: bool);
+}
+
+/// An LED on a GPIO, which is lit when the pin is high.
+impl<P: OutputPin> StatusLed for P {
+	fn set(&mut self, on: bool) {
+		// Failing to blink isn't worth crashing over
+		let _ = if on { self.set_high() } else { self.set_low() };
+	}
+}
@@ -0,0 +1,100 @@
+//! Support for the TCA9548A I2C multiplexer, which lets us connect several IMUs that
+//! share the same address to one I2C bus.
+
+use crate::aliases::I2c;
+
+use core::cell::{Cell, RefCell};
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+/// Default address of the mux, with A0-A2 all pulled low.
+pub const ADDR: u8 = 0x70;
+/// Number of downstream buses that the mux has.
+pub const NUM_CHANNELS: usize = 8;
+
+/// Owns the I2C bus that the mux is on, and hands out a [`MuxChannel`] for each of
+/// its downstream buses.
+///
+/// Devices that are directly on the upstream bus will be visible from every channel.
+pub struct Tca9548a<I: I2c> {
+	bus: RefCell<I>,
+	/// The channel that the mux is currently switched to, or `None` if we don't know.
+	selected: Cell<Option<u8>>,
+}
+impl<I: I2c> Tca9548a<I> {
+	pub fn new(bus: I) -> Self {
+		Self {
+			bus: RefCell::new(bus),
+			selected: Cell::new(None),
+		}
+	}
+
+	/// Gets an [`I2c`] for the downstream bus `channel`.
+	///
+	/// # Panics
+	/// Panics if `channel` is not less than [`NUM_CHANNELS`].
+	pub fn channel(&self, channel: u8) -> MuxChannel<'_, I> {
+		assert!(usize::from(channel) < NUM_CHANNELS, "invalid mux channel");
+		MuxChannel { mux: self, channel }
+	}
+
+	/// Switches the mux to `channel` if it isn't already, then runs `f` on the bus.
+	fn with_channel<T>(
+		&self,
+		channel: u8,
+		f: impl FnOnce(&mut I) -> Result<T, <I as I2c>::Error>,
+	) -> Result<T, <I as I2c>::Error> {
+		let mut bus = self.bus.borrow_mut();
+		if self.selected.get() != Some(channel) {
+			// Forget the channel first, so that if the write fails we select it again
+			// next time instead of assuming it worked.
+			self.selected.set(None);
+			bus.write(ADDR, &[1 << channel])?;
+			self.selected.set(Some(channel));
+		}
+		f(&mut bus)
+	}
+}
+
+/// One of the downstream buses of a [`Tca9548a`].
+pub struct MuxChannel<'a, I: I2c> {
+	mux: &'a Tca9548a<I>,
+	channel: u8,
+}
+impl<I: I2c> MuxChannel<'_, I> {
+	#[allow(dead_code)]
+	pub fn channel(&self) -> u8 {
+		self.channel
+	}
+}
+
+impl<I: I2c> Write for MuxChannel<'_, I> {
+	type Error = <I as I2c>::Error;
+
+	fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+		self.mux
+			.with_channel(self.channel, |bus| bus.write(address, bytes))
+	}
+}
+
+impl<I: I2c> WriteRead for MuxChannel<'_, I> {
+	type Error = <I as I2c>::Error;
+
+	fn write_read(
+		&mut self,
+		address: u8,
+		bytes: &[u8],
+		buffer: &mut [u8],
+	) -> Result<(), Self::Error> {
+		self.mux
+			.with_channel(self.channel, |bus| bus.write_read(address, bytes, buffer))
+	}
+}
+
+impl<I: I2c> Read for MuxChannel<'_, I> {
+	type Error = <I as I2c>::Error;
+
+	fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+		self.mux
+			.with_channel(self.channel, |bus| bus.read(address, buffer))
+	}
+}
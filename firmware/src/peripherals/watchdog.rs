@@ -0,0 +1,13 @@
+//! The hardware watchdog, which resets the MCU unless it is fed in time.
+
+use embassy_time::Duration;
+
+/// A hardware watchdog. It stays off until [`Self::start()`] is called.
+pub trait Watchdog {
+	/// Starts the watchdog, so that the MCU resets unless [`Self::feed()`] is called
+	/// at least every `timeout`. Once started it can't be stopped.
+	fn start(&mut self, timeout: Duration);
+
+	/// Restarts the countdown to the reset.
+	fn feed(&mut self);
+}
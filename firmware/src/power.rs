@@ -0,0 +1,101 @@
+//! Sends the tracker to deep sleep when it has been set down, to save the battery.
+//!
+//! The IMU task watches for stillness with [`Stillness`]. Once every IMU has been still
+//! for [`SLEEP_AFTER`], it arms their wake-on-motion and signals [`SLEEP`], and the
+//! power task turns off the MCU until the IMU interrupt line wakes it up again.
+
+use defmt::{debug, error, info};
+use embassy_executor::task;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::aliases::ඞ::PowerConcrete;
+use crate::imu::Quat;
+use crate::peripherals::power::PowerManager;
+
+/// How long the IMUs have to be still before we go to sleep.
+pub const SLEEP_AFTER: Duration = Duration::from_secs(5 * 60);
+/// The IMUs count as still while they rotate slower than this, in radians per second.
+const STILL_RATE: f32 = 3. * core::f32::consts::PI / 180.;
+/// How often the rotation is checked against [`STILL_RATE`]. Checking every reading
+/// would let slow drift through, and make noise look like motion.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Acceleration that wakes the tracker up, in mg. Lower is more sensitive.
+pub const MOTION_THRESHOLD_MG: u16 = 40;
+/// The longest we may take to notice motion while asleep. Longer saves more power.
+pub const WAKE_LATENCY: Duration = Duration::from_millis(200);
+
+/// Gives the last log lines time to leave before the MCU turns off.
+const LOG_FLUSH_DELAY: Duration = Duration::from_millis(100);
+
+/// Signalled by the IMU task once the tracker should sleep. The value tells whether
+/// wake-on-motion was armed for every IMU.
+pub static SLEEP: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Tracks how long an IMU has been still.
+pub struct Stillness {
+	/// The orientation at the last check.
+	last: Option<(Instant, Quat)>,
+	still_since: Instant,
+}
+impl Stillness {
+	pub fn new() -> Self {
+		Self {
+			last: None,
+			still_since: Instant::now(),
+		}
+	}
+
+	/// Feeds a new orientation of the IMU.
+	pub fn update(&mut self, q: Quat) {
+		let now = Instant::now();
+		let Some((at, last)) = self.last else {
+			self.last = Some((now, q));
+			return;
+		};
+		let elapsed = now - at;
+		if elapsed < CHECK_INTERVAL {
+			return;
+		}
+		let rate = last.angle_to(&q) / (elapsed.as_micros() as f32 / 1_000_000.);
+		if rate > STILL_RATE {
+			self.still_since = now;
+		}
+		self.last = Some((now, q));
+	}
+
+	/// Whether the IMU has been still for at least [`SLEEP_AFTER`].
+	pub fn is_sleepy(&self) -> bool {
+		self.still_since.elapsed() >= SLEEP_AFTER
+	}
+}
+
+/// Puts the MCU to sleep once [`SLEEP`] is signalled.
+#[task]
+pub async fn power_task(power: PowerConcrete) -> ! {
+	power_task_inner(power).await
+}
+
+/// Same as [`power_task()`] but this version's arguments are type erased behind impl
+/// Trait to avoid accidentally accessing concrete behavior.
+async fn power_task_inner(mut power: impl PowerManager) -> ! {
+	debug!("Power task");
+	if power.woke_from_sleep() {
+		info!("Woke up from deep sleep");
+	}
+
+	let armed = SLEEP.wait().await;
+	if !armed {
+		// The IMUs are already half way to sleep, so starting over is all we can do
+		error!("Failed to set up wake-on-motion, restarting instead of sleeping");
+		power.reset();
+	}
+	info!(
+		"No motion for {}s, going to deep sleep",
+		SLEEP_AFTER.as_secs()
+	);
+	Timer::after(LOG_FLUSH_DELAY).await;
+	power.deep_sleep()
+}
@@ -0,0 +1,176 @@
+//! What the tracker is doing, as shown to the user with the status LED.
+//!
+//! Tasks publish what they are doing with [`STATUS`], and the status task decides
+//! which blink pattern that maps to. All the patterns are defined here, so that they
+//! stay consistent.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+
+/// The state of the tracker, updated by the tasks that know about each part of it.
+pub static STATUS: Status = Status::new();
+
+/// Parts of the state of the tracker that are shown on the status LED.
+#[derive(defmt::Format, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Flag {
+	/// We are exchanging packets with the server.
+	Connected,
+	/// There are no WiFi credentials, and we are waiting for them over serial.
+	WaitingForCredentials,
+	/// An IMU is being calibrated, and should be kept still.
+	Calibrating,
+	/// The battery needs charging.
+	LowBattery,
+}
+impl Flag {
+	const fn bit(self) -> u8 {
+		1 << self as u8
+	}
+}
+
+pub struct Status {
+	flags: Mutex<CriticalSectionRawMutex, Cell<u8>>,
+	/// Signalled whenever a flag changes.
+	changed: Signal<CriticalSectionRawMutex, ()>,
+}
+impl Status {
+	pub const fn new() -> Self {
+		Self {
+			flags: Mutex::new(Cell::new(0)),
+			changed: Signal::new(),
+		}
+	}
+
+	/// Sets or clears `flag`.
+	pub fn set(&self, flag: Flag, on: bool) {
+		let changed = self.flags.lock(|flags| {
+			let old = flags.get();
+			let new = if on {
+				old | flag.bit()
+			} else {
+				old & !flag.bit()
+			};
+			flags.set(new);
+			old != new
+		});
+		if changed {
+			self.changed.signal(());
+		}
+	}
+
+	#[allow(dead_code)]
+	pub fn get(&self, flag: Flag) -> bool {
+		self.flags.lock(|flags| flags.get() & flag.bit() != 0)
+	}
+}
+
+#[cfg(feature = "status-led")]
+pub use self::led::status_task;
+
+#[cfg(feature = "status-led")]
+mod led {
+	use super::{Flag, STATUS};
+	use crate::aliases::ඞ::LedConcrete;
+	use crate::peripherals::status_led::StatusLed;
+
+	use defmt::debug;
+	use embassy_executor::task;
+	use embassy_futures::select::select;
+	use embassy_time::{Duration, Timer};
+
+	/// How the LED is driven: `flashes` flashes of `on`, separated by `off`, and then
+	/// `pause` before repeating.
+	#[derive(defmt::Format, Debug, Copy, Clone, Eq, PartialEq)]
+	enum Pattern {
+		Solid,
+		Blink {
+			flashes: u8,
+			on: Duration,
+			off: Duration,
+			pause: Duration,
+		},
+	}
+
+	/// Connected to the server.
+	const CONNECTED: Pattern = Pattern::Solid;
+	/// Slow blink while looking for the server.
+	const SEARCHING: Pattern = Pattern::Blink {
+		flashes: 1,
+		on: Duration::from_millis(500),
+		off: Duration::from_millis(0),
+		pause: Duration::from_millis(500),
+	};
+	/// Fast blink while the IMUs should be kept still.
+	const CALIBRATING: Pattern = Pattern::Blink {
+		flashes: 1,
+		on: Duration::from_millis(100),
+		off: Duration::from_millis(0),
+		pause: Duration::from_millis(100),
+	};
+	/// Two short flashes while waiting for WiFi credentials.
+	const WAITING_FOR_CREDENTIALS: Pattern = Pattern::Blink {
+		flashes: 2,
+		on: Duration::from_millis(100),
+		off: Duration::from_millis(200),
+		pause: Duration::from_millis(1000),
+	};
+	/// Three short flashes when the battery is low.
+	const LOW_BATTERY: Pattern = Pattern::Blink {
+		flashes: 3,
+		on: Duration::from_millis(100),
+		off: Duration::from_millis(200),
+		pause: Duration::from_millis(1500),
+	};
+
+	/// Picks the pattern to show, most important state first.
+	fn pattern() -> Pattern {
+		if STATUS.get(Flag::Calibrating) {
+			CALIBRATING
+		} else if STATUS.get(Flag::WaitingForCredentials) {
+			WAITING_FOR_CREDENTIALS
+		} else if STATUS.get(Flag::LowBattery) {
+			LOW_BATTERY
+		} else if STATUS.get(Flag::Connected) {
+			CONNECTED
+		} else {
+			SEARCHING
+		}
+	}
+
+	/// Shows the state of the tracker on the status LED.
+	#[task]
+	pub async fn status_task(led: LedConcrete) -> ! {
+		status_task_inner(led).await
+	}
+
+	/// Same as [`status_task()`] but this version's arguments are type erased behind
+	/// impl Trait to avoid accidentally accessing concrete behavior.
+	async fn status_task_inner(mut led: impl StatusLed) -> ! {
+		debug!("Status task");
+		loop {
+			let pattern = pattern();
+			debug!("Status LED: {}", pattern);
+			// Start over with the new pattern as soon as something changes
+			select(show(&mut led, pattern), STATUS.changed.wait()).await;
+		}
+	}
+
+	/// Shows `pattern` until cancelled.
+	async fn show(led: &mut impl StatusLed, pattern: Pattern) -> ! {
+		let Pattern::Blink { flashes, on, off, pause } = pattern else {
+			led.set(true);
+			return core::future::pending().await;
+		};
+		loop {
+			for i in 0..flashes {
+				led.set(true);
+				Timer::after(on).await;
+				led.set(false);
+				Timer::after(if i + 1 < flashes { off } else { pause }).await;
+			}
+		}
+	}
+}
@@ -0,0 +1,158 @@
+//! defmt logger over USB serial, for boards with native USB that don't use
+//! `defmt-bbq`.
+//!
+//! Frames are encoded into a bounded [`Pipe`], and [`logger_task()`] sends them to
+//! the USB CDC endpoint. If the host isn't reading, the pipe fills up and new frames
+//! are dropped whole, so logging never waits on the host and the stream stays
+//! decodable.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use defmt::debug;
+use embassy_futures::join::join;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pipe::Pipe;
+use embassy_usb::driver::EndpointError;
+
+const MAX_PACKET_SIZE: u8 = 64; // Largest packet of a full speed bulk endpoint
+/// Bytes of encoded frames waiting for the host
+const BUFFER_SIZE: usize = 1024;
+/// Frames larger than this are dropped
+const MAX_FRAME_SIZE: usize = 256;
+
+static PIPE: Pipe<CriticalSectionRawMutex, BUFFER_SIZE> = Pipe::new();
+/// Frames dropped since the last time the host caught up
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+#[defmt::global_logger]
+struct UsbLogger;
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+static mut CS_RESTORE: critical_section::RestoreState =
+	critical_section::RestoreState::invalid();
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+/// The frame being logged, until it is complete and can go into the pipe whole
+static mut FRAME: heapless::Vec<u8, MAX_FRAME_SIZE> = heapless::Vec::new();
+static mut FRAME_TRUNCATED: bool = false;
+
+fn write_frame(bytes: &[u8]) {
+	// Safety: only called by the logger, inside its critical section
+	unsafe {
+		if FRAME.extend_from_slice(bytes).is_err() {
+			FRAME_TRUNCATED = true;
+		}
+	}
+}
+
+unsafe impl defmt::Logger for UsbLogger {
+	fn acquire() {
+		let restore = unsafe { critical_section::acquire() };
+		if TAKEN.load(Ordering::Relaxed) {
+			panic!("defmt logger taken reentrantly")
+		}
+		TAKEN.store(true, Ordering::Relaxed);
+		// Safety: we are in the critical section, and nothing else touches these
+		unsafe {
+			CS_RESTORE = restore;
+			FRAME.clear();
+			FRAME_TRUNCATED = false;
+			ENCODER.start_frame(write_frame);
+		}
+	}
+
+	unsafe fn flush() {
+		// Frames are sent by `logger_task()`, which we can't wait for here
+	}
+
+	unsafe fn release() {
+		ENCODER.end_frame(write_frame);
+		let fits = !FRAME_TRUNCATED && FRAME.len() <= BUFFER_SIZE - PIPE.len();
+		if fits {
+			let _ = PIPE.try_write(&FRAME);
+		} else {
+			DROPPED.fetch_add(1, Ordering::Relaxed);
+		}
+		TAKEN.store(false, Ordering::Relaxed);
+		critical_section::release(CS_RESTORE);
+	}
+
+	unsafe fn write(bytes: &[u8]) {
+		ENCODER.write(bytes, write_frame);
+	}
+}
+
+pub async fn logger_task(driver: crate::aliases::ඞ::UsbDriverConcrete<'static>) {
+	debug!("USB logger task!");
+
+	let config = {
+		let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
+		config.manufacturer = Some("Ferrous SlimeVR");
+		config.product = Some("rp2040");
+		config.serial_number = Some("6969");
+		config.max_power = 100;
+		config.max_packet_size_0 = MAX_PACKET_SIZE;
+
+		// Required for windows compatiblity.
+		// https://developer.nordicsemi.com/nRF_Connect_SDK/doc/1.9.1/kconfig/CONFIG_CDC_ACM_IAD.html#help
+		config.device_class = 0xEF;
+		config.device_sub_class = 0x02;
+		config.device_protocol = 0x01;
+		config.composite_with_iads = true;
+
+		config
+	};
+
+	let mut device_descriptor = [0; 256];
+	let mut config_descriptor = [0; 256];
+	let mut bos_descriptor = [0; 256];
+	let mut control_buf = [0; 64];
+
+	let mut state = embassy_usb::class::cdc_acm::State::new();
+
+	let mut builder = embassy_usb::Builder::new(
+		driver,
+		config,
+		&mut device_descriptor,
+		&mut config_descriptor,
+		&mut bos_descriptor,
+		&mut control_buf,
+		None,
+	);
+	let mut usb_class = embassy_usb::class::cdc_acm::CdcAcmClass::new(
+		&mut builder,
+		&mut state,
+		MAX_PACKET_SIZE.into(),
+	);
+	let mut usb_device = builder.build();
+	let usb_fut = usb_device.run();
+
+	let write_fut = async {
+		let mut buf = [0; MAX_PACKET_SIZE as usize];
+		loop {
+			usb_class.wait_connection().await;
+			debug!("Established USB serial");
+			loop {
+				let len = PIPE.read(&mut buf).await;
+				match usb_class.write_packet(&buf[..len]).await {
+					Ok(()) => (),
+					// Can't happen, `buf` is one packet
+					Err(EndpointError::BufferOverflow) => (),
+					// The host may get half a frame, but decoding picks up again at
+					// the next one
+					Err(EndpointError::Disabled) => break,
+				}
+				if PIPE.is_empty() {
+					let dropped = DROPPED.swap(0, Ordering::Relaxed);
+					if dropped > 0 {
+						defmt::warn!(
+							"Dropped {} log frames, USB was too slow",
+							dropped
+						);
+					}
+				}
+			}
+		}
+	};
+
+	join(usb_fut, write_fut).await;
+}
@@ -1,5 +1,6 @@
 use embassy_futures::yield_now;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::Duration;
 
 /// Signals are used for concurrently updating values, where we only care about
 /// keeping the latest value around
@@ -44,8 +45,36 @@ pub fn retry<A, T, E>(
 	last_result
 }
 
+/// Exponential backoff with the durations of our timer, see
+/// [`firmware_core::backoff::Backoff`].
+#[allow(dead_code)]
+pub type Backoff = firmware_core::backoff::Backoff<Duration>;
+
+/// Parses a decimal number at compile time, such as a pin number from an env var.
+#[allow(dead_code)]
+pub const fn parse_u8(s: &str) -> u8 {
+	let n = parse_u32(s);
+	assert!(n <= u8::MAX as u32, "number too large");
+	n as u8
+}
+
+/// Parses a decimal number at compile time, such as a frequency from an env var.
+pub const fn parse_u32(s: &str) -> u32 {
+	let bytes = s.as_bytes();
+	assert!(!bytes.is_empty(), "empty number");
+	let mut n: u32 = 0;
+	let mut i = 0;
+	while i < bytes.len() {
+		assert!(bytes[i].is_ascii_digit(), "not a decimal number");
+		n = n * 10 + (bytes[i] - b'0') as u32;
+		i += 1;
+	}
+	n
+}
+
 /// Converts a nb::Result to an async function by looping and yielding to the async
 /// executor.
+#[allow(dead_code)]
 pub async fn nb2a<T, E>(mut f: impl FnMut() -> nb::Result<T, E>) -> Result<T, E> {
 	loop {
 		let v = f();
@@ -0,0 +1,108 @@
+//! Resets the MCU when a task hangs, instead of silently freezing.
+//!
+//! Watched tasks [`Pets::pet()`] [`WATCHDOG`] whenever they make progress. Every
+//! [`PET_INTERVAL`] the watchdog task checks that all of them did since the last check,
+//! and only then feeds the hardware watchdog. If a task stops petting, or blocks the
+//! executor so that the watchdog task doesn't get to run, the MCU resets after
+//! [`TIMEOUT`].
+//!
+//! Tasks that legitimately wait forever, like the network task while it waits for
+//! WiFi credentials, aren't watched. They still trip the watchdog if they block.
+
+use core::cell::Cell;
+
+use defmt::{debug, info, warn};
+use embassy_executor::task;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Timer};
+
+use crate::aliases::ඞ::WatchdogConcrete;
+use crate::peripherals::watchdog::Watchdog;
+
+/// How often the pets are checked and the hardware watchdog is fed.
+pub const PET_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a task may go without petting before the MCU resets.
+///
+/// Some work blocks the executor on purpose, so this has plenty of headroom over the
+/// slowest of it: initializing an IMU again takes around a second, and calibrating
+/// one takes up to 4s at 50Hz. Setting up the IMUs at boot can take much longer with
+/// a mux, so the watchdog only starts once every watched task has petted once.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The tasks that have to pet the watchdog.
+#[derive(defmt::Format, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Task {
+	Imu,
+}
+impl Task {
+	const ALL: [Task; 1] = [Task::Imu];
+
+	const fn bit(self) -> u8 {
+		1 << self as u8
+	}
+}
+
+/// Which tasks have petted the watchdog since the last check.
+pub static WATCHDOG: Pets = Pets::new();
+
+pub struct Pets {
+	petted: Mutex<CriticalSectionRawMutex, Cell<u8>>,
+}
+impl Pets {
+	pub const fn new() -> Self {
+		Self {
+			petted: Mutex::new(Cell::new(0)),
+		}
+	}
+
+	/// Tells the watchdog that `task` is still making progress.
+	pub fn pet(&self, task: Task) {
+		self.petted
+			.lock(|petted| petted.set(petted.get() | task.bit()));
+	}
+
+	/// The tasks that haven't petted since the last call.
+	fn take_missing(&self) -> impl Iterator<Item = Task> {
+		let petted = self.petted.lock(|petted| petted.replace(0));
+		Task::ALL
+			.into_iter()
+			.filter(move |task| petted & task.bit() == 0)
+	}
+}
+
+/// Feeds the hardware watchdog while every watched task keeps petting [`WATCHDOG`].
+#[task]
+pub async fn watchdog_task(watchdog: WatchdogConcrete) -> ! {
+	watchdog_task_inner(watchdog).await
+}
+
+/// Same as [`watchdog_task()`] but this version's arguments are type erased behind
+/// impl Trait to avoid accidentally accessing concrete behavior.
+async fn watchdog_task_inner(mut watchdog: impl Watchdog) -> ! {
+	debug!("Watchdog task");
+	// Wait for the tasks to get through their setup, which may be slow
+	loop {
+		Timer::after(PET_INTERVAL).await;
+		if WATCHDOG.take_missing().next().is_none() {
+			break;
+		}
+	}
+	info!(
+		"Starting the watchdog with a {}s timeout",
+		TIMEOUT.as_secs()
+	);
+	watchdog.start(TIMEOUT);
+
+	loop {
+		Timer::after(PET_INTERVAL).await;
+		let mut all_petted = true;
+		for task in WATCHDOG.take_missing() {
+			warn!("{} task hasn't petted the watchdog", task);
+			all_petted = false;
+		}
+		if all_petted {
+			watchdog.feed();
+		}
+	}
+}
@@ -0,0 +1,73 @@
+use core::ops::Mul;
+
+/// Exponential backoff, for spacing out retries of something that keeps failing. The
+/// delay doubles after each attempt, up to `max`.
+///
+/// `D` is whatever duration the timer of the caller uses, like
+/// [`core::time::Duration`].
+pub struct Backoff<D> {
+	initial: D,
+	max: D,
+	next: D,
+}
+impl<D: Copy + Ord + Mul<u32, Output = D>> Backoff<D> {
+	pub const fn new(initial: D, max: D) -> Self {
+		Self {
+			initial,
+			max,
+			next: initial,
+		}
+	}
+
+	/// Returns how long to wait before the next attempt.
+	pub fn next_delay(&mut self) -> D {
+		let delay = self.next;
+		self.next = (delay * 2).min(self.max);
+		delay
+	}
+
+	/// Goes back to the initial delay, after an attempt succeeded.
+	pub fn reset(&mut self) {
+		self.next = self.initial;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use core::time::Duration;
+
+	#[test]
+	fn backoff_doubles_up_to_max() {
+		let ms = Duration::from_millis;
+		let mut backoff = Backoff::new(ms(250), ms(4000));
+		let delays: [_; 7] = core::array::from_fn(|_| backoff.next_delay());
+		assert_eq!(
+			delays,
+			[
+				ms(250),
+				ms(500),
+				ms(1000),
+				ms(2000),
+				ms(4000),
+				ms(4000),
+				ms(4000)
+			]
+		);
+
+		backoff.reset();
+		assert_eq!(backoff.next_delay(), ms(250));
+		assert_eq!(backoff.next_delay(), ms(500));
+	}
+
+	#[test]
+	fn backoff_caps_uneven_max() {
+		let ms = Duration::from_millis;
+		let mut backoff = Backoff::new(ms(300), ms(1000));
+		assert_eq!(backoff.next_delay(), ms(300));
+		assert_eq!(backoff.next_delay(), ms(600));
+		assert_eq!(backoff.next_delay(), ms(1000));
+		assert_eq!(backoff.next_delay(), ms(1000));
+	}
+}
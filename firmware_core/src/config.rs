@@ -0,0 +1,417 @@
+//! The settings of the tracker that survive reboots, and how they are encoded into a
+//! single flash record.
+//!
+//! The record starts with [`VERSION`] and a CRC-32 of the rest. Records of older
+//! versions are migrated, see [`payload_lens()`]. Storing the record is up to the
+//! firmware.
+
+use firmware_protocol::BodyPart;
+use heapless::String;
+
+use crate::crc::Crc32;
+use crate::fusion::mag::MagCalibration;
+use crate::Vec3;
+
+/// Bumped whenever the layout of the record changes.
+pub const VERSION: u16 = 4;
+/// The version and the CRC-32 of the payload.
+const HEADER_LEN: usize = 2 + 4;
+
+/// Longest SSID allowed by the WiFi standard.
+pub const MAX_SSID_LEN: usize = 32;
+/// Longest WPA2 passphrase.
+pub const MAX_PASSWORD_LEN: usize = 64;
+/// Lowest WiFi transmit power that can be configured, in dBm. Any lower and the
+/// tracker struggles to associate even right next to the access point.
+pub const MIN_TX_POWER: u8 = 8;
+/// Highest transmit power of the ESP radios, in dBm.
+pub const MAX_TX_POWER: u8 = 20;
+
+/// The SSID and the password, each prefixed with its length.
+const WIFI_LEN: usize = 1 + MAX_SSID_LEN + 1 + MAX_PASSWORD_LEN;
+/// A presence flag and the address.
+const SERVER_LEN: usize = 1 + 4;
+/// A presence flag and 3 f32s, for each IMU.
+const BIAS_LEN: usize = 1 + 3 * 4;
+/// A presence flag, and 3 f32s for the offset and 3 for the scale, for each IMU.
+const MAG_LEN: usize = 1 + 6 * 4;
+/// The id of the body part, for each IMU.
+const BODY_PART_LEN: usize = 1;
+/// The transmit power in dBm, or 0 for the maximum.
+const TX_POWER_LEN: usize = 1;
+
+/// Length of the payload of the current version, for `imus` IMUs.
+const fn payload_len(imus: usize) -> usize {
+	WIFI_LEN
+		+ SERVER_LEN
+		+ BIAS_LEN * imus
+		+ MAG_LEN * imus
+		+ BODY_PART_LEN * imus
+		+ TX_POWER_LEN
+}
+
+/// Length of a record of the current version, for `imus` IMUs.
+pub const fn record_len(imus: usize) -> usize {
+	HEADER_LEN + payload_len(imus)
+}
+
+/// Length of the payload of each version, starting from 1, for `imus` IMUs. Each
+/// version only appended fields, so an older payload is read by leaving the fields
+/// that it lacks at their defaults.
+pub const fn payload_lens(imus: usize) -> [usize; VERSION as usize] {
+	[
+		WIFI_LEN + SERVER_LEN + BIAS_LEN * imus,
+		WIFI_LEN + SERVER_LEN + BIAS_LEN * imus + MAG_LEN * imus,
+		payload_len(imus) - TX_POWER_LEN,
+		payload_len(imus),
+	]
+}
+
+/// The settings, for a tracker with up to `IMUS` IMUs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config<const IMUS: usize> {
+	/// The WiFi network to join. Empty until it is provisioned.
+	pub wifi_ssid: String<MAX_SSID_LEN>,
+	pub wifi_password: String<MAX_PASSWORD_LEN>,
+	/// The limit on the WiFi transmit power, in dBm between [`MIN_TX_POWER`] and
+	/// [`MAX_TX_POWER`]. `None` leaves it at the maximum.
+	pub wifi_tx_power: Option<u8>,
+	/// The address of the last server we found.
+	pub server_address: Option<[u8; 4]>,
+	/// The gyroscope bias of each IMU, indexed by sensor id. `None` for absent IMUs and
+	/// for IMUs that calibrate themselves.
+	pub gyro_biases: [Option<Vec3>; IMUS],
+	/// The magnetometer calibration of each IMU, indexed by sensor id. `None` for IMUs
+	/// without a calibrated magnetometer.
+	pub mag_calibrations: [Option<MagCalibration>; IMUS],
+	/// Where each IMU is worn, indexed by sensor id. [`BodyPart::Unassigned`] until
+	/// the user picks one.
+	pub body_parts: [BodyPart; IMUS],
+}
+impl<const IMUS: usize> Default for Config<IMUS> {
+	fn default() -> Self {
+		Self {
+			wifi_ssid: String::new(),
+			wifi_password: String::new(),
+			wifi_tx_power: None,
+			server_address: None,
+			gyro_biases: [None; IMUS],
+			mag_calibrations: [None; IMUS],
+			body_parts: [BodyPart::Unassigned; IMUS],
+		}
+	}
+}
+impl<const IMUS: usize> Config<IMUS> {
+	/// Writes the record of the config to the start of `record`, and returns its
+	/// length, which is [`record_len()`].
+	///
+	/// # Panics
+	/// Panics if `record` is shorter than that.
+	pub fn to_record(&self, record: &mut [u8]) -> usize {
+		let len = record_len(IMUS);
+		let (header, payload) = record[..len].split_at_mut(HEADER_LEN);
+		self.write_payload(payload);
+		header[0..2].copy_from_slice(&VERSION.to_le_bytes());
+		header[2..6].copy_from_slice(&crc32(payload).to_le_bytes());
+		len
+	}
+
+	/// Reads a record written by [`Self::to_record()`], of this version or an older
+	/// one. `record` must be exactly as long as what was written.
+	pub fn from_record(record: &[u8]) -> Result<Self, Invalid> {
+		let version = version(record);
+		let len = usize::from(version)
+			.checked_sub(1)
+			.and_then(|i| payload_lens(IMUS).get(i))
+			.map(|payload_len| HEADER_LEN + payload_len);
+		if len != Some(record.len()) {
+			return Err(Invalid::WrongVersion(version));
+		}
+		let (header, payload) = record.split_at(HEADER_LEN);
+		let crc = u32::from_le_bytes(header[2..6].try_into().unwrap());
+		if crc != crc32(payload) {
+			return Err(Invalid::BadCrc);
+		}
+		Self::read_payload(payload).ok_or(Invalid::BadPayload)
+	}
+
+	fn write_payload(&self, payload: &mut [u8]) {
+		payload.fill(0);
+		let (wifi, rest) = payload.split_at_mut(WIFI_LEN);
+		let (ssid, password) = wifi.split_at_mut(1 + MAX_SSID_LEN);
+		write_str(ssid, &self.wifi_ssid);
+		write_str(password, &self.wifi_password);
+
+		let (server, rest) = rest.split_at_mut(SERVER_LEN);
+		let (biases, rest) = rest.split_at_mut(BIAS_LEN * IMUS);
+		let (mags, rest) = rest.split_at_mut(MAG_LEN * IMUS);
+		let (parts, tx_power) = rest.split_at_mut(BODY_PART_LEN * IMUS);
+		if let Some(address) = self.server_address {
+			server[0] = 1;
+			server[1..].copy_from_slice(&address);
+		}
+
+		for (bias, entry) in self
+			.gyro_biases
+			.iter()
+			.zip(biases.chunks_exact_mut(BIAS_LEN))
+		{
+			let Some(bias) = bias else { continue };
+			entry[0] = 1;
+			write_f32s(&mut entry[1..], bias.iter());
+		}
+
+		for (cal, entry) in self
+			.mag_calibrations
+			.iter()
+			.zip(mags.chunks_exact_mut(MAG_LEN))
+		{
+			let Some(cal) = cal else { continue };
+			entry[0] = 1;
+			write_f32s(&mut entry[1..], cal.offset.iter().chain(&cal.scale));
+		}
+
+		for (&part, entry) in self.body_parts.iter().zip(parts) {
+			*entry = part.into();
+		}
+		tx_power[0] = self.wifi_tx_power.unwrap_or(0);
+	}
+
+	/// Reads a payload written by [`Self::write_payload()`]. The payload of an older
+	/// version ends early, and the sections past its end are left at their defaults.
+	fn read_payload(mut payload: &[u8]) -> Option<Self> {
+		let wifi = split_section(&mut payload, WIFI_LEN);
+		let (ssid, password) = wifi.split_at(1 + MAX_SSID_LEN);
+		let server = split_section(&mut payload, SERVER_LEN);
+		let biases = split_section(&mut payload, BIAS_LEN * IMUS);
+		let mags = split_section(&mut payload, MAG_LEN * IMUS);
+		let parts = split_section(&mut payload, BODY_PART_LEN * IMUS);
+		let tx_power = split_section(&mut payload, TX_POWER_LEN);
+
+		let server_address = match server[0] {
+			0 => None,
+			_ => Some(server[1..].try_into().unwrap()),
+		};
+
+		let mut gyro_biases = [None; IMUS];
+		for (bias, entry) in gyro_biases.iter_mut().zip(biases.chunks_exact(BIAS_LEN)) {
+			if entry[0] == 0 {
+				continue;
+			}
+			*bias = Some(read_vec3(&entry[1..]));
+		}
+
+		let mut mag_calibrations = [None; IMUS];
+		for (cal, entry) in mag_calibrations.iter_mut().zip(mags.chunks_exact(MAG_LEN))
+		{
+			if entry[0] == 0 {
+				continue;
+			}
+			*cal = Some(MagCalibration {
+				offset: read_vec3(&entry[1..]),
+				scale: read_vec3(&entry[1 + 3 * 4..]),
+			});
+		}
+
+		let mut body_parts = [BodyPart::Unassigned; IMUS];
+		for (part, &entry) in body_parts.iter_mut().zip(parts) {
+			*part = entry.into();
+		}
+
+		let wifi_tx_power = match tx_power.first().copied().unwrap_or(0) {
+			0 => None,
+			dbm @ MIN_TX_POWER..=MAX_TX_POWER => Some(dbm),
+			_ => return None,
+		};
+
+		Some(Self {
+			wifi_ssid: read_str(ssid)?,
+			wifi_password: read_str(password)?,
+			wifi_tx_power,
+			server_address,
+			gyro_biases,
+			mag_calibrations,
+			body_parts,
+		})
+	}
+}
+
+/// Why a stored record couldn't be used.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Invalid {
+	/// Nothing was stored. Only the store of the record can tell, so
+	/// [`Config::from_record()`] never returns this.
+	Missing,
+	WrongVersion(u16),
+	BadCrc,
+	BadPayload,
+}
+
+/// Splits the next `len` bytes off the start of `payload`. Returns fewer if the
+/// payload ends before that, which it does for older versions.
+fn split_section<'a>(payload: &mut &'a [u8], len: usize) -> &'a [u8] {
+	let (section, rest) = payload.split_at(len.min(payload.len()));
+	*payload = rest;
+	section
+}
+
+/// Writes `values` into `buf` back to back, as little endian.
+fn write_f32s<'a>(buf: &mut [u8], values: impl Iterator<Item = &'a f32>) {
+	for (v, bytes) in values.zip(buf.chunks_exact_mut(4)) {
+		bytes.copy_from_slice(&v.to_le_bytes());
+	}
+}
+
+/// Reads 3 little endian f32s from the start of `buf`.
+fn read_vec3(buf: &[u8]) -> Vec3 {
+	let axis = |i: usize| f32::from_le_bytes(buf[i * 4..][..4].try_into().unwrap());
+	Vec3::new(axis(0), axis(1), axis(2))
+}
+
+/// Writes `s` into `buf`, prefixed with its length.
+fn write_str(buf: &mut [u8], s: &str) {
+	buf[0] = s.len() as u8;
+	buf[1..][..s.len()].copy_from_slice(s.as_bytes());
+}
+
+/// Reads a string written by [`write_str()`].
+fn read_str<const N: usize>(buf: &[u8]) -> Option<String<N>> {
+	let (len, rest) = buf.split_first()?;
+	let s = core::str::from_utf8(rest.get(..usize::from(*len))?).ok()?;
+	let mut string = String::new();
+	string.push_str(s).ok()?;
+	Some(string)
+}
+
+/// The version at the start of a record, which may be shorter than a current one. 0,
+/// which no version is, if the record is too short to have one.
+fn version(record: &[u8]) -> u16 {
+	match record {
+		[low, high, ..] => u16::from_le_bytes([*low, *high]),
+		_ => 0,
+	}
+}
+
+fn crc32(data: &[u8]) -> u32 {
+	let mut crc = Crc32::new();
+	crc.update(data);
+	crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Enough to check that the fields of each IMU stay apart.
+	const IMUS: usize = 2;
+	const RECORD_LEN: usize = record_len(IMUS);
+
+	fn provisioned() -> Config<IMUS> {
+		let mut config = Config {
+			wifi_ssid: String::from("slimes"),
+			wifi_password: String::from("hunter22"),
+			wifi_tx_power: Some(12),
+			server_address: Some([192, 168, 1, 2]),
+			..Config::default()
+		};
+		config.gyro_biases[0] = Some(Vec3::new(0.01, -0.02, 0.03));
+		config.mag_calibrations[1] = Some(MagCalibration {
+			offset: Vec3::new(10., -20., 5.),
+			scale: Vec3::new(1., 1.1, 0.9),
+		});
+		config.body_parts[0] = BodyPart::Chest;
+		config.body_parts[1] = BodyPart::Hip;
+		config
+	}
+
+	fn encode(config: &Config<IMUS>) -> [u8; RECORD_LEN] {
+		let mut record = [0; RECORD_LEN];
+		assert_eq!(config.to_record(&mut record), RECORD_LEN);
+		record
+	}
+
+	#[test]
+	fn round_trip() {
+		let record = encode(&provisioned());
+		assert_eq!(Config::from_record(&record), Ok(provisioned()));
+
+		let record = encode(&Config::default());
+		assert_eq!(Config::from_record(&record), Ok(Config::default()));
+	}
+
+	/// The record of `config` as an older `version` wrote it, by cutting off the
+	/// fields added since.
+	fn encode_old(version: u16, config: &Config<IMUS>) -> Vec<u8> {
+		let record = encode(config);
+		let payload_len = payload_lens(IMUS)[usize::from(version) - 1];
+		let payload = &record[HEADER_LEN..][..payload_len];
+		let mut old = Vec::new();
+		old.extend_from_slice(&version.to_le_bytes());
+		old.extend_from_slice(&crc32(payload).to_le_bytes());
+		old.extend_from_slice(payload);
+		old
+	}
+
+	#[test]
+	fn migrates_old_versions() {
+		let new = provisioned();
+		// What survives of it in each version, oldest first
+		let v1 = Config {
+			wifi_tx_power: None,
+			mag_calibrations: [None; IMUS],
+			body_parts: [BodyPart::Unassigned; IMUS],
+			..new.clone()
+		};
+		let v2 = Config {
+			mag_calibrations: new.mag_calibrations,
+			..v1.clone()
+		};
+		let v3 = Config {
+			body_parts: new.body_parts,
+			..v2.clone()
+		};
+
+		for (version, expected) in [(1, v1), (2, v2), (3, v3), (VERSION, new)] {
+			let record = encode_old(version, &provisioned());
+			let config = Config::from_record(&record);
+			assert_eq!(config, Ok(expected), "version {version}");
+		}
+	}
+
+	#[test]
+	fn corrupt_record_fails_crc() {
+		let mut record = encode(&provisioned());
+		// Into the SSID
+		record[HEADER_LEN + 1] ^= 0x20;
+		assert_eq!(Config::<IMUS>::from_record(&record), Err(Invalid::BadCrc));
+	}
+
+	#[test]
+	fn unknown_version_is_rejected() {
+		let mut record = encode(&provisioned());
+		record[0..2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+		let invalid = Invalid::WrongVersion(VERSION + 1);
+		assert_eq!(Config::<IMUS>::from_record(&record), Err(invalid));
+
+		// Cut short, so it isn't a record of its version
+		let record = encode(&provisioned());
+		let invalid = Invalid::WrongVersion(VERSION);
+		let short = &record[..RECORD_LEN - 1];
+		assert_eq!(Config::<IMUS>::from_record(short), Err(invalid));
+		assert_eq!(
+			Config::<IMUS>::from_record(&record[..1]),
+			Err(Invalid::WrongVersion(0))
+		);
+	}
+
+	#[test]
+	fn bad_tx_power_is_rejected() {
+		let mut config = provisioned();
+		config.wifi_tx_power = Some(MAX_TX_POWER + 1);
+		let record = encode(&config);
+		assert_eq!(
+			Config::<IMUS>::from_record(&record),
+			Err(Invalid::BadPayload)
+		);
+	}
+}
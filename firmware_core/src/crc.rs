@@ -0,0 +1,53 @@
+/// CRC-32 as used by zlib, computed bit by bit to avoid a lookup table.
+pub struct Crc32 {
+	state: u32,
+}
+impl Crc32 {
+	pub fn new() -> Self {
+		Self::with_state(u32::MAX)
+	}
+
+	/// Starts from `state` instead of the usual all ones, for the variants that
+	/// don't invert the initial value.
+	pub fn with_state(state: u32) -> Self {
+		Self { state }
+	}
+
+	pub fn update(&mut self, data: &[u8]) {
+		for &byte in data {
+			self.state ^= u32::from(byte);
+			for _ in 0..8 {
+				let mask = (self.state & 1).wrapping_neg();
+				self.state = (self.state >> 1) ^ (0xEDB8_8320 & mask);
+			}
+		}
+	}
+
+	pub fn finish(&self) -> u32 {
+		!self.state
+	}
+}
+impl Default for Crc32 {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn check_value() {
+		// The check value of the CRC catalogue, for the ASCII digits 1 to 9
+		let mut crc = Crc32::new();
+		crc.update(b"123456789");
+		assert_eq!(crc.finish(), 0xCBF4_3926);
+
+		// Split up the same
+		let mut crc = Crc32::new();
+		crc.update(b"1234");
+		crc.update(b"56789");
+		assert_eq!(crc.finish(), 0xCBF4_3926);
+	}
+}
@@ -0,0 +1,181 @@
+use crate::fusion::Fusion;
+#[cfg(not(feature = "std"))]
+use crate::Float;
+use crate::{Quat, Vec3};
+
+use nalgebra::{Matrix3x4, Matrix6x4, Quaternion, Vector6};
+
+/// Madgwick's gradient descent orientation filter, using the accelerometer and
+/// gyroscope, and the magnetometer if there is one. See
+/// <https://x-io.co.uk/open-source-imu-and-ahrs-algorithms/>.
+pub struct Madgwick {
+	q: Quat,
+	beta: f32,
+}
+impl Madgwick {
+	/// The gain used by [`Madgwick::default()`]. Higher values trust the accelerometer
+	/// more, which corrects drift faster but lets more noise through.
+	pub const DEFAULT_BETA: f32 = 0.1;
+
+	pub fn new(beta: f32) -> Self {
+		Self {
+			q: Quat::identity(),
+			beta,
+		}
+	}
+}
+impl Default for Madgwick {
+	fn default() -> Self {
+		Self::new(Self::DEFAULT_BETA)
+	}
+}
+
+impl Fusion for Madgwick {
+	fn update(&mut self, gyro: Vec3, accel: Vec3, dt: f32) -> Quat {
+		let q = self.q.into_inner();
+		let (q0, q1, q2, q3) = (q.w, q.i, q.j, q.k);
+
+		// Rate of change of the quaternion, according to the gyroscope
+		let mut q_dot = q * Quaternion::from_imag(gyro) * 0.5;
+
+		// Only correct with the accelerometer if it gives us a direction
+		if let Some(a) = accel.try_normalize(0.) {
+			// Error between where the estimate says gravity is and where the
+			// accelerometer says it is
+			let f = Vec3::new(
+				2. * (q1 * q3 - q0 * q2) - a.x,
+				2. * (q0 * q1 + q2 * q3) - a.y,
+				2. * (0.5 - q1 * q1 - q2 * q2) - a.z,
+			);
+			#[rustfmt::skip]
+			let jacobian = Matrix3x4::new(
+				-2. * q2, 2. * q3, -2. * q0, 2. * q1,
+				2. * q1, 2. * q0, 2. * q3, 2. * q2,
+				0., -4. * q1, -4. * q2, 0.,
+			);
+			// Gradient is in (w, i, j, k) order
+			let gradient = jacobian.transpose() * f;
+			if let Some(s) = gradient.try_normalize(0.) {
+				q_dot -= Quaternion::new(s[0], s[1], s[2], s[3]) * self.beta;
+			}
+		}
+
+		self.q = Quat::from_quaternion(q + q_dot * dt);
+		self.q
+	}
+
+	fn update_gyro(&mut self, gyro: Vec3, dt: f32) -> Quat {
+		let q = self.q.into_inner();
+		let q_dot = q * Quaternion::from_imag(gyro) * 0.5;
+		self.q = Quat::from_quaternion(q + q_dot * dt);
+		self.q
+	}
+
+	fn update_marg(&mut self, gyro: Vec3, accel: Vec3, mag: Vec3, dt: f32) -> Quat {
+		// Both directions are needed to correct the estimate
+		let (Some(a), Some(m)) = (accel.try_normalize(0.), mag.try_normalize(0.)) else {
+			return self.update(gyro, accel, dt);
+		};
+		let q = self.q.into_inner();
+		let (q0, q1, q2, q3) = (q.w, q.i, q.j, q.k);
+
+		// Rate of change of the quaternion, according to the gyroscope
+		let mut q_dot = q * Quaternion::from_imag(gyro) * 0.5;
+
+		// The magnetic field in the earth frame, rotated around the vertical so that
+		// it has no y component. Only inclination matters, the heading is what we
+		// estimate.
+		let h = q * Quaternion::from_imag(m) * q.conjugate();
+		let bx = h.i.hypot(h.j);
+		let bz = h.k;
+
+		// Error between where the estimate says gravity and the magnetic field are,
+		// and where the sensors say they are
+		let f = Vector6::new(
+			2. * (q1 * q3 - q0 * q2) - a.x,
+			2. * (q0 * q1 + q2 * q3) - a.y,
+			2. * (0.5 - q1 * q1 - q2 * q2) - a.z,
+			2. * bx * (0.5 - q2 * q2 - q3 * q3) + 2. * bz * (q1 * q3 - q0 * q2) - m.x,
+			2. * bx * (q1 * q2 - q0 * q3) + 2. * bz * (q0 * q1 + q2 * q3) - m.y,
+			2. * bx * (q0 * q2 + q1 * q3) + 2. * bz * (0.5 - q1 * q1 - q2 * q2) - m.z,
+		);
+		#[rustfmt::skip]
+		let jacobian = Matrix6x4::new(
+			-2. * q2, 2. * q3, -2. * q0, 2. * q1,
+			2. * q1, 2. * q0, 2. * q3, 2. * q2,
+			0., -4. * q1, -4. * q2, 0.,
+			-2. * bz * q2, 2. * bz * q3,
+				-4. * bx * q2 - 2. * bz * q0, -4. * bx * q3 + 2. * bz * q1,
+			-2. * bx * q3 + 2. * bz * q1, 2. * bx * q2 + 2. * bz * q0,
+				2. * bx * q1 + 2. * bz * q3, -2. * bx * q0 + 2. * bz * q2,
+			2. * bx * q2, 2. * bx * q3 - 4. * bz * q1,
+				2. * bx * q0 - 4. * bz * q2, 2. * bx * q1,
+		);
+		// Gradient is in (w, i, j, k) order
+		let gradient = jacobian.transpose() * f;
+		if let Some(s) = gradient.try_normalize(0.) {
+			q_dot -= Quaternion::new(s[0], s[1], s[2], s[3]) * self.beta;
+		}
+
+		self.q = Quat::from_quaternion(q + q_dot * dt);
+		self.q
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The gradient steps have a fixed size, so the estimate keeps hopping around the
+	/// solution by about `beta * dt`.
+	const TOLERANCE: f32 = 5e-3;
+
+	#[test]
+	fn gyro_only_integrates() {
+		let gyro = Vec3::new(0.5, -1., 0.25);
+		let mut madgwick = Madgwick::default();
+		let mut gyro_only = Madgwick::default();
+		let (mut q, mut q_gyro) = (Quat::identity(), Quat::identity());
+		for _ in 0..100 {
+			// Without an accelerometer reading there is nothing to correct with
+			q = madgwick.update(gyro, Vec3::zeros(), 0.01);
+			q_gyro = gyro_only.update_gyro(gyro, 0.01);
+		}
+		let expected = Quat::from_scaled_axis(gyro);
+		assert!(q.angle_to(&expected) < 1e-3, "{q:?} {expected:?}");
+		assert!(q_gyro.angle_to(&expected) < 1e-3, "{q_gyro:?} {expected:?}");
+	}
+
+	/// Only gravity, tilted away from `+Z` in the frame of the sensor
+	#[test]
+	fn gravity_converges_to_z_up() {
+		let measured_up = Vec3::new(0.3, -0.5, 0.8).normalize();
+		let mut madgwick = Madgwick::default();
+		let mut q = Quat::identity();
+		for _ in 0..2000 {
+			q = madgwick.update(Vec3::zeros(), measured_up, 0.01);
+		}
+		let up = q.transform_vector(&measured_up);
+		assert!((up - Vec3::z()).norm() < TOLERANCE, "{up:?}");
+	}
+
+	/// Gravity and the magnetic field pin down the heading too
+	#[test]
+	fn marg_converges_to_orientation() {
+		let orientation = Quat::from_euler_angles(0.3, -0.2, 1.);
+		// Pointing north along `+X` and down into the ground, in µT
+		let field = Vec3::new(20., 0., -45.);
+		let accel = orientation.inverse_transform_vector(&Vec3::z());
+		let mag = orientation.inverse_transform_vector(&field);
+
+		let mut madgwick = Madgwick::default();
+		let mut q = Quat::identity();
+		for _ in 0..3000 {
+			q = madgwick.update_marg(Vec3::zeros(), accel, mag, 0.01);
+		}
+		assert!(
+			q.angle_to(&orientation) < TOLERANCE,
+			"{q:?} {orientation:?}"
+		);
+	}
+}
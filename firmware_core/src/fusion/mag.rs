@@ -0,0 +1,139 @@
+//! Hard and soft iron calibration of magnetometers.
+//!
+//! Metal and magnets close to the magnetometer distort the field it measures. Hard
+//! iron adds a constant offset to every reading, and soft iron stretches the field by
+//! a different amount along each axis. So when the tracker is rotated in every
+//! direction, the readings trace an ellipsoid instead of a sphere around the origin.
+//!
+//! We approximate that ellipsoid as aligned with the axes of the sensor, so it is
+//! described by the minimum and maximum reading on each axis. Those are collected
+//! with a [`MagCollector`] while the user waves the tracker around in a figure eight.
+
+use crate::Vec3;
+
+/// Readings to collect before a calibration can be trusted.
+const MIN_SAMPLES: u32 = 200;
+/// The earth's magnetic field is 25µT to 65µT strong, so if the readings span less
+/// than this (in µT) on any axis, the tracker wasn't rotated enough.
+const MIN_RADIUS_UT: f32 = 10.;
+
+/// Corrects the readings of a magnetometer, see the [module](self) documentation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MagCalibration {
+	/// Center of the ellipsoid, subtracted from readings to remove hard iron.
+	pub offset: Vec3,
+	/// Multiplied with each axis after subtracting `offset`, to squash the ellipsoid
+	/// into a sphere and remove soft iron.
+	pub scale: Vec3,
+}
+impl MagCalibration {
+	pub fn correct(&self, raw: Vec3) -> Vec3 {
+		(raw - self.offset).component_mul(&self.scale)
+	}
+}
+impl Default for MagCalibration {
+	/// Leaves readings unchanged
+	fn default() -> Self {
+		Self {
+			offset: Vec3::zeros(),
+			scale: Vec3::repeat(1.),
+		}
+	}
+}
+
+/// Collects the extremes of the magnetometer readings, to compute a
+/// [`MagCalibration`] from.
+#[derive(Debug, Clone)]
+pub struct MagCollector {
+	min: Vec3,
+	max: Vec3,
+	count: u32,
+}
+impl MagCollector {
+	pub fn new() -> Self {
+		Self {
+			min: Vec3::repeat(f32::INFINITY),
+			max: Vec3::repeat(f32::NEG_INFINITY),
+			count: 0,
+		}
+	}
+
+	/// Adds an uncorrected reading.
+	pub fn add(&mut self, raw: Vec3) {
+		self.min = self.min.inf(&raw);
+		self.max = self.max.sup(&raw);
+		self.count += 1;
+	}
+
+	/// The calibration that turns the readings so far into a sphere, with the average
+	/// radius of the ellipsoid. `None` if the tracker wasn't rotated enough.
+	pub fn finish(&self) -> Option<MagCalibration> {
+		let radii = (self.max - self.min) / 2.;
+		if self.count < MIN_SAMPLES || radii.min() < MIN_RADIUS_UT {
+			return None;
+		}
+		let mean = radii.sum() / 3.;
+		Some(MagCalibration {
+			offset: (self.max + self.min) / 2.,
+			scale: radii.map(|r| mean / r),
+		})
+	}
+}
+impl Default for MagCollector {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Readings of a 50µT field from directions all around the sensor, including
+	/// along each axis, distorted by `offset` and stretched by `stretch`.
+	fn ellipsoid(offset: Vec3, stretch: Vec3) -> impl Iterator<Item = Vec3> {
+		let steps = || (-2..=2).map(|i| i as f32);
+		steps()
+			.flat_map(move |x| steps().map(move |y| (x, y)))
+			.flat_map(move |(x, y)| steps().map(move |z| Vec3::new(x, y, z)))
+			.filter_map(|dir| dir.try_normalize(0.))
+			.map(move |dir| (dir * 50.).component_mul(&stretch) + offset)
+	}
+
+	#[test]
+	fn ellipsoid_becomes_sphere() {
+		let offset = Vec3::new(15., -30., 8.);
+		let stretch = Vec3::new(1.2, 0.8, 1.);
+		let mut collector = MagCollector::new();
+		// Going around a few times, to have enough samples
+		for _ in 0..3 {
+			ellipsoid(offset, stretch).for_each(|raw| collector.add(raw));
+		}
+
+		let cal = collector.finish().unwrap();
+		assert!((cal.offset - offset).norm() < 1e-3, "{cal:?}");
+		for raw in ellipsoid(offset, stretch) {
+			let corrected = cal.correct(raw);
+			// The average of the radii 60, 40 and 50
+			assert!(
+				(corrected.norm() - 50.).abs() < 1e-3,
+				"{raw:?} {corrected:?}"
+			);
+		}
+	}
+
+	#[test]
+	fn needs_enough_rotation() {
+		let mut collector = MagCollector::new();
+		for _ in 0..MIN_SAMPLES {
+			collector.add(Vec3::new(20., 5., -40.));
+		}
+		assert_eq!(collector.finish(), None);
+
+		let mut collector = MagCollector::new();
+		ellipsoid(Vec3::zeros(), Vec3::repeat(1.)).for_each(|raw| collector.add(raw));
+		// Sweeps every axis, but not for long enough
+		assert!(collector.count < MIN_SAMPLES);
+		assert_eq!(collector.finish(), None);
+	}
+}
@@ -0,0 +1,119 @@
+use crate::fusion::Fusion;
+use crate::{Quat, Vec3};
+
+use nalgebra::Quaternion;
+
+/// Mahony's complementary filter, using only the accelerometer and gyroscope. It is
+/// cheaper than [`Madgwick`](super::madgwick::Madgwick), which matters on MCUs
+/// without a good FPU.
+///
+/// The accelerometer error is fed back into the gyroscope through a PI controller,
+/// so the integral term also learns the gyroscope bias. Up is `+Y`, like in the
+/// `conventions` of `skeletal_model`, so a tracker at rest converges to the rotation
+/// that turns the measured gravity onto `+Y`.
+pub struct Mahony {
+	q: Quat,
+	kp: f32,
+	ki: f32,
+	/// Largest magnitude of each component of `integral`, in radians per second.
+	integral_limit: f32,
+	/// Integrated error, which acts as a gyroscope bias correction.
+	integral: Vec3,
+}
+impl Mahony {
+	pub const DEFAULT_KP: f32 = 1.;
+	pub const DEFAULT_KI: f32 = 0.;
+	/// Default for the integral windup clamp. The bias of the gyros we use is well
+	/// below this.
+	pub const DEFAULT_INTEGRAL_LIMIT: f32 = 0.1;
+
+	pub fn new(kp: f32, ki: f32) -> Self {
+		Self {
+			q: Quat::identity(),
+			kp,
+			ki,
+			integral_limit: Self::DEFAULT_INTEGRAL_LIMIT,
+			integral: Vec3::zeros(),
+		}
+	}
+
+	/// Sets the integral windup clamp, in radians per second.
+	pub fn with_integral_limit(self, integral_limit: f32) -> Self {
+		Self {
+			integral_limit,
+			..self
+		}
+	}
+
+	/// Turns the estimate by the corrected angular velocity `gyro` for `dt` seconds.
+	fn integrate(&mut self, gyro: Vec3, dt: f32) -> Quat {
+		let q = self.q.into_inner();
+		let q_dot = q * Quaternion::from_imag(gyro) * 0.5;
+		self.q = Quat::from_quaternion(q + q_dot * dt);
+		self.q
+	}
+}
+impl Default for Mahony {
+	fn default() -> Self {
+		Self::new(Self::DEFAULT_KP, Self::DEFAULT_KI)
+	}
+}
+
+impl Fusion for Mahony {
+	fn update(&mut self, gyro: Vec3, accel: Vec3, dt: f32) -> Quat {
+		let mut gyro = gyro;
+
+		// Only correct with the accelerometer if it gives us a direction
+		if let Some(a) = accel.try_normalize(0.) {
+			// Where the estimate thinks gravity (well, up) is, in the sensor's frame
+			let v = self.q.inverse_transform_vector(&Vec3::y());
+			let error = a.cross(&v);
+
+			if self.ki > 0. {
+				let limit = self.integral_limit;
+				self.integral += error * (self.ki * dt);
+				self.integral = self.integral.map(|x| x.clamp(-limit, limit));
+			} else {
+				self.integral = Vec3::zeros();
+			}
+			gyro += error * self.kp + self.integral;
+		}
+
+		self.integrate(gyro, dt)
+	}
+
+	fn update_gyro(&mut self, gyro: Vec3, dt: f32) -> Quat {
+		// The bias learned so far still applies
+		self.integrate(gyro + self.integral, dt)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Only gravity, tilted away from `+Y` in the frame of the sensor
+	#[test]
+	fn gravity_converges_to_y_up() {
+		let measured_up = Vec3::new(0.3, 0.8, -0.5).normalize();
+		let mut mahony = Mahony::new(Mahony::DEFAULT_KP, 0.1);
+		let mut q = Quat::identity();
+		// The integral term settles in tens of seconds
+		for _ in 0..10_000 {
+			q = mahony.update(Vec3::zeros(), measured_up, 0.01);
+		}
+		let up = q.transform_vector(&measured_up);
+		assert!((up - Vec3::y()).norm() < 1e-3, "{up:?}");
+		// Nothing to learn without a gyroscope bias
+		assert!(mahony.integral.norm() < 1e-3, "{:?}", mahony.integral);
+	}
+
+	#[test]
+	fn level_stays_level() {
+		let mut mahony = Mahony::default();
+		for _ in 0..100 {
+			let q = mahony.update(Vec3::zeros(), Vec3::y(), 0.01);
+			assert!(q.angle() < 1e-6, "{q:?}");
+		}
+	}
+}
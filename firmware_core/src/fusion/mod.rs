@@ -0,0 +1,96 @@
+//! Sensor fusion algorithms, which turn raw IMU readings into an orientation.
+
+pub mod madgwick;
+pub mod mag;
+pub mod mahony;
+pub mod temperature;
+
+#[cfg(not(feature = "std"))]
+use crate::Float;
+use crate::{Quat, Vec3};
+
+/// A sensor fusion algorithm, which estimates orientation from raw IMU readings.
+pub trait Fusion {
+	/// Updates the estimate with a new reading, `dt` seconds after the previous one.
+	///
+	/// `gyro` is in radians per second and `accel` is in g.
+	fn update(&mut self, gyro: Vec3, accel: Vec3, dt: f32) -> Quat;
+
+	/// Same as [`Self::update()`], but also corrects the heading with `mag`, the
+	/// calibrated magnetic field in µT. Algorithms that can't use a magnetometer
+	/// ignore it.
+	fn update_marg(&mut self, gyro: Vec3, accel: Vec3, _mag: Vec3, dt: f32) -> Quat {
+		self.update(gyro, accel, dt)
+	}
+
+	/// Same as [`Self::update()`], but only integrates the gyroscope, for readings
+	/// where the accelerometer can't be trusted.
+	fn update_gyro(&mut self, gyro: Vec3, dt: f32) -> Quat;
+}
+
+/// Whether `accel` tells us where down is. When it reads further than `tolerance_g`
+/// from 1g, it is saturated or the tracker is accelerating hard.
+pub fn accel_is_usable(accel: &Vec3, tolerance_g: f32) -> bool {
+	(accel.norm() - 1.).abs() <= tolerance_g
+}
+
+/// Updates `fusion` with one reading, correcting with the accelerometer and `mag` only
+/// while the accelerometer [is usable](accel_is_usable). Otherwise it relies on the
+/// gyroscope alone.
+pub fn step(
+	fusion: &mut impl Fusion,
+	gyro: Vec3,
+	accel: Vec3,
+	mag: Option<Vec3>,
+	dt: f32,
+	tolerance_g: f32,
+) -> Quat {
+	if !accel_is_usable(&accel, tolerance_g) {
+		return fusion.update_gyro(gyro, dt);
+	}
+	match mag {
+		Some(mag) => fusion.update_marg(gyro, accel, mag, dt),
+		None => fusion.update(gyro, accel, dt),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::fusion::mahony::Mahony;
+
+	/// The default of the firmware
+	const TOLERANCE_G: f32 = 0.5;
+
+	/// Turning at a constant rate while the accelerometer spikes to 4g along `+X`
+	#[test]
+	fn accel_spike_only_integrates_gyro() {
+		let dt = 0.01;
+		let gyro = Vec3::new(0.2, 1., -0.3);
+		let spike = Vec3::x() * 4.;
+		assert!(!accel_is_usable(&spike, TOLERANCE_G));
+		assert!(accel_is_usable(&Vec3::y(), TOLERANCE_G));
+
+		let mut mahony = Mahony::default();
+		let mut ungated = Mahony::default();
+		let mut expected = Quat::identity();
+		let (mut q, mut jerked) = (Quat::identity(), Quat::identity());
+		for _ in 0..50 {
+			q = step(&mut mahony, gyro, spike, None, dt, TOLERANCE_G);
+			jerked = ungated.update(gyro, spike, dt);
+			expected *= Quat::from_scaled_axis(gyro * dt);
+		}
+		assert!(q.angle_to(&expected) < 1e-3, "{q:?} {expected:?}");
+		// Without the check, the estimate is pulled toward the spike
+		assert!(jerked.angle_to(&expected) > 0.05, "{jerked:?}");
+
+		// Correcting again once the accelerometer reads 1g, so the estimate levels out.
+		// Yaw can't be seen from gravity, so only where up ends up is checked.
+		for _ in 0..2000 {
+			q = step(&mut mahony, Vec3::zeros(), Vec3::y(), None, dt, TOLERANCE_G);
+		}
+		let up = q.transform_vector(&Vec3::y());
+		assert!((up - Vec3::y()).norm() < 1e-3, "{up:?}");
+	}
+}
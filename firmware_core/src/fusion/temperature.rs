@@ -0,0 +1,153 @@
+//! Temperature compensation of the gyroscope bias. The bias of MEMS gyros drifts
+//! roughly linearly with temperature, and trackers warm up by several degrees over a
+//! session, which shows up as yaw drift.
+//!
+//! We collect `(temperature, bias)` samples whenever the IMU is at rest, and fit a line
+//! through them for each axis.
+
+#[cfg(not(feature = "std"))]
+use crate::Float;
+use crate::Vec3;
+
+/// How many samples we remember. When full, the sample closest in temperature to the
+/// new one is replaced, so we keep covering the widest range we have seen.
+const MAX_SAMPLES: usize = 8;
+/// Samples closer than this in °C to an existing one replace it instead of being added.
+const MIN_SAMPLE_SPACING: f32 = 0.5;
+/// The samples need to span at least this many °C before we trust the fit.
+const MIN_SPREAD: f32 = 2.;
+
+/// A linear model of the gyroscope bias over temperature.
+#[derive(Debug, Clone)]
+pub struct TempCompensation {
+	samples: [(f32, Vec3); MAX_SAMPLES],
+	len: usize,
+	/// Change of the bias per °C, for each axis. `None` until we have enough samples.
+	slope: Option<Vec3>,
+	/// Bias at 0°C, for each axis.
+	intercept: Vec3,
+}
+impl TempCompensation {
+	pub fn new() -> Self {
+		Self {
+			samples: [(0., Vec3::zeros()); MAX_SAMPLES],
+			len: 0,
+			slope: None,
+			intercept: Vec3::zeros(),
+		}
+	}
+
+	/// Records the `bias` that was measured at rest at `temp` °C and refits the model.
+	pub fn add_sample(&mut self, temp: f32, bias: Vec3) {
+		let nearest = self.samples[..self.len]
+			.iter()
+			.enumerate()
+			.map(|(i, (t, _))| (i, (t - temp).abs()))
+			.min_by(|(_, a), (_, b)| a.total_cmp(b));
+		match nearest {
+			Some((i, dist)) if dist < MIN_SAMPLE_SPACING || self.len == MAX_SAMPLES => {
+				self.samples[i] = (temp, bias);
+			}
+			_ => {
+				self.samples[self.len] = (temp, bias);
+				self.len += 1;
+			}
+		}
+		self.fit();
+	}
+
+	/// The bias at `temp` °C, or `None` if there aren't enough samples yet.
+	pub fn bias_at(&self, temp: f32) -> Option<Vec3> {
+		self.slope.map(|slope| self.intercept + slope * temp)
+	}
+
+	/// Least squares fit of a line through the samples, independently for each axis.
+	fn fit(&mut self) {
+		let samples = &self.samples[..self.len];
+		let (min, max) = samples
+			.iter()
+			.fold((f32::MAX, f32::MIN), |(min, max), (t, _)| {
+				(min.min(*t), max.max(*t))
+			});
+		if self.len < 2 || max - min < MIN_SPREAD {
+			self.slope = None;
+			return;
+		}
+
+		let n = self.len as f32;
+		let mean_temp = samples.iter().map(|(t, _)| t).sum::<f32>() / n;
+		let mean_bias = samples.iter().map(|(_, b)| b).sum::<Vec3>() / n;
+		let mut covariance = Vec3::zeros();
+		let mut variance = 0.;
+		for (t, b) in samples {
+			let dt = t - mean_temp;
+			covariance += (b - mean_bias) * dt;
+			variance += dt * dt;
+		}
+		let slope = covariance / variance;
+		self.intercept = mean_bias - slope * mean_temp;
+		self.slope = Some(slope);
+	}
+}
+impl Default for TempCompensation {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Bias that drifts by `(0.001, -0.002, 0.0005)` rad/s per °C
+	fn bias(temp: f32) -> Vec3 {
+		Vec3::new(0.01, 0.02, -0.01) + Vec3::new(0.001, -0.002, 0.0005) * temp
+	}
+
+	#[test]
+	fn fits_linear_drift() {
+		let mut comp = TempCompensation::new();
+		for temp in [25., 27., 30., 34.] {
+			comp.add_sample(temp, bias(temp));
+		}
+		for temp in [20., 31.5, 40.] {
+			let fitted = comp.bias_at(temp).unwrap();
+			assert!((fitted - bias(temp)).norm() < 1e-5, "{temp} {fitted:?}");
+		}
+	}
+
+	#[test]
+	fn needs_spread() {
+		let mut comp = TempCompensation::new();
+		assert_eq!(comp.bias_at(25.), None);
+		comp.add_sample(25., bias(25.));
+		comp.add_sample(26., bias(26.));
+		assert_eq!(comp.bias_at(25.), None);
+		comp.add_sample(27.5, bias(27.5));
+		assert!(comp.bias_at(25.).is_some());
+	}
+
+	#[test]
+	fn close_samples_replace() {
+		let mut comp = TempCompensation::new();
+		comp.add_sample(25., bias(25.));
+		comp.add_sample(25.2, bias(25.2));
+		assert_eq!(comp.len, 1);
+		assert_eq!(comp.samples[0].0, 25.2);
+	}
+
+	#[test]
+	fn full_keeps_range() {
+		let mut comp = TempCompensation::new();
+		for i in 0..MAX_SAMPLES {
+			let temp = 20. + i as f32;
+			comp.add_sample(temp, bias(temp));
+		}
+		// Replaces the sample at 23°C, so 20°C and 27°C are still there
+		comp.add_sample(23.4, bias(23.4));
+		assert_eq!(comp.len, MAX_SAMPLES);
+		let temps = comp.samples.map(|(t, _)| t);
+		assert!(temps.contains(&20.) && temps.contains(&27.), "{temps:?}");
+		assert!(!temps.contains(&23.) && temps.contains(&23.4), "{temps:?}");
+	}
+}
@@ -0,0 +1,203 @@
+//! How the IMUs are configured.
+
+/// How often the IMUs should give us readings.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SampleRate {
+	Hz50,
+	Hz100,
+	Hz200,
+	Hz500,
+}
+impl SampleRate {
+	pub const fn hz(self) -> u32 {
+		match self {
+			Self::Hz50 => 50,
+			Self::Hz100 => 100,
+			Self::Hz200 => 200,
+			Self::Hz500 => 500,
+		}
+	}
+
+	/// The rate of `hz`, or `None` if it isn't one of the supported rates.
+	pub const fn from_hz(hz: u32) -> Option<Self> {
+		match hz {
+			50 => Some(Self::Hz50),
+			100 => Some(Self::Hz100),
+			200 => Some(Self::Hz200),
+			500 => Some(Self::Hz500),
+			_ => None,
+		}
+	}
+
+	/// Time between readings, in microseconds.
+	pub const fn period_us(self) -> u32 {
+		1_000_000 / self.hz()
+	}
+
+	/// The sample rate divider for an IMU that samples at `base_hz` and outputs
+	/// `base_hz / (1 + div)`. The output is never slower than `self`.
+	pub const fn divider(self, base_hz: u32) -> u8 {
+		(base_hz / self.hz() - 1) as u8
+	}
+}
+
+/// Time between the readings of an IMU that samples at `base_hz` and outputs
+/// `base_hz / (1 + div)`, in microseconds. See [`SampleRate::divider()`].
+pub const fn divided_period_us(base_hz: u32, div: u8) -> u64 {
+	1_000_000 * (div as u64 + 1) / base_hz as u64
+}
+
+/// The full scale range of a gyroscope, in degrees per second. A lower range measures
+/// slow rotations more finely, a higher one doesn't clip fast ones.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GyroRange {
+	Dps250,
+	Dps500,
+	Dps1000,
+	Dps2000,
+}
+impl GyroRange {
+	pub const fn dps(self) -> u32 {
+		match self {
+			Self::Dps250 => 250,
+			Self::Dps500 => 500,
+			Self::Dps1000 => 1000,
+			Self::Dps2000 => 2000,
+		}
+	}
+
+	/// The value of the 2 bit `FS_SEL` field of the InvenSense IMUs.
+	pub const fn fs_sel(self) -> u8 {
+		match self {
+			Self::Dps250 => 0b00,
+			Self::Dps500 => 0b01,
+			Self::Dps1000 => 0b10,
+			Self::Dps2000 => 0b11,
+		}
+	}
+
+	/// Sensitivity of the InvenSense IMUs at this range, from their datasheets.
+	pub const fn lsb_per_dps(self) -> f32 {
+		match self {
+			Self::Dps250 => 131.,
+			Self::Dps500 => 65.5,
+			Self::Dps1000 => 32.8,
+			Self::Dps2000 => 16.4,
+		}
+	}
+}
+
+/// The full scale range of an accelerometer, in g. A lower range measures small
+/// accelerations more finely, a higher one doesn't clip hard impacts.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AccelRange {
+	G2,
+	G4,
+	G8,
+	G16,
+}
+impl AccelRange {
+	pub const fn g(self) -> u32 {
+		match self {
+			Self::G2 => 2,
+			Self::G4 => 4,
+			Self::G8 => 8,
+			Self::G16 => 16,
+		}
+	}
+
+	/// The value of the 2 bit `FS_SEL` field of the InvenSense IMUs.
+	pub const fn fs_sel(self) -> u8 {
+		match self {
+			Self::G2 => 0b00,
+			Self::G4 => 0b01,
+			Self::G8 => 0b10,
+			Self::G16 => 0b11,
+		}
+	}
+
+	/// Sensitivity of the InvenSense IMUs at this range, from their datasheets.
+	pub const fn lsb_per_g(self) -> f32 {
+		32768. / self.g() as f32
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const RATES: [SampleRate; 4] = [
+		SampleRate::Hz50,
+		SampleRate::Hz100,
+		SampleRate::Hz200,
+		SampleRate::Hz500,
+	];
+
+	#[test]
+	fn period_matches_rate() {
+		for rate in RATES {
+			assert_eq!(rate.period_us() * rate.hz(), 1_000_000, "{rate:?}");
+		}
+	}
+
+	#[test]
+	fn divider_never_samples_slower() {
+		// The bases of the MPUs and the ICM-20948
+		for base_hz in [1000, 1125] {
+			for rate in RATES {
+				let output = base_hz / (1 + u32::from(rate.divider(base_hz)));
+				assert!(output >= rate.hz(), "{base_hz} {rate:?} {output}");
+				// Any larger divider would be too slow
+				let slower = base_hz / (2 + u32::from(rate.divider(base_hz)));
+				assert!(slower < rate.hz(), "{base_hz} {rate:?} {slower}");
+			}
+		}
+		assert_eq!(SampleRate::Hz100.divider(1000), 9);
+		assert_eq!(SampleRate::Hz100.divider(1125), 10);
+	}
+
+	#[test]
+	fn from_hz_round_trips() {
+		for rate in RATES {
+			assert_eq!(SampleRate::from_hz(rate.hz()), Some(rate));
+		}
+		assert_eq!(SampleRate::from_hz(0), None);
+		assert_eq!(SampleRate::from_hz(120), None);
+	}
+
+	#[test]
+	fn divided_period_matches_output_rate() {
+		assert_eq!(divided_period_us(1000, 9), 10_000);
+		// 1125Hz / 11 is 102.27Hz
+		assert_eq!(divided_period_us(1125, 10), 9777);
+		assert_eq!(divided_period_us(1000, 0), 1000);
+	}
+
+	#[test]
+	fn full_scale_reading_is_range() {
+		let ranges = [
+			GyroRange::Dps250,
+			GyroRange::Dps500,
+			GyroRange::Dps1000,
+			GyroRange::Dps2000,
+		];
+		for (i, range) in ranges.into_iter().enumerate() {
+			assert_eq!(range.fs_sel(), i as u8);
+			// The datasheet sensitivities are rounded
+			let full_scale = f32::from(i16::MAX) / range.lsb_per_dps();
+			let error = (full_scale - range.dps() as f32).abs() / range.dps() as f32;
+			assert!(error < 0.01, "{range:?} {full_scale}");
+		}
+
+		let ranges = [
+			AccelRange::G2,
+			AccelRange::G4,
+			AccelRange::G8,
+			AccelRange::G16,
+		];
+		for (i, range) in ranges.into_iter().enumerate() {
+			assert_eq!(range.fs_sel(), i as u8);
+			assert_eq!(f32::from(i16::MIN) / range.lsb_per_g(), -(range.g() as f32));
+		}
+	}
+}
@@ -0,0 +1,24 @@
+//! The parts of the [firmware] that don't touch the hardware, such as the sensor
+//! fusion. They live in this crate so that their tests can run on the host, which the
+//! firmware itself can't do.
+//!
+//! [firmware]: https://github.com/SlimeVR/SlimeVR-Rust/tree/main/firmware
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+pub mod backoff;
+pub mod config;
+pub mod crc;
+pub mod fusion;
+pub mod imu;
+pub mod motion;
+pub mod protocol;
+pub mod relative;
+pub mod smoothing;
+
+/// Float math that `core` doesn't have, like `sqrt()`
+#[cfg(not(feature = "std"))]
+pub(crate) use num_traits::Float;
+
+pub type Quat = nalgebra::UnitQuaternion<f32>;
+pub type Vec3 = nalgebra::Vector3<f32>;
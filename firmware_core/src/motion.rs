@@ -0,0 +1,86 @@
+//! Made up motion, for the fake IMU of the firmware.
+
+use nalgebra::Quaternion;
+
+use crate::{Quat, Vec3};
+
+/// How fast [`Motion::YawSweep`] turns, in degrees per second.
+const SWEEP_DEG_PER_SEC: f32 = 30.;
+/// How far [`Motion::Tilt`] is tilted, in degrees.
+const TILT_DEG: f32 = 30.;
+
+/// What a fake IMU pretends to do.
+#[derive(Debug, Copy, Clone)]
+pub enum Motion {
+	/// Lies still without any rotation.
+	Identity,
+	/// Turns around the Z axis, which is up for the IMU, at [`SWEEP_DEG_PER_SEC`].
+	YawSweep,
+	/// Lies still, rotated by [`TILT_DEG`] around the X axis.
+	Tilt,
+	/// Plays back recorded quaternions as `[w, i, j, k]`, one per sample, in a loop.
+	Replay(&'static [[f32; 4]]),
+}
+impl Motion {
+	/// The rotation after `elapsed_us`, with `period_us` between the samples of a
+	/// replay.
+	pub fn at(self, elapsed_us: u64, period_us: u32) -> Quat {
+		match self {
+			Motion::Identity => Quat::identity(),
+			Motion::YawSweep => Quat::from_axis_angle(
+				&Vec3::z_axis(),
+				sweep_yaw(elapsed_us).to_radians(),
+			),
+			Motion::Tilt => {
+				Quat::from_axis_angle(&Vec3::x_axis(), TILT_DEG.to_radians())
+			}
+			Motion::Replay(recording) => {
+				let sample = elapsed_us / u64::from(period_us);
+				let [w, i, j, k] = recording[sample as usize % recording.len()];
+				Quat::from_quaternion(Quaternion::new(w, i, j, k))
+			}
+		}
+	}
+}
+
+/// The yaw of [`Motion::YawSweep`] after `elapsed_us`, in degrees within `0..360`.
+fn sweep_yaw(elapsed_us: u64) -> f32 {
+	/// How long one full turn takes, in microseconds.
+	const TURN_US: u64 = (360. / SWEEP_DEG_PER_SEC * 1_000_000.) as u64;
+	// Whole turns are dropped before converting to f32, so that the angle doesn't get
+	// less precise the longer we run
+	(elapsed_us % TURN_US) as f32 / TURN_US as f32 * 360.
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn yaw_sweep_wraps() {
+		// A full turn takes 12s at 30°/s
+		assert_eq!(sweep_yaw(0), 0.);
+		assert!((sweep_yaw(3_000_000) - 90.).abs() < 1e-3);
+		assert!((sweep_yaw(12_000_000 + 6_000_000) - 180.).abs() < 1e-3);
+		// A day is a whole number of turns, and still as precise as at the start
+		let day_us = 24 * 3600 * 1_000_000;
+		assert_eq!(sweep_yaw(day_us + 1_000), sweep_yaw(1_000));
+
+		let q = Motion::YawSweep.at(3_000_000, 10_000);
+		assert!((q.angle() - 90f32.to_radians()).abs() < 1e-3, "{q:?}");
+	}
+
+	#[test]
+	fn replay_loops_per_sample() {
+		const RECORDING: [[f32; 4]; 2] = [[1., 0., 0., 0.], [0., 0., 0., 1.]];
+		let replay = Motion::Replay(&RECORDING);
+		let period_us = 10_000;
+
+		let turned = Quat::from_quaternion(Quaternion::new(0., 0., 0., 1.));
+		assert_eq!(replay.at(0, period_us), Quat::identity());
+		assert_eq!(replay.at(9_999, period_us), Quat::identity());
+		assert_eq!(replay.at(10_000, period_us), turned);
+		// Back to the start after both samples
+		assert_eq!(replay.at(20_000, period_us), Quat::identity());
+	}
+}
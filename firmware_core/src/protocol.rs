@@ -0,0 +1,40 @@
+//! What the firmware tells the server about the tracker.
+
+use firmware_protocol::ImuType;
+
+/// The sensor id and type of each IMU in `types` that was initialized, which are the
+/// sensors that the handshake announces.
+pub fn advertised_sensors<const N: usize>(
+	types: [Option<ImuType>; N],
+) -> impl Iterator<Item = (u8, ImuType)> {
+	types
+		.into_iter()
+		.enumerate()
+		.filter_map(|(sensor_id, imu_type)| Some((sensor_id as u8, imu_type?)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn advertised<const N: usize>(types: [Option<ImuType>; N]) -> Vec<(u8, ImuType)> {
+		advertised_sensors(types).collect()
+	}
+
+	#[test]
+	fn advertises_initialized_imus() {
+		let types = [Some(ImuType::Bmi160), Some(ImuType::Mpu6050)];
+		let expected = [(0, ImuType::Bmi160), (1, ImuType::Mpu6050)];
+		assert_eq!(advertised(types), expected);
+	}
+
+	#[test]
+	fn skips_imu_that_failed_to_init() {
+		// The sensor ids stay those of the IMU slots
+		let types = [Some(ImuType::Bmi160), None, Some(ImuType::Icm20948)];
+		let expected = [(0, ImuType::Bmi160), (2, ImuType::Icm20948)];
+		assert_eq!(advertised(types), expected);
+
+		assert_eq!(advertised([None, None]), []);
+	}
+}
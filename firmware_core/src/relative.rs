@@ -0,0 +1,72 @@
+//! Optional "relative" output, for trackers on a desk or a chair that tilts with the
+//! user. The fusion corrects pitch and roll towards gravity, so tilting the furniture
+//! shows up as the user tilting. Instead, the rotations are reported relative to a
+//! reference orientation, which is captured from the first reading and again after
+//! [`Relative::rezero()`], such as while the user sits at rest.
+//!
+//! The reference is on the IMU's side, so a reading equal to the reference comes out
+//! as identity, and rotations afterwards are in the frame the IMU had at the time.
+
+use crate::Quat;
+
+/// Makes the rotations of one IMU relative to a reference. See the [module](self)
+/// documentation.
+#[derive(Debug, Copy, Clone)]
+pub struct Relative {
+	reference: Option<Quat>,
+}
+impl Relative {
+	pub const fn new() -> Self {
+		Self { reference: None }
+	}
+
+	/// Returns `q` relative to the reference, taking `q` as the reference if there is
+	/// none yet.
+	pub fn update(&mut self, q: Quat) -> Quat {
+		let reference = *self.reference.get_or_insert(q);
+		reference.inverse() * q
+	}
+
+	/// Forgets the reference, so that the next rotation becomes the new one.
+	pub fn rezero(&mut self) {
+		self.reference = None;
+	}
+}
+impl Default for Relative {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::Vec3;
+
+	#[test]
+	fn first_reading_is_reference() {
+		let mut relative = Relative::new();
+		let tilted = Quat::from_scaled_axis(Vec3::x() * 0.4);
+		assert!(relative.update(tilted).angle() < 1e-6);
+
+		// Turning afterwards is in the frame the IMU had at the reference
+		let turn = Quat::from_scaled_axis(Vec3::z() * 0.3);
+		let q = relative.update(tilted * turn);
+		assert!(q.angle_to(&turn) < 1e-6, "{q:?}");
+	}
+
+	#[test]
+	fn rezero_takes_next_reading() {
+		let mut relative = Relative::new();
+		relative.update(Quat::identity());
+		let tilted = Quat::from_scaled_axis(Vec3::y() * -0.6);
+		assert!(relative.update(tilted).angle_to(&tilted) < 1e-6);
+
+		relative.rezero();
+		assert!(relative.update(tilted).angle() < 1e-6);
+		// The old reference is gone
+		let q = relative.update(Quat::identity());
+		assert!(q.angle_to(&tilted.inverse()) < 1e-6, "{q:?}");
+	}
+}
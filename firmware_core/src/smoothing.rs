@@ -0,0 +1,103 @@
+//! Optional low-pass filter on the rotations that we send, to hide the jitter that
+//! remains after fusion while the tracker is at rest.
+//!
+//! Each new rotation is slerped from the previous output toward the new reading, so
+//! with a factor `a`, the output keeps `a` of the remaining angle after every sample.
+//! A step settles to within 1% after `ln(0.01) / ln(a)` samples, which is 7 samples
+//! for `a = 0.5` and 44 for `a = 0.9`. The cost is that much latency.
+
+use crate::Quat;
+
+/// Smooths the rotations of one IMU. See the [module](self) documentation.
+#[derive(Debug, Copy, Clone)]
+pub struct Smoothing {
+	factor: f32,
+	prev: Option<Quat>,
+}
+impl Smoothing {
+	/// `factor` is how much of the previous output is kept for each sample, from 0
+	/// (no smoothing) up to but not including 1.
+	pub const fn new(factor: f32) -> Self {
+		Self { factor, prev: None }
+	}
+
+	/// Blends `q` into the previous output, and returns the new output. With a factor
+	/// of 0, `q` is returned as it is.
+	pub fn update(&mut self, q: Quat) -> Quat {
+		if self.factor <= 0. {
+			return q;
+		}
+		let out = match self.prev {
+			// Slerp takes the shorter way, even if the IMU flipped the sign of `q`. It
+			// only fails for rotations that are exactly opposite, where there is no
+			// single way to blend them, so we jump instead.
+			Some(prev) => prev.try_slerp(&q, 1. - self.factor, 1e-6).unwrap_or(q),
+			None => q,
+		};
+		self.prev = Some(out);
+		out
+	}
+
+	/// Changes how much of the previous output is kept, from the next sample on.
+	pub fn set_factor(&mut self, factor: f32) {
+		self.factor = factor;
+	}
+
+	/// Forgets the previous output, so that the next rotation is used as it is.
+	pub fn reset(&mut self) {
+		self.prev = None;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::Vec3;
+
+	#[test]
+	fn no_smoothing_passes_through() {
+		let mut smoothing = Smoothing::new(0.);
+		let q = Quat::from_scaled_axis(Vec3::new(0.1, 0.2, 0.3));
+		assert_eq!(smoothing.update(Quat::identity()), Quat::identity());
+		assert_eq!(smoothing.update(q), q);
+	}
+
+	#[test]
+	fn step_settles_as_documented() {
+		let mut smoothing = Smoothing::new(0.5);
+		let target = Quat::from_scaled_axis(Vec3::y() * 1.);
+		smoothing.update(Quat::identity());
+		// Keeps half of the remaining angle every sample
+		let first = smoothing.update(target);
+		assert!((first.angle_to(&target) - 0.5).abs() < 1e-5, "{first:?}");
+		// Within 1% after 7 samples
+		for _ in 2..6 {
+			smoothing.update(target);
+		}
+		let sixth = smoothing.update(target);
+		let seventh = smoothing.update(target);
+		assert!(sixth.angle_to(&target) > 0.01, "{sixth:?}");
+		assert!(seventh.angle_to(&target) < 0.01, "{seventh:?}");
+	}
+
+	#[test]
+	fn reset_forgets_previous() {
+		let mut smoothing = Smoothing::new(0.9);
+		smoothing.update(Quat::identity());
+		smoothing.reset();
+		let q = Quat::from_scaled_axis(Vec3::x() * 2.);
+		assert_eq!(smoothing.update(q), q);
+	}
+
+	#[test]
+	fn flipped_sign_takes_short_way() {
+		let mut smoothing = Smoothing::new(0.5);
+		let q = Quat::from_scaled_axis(Vec3::z() * 0.2);
+		smoothing.update(q);
+		// The same rotation, with the sign of every component flipped
+		let flipped = Quat::new_unchecked(-q.into_inner());
+		let out = smoothing.update(flipped);
+		assert!(out.angle_to(&q) < 1e-5, "{out:?}");
+	}
+}
@@ -0,0 +1,391 @@
+//! Sending several serverbound packets in one datagram.
+//!
+//! Every datagram costs airtime for its headers, so trackers with several IMUs send
+//! the rotations of all of them together. This uses the bundle of the official
+//! protocol: the usual header with tag [`BUNDLE_TAG`], followed by each packet as a
+//! big endian `u16` length and the packet without its sequence number.
+//!
+//! A [`Batcher`] decides which packets go into a bundle, and when it is sent.
+//! [`pack()`] splits them into several bundles if they don't fit into one datagram.
+
+use alloc::vec::Vec;
+use deku::prelude::*;
+
+use crate::{DeserializeError, Packet, SbPacket, SerializeError};
+
+/// The tag of a bundle, in place of the tag of a single packet.
+pub const BUNDLE_TAG: u32 = 100;
+/// Length of the tag and sequence number at the start of a packet.
+const HEADER_LEN: usize = 4 + 8;
+
+/// Several serverbound packets, to be sent in one datagram.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bundle {
+	/// The packets serialized so far, without the header of the bundle.
+	body: Vec<u8>,
+	count: usize,
+}
+impl Bundle {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a packet to the end of the bundle.
+	pub fn push(&mut self, packet: SbPacket) -> Result<(), SerializeError> {
+		self.push_bytes(&Packet::new(0, packet).to_bytes()?)
+	}
+
+	/// Adds a packet that was already serialized with its header.
+	fn push_bytes(&mut self, bytes: &[u8]) -> Result<(), SerializeError> {
+		// Bundled packets leave out the sequence number
+		let len = u16::try_from(bytes.len() - 8)
+			.map_err(|_| SerializeError::BufferTooSmall)?;
+		self.body.extend_from_slice(&len.to_be_bytes());
+		self.body.extend_from_slice(&bytes[..4]);
+		self.body.extend_from_slice(&bytes[HEADER_LEN..]);
+		self.count += 1;
+		Ok(())
+	}
+
+	/// The number of packets in the bundle.
+	pub fn len(&self) -> usize {
+		self.count
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.count == 0
+	}
+
+	/// The number of bytes that [`Self::serialize_into()`] writes.
+	pub fn serialized_len(&self) -> usize {
+		HEADER_LEN + self.body.len()
+	}
+
+	/// Serialize the bundle into a byte slice, returning the number of bytes written.
+	/// Like [`Packet::serialize_into()`], but with the packets of the bundle as data.
+	pub fn serialize_into(
+		&self,
+		seq: u64,
+		buf: &mut [u8],
+	) -> Result<usize, SerializeError> {
+		let len = HEADER_LEN + self.body.len();
+		if len > buf.len() {
+			return Err(SerializeError::BufferTooSmall);
+		}
+		buf[..4].copy_from_slice(&BUNDLE_TAG.to_be_bytes());
+		buf[4..HEADER_LEN].copy_from_slice(&seq.to_be_bytes());
+		buf[HEADER_LEN..len].copy_from_slice(&self.body);
+		Ok(len)
+	}
+
+	/// Splits a serialized bundle into its sequence number and packets.
+	pub fn deserialize_from(
+		buf: &[u8],
+	) -> Result<(u64, Vec<SbPacket>), DeserializeError> {
+		if buf.len() < HEADER_LEN || buf[..4] != BUNDLE_TAG.to_be_bytes() {
+			return Err(DeserializeError::InvalidBundle);
+		}
+		let seq = u64::from_be_bytes(buf[4..HEADER_LEN].try_into().unwrap());
+
+		let mut rest = &buf[HEADER_LEN..];
+		let mut packets = Vec::new();
+		while !rest.is_empty() {
+			let Some(len) = rest.get(..2) else {
+				return Err(DeserializeError::InvalidBundle);
+			};
+			let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+			let Some(packet) = rest.get(2..2 + len).filter(|p| p.len() >= 4) else {
+				return Err(DeserializeError::InvalidBundle);
+			};
+			// Put back the sequence number, so it can be read like any packet
+			let mut bytes = Vec::with_capacity(len + 8);
+			bytes.extend_from_slice(&packet[..4]);
+			bytes.extend_from_slice(&[0; 8]);
+			bytes.extend_from_slice(&packet[4..]);
+			let (_, packet) = Packet::<SbPacket>::deserialize_from(&bytes)?.split();
+			packets.push(packet);
+			rest = &rest[2 + len..];
+		}
+		Ok((seq, packets))
+	}
+}
+
+/// Length that a serialized packet with its header adds to a bundle.
+fn bundled_len(bytes: &[u8]) -> usize {
+	2 + bytes.len() - 8
+}
+
+/// What [`pack()`] made of the packets.
+#[derive(Debug, Default, PartialEq)]
+pub struct Packed {
+	/// The packets that fit, in their order.
+	pub bundles: Vec<Bundle>,
+	/// The packets that are too long to be sent even in a bundle of their own.
+	pub oversized: Vec<SbPacket>,
+}
+
+/// Puts `packets` into bundles in their order, starting a new bundle whenever the next
+/// packet would make the current one longer than `max_len` bytes when serialized.
+///
+/// The protocol has no way to split up a single packet, so any that doesn't fit
+/// into an empty bundle is left out, instead of being sent cut off.
+pub fn pack(
+	packets: impl IntoIterator<Item = SbPacket>,
+	max_len: usize,
+) -> Result<Packed, SerializeError> {
+	let mut packed = Packed::default();
+	let mut bundle = Bundle::new();
+	for packet in packets {
+		let packet = Packet::new(0, packet);
+		let bytes = packet.to_bytes()?;
+		let len = bundled_len(&bytes);
+		if HEADER_LEN + len > max_len {
+			packed.oversized.push(packet.split().1);
+			continue;
+		}
+		if bundle.serialized_len() + len > max_len {
+			packed.bundles.push(core::mem::take(&mut bundle));
+		}
+		bundle.push_bytes(&bytes)?;
+	}
+	if !bundle.is_empty() {
+		packed.bundles.push(bundle);
+	}
+	Ok(packed)
+}
+
+/// Decides when the packets collected by a [`Batcher`] are sent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FlushPolicy {
+	/// Send as soon as this many trackers are waiting to be sent.
+	pub trackers: usize,
+	/// Send at the latest this many microseconds after the oldest packet waiting was
+	/// added, even if not every tracker has something new.
+	pub max_latency_us: u64,
+}
+
+/// Collects the latest packets of each tracker, until its [`FlushPolicy`] says to
+/// send them in one [`Bundle`].
+#[derive(Debug)]
+pub struct Batcher {
+	pub policy: FlushPolicy,
+	/// The packets of each tracker that is waiting, by sensor id.
+	pending: Vec<(u8, Vec<SbPacket>)>,
+	/// When the oldest packet waiting was added, in microseconds.
+	since: Option<u64>,
+}
+impl Batcher {
+	pub fn new(policy: FlushPolicy) -> Self {
+		Self {
+			policy,
+			pending: Vec::new(),
+			since: None,
+		}
+	}
+
+	/// Adds the packets of a new reading of the tracker `sensor_id`, replacing the
+	/// ones of an older reading that is still waiting. `now_us` is the current time,
+	/// in microseconds. Returns whether to [`Self::take()`] the packets now.
+	pub fn add(&mut self, now_us: u64, sensor_id: u8, packets: Vec<SbPacket>) -> bool {
+		match self.pending.iter_mut().find(|(id, _)| *id == sensor_id) {
+			Some((_, waiting)) => *waiting = packets,
+			None => self.pending.push((sensor_id, packets)),
+		}
+		self.since.get_or_insert(now_us);
+		self.pending.len() >= self.policy.trackers || self.is_due(now_us)
+	}
+
+	/// When the packets waiting must be sent at the latest, in microseconds. `None`
+	/// if there are none.
+	pub fn deadline(&self) -> Option<u64> {
+		self.since.map(|since| since + self.policy.max_latency_us)
+	}
+
+	/// Whether the packets waiting must be sent by `now_us`.
+	pub fn is_due(&self, now_us: u64) -> bool {
+		self.deadline().map_or(false, |deadline| now_us >= deadline)
+	}
+
+	/// Returns the packets waiting, ordered by sensor id.
+	pub fn take(&mut self) -> Vec<SbPacket> {
+		self.since = None;
+		let mut pending = core::mem::take(&mut self.pending);
+		pending.sort_by_key(|(id, _)| *id);
+		pending
+			.into_iter()
+			.flat_map(|(_, packets)| packets)
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::{SensorDataType, SlimeQuaternion};
+	use alloc::vec;
+
+	fn rotation(sensor_id: u8, w: f32) -> SbPacket {
+		SbPacket::RotationData {
+			sensor_id,
+			data_type: SensorDataType::Normal,
+			quat: SlimeQuaternion {
+				i: 0.,
+				j: 0.,
+				k: 0.,
+				w,
+			},
+			calibration_info: 0,
+		}
+	}
+
+	#[test]
+	fn bundle_layout() {
+		let mut bundle = Bundle::new();
+		bundle.push(SbPacket::Heartbeat).unwrap();
+		bundle
+			.push(SbPacket::Ping {
+				challenge: [1, 3, 3, 7],
+			})
+			.unwrap();
+		assert_eq!(bundle.len(), 2);
+
+		let mut buf = [0; 64];
+		let len = bundle.serialize_into(5, &mut buf).unwrap();
+		#[rustfmt::skip]
+		let expected = [
+			0, 0, 0, 100, // Tag
+			0, 0, 0, 0, 0, 0, 0, 5, // Sequence
+			0, 4, // Length
+			0, 0, 0, 0, // Heartbeat
+			0, 8, // Length
+			0, 0, 0, 10, // Ping
+			1, 3, 3, 7, // Challenge
+		];
+		assert_eq!(&buf[..len], &expected);
+
+		let (seq, packets) = Bundle::deserialize_from(&buf[..len]).unwrap();
+		assert_eq!(seq, 5);
+		assert_eq!(
+			packets,
+			vec![
+				SbPacket::Heartbeat,
+				SbPacket::Ping {
+					challenge: [1, 3, 3, 7]
+				}
+			]
+		);
+
+		assert!(bundle.serialize_into(5, &mut buf[..len - 1]).is_err());
+		assert_eq!(
+			Bundle::deserialize_from(&buf[..len - 1]),
+			Err(DeserializeError::InvalidBundle)
+		);
+	}
+
+	#[test]
+	fn pack_splits_at_limit() {
+		let packets = || (0..5).map(|i| rotation(i, i as f32));
+		let mut one = Bundle::new();
+		one.push(rotation(0, 0.)).unwrap();
+		let entry = one.serialized_len() - HEADER_LEN;
+		// Room for two rotations, and almost a third
+		let max_len = HEADER_LEN + 3 * entry - 1;
+
+		let packed = pack(packets(), max_len).unwrap();
+		assert!(packed.oversized.is_empty());
+		assert_eq!(
+			packed.bundles.iter().map(Bundle::len).collect::<Vec<_>>(),
+			[2, 2, 1]
+		);
+		// Every packet arrives whole, and in order
+		let mut buf = [0; 256];
+		let mut received = Vec::new();
+		for bundle in &packed.bundles {
+			assert!(bundle.serialized_len() <= max_len);
+			assert!(bundle.serialize_into(0, &mut buf[..max_len]).is_ok());
+			let len = bundle.serialized_len();
+			received.extend(Bundle::deserialize_from(&buf[..len]).unwrap().1);
+		}
+		assert_eq!(received, packets().collect::<Vec<_>>());
+
+		// Exactly at the limit still fits
+		let packed = pack(packets(), HEADER_LEN + 5 * entry).unwrap();
+		assert_eq!(packed.bundles.len(), 1);
+		assert_eq!(packed.bundles[0].serialized_len(), HEADER_LEN + 5 * entry);
+	}
+
+	#[test]
+	fn pack_rejects_oversized() {
+		let mut one = Bundle::new();
+		one.push(rotation(0, 0.)).unwrap();
+		// A heartbeat fits, but a rotation doesn't even on its own
+		let max_len = one.serialized_len() - 1;
+		let packets = vec![SbPacket::Heartbeat, rotation(0, 1.), SbPacket::Heartbeat];
+		let packed = pack(packets, max_len).unwrap();
+		assert_eq!(packed.oversized, vec![rotation(0, 1.)]);
+		assert_eq!(packed.bundles.len(), 1);
+		assert_eq!(packed.bundles[0].len(), 2);
+		assert!(packed.bundles[0].serialized_len() <= max_len);
+
+		assert_eq!(pack([], max_len).unwrap(), Packed::default());
+	}
+
+	#[test]
+	fn batch_staggered_imus() {
+		const PERIOD_US: u64 = 10_000;
+		let mut batcher = Batcher::new(FlushPolicy {
+			trackers: 3,
+			max_latency_us: PERIOD_US / 2,
+		});
+
+		// Three IMUs at the same rate, but each a bit later than the previous
+		let mut bundles = Vec::new();
+		for (now, sensor_id) in [(0, 0), (1_000, 1), (2_000, 2), (10_000, 0)] {
+			let w = now as f32;
+			if batcher.add(now, sensor_id, vec![rotation(sensor_id, w)]) {
+				bundles.push(batcher.take());
+			}
+		}
+		// The first three are sent together as soon as the last one arrives
+		assert_eq!(
+			bundles,
+			vec![vec![
+				rotation(0, 0.),
+				rotation(1, 1_000.),
+				rotation(2, 2_000.)
+			]]
+		);
+		assert_eq!(batcher.deadline(), Some(10_000 + PERIOD_US / 2));
+
+		// IMU 1 stopped, so the others are sent once they waited too long
+		assert!(!batcher.add(12_000, 2, vec![rotation(2, 12_000.)]));
+		assert!(!batcher.is_due(14_999));
+		assert!(batcher.is_due(15_000));
+		assert_eq!(
+			batcher.take(),
+			vec![rotation(0, 10_000.), rotation(2, 12_000.)]
+		);
+		assert_eq!(batcher.deadline(), None);
+	}
+
+	#[test]
+	fn batch_keeps_latest() {
+		let mut batcher = Batcher::new(FlushPolicy {
+			trackers: 2,
+			max_latency_us: 5_000,
+		});
+		// IMU 0 is faster than the latency, so only its newest reading is sent
+		assert!(!batcher.add(0, 0, vec![rotation(0, 0.)]));
+		assert!(!batcher.add(2_000, 0, vec![rotation(0, 2_000.)]));
+		assert!(batcher.add(3_000, 1, vec![rotation(1, 3_000.)]));
+		assert_eq!(
+			batcher.take(),
+			vec![rotation(0, 2_000.), rotation(1, 3_000.)]
+		);
+
+		// Waiting is measured from the oldest reading
+		assert!(!batcher.add(10_000, 0, vec![rotation(0, 10_000.)]));
+		assert!(batcher.add(15_000, 0, vec![rotation(0, 15_000.)]));
+	}
+}
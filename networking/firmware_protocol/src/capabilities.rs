@@ -0,0 +1,184 @@
+//! Negotiation of the parts of the protocol that not every server supports.
+//!
+//! Right after the handshake, the tracker sends
+//! [`SbPacket::Capabilities`](crate::SbPacket::Capabilities) with everything it can
+//! send, and a server that understands it answers with [`CbPacket::Capabilities`].
+//! The tracker then only uses what both sides support.
+//!
+//! Servers that predate this never answer, so the tracker never waits for the answer.
+//! Until one arrives, it assumes the server only supports [`Capabilities::BASELINE`],
+//! which is the official SlimeVR protocol.
+
+use alloc::format;
+use deku::prelude::*;
+
+use crate::CbPacket;
+
+/// A set of optional features of the protocol. Bits that one side doesn't know about
+/// are ignored, so that newer trackers and servers can add more.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(endian = "e", ctx = "e: deku::ctx::Endian")]
+pub struct Capabilities {
+	bits: u32,
+}
+impl Capabilities {
+	pub const NONE: Self = Self::from_bits(0);
+	/// [`SbPacket::BatteryLevel`](crate::SbPacket::BatteryLevel)
+	pub const BATTERY: Self = Self::from_bits(1 << 0);
+	/// [`SbPacket::RawImuData`](crate::SbPacket::RawImuData)
+	pub const RAW_IMU_DATA: Self = Self::from_bits(1 << 1);
+	/// [`SbPacket::RotationTimestamp`](crate::SbPacket::RotationTimestamp)
+	pub const ROTATION_TIMESTAMP: Self = Self::from_bits(1 << 2);
+	/// [`SbPacket::TrackerPosition`](crate::SbPacket::TrackerPosition)
+	pub const TRACKER_POSITION: Self = Self::from_bits(1 << 3);
+	/// Firmware updates, with [`CbPacket::OtaBegin`] and the packets that follow it
+	pub const OTA: Self = Self::from_bits(1 << 4);
+	/// Changing the [`TrackerConfig`](crate::TrackerConfig) with [`CbPacket::Config`]
+	pub const CONFIG: Self = Self::from_bits(1 << 5);
+
+	/// What every server supports, even one that doesn't answer
+	/// [`SbPacket::Capabilities`](crate::SbPacket::Capabilities).
+	pub const BASELINE: Self = Self::BATTERY;
+
+	pub const fn from_bits(bits: u32) -> Self {
+		Self { bits }
+	}
+
+	pub const fn bits(self) -> u32 {
+		self.bits
+	}
+
+	pub const fn union(self, other: Self) -> Self {
+		Self::from_bits(self.bits | other.bits)
+	}
+
+	pub const fn intersection(self, other: Self) -> Self {
+		Self::from_bits(self.bits & other.bits)
+	}
+
+	/// Whether all of `other` is in `self`.
+	pub const fn contains(self, other: Self) -> bool {
+		self.bits & other.bits == other.bits
+	}
+}
+
+/// Keeps track of which capabilities can be used with the server, for one
+/// connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Negotiation {
+	ours: Capabilities,
+	/// What the server answered, if it did
+	theirs: Option<Capabilities>,
+}
+impl Negotiation {
+	/// Starts a negotiation where we support `ours`.
+	pub const fn new(ours: Capabilities) -> Self {
+		Self { ours, theirs: None }
+	}
+
+	/// What we support, to send in
+	/// [`SbPacket::Capabilities`](crate::SbPacket::Capabilities).
+	pub const fn ours(&self) -> Capabilities {
+		self.ours
+	}
+
+	/// Forgets the answer of the server, for when we handshake again.
+	pub fn reset(&mut self) {
+		self.theirs = None;
+	}
+
+	/// Takes note of the answer of the server, if `packet` is one. Other packets are
+	/// ignored.
+	pub fn handle(&mut self, packet: &CbPacket) {
+		if let CbPacket::Capabilities { capabilities } = packet {
+			self.theirs = Some(*capabilities);
+		}
+	}
+
+	/// Whether the server answered
+	/// [`SbPacket::Capabilities`](crate::SbPacket::Capabilities) since the last reset.
+	pub const fn is_answered(&self) -> bool {
+		self.theirs.is_some()
+	}
+
+	/// What both we and the server support.
+	pub const fn accepted(&self) -> Capabilities {
+		let theirs = match self.theirs {
+			Some(theirs) => theirs,
+			None => Capabilities::BASELINE,
+		};
+		self.ours.intersection(theirs)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::Packet;
+
+	const OURS: Capabilities = Capabilities::BATTERY
+		.union(Capabilities::RAW_IMU_DATA)
+		.union(Capabilities::ROTATION_TIMESTAMP);
+
+	fn cb_packet(bytes: &[u8]) -> CbPacket {
+		let (_seq, packet) = Packet::deserialize_from(bytes).unwrap().split();
+		packet
+	}
+
+	#[test]
+	fn set_operations() {
+		let both = Capabilities::BATTERY.union(Capabilities::OTA);
+		assert_eq!(both.bits(), 0b10001);
+		assert!(both.contains(Capabilities::OTA));
+		assert!(both.contains(Capabilities::NONE));
+		assert!(!both.contains(Capabilities::RAW_IMU_DATA));
+		assert_eq!(both.intersection(Capabilities::OTA), Capabilities::OTA);
+	}
+
+	#[test]
+	fn old_server() {
+		let mut negotiation = Negotiation::new(OURS);
+		// All that a server without capabilities sends back
+		for packet in [
+			cb_packet(b"\x03Hey OVR =D 5"),
+			cb_packet(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]),
+			cb_packet(&[0, 0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4]),
+		] {
+			negotiation.handle(&packet);
+		}
+		assert!(!negotiation.is_answered());
+		// The official packets keep working, the others are off
+		assert_eq!(negotiation.accepted(), Capabilities::BATTERY);
+	}
+
+	#[test]
+	fn new_server() {
+		let mut negotiation = Negotiation::new(OURS);
+		let answer = cb_packet(&[
+			0, 0, 0, 204, // Tag
+			0, 0, 0, 0, 0, 0, 0, 0, // Sequence
+			0, 0, 0, 0b1110, // Capabilities
+		]);
+		negotiation.handle(&answer);
+		assert!(negotiation.is_answered());
+		// Neither our battery nor their tracker positions
+		assert_eq!(
+			negotiation.accepted(),
+			Capabilities::RAW_IMU_DATA.union(Capabilities::ROTATION_TIMESTAMP)
+		);
+
+		negotiation.reset();
+		assert_eq!(negotiation.accepted(), Capabilities::BATTERY);
+	}
+
+	#[test]
+	fn newer_server() {
+		let mut negotiation = Negotiation::new(OURS);
+		// Bits that we don't know about yet are ignored
+		negotiation.handle(&CbPacket::Capabilities {
+			capabilities: Capabilities::from_bits(u32::MAX),
+		});
+		assert_eq!(negotiation.accepted(), OURS);
+	}
+}
@@ -1,6 +1,9 @@
 use alloc::format;
+use alloc::vec::Vec;
 use deku::prelude::*;
 
+use crate::{Capabilities, TrackerConfig};
+
 #[derive(Debug, PartialEq, Eq, DekuRead, DekuWrite)]
 #[deku(ctx = "_: deku::ctx::Endian, tag: u32", id = "tag", endian = "big")]
 #[non_exhaustive]
@@ -14,6 +17,36 @@ pub enum CbPacket {
 		/// Arbitrary bytes sent by the server that must be echoed
 		challenge: [u8; 4],
 	},
+	/// Starts a firmware update. Not part of the official SlimeVR protocol.
+	#[deku(id = "200")]
+	OtaBegin {
+		/// Size of the new image, in bytes
+		size: u32,
+		/// CRC-32 (as used by zlib) of the whole image
+		crc32: u32,
+	},
+	/// A piece of the new image. Chunks must arrive in order, and all but the last
+	/// must be a multiple of 4 bytes long.
+	#[deku(id = "201")]
+	OtaChunk {
+		/// Where in the image `data` goes
+		offset: u32,
+		len: u16,
+		#[deku(count = "len")]
+		data: Vec<u8>,
+	},
+	/// The whole image has been sent, and should be verified and booted.
+	#[deku(id = "202")]
+	OtaEnd,
+	/// Which of the [`SbPacket::Capabilities`](crate::SbPacket::Capabilities) of the
+	/// tracker the server supports. Not part of the official SlimeVR protocol.
+	#[deku(id = "204")]
+	Capabilities { capabilities: Capabilities },
+	/// Changes the settings of the tracker, which answers with
+	/// [`SbPacket::Config`](crate::SbPacket::Config). Not part of the official SlimeVR
+	/// protocol.
+	#[deku(id = "205")]
+	Config { config: TrackerConfig },
 	/// u32::from_be_bytes([3, b'H', b'e', b'y']) -> 55076217
 	#[deku(id = "55076217")]
 	HandshakeResponse {
@@ -53,6 +86,69 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn ota_begin() {
+		test(
+			CbPacket::OtaBegin {
+				size: 0x01020304,
+				crc32: 0x05060708,
+			},
+			&[
+				1, 2, 3, 4, // Size
+				5, 6, 7, 8, // CRC
+			],
+		);
+	}
+
+	#[test]
+	fn ota_chunk() {
+		test(
+			CbPacket::OtaChunk {
+				offset: 0x01020304,
+				len: 3,
+				data: alloc::vec![5, 6, 7],
+			},
+			&[
+				1, 2, 3, 4, // Offset
+				0, 3, // Length
+				5, 6, 7, // Data
+			],
+		);
+	}
+
+	#[test]
+	fn ota_end() {
+		test(CbPacket::OtaEnd, &[]);
+	}
+
+	#[test]
+	fn capabilities() {
+		test(
+			CbPacket::Capabilities {
+				capabilities: Capabilities::from_bits(0x01020304),
+			},
+			&[1, 2, 3, 4],
+		);
+	}
+
+	#[test]
+	fn config() {
+		test(
+			CbPacket::Config {
+				config: TrackerConfig {
+					sample_rate_hz: 100,
+					smoothing_permille: 500,
+					prediction_lead_ms: 4,
+				},
+			},
+			&[
+				0, 100, // Sample rate
+				1, 244, // Smoothing
+				0, 4, // Prediction lead
+			],
+		);
+	}
+
 	#[test]
 	fn handshake_response() {
 		// 3"Hey" -> [3, 72, 101, 121] -> 55076217
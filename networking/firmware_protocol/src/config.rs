@@ -0,0 +1,24 @@
+//! Settings of the tracker that the server can change while it runs.
+//!
+//! The server sends [`CbPacket::Config`](crate::CbPacket::Config), and the tracker
+//! answers with [`SbPacket::Config`](crate::SbPacket::Config) holding the settings it
+//! actually uses. Settings that are out of range, or that the tracker can't change, are
+//! left as they were, so the server should compare the answer with what it asked for.
+
+use alloc::format;
+use deku::prelude::*;
+
+/// The tuning of the rotations that the tracker sends. Fractions are sent as integer
+/// thousandths, so that every tracker rounds them the same way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(endian = "e", ctx = "e: deku::ctx::Endian")]
+pub struct TrackerConfig {
+	/// How often the IMUs are sampled, in Hz
+	pub sample_rate_hz: u16,
+	/// How much of the previous rotation each sample keeps, in thousandths. 0 turns
+	/// smoothing off.
+	pub smoothing_permille: u16,
+	/// How far ahead the rotations are predicted, in milliseconds. 0 turns prediction
+	/// off.
+	pub prediction_lead_ms: u16,
+}
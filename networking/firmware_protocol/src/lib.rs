@@ -2,12 +2,20 @@
 
 extern crate alloc;
 
+mod bundle;
+mod capabilities;
 mod clientbound;
+mod config;
+mod sequence;
 mod serverbound;
 
+pub use bundle::*;
+pub use capabilities::*;
 pub use clientbound::*;
+pub use config::*;
 pub use deku;
 use deku::ctx::Endian;
+pub use sequence::*;
 pub use serverbound::*;
 
 use alloc::format;
@@ -109,7 +117,7 @@ where
 	/// Identifies the variant of the packet.
 	tag: u32,
 	/// Sequence number for the packet. It is incremented for each subsequent packet and is used to reject out of order
-	/// packets. This is sometimes referred to as the packet id
+	/// packets. This is sometimes referred to as the packet id. See [`SeqCounter`] and [`SeqTracker`].
 	seq: u64,
 	#[deku(ctx = "*tag")]
 	data: D,
@@ -177,6 +185,8 @@ pub enum DeserializeError {
 	Deku(::deku::DekuError),
 	/// Unexpectedly had bytes remaining after deserialization.
 	BytesRemaining,
+	/// A [`Bundle`] had the wrong tag, or was cut off.
+	InvalidBundle,
 }
 impl From<::deku::DekuError> for DeserializeError {
 	fn from(deku: ::deku::DekuError) -> Self {
@@ -0,0 +1,155 @@
+//! Sequence numbers of packets, so that the receiver can notice lost and reordered
+//! packets.
+//!
+//! Sequence number 0 means that the packet isn't numbered, and is always accepted. The
+//! SlimeVR server numbers its packets like that, and treats our packets the same way.
+//! So numbering starts at 1, and skips 0 when it wraps around.
+//!
+//! Wrapping is handled with serial number arithmetic as in RFC 1982: a sequence number
+//! is newer than another if it is less than half the range ahead of it.
+
+/// Whether `seq` was sent after `than`, taking wrapping around into account.
+pub fn is_newer(seq: u64, than: u64) -> bool {
+	(seq.wrapping_sub(than) as i64) > 0
+}
+
+/// Hands out the sequence numbers of outgoing packets.
+#[derive(Debug, Clone)]
+pub struct SeqCounter {
+	next: u64,
+}
+impl SeqCounter {
+	pub const fn new() -> Self {
+		Self { next: 1 }
+	}
+
+	/// Returns the sequence number for the next packet.
+	pub fn next_seq(&mut self) -> u64 {
+		let seq = self.next;
+		self.next = match seq.wrapping_add(1) {
+			0 => 1,
+			next => next,
+		};
+		seq
+	}
+}
+impl Default for SeqCounter {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// What [`SeqTracker::receive()`] made of a sequence number.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SeqStatus {
+	/// The packet directly follows the previous one, is the first one, or isn't
+	/// numbered.
+	InOrder,
+	/// The packet is newer than the previous one, but this many packets in between
+	/// were lost, or are still on their way.
+	Skipped(u64),
+	/// The packet is not newer than one that was already received, so it should be
+	/// discarded.
+	Stale,
+}
+
+/// Keeps track of the sequence numbers of incoming packets.
+#[derive(Debug, Clone, Default)]
+pub struct SeqTracker {
+	last: Option<u64>,
+}
+impl SeqTracker {
+	pub const fn new() -> Self {
+		Self { last: None }
+	}
+
+	/// Checks the sequence number of a received packet.
+	pub fn receive(&mut self, seq: u64) -> SeqStatus {
+		if seq == 0 {
+			return SeqStatus::InOrder;
+		}
+		let Some(last) = self.last else {
+			self.last = Some(seq);
+			return SeqStatus::InOrder;
+		};
+		if !is_newer(seq, last) {
+			return SeqStatus::Stale;
+		}
+		self.last = Some(seq);
+		// 0 isn't handed out, so it isn't lost either
+		let mut gap = seq.wrapping_sub(last) - 1;
+		if seq < last {
+			gap -= 1;
+		}
+		match gap {
+			0 => SeqStatus::InOrder,
+			n => SeqStatus::Skipped(n),
+		}
+	}
+
+	/// Forgets the previous packets, such as when the other side restarted.
+	pub fn reset(&mut self) {
+		self.last = None;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn counter_counts_up_from_one() {
+		let mut counter = SeqCounter::new();
+		assert_eq!(counter.next_seq(), 1);
+		assert_eq!(counter.next_seq(), 2);
+		assert_eq!(counter.next_seq(), 3);
+	}
+
+	#[test]
+	fn counter_wraps_without_duplicates() {
+		let mut counter = SeqCounter { next: u64::MAX - 1 };
+		let mut tracker = SeqTracker::new();
+		let seqs: [u64; 4] = core::array::from_fn(|_| counter.next_seq());
+		assert_eq!(seqs, [u64::MAX - 1, u64::MAX, 1, 2]);
+		for seq in seqs {
+			assert_eq!(tracker.receive(seq), SeqStatus::InOrder);
+		}
+		// The numbers from before the wrap are now in the past
+		assert_eq!(tracker.receive(u64::MAX), SeqStatus::Stale);
+		assert_eq!(tracker.receive(2), SeqStatus::Stale);
+	}
+
+	#[test]
+	fn tracker_detects_loss_and_reordering() {
+		let mut tracker = SeqTracker::new();
+		assert_eq!(tracker.receive(5), SeqStatus::InOrder);
+		assert_eq!(tracker.receive(6), SeqStatus::InOrder);
+		assert_eq!(tracker.receive(9), SeqStatus::Skipped(2));
+		assert_eq!(tracker.receive(8), SeqStatus::Stale);
+		assert_eq!(tracker.receive(9), SeqStatus::Stale);
+		assert_eq!(tracker.receive(u64::MAX), SeqStatus::Stale);
+		// Across the wrap, 0 doesn't count as lost
+		let mut tracker = SeqTracker::new();
+		tracker.receive(u64::MAX - 1);
+		assert_eq!(tracker.receive(2), SeqStatus::Skipped(2));
+	}
+
+	#[test]
+	fn unnumbered_is_always_accepted() {
+		let mut tracker = SeqTracker::new();
+		assert_eq!(tracker.receive(0), SeqStatus::InOrder);
+		assert_eq!(tracker.receive(10), SeqStatus::InOrder);
+		assert_eq!(tracker.receive(0), SeqStatus::InOrder);
+		assert_eq!(tracker.receive(0), SeqStatus::InOrder);
+		assert_eq!(tracker.receive(11), SeqStatus::InOrder);
+	}
+
+	#[test]
+	fn newer_is_wrap_aware() {
+		assert!(is_newer(2, 1));
+		assert!(!is_newer(1, 2));
+		assert!(!is_newer(1, 1));
+		assert!(is_newer(1, u64::MAX));
+		assert!(!is_newer(u64::MAX, 1));
+	}
+}
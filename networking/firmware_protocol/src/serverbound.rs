@@ -1,7 +1,7 @@
 use alloc::format;
 use deku::prelude::*;
 
-use crate::{SlimeQuaternion, SlimeString};
+use crate::{Capabilities, SlimeQuaternion, SlimeString, TrackerConfig};
 
 #[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 #[deku(ctx = "_: deku::ctx::Endian, tag: u32", id = "tag", endian = "big")]
@@ -28,6 +28,13 @@ pub enum SbPacket {
 	},
 	#[deku(id = "10")]
 	Ping { challenge: [u8; 4] },
+	#[deku(id = "12")]
+	BatteryLevel {
+		/// Battery voltage, in volts.
+		voltage: f32,
+		/// Remaining charge, from 0 to 1.
+		level: f32,
+	},
 	#[deku(id = "15")]
 	SensorInfo {
 		sensor_id: u8,
@@ -41,8 +48,56 @@ pub enum SbPacket {
 		quat: SlimeQuaternion,
 		calibration_info: u8,
 	},
+	#[deku(id = "20")]
+	Temperature {
+		sensor_id: u8,
+		/// Temperature of the IMU, in °C.
+		temperature: f32,
+	},
 	#[deku(id = "21")]
 	UserAction { action: ActionType },
+	/// Progress of a firmware update. Not part of the official SlimeVR protocol.
+	#[deku(id = "200")]
+	OtaProgress {
+		status: OtaStatus,
+		/// How many bytes of the image have been received. The next chunk should
+		/// start here.
+		received: u32,
+	},
+	/// Raw readings of an IMU, before sensor fusion. Not part of the official SlimeVR
+	/// protocol.
+	#[deku(id = "201")]
+	RawImuData {
+		sensor_id: u8,
+		/// Acceleration, in g.
+		accel: (f32, f32, f32),
+		/// Angular velocity with the bias removed, in radians per second.
+		gyro: (f32, f32, f32),
+	},
+	/// When the `RotationData` of the same sensor that was sent right before this was
+	/// measured. Not part of the official SlimeVR protocol.
+	#[deku(id = "202")]
+	RotationTimestamp {
+		sensor_id: u8,
+		/// Microseconds since the tracker booted. The clocks of the tracker and the
+		/// server are not synchronized, so this is only meaningful relative to other
+		/// timestamps from the same tracker, and starts over when it reboots.
+		micros: u64,
+	},
+	/// The body part that a sensor was assigned to on the tracker itself. Not part of
+	/// the official SlimeVR protocol.
+	#[deku(id = "203")]
+	TrackerPosition { sensor_id: u8, body_part: BodyPart },
+	/// What the tracker supports beyond the official SlimeVR protocol, sent after the
+	/// handshake. Not part of the official SlimeVR protocol. See
+	/// [`Negotiation`](crate::Negotiation).
+	#[deku(id = "204")]
+	Capabilities { capabilities: Capabilities },
+	/// The settings that the tracker uses, sent in answer to
+	/// [`CbPacket::Config`](crate::CbPacket::Config). Not part of the official SlimeVR
+	/// protocol.
+	#[deku(id = "205")]
+	Config { config: TrackerConfig },
 }
 
 #[derive(Debug, PartialEq, Eq, DekuRead, DekuWrite)]
@@ -78,7 +133,7 @@ pub enum BoardType {
 	Unknown(u32),
 }
 
-#[derive(Debug, PartialEq, Eq, DekuRead, DekuWrite)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DekuRead, DekuWrite)]
 #[deku(type = "u8", ctx = "_: deku::ctx::Endian", endian = "big")]
 #[non_exhaustive]
 /// The intertial measurement unit in use
@@ -101,6 +156,10 @@ pub enum ImuType {
 	Bmi160,
 	#[deku(id = "9")]
 	Icm20948,
+	#[deku(id = "12")]
+	Lsm6ds3trc,
+	#[deku(id = "13")]
+	Lsm6dsv,
 	#[deku(id_pat = "_")]
 	Unknown(u8),
 }
@@ -114,6 +173,8 @@ pub enum McuType {
 	Esp8266,
 	#[deku(id = "2")]
 	Esp32,
+	#[deku(id = "6")]
+	Esp32C3,
 	#[deku(id_pat = "_")]
 	Unknown(u32),
 }
@@ -157,6 +218,126 @@ pub enum ActionType {
 	Unknown(u8),
 }
 
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(type = "u8", ctx = "_: deku::ctx::Endian", endian = "big")]
+#[non_exhaustive]
+/// Where a tracker is worn. The ids are the same as the `BodyPart` of SolarXR.
+pub enum BodyPart {
+	#[default]
+	/// Not assigned to a body part yet, so the server should ask the user
+	#[deku(id = "0")]
+	Unassigned,
+	#[deku(id = "1")]
+	Head,
+	#[deku(id = "2")]
+	Neck,
+	#[deku(id = "3")]
+	Chest,
+	#[deku(id = "4")]
+	Waist,
+	#[deku(id = "5")]
+	Hip,
+	#[deku(id = "6")]
+	LeftUpperLeg,
+	#[deku(id = "7")]
+	RightUpperLeg,
+	#[deku(id = "8")]
+	LeftLowerLeg,
+	#[deku(id = "9")]
+	RightLowerLeg,
+	#[deku(id = "10")]
+	LeftFoot,
+	#[deku(id = "11")]
+	RightFoot,
+	#[deku(id = "14")]
+	LeftLowerArm,
+	#[deku(id = "15")]
+	RightLowerArm,
+	#[deku(id = "16")]
+	LeftUpperArm,
+	#[deku(id = "17")]
+	RightUpperArm,
+	#[deku(id = "18")]
+	LeftHand,
+	#[deku(id = "19")]
+	RightHand,
+	#[deku(id = "20")]
+	LeftShoulder,
+	#[deku(id = "21")]
+	RightShoulder,
+	#[deku(id_pat = "_")]
+	Unknown(u8),
+}
+impl From<u8> for BodyPart {
+	fn from(id: u8) -> Self {
+		match id {
+			0 => Self::Unassigned,
+			1 => Self::Head,
+			2 => Self::Neck,
+			3 => Self::Chest,
+			4 => Self::Waist,
+			5 => Self::Hip,
+			6 => Self::LeftUpperLeg,
+			7 => Self::RightUpperLeg,
+			8 => Self::LeftLowerLeg,
+			9 => Self::RightLowerLeg,
+			10 => Self::LeftFoot,
+			11 => Self::RightFoot,
+			14 => Self::LeftLowerArm,
+			15 => Self::RightLowerArm,
+			16 => Self::LeftUpperArm,
+			17 => Self::RightUpperArm,
+			18 => Self::LeftHand,
+			19 => Self::RightHand,
+			20 => Self::LeftShoulder,
+			21 => Self::RightShoulder,
+			id => Self::Unknown(id),
+		}
+	}
+}
+impl From<BodyPart> for u8 {
+	fn from(part: BodyPart) -> Self {
+		match part {
+			BodyPart::Unassigned => 0,
+			BodyPart::Head => 1,
+			BodyPart::Neck => 2,
+			BodyPart::Chest => 3,
+			BodyPart::Waist => 4,
+			BodyPart::Hip => 5,
+			BodyPart::LeftUpperLeg => 6,
+			BodyPart::RightUpperLeg => 7,
+			BodyPart::LeftLowerLeg => 8,
+			BodyPart::RightLowerLeg => 9,
+			BodyPart::LeftFoot => 10,
+			BodyPart::RightFoot => 11,
+			BodyPart::LeftLowerArm => 14,
+			BodyPart::RightLowerArm => 15,
+			BodyPart::LeftUpperArm => 16,
+			BodyPart::RightUpperArm => 17,
+			BodyPart::LeftHand => 18,
+			BodyPart::RightHand => 19,
+			BodyPart::LeftShoulder => 20,
+			BodyPart::RightShoulder => 21,
+			BodyPart::Unknown(id) => id,
+		}
+	}
+}
+
+#[derive(Debug, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(type = "u8", ctx = "_: deku::ctx::Endian", endian = "big")]
+/// State of a firmware update
+pub enum OtaStatus {
+	#[deku(id = "0")]
+	/// The image is being received
+	Receiving,
+	#[deku(id = "1")]
+	/// The image was verified and will be booted
+	Complete,
+	#[deku(id = "2")]
+	/// The update was abandoned, and the current firmware will keep running
+	Failed,
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::*;
@@ -204,6 +385,31 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn mcu_type() {
+		test(
+			SbPacket::Handshake {
+				board: BoardType::Custom,
+				imu: ImuType::Unknown(0xFF),
+				mcu: McuType::Esp32C3,
+				imu_info: (0, 0, 0),
+				build: 10,
+				firmware: SlimeString::from(""),
+				mac_address: [0; 6],
+			},
+			&[
+				0, 0, 0, 4, // Board
+				0, 0, 0,   // Pad
+				255, // IMU
+				0, 0, 0, 6, // MCU
+				0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // Info
+				0, 0, 0, 10, // Build
+				0,  // Firmware
+				0, 0, 0, 0, 0, 0, // MAC
+			],
+		);
+	}
+
 	#[test]
 	fn acceleration() {
 		test(
@@ -234,6 +440,147 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn battery_level() {
+		test(
+			SbPacket::BatteryLevel {
+				voltage: f32::from_be_bytes([1, 2, 3, 4]),
+				level: f32::from_be_bytes([5, 6, 7, 8]),
+			},
+			&[
+				1, 2, 3, 4, // Voltage
+				5, 6, 7, 8, // Level
+			],
+		);
+	}
+
+	#[test]
+	fn temperature() {
+		test(
+			SbPacket::Temperature {
+				sensor_id: 3,
+				temperature: f32::from_be_bytes([1, 2, 3, 4]),
+			},
+			&[
+				3, // Sensor ID
+				1, 2, 3, 4, // Temperature
+			],
+		);
+	}
+
+	#[test]
+	fn ota_progress() {
+		test(
+			SbPacket::OtaProgress {
+				status: OtaStatus::Failed,
+				received: 0x01020304,
+			},
+			&[
+				2, // Status
+				1, 2, 3, 4, // Received
+			],
+		);
+	}
+
+	#[test]
+	fn raw_imu_data() {
+		test(
+			SbPacket::RawImuData {
+				sensor_id: 2,
+				accel: (
+					f32::from_be_bytes([1, 2, 3, 4]),
+					f32::from_be_bytes([5, 6, 7, 8]),
+					f32::from_be_bytes([9, 10, 11, 12]),
+				),
+				gyro: (
+					f32::from_be_bytes([13, 14, 15, 16]),
+					f32::from_be_bytes([17, 18, 19, 20]),
+					f32::from_be_bytes([21, 22, 23, 24]),
+				),
+			},
+			&[
+				2, // ID
+				1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, // Accel
+				13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, // Gyro
+			],
+		);
+	}
+
+	#[test]
+	fn rotation_timestamp() {
+		test(
+			SbPacket::RotationTimestamp {
+				sensor_id: 3,
+				micros: 0x0102030405060708,
+			},
+			&[
+				3, // ID
+				1, 2, 3, 4, 5, 6, 7, 8, // Timestamp
+			],
+		);
+	}
+
+	#[test]
+	fn tracker_position() {
+		test(
+			SbPacket::TrackerPosition {
+				sensor_id: 1,
+				body_part: BodyPart::LeftFoot,
+			},
+			&[
+				1,  // ID
+				10, // Body part
+			],
+		);
+		test(
+			SbPacket::TrackerPosition {
+				sensor_id: 0,
+				body_part: BodyPart::Unassigned,
+			},
+			&[
+				0, // ID
+				0, // Body part
+			],
+		);
+	}
+
+	#[test]
+	fn capabilities() {
+		test(
+			SbPacket::Capabilities {
+				capabilities: Capabilities::BATTERY.union(Capabilities::OTA),
+			},
+			&[0, 0, 0, 0b10001],
+		);
+	}
+
+	#[test]
+	fn config() {
+		test(
+			SbPacket::Config {
+				config: TrackerConfig {
+					sample_rate_hz: 500,
+					smoothing_permille: 0,
+					prediction_lead_ms: 10,
+				},
+			},
+			&[
+				1, 244, // Sample rate
+				0, 0, // Smoothing
+				0, 10, // Prediction lead
+			],
+		);
+	}
+
+	#[test]
+	fn body_part_ids() {
+		for id in 0..=u8::MAX {
+			assert_eq!(u8::from(BodyPart::from(id)), id);
+		}
+		assert_eq!(BodyPart::from(12), BodyPart::Unknown(12));
+		assert_eq!(BodyPart::default(), BodyPart::Unassigned);
+	}
+
 	#[test]
 	fn sensor_info() {
 		test(
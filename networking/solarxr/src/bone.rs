@@ -0,0 +1,226 @@
+use crate::FeedUpdate;
+
+use nalgebra::{Isometry3, Quaternion, Translation3, UnitQuaternion};
+use solarxr_protocol::data_feed::tracker::Bone;
+use solarxr_protocol::datatypes::BodyPart;
+
+/// Reads the pose of a bone in the data feed, or `None` if the server left out its
+/// position or rotation
+fn bone_pose(bone: Bone<'_>) -> Option<Isometry3<f32>> {
+	let Some(pos) = bone.head_position_g() else {
+		log::warn!("No position");
+		return None;
+	};
+	let Some(rot) = bone.rotation_g() else {
+		log::warn!("No rotation");
+		return None;
+	};
+	let translation = Translation3::new(pos.x(), pos.y(), pos.z());
+	let rotation = UnitQuaternion::from_quaternion(Quaternion::new(
+		rot.w(),
+		rot.x(),
+		rot.y(),
+		rot.z(),
+	));
+	Some(Isometry3::from_parts(translation, rotation))
+}
+
+/// How far the norm of a rotation may be from 1 before it is considered invalid. The
+/// server sends normalized quaternions, so this only allows for rounding.
+const MAX_NORM_ERROR: f32 = 1e-3;
+
+/// Whether `pose` can be drawn: its position and rotation are finite, and its rotation
+/// is a unit quaternion. [`FeedUpdate::bones()`] passes along whatever the server sent,
+/// and turns a zero quaternion into NaNs when normalizing it.
+pub fn is_valid_pose(pose: &Isometry3<f32>) -> bool {
+	let finite = pose.translation.vector.iter().all(|v| v.is_finite())
+		&& pose.rotation.coords.iter().all(|v| v.is_finite());
+	finite && (pose.rotation.norm() - 1.).abs() <= MAX_NORM_ERROR
+}
+
+impl FeedUpdate {
+	/// The head pose and length of every bone in this update, in the order the server
+	/// sent them, so that the last one of each kind is the latest.
+	///
+	/// Bones are identified by any `K` that can be converted from a [`BodyPart`]. Bones
+	/// whose body part doesn't convert, or that have no pose, are skipped. The poses
+	/// are not checked otherwise, see [`is_valid_pose()`].
+	pub fn bones<K>(&self) -> impl Iterator<Item = (K, Isometry3<f32>, f32)> + '_
+	where
+		K: TryFrom<BodyPart>,
+		K::Error: std::fmt::Debug,
+	{
+		let msgs = self.0.table().data_feed_msgs();
+		msgs.into_iter()
+			.flatten()
+			.filter_map(|m| m.message_as_data_feed_update()?.bones())
+			.flatten()
+			.filter_map(|b| {
+				let part = b.body_part();
+				log::trace!("body_part: {part:?}");
+				let kind = K::try_from(part)
+					.map_err(|e| log::trace!("Filtering out {e:?}"))
+					.ok()?;
+				Some((kind, bone_pose(b)?, b.bone_length()))
+			})
+	}
+
+	/// Like [`Self::bones()`], but also says which data feed each bone is from, as the
+	/// index of its config in the `StartDataFeed` request. Every feed is its own
+	/// skeleton, so this tells their bones apart.
+	///
+	/// This version of the protocol doesn't say which feed a `DataFeedUpdate` is for,
+	/// and [`FeedConfig`](crate::FeedConfig) only starts one. So the updates in one
+	/// message are successive updates of that feed, and all bones are from feed 0.
+	pub fn feed_bones<K>(
+		&self,
+	) -> impl Iterator<Item = (usize, K, Isometry3<f32>, f32)> + '_
+	where
+		K: TryFrom<BodyPart>,
+		K::Error: std::fmt::Debug,
+	{
+		self.bones()
+			.map(|(kind, iso, length)| (0, kind, iso, length))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::Data;
+
+	use solarxr_protocol::data_feed::tracker::BoneArgs;
+	use solarxr_protocol::data_feed::{
+		DataFeedMessage, DataFeedMessageHeader, DataFeedMessageHeaderArgs,
+		DataFeedUpdate, DataFeedUpdateArgs,
+	};
+	use solarxr_protocol::datatypes::math::{Quat, Vec3f};
+	use solarxr_protocol::flatbuffers::FlatBufferBuilder;
+	use solarxr_protocol::{MessageBundle, MessageBundleArgs};
+
+	/// Only takes the neck, to check that other body parts are skipped
+	#[derive(Debug, PartialEq)]
+	struct Neck;
+	impl TryFrom<BodyPart> for Neck {
+		type Error = BodyPart;
+
+		fn try_from(part: BodyPart) -> Result<Self, Self::Error> {
+			match part {
+				BodyPart::NECK => Ok(Self),
+				other => Err(other),
+			}
+		}
+	}
+
+	/// Builds a message with one `DataFeedUpdate` per slice of bones, each given as
+	/// their body part, position and length
+	#[allow(clippy::needless_update)]
+	fn update_with(updates: &[&[(BodyPart, Option<Vec3f>, f32)]]) -> FeedUpdate {
+		let mut fbb = FlatBufferBuilder::new();
+		let mut headers = Vec::new();
+		for bones in updates {
+			let bones: Vec<_> = bones
+				.iter()
+				.map(|&(body_part, pos, bone_length)| {
+					Bone::create(
+						&mut fbb,
+						&BoneArgs {
+							body_part,
+							rotation_g: Some(&Quat::new(0., 0., 0., 1.)),
+							bone_length,
+							head_position_g: pos.as_ref(),
+							..Default::default()
+						},
+					)
+				})
+				.collect();
+			let bones = fbb.create_vector(&bones);
+			let update = DataFeedUpdate::create(
+				&mut fbb,
+				&DataFeedUpdateArgs {
+					bones: Some(bones),
+					..Default::default()
+				},
+			);
+			headers.push(DataFeedMessageHeader::create(
+				&mut fbb,
+				&DataFeedMessageHeaderArgs {
+					message_type: DataFeedMessage::DataFeedUpdate,
+					message: Some(update.as_union_value()),
+					..Default::default()
+				},
+			));
+		}
+		let headers = fbb.create_vector(&headers);
+		let root = MessageBundle::create(
+			&mut fbb,
+			&MessageBundleArgs {
+				data_feed_msgs: Some(headers),
+				..Default::default()
+			},
+		);
+		fbb.finish(root, None);
+		FeedUpdate(Data::from_vec(fbb.finished_data().to_vec()).unwrap())
+	}
+
+	#[test]
+	fn skips_unknown_and_incomplete_bones() {
+		let update = update_with(&[&[
+			(BodyPart::NECK, Some(Vec3f::new(0., 1.5, 0.)), 0.1),
+			(BodyPart::CHEST, Some(Vec3f::new(0., 1.4, 0.)), 0.2),
+			(BodyPart::NECK, None, 0.3),
+		]]);
+
+		let bones: Vec<_> = update.bones::<Neck>().collect();
+		let [(kind, iso, length)]: [_; 1] = bones.try_into().unwrap();
+		assert_eq!(kind, Neck);
+		assert_eq!(iso.translation.vector.y, 1.5);
+		assert_eq!(iso.rotation, UnitQuaternion::identity());
+		assert_eq!(length, 0.1);
+	}
+
+	#[test]
+	fn updates_of_one_feed() {
+		let neck = |y| (BodyPart::NECK, Some(Vec3f::new(0., y, 0.)), 0.1);
+		// The server batched two updates of the same feed into one message
+		let update = update_with(&[&[neck(1.5)], &[neck(1.6)]]);
+
+		let feeds: Vec<_> = update
+			.feed_bones::<Neck>()
+			.map(|(feed, _kind, iso, _length)| (feed, iso.translation.vector.y))
+			.collect();
+		assert_eq!(feeds, [(0, 1.5), (0, 1.6)]);
+	}
+
+	#[test]
+	fn invalid_poses() {
+		let pose = |t: [f32; 3], (w, i, j, k): (f32, f32, f32, f32)| {
+			let rotation = UnitQuaternion::new_unchecked(Quaternion::new(w, i, j, k));
+			Isometry3::from_parts(Translation3::new(t[0], t[1], t[2]), rotation)
+		};
+		assert!(is_valid_pose(&pose([0., 1.5, 0.], (1., 0., 0., 0.))));
+		assert!(is_valid_pose(&pose([-3., 0., 2.], (0.5, 0.5, -0.5, 0.5))));
+
+		for t in [
+			[f32::NAN, 0., 0.],
+			[0., f32::INFINITY, 0.],
+			[0., 0., -f32::INFINITY],
+		] {
+			assert!(!is_valid_pose(&pose(t, (1., 0., 0., 0.))), "{t:?}");
+		}
+		for q in [
+			(f32::NAN, 0., 0., 0.),
+			(1., f32::INFINITY, 0., 0.),
+			(0., 0., 0., 0.),
+			(2., 0., 0., 0.),
+		] {
+			assert!(!is_valid_pose(&pose([0.; 3], q)), "{q:?}");
+		}
+
+		// What `bones()` makes of a zero quaternion from the server
+		let zero = UnitQuaternion::from_quaternion(Quaternion::new(0., 0., 0., 0.));
+		let pose = Isometry3::from_parts(Translation3::identity(), zero);
+		assert!(!is_valid_pose(&pose));
+	}
+}
@@ -56,3 +56,52 @@ impl Debug for Data {
 
 #[derive(Debug)]
 pub struct FeedUpdate(pub Data);
+impl FeedUpdate {
+	/// The flatbuffer exactly as it was received from the server, for recording the
+	/// update and replaying it later with [`Self::from_bytes()`]. Borrows the update,
+	/// so nothing is copied unless the caller does.
+	pub fn as_bytes(&self) -> &[u8] {
+		self.0.as_slice()
+	}
+
+	/// Like [`Self::as_bytes()`], but takes the bytes out of the update without
+	/// copying them.
+	pub fn into_bytes(self) -> Vec<u8> {
+		self.0.into_vec()
+	}
+
+	/// Parses bytes from [`Self::as_bytes()`] back into an update. They are verified
+	/// like the ones from the server, so a corrupted recording gives an error, along
+	/// with the bytes, instead of undefined behavior.
+	pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, (Vec<u8>, DecodeError)> {
+		Data::from_vec(bytes).map(Self)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::settings::DisplaySettings;
+
+	#[test]
+	fn bytes_round_trip() {
+		let update = FeedUpdate(DisplaySettings::default().to_data());
+		let bytes = update.as_bytes().to_vec();
+		let replayed = FeedUpdate::from_bytes(bytes.clone()).unwrap();
+		assert_eq!(replayed.as_bytes(), bytes);
+		assert_eq!(
+			replayed.0.table().pub_sub_msgs().map(|m| m.len()),
+			update.0.table().pub_sub_msgs().map(|m| m.len())
+		);
+		assert_eq!(replayed.into_bytes(), bytes);
+	}
+
+	#[test]
+	fn corrupted_bytes() {
+		let garbage = vec![0xff; 7];
+		let (bytes, err) = FeedUpdate::from_bytes(garbage.clone()).unwrap_err();
+		assert_eq!(bytes, garbage);
+		assert!(matches!(err, DecodeError::FbVerification(_)));
+	}
+}
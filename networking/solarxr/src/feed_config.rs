@@ -0,0 +1,186 @@
+use solarxr_protocol::data_feed::device_data::{DeviceDataMask, DeviceDataMaskArgs};
+use solarxr_protocol::data_feed::tracker::{TrackerDataMask, TrackerDataMaskArgs};
+use solarxr_protocol::data_feed::{
+	DataFeedConfig, DataFeedConfigArgs, DataFeedMessage, DataFeedMessageHeader,
+	DataFeedMessageHeaderArgs, StartDataFeed, StartDataFeedArgs,
+};
+use solarxr_protocol::flatbuffers::{FlatBufferBuilder, WIPOffset};
+use std::time::Duration;
+
+/// The fields of each tracker to request in the data feed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrackerFields {
+	/// Includes the body part, see [`crate::tracker::TrackerState`]
+	pub info: bool,
+	pub status: bool,
+	pub rotation: bool,
+	pub position: bool,
+}
+
+/// Builder for the data feed that is requested from the server on every connection.
+///
+/// Only request what you use, the server sends all of it many times per second.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedConfig {
+	update_interval: Duration,
+	bones: bool,
+	trackers: Option<TrackerFields>,
+	device_status: bool,
+}
+impl FeedConfig {
+	/// Requests nothing, updating at most every 10ms
+	pub fn new() -> Self {
+		Self {
+			update_interval: Duration::from_millis(10),
+			bones: false,
+			trackers: None,
+			device_status: false,
+		}
+	}
+
+	/// Sets the minimum time between updates. It is rounded down to milliseconds.
+	pub fn update_interval(self, interval: Duration) -> Self {
+		Self {
+			update_interval: interval,
+			..self
+		}
+	}
+
+	/// Sets whether to request the skeleton's bones
+	pub fn bones(self, bones: bool) -> Self {
+		Self { bones, ..self }
+	}
+
+	/// Sets which fields to request of each tracker of each device, if any
+	pub fn trackers(self, trackers: Option<TrackerFields>) -> Self {
+		Self { trackers, ..self }
+	}
+
+	/// Sets whether to request the status of each device, like its battery
+	pub fn device_status(self, device_status: bool) -> Self {
+		Self {
+			device_status,
+			..self
+		}
+	}
+
+	/// Builds the `StartDataFeed` message that requests this feed
+	#[allow(clippy::needless_update)]
+	pub fn to_fb<'a>(
+		&self,
+		fbb: &mut FlatBufferBuilder<'a>,
+	) -> WIPOffset<DataFeedMessageHeader<'a>> {
+		let data_mask = (self.trackers.is_some() || self.device_status).then(|| {
+			let tracker_data = self.trackers.map(|t| {
+				TrackerDataMask::create(
+					fbb,
+					&TrackerDataMaskArgs {
+						info: t.info,
+						status: t.status,
+						rotation: t.rotation,
+						position: t.position,
+						..Default::default()
+					},
+				)
+			});
+			DeviceDataMask::create(
+				fbb,
+				&DeviceDataMaskArgs {
+					tracker_data,
+					device_data: self.device_status,
+					..Default::default()
+				},
+			)
+		});
+
+		let minimum_time_since_last = self
+			.update_interval
+			.as_millis()
+			.try_into()
+			.unwrap_or(u16::MAX);
+		let data_feed_config = DataFeedConfig::create(
+			fbb,
+			&DataFeedConfigArgs {
+				minimum_time_since_last,
+				data_mask,
+				bone_mask: self.bones,
+				..Default::default()
+			},
+		);
+		let data_feed_config = fbb.create_vector(&[data_feed_config]);
+
+		let start_data_feed = StartDataFeed::create(
+			fbb,
+			&StartDataFeedArgs {
+				data_feeds: Some(data_feed_config),
+			},
+		);
+		DataFeedMessageHeader::create(
+			fbb,
+			&DataFeedMessageHeaderArgs {
+				message_type: DataFeedMessage::StartDataFeed,
+				message: Some(start_data_feed.as_union_value()),
+				..Default::default()
+			},
+		)
+	}
+}
+impl Default for FeedConfig {
+	/// Bones, and the info and status of every tracker with the status of its device
+	fn default() -> Self {
+		Self::new()
+			.bones(true)
+			.trackers(Some(TrackerFields {
+				info: true,
+				status: true,
+				..Default::default()
+			}))
+			.device_status(true)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use solarxr_protocol::flatbuffers::root;
+
+	#[test]
+	fn masks() {
+		let config = FeedConfig::new()
+			.update_interval(Duration::from_millis(20))
+			.bones(true)
+			.trackers(Some(TrackerFields {
+				status: true,
+				..Default::default()
+			}));
+
+		let mut fbb = FlatBufferBuilder::new();
+		let header = config.to_fb(&mut fbb);
+		fbb.finish(header, None);
+		let header = root::<DataFeedMessageHeader>(fbb.finished_data()).unwrap();
+
+		let feeds = header.message_as_start_data_feed().unwrap().data_feeds();
+		let feed = feeds.unwrap().get(0);
+		assert_eq!(feed.minimum_time_since_last(), 20);
+		assert!(feed.bone_mask());
+		let data_mask = feed.data_mask().unwrap();
+		assert!(!data_mask.device_data());
+		let tracker_mask = data_mask.tracker_data().unwrap();
+		assert!(tracker_mask.status());
+		assert!(!tracker_mask.info());
+	}
+
+	#[test]
+	fn nothing_requested() {
+		let mut fbb = FlatBufferBuilder::new();
+		let header = FeedConfig::new().to_fb(&mut fbb);
+		fbb.finish(header, None);
+		let header = root::<DataFeedMessageHeader>(fbb.finished_data()).unwrap();
+
+		let feeds = header.message_as_start_data_feed().unwrap().data_feeds();
+		let feed = feeds.unwrap().get(0);
+		assert!(!feed.bone_mask());
+		assert!(feed.data_mask().is_none());
+	}
+}
@@ -1,34 +1,170 @@
+mod bone;
 mod data;
+pub mod feed_config;
+pub mod queue;
+pub mod rpc;
 pub mod settings;
 mod state_machine;
 pub mod topic;
+pub mod tracker;
 
 pub use solarxr_protocol as protocol;
 
+pub use crate::bone::is_valid_pose;
 pub use crate::data::{Data, DecodeError, FeedUpdate};
+pub use crate::feed_config::FeedConfig;
 use crate::state_machine::{ClientStateMachine, DeserializeError, RecvError};
 
 use core::future::Future;
+use std::time::Duration;
 use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
 use tokio_tungstenite::MaybeTlsStream;
 use tokio_tungstenite::WebSocketStream;
 
 type Wss = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
+/// How long to wait before retrying after the first failed connection attempt
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(250);
+/// The delay doubles with every failed attempt until it reaches this
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Options for how to connect to the server
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+	/// Accept any TLS certificate when connecting to a `wss://` URL, such as a self
+	/// signed one or one for a different hostname. This makes TLS vulnerable to
+	/// man-in-the-middle attacks, so only use it on networks you trust.
+	pub accept_invalid_certs: bool,
+	/// The data feed to request
+	pub feed: FeedConfig,
+}
+
+/// What the connection to the server is doing, as reported by [`run_with_status()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionStatus {
+	/// Trying to connect. `attempt` counts the attempts since the last connection,
+	/// starting at 1.
+	Connecting { attempt: u32 },
+	/// Connected, with the data feed requested
+	Connected,
+	/// The last `attempts` attempts in a row failed, the last one because of `reason`.
+	/// Another attempt follows after a delay.
+	Failed { attempts: u32, reason: String },
+	/// An established connection dropped because of `reason`. Connecting again
+	/// follows right away.
+	Lost { reason: String },
+}
+
 /// Returns a future that will run forever, continually callin the callbacks as necessary
+///
+/// If the server can't be reached or the connection drops, this keeps trying to
+/// connect again, waiting a bit longer after each failure. Every new connection
+/// requests the data feed and subscribes to the overlay topic again, so the callback
+/// keeps getting updates as if nothing happened.
+///
+/// `connect_to` is a `ws://` or `wss://` URL. To also send messages to the server, use
+/// [`run_with_options()`].
 pub async fn run<Fut>(
 	connect_to: String,
+	data_feed_callback: impl FnMut(FeedUpdate) -> Fut,
+) -> !
+where
+	Fut: Future<Output = ()>,
+{
+	// Nothing is ever sent, so the sender can go right away
+	let (_, outgoing) = mpsc::unbounded_channel();
+	run_with_options(
+		connect_to,
+		ConnectOptions::default(),
+		outgoing,
+		data_feed_callback,
+	)
+	.await
+}
+
+/// Same as [`run_with_options()`], but puts every update into the bounded `updates`
+/// queue instead of calling a callback, for consumers that must not silently skip
+/// updates. How the connection behaves when the consumer falls behind depends on the
+/// [`queue::Overflow`] policy of the queue, see the [`queue`] module.
+pub async fn run_queued(
+	connect_to: String,
+	options: ConnectOptions,
+	outgoing: mpsc::UnboundedReceiver<Data>,
+	updates: queue::Sender<FeedUpdate>,
+) -> ! {
+	let updates = &updates;
+	run_with_options(connect_to, options, outgoing, move |update| async move {
+		if updates.send(update).await.is_err() {
+			log::trace!("Nobody is receiving from the queue, dropping the update");
+		}
+	})
+	.await
+}
+
+/// Same as [`run()`], but connects according to `options`, and sends anything that
+/// arrives on `outgoing` to the server. Messages are sent as soon as they arrive, also
+/// while the callback isn't getting any updates. Messages sent while disconnected are
+/// dropped.
+///
+/// To make the server do something, send a [`rpc::Request`] to `outgoing` with
+/// [`rpc::Request::to_data`]. Its responses arrive in the `rpc_msgs` of the
+/// `FeedUpdate`s.
+pub async fn run_with_options<Fut>(
+	connect_to: String,
+	options: ConnectOptions,
+	outgoing: mpsc::UnboundedReceiver<Data>,
+	data_feed_callback: impl FnMut(FeedUpdate) -> Fut,
+) -> !
+where
+	Fut: Future<Output = ()>,
+{
+	let (status, _) = watch::channel(ConnectionStatus::Connecting { attempt: 1 });
+	run_with_status(connect_to, options, outgoing, status, data_feed_callback).await
+}
+
+/// Same as [`run_with_options()`], but also reports what the connection is doing to
+/// `status`, for applications that want to tell the user or give up at some point.
+pub async fn run_with_status<Fut>(
+	connect_to: String,
+	options: ConnectOptions,
+	mut outgoing: mpsc::UnboundedReceiver<Data>,
+	status: watch::Sender<ConnectionStatus>,
 	mut data_feed_callback: impl FnMut(FeedUpdate) -> Fut,
 ) -> !
 where
 	Fut: Future<Output = ()>,
 {
-	let mut disconnected = Some(ClientStateMachine::new(connect_to));
+	let mut disconnected = Some(ClientStateMachine::new(connect_to, options));
+	let mut retry_delay = None;
+	// Failed attempts since the last connection
+	let mut failures = 0;
 	loop {
+		if let Some(delay) = retry_delay {
+			log::debug!("Reconnecting in {delay:?}");
+			tokio::time::sleep(delay).await;
+		}
+		// Until we are connected again, the next attempt waits longer
+		retry_delay = Some(
+			retry_delay
+				.map_or(MIN_RETRY_DELAY, |d: Duration| (d * 2).min(MAX_RETRY_DELAY)),
+		);
+		status.send_replace(ConnectionStatus::Connecting {
+			attempt: failures + 1,
+		});
+		let mut fail = |reason: String| {
+			failures += 1;
+			status.send_replace(ConnectionStatus::Failed {
+				attempts: failures,
+				reason,
+			});
+		};
+
 		let ready = match disconnected.take().unwrap().connect().await {
 			Ok(ready) => ready,
 			Err((d, err)) => {
 				log::error!("Error while connecting: {}", err);
+				fail(err.to_string());
 				disconnected = Some(d);
 				continue;
 			}
@@ -48,30 +184,37 @@ where
 					}
 				};
 
-				log::error!("{:?}", err.wrap_err("Error while requesting feed"));
+				let err = err.wrap_err("Error while requesting feed");
+				log::error!("{:?}", err);
+				fail(format!("{err:#}"));
 				disconnected = Some(d);
 				continue;
 			}
 		};
+		log::info!("Connected to the server");
+		failures = 0;
+		status.send_replace(ConnectionStatus::Connected);
+		// Don't send anything that was queued up for a previous connection
+		while outgoing.try_recv().is_ok() {}
 		let mut active = Some(active);
 		loop {
 			use RecvError as E;
-			match active.take().unwrap().recv().await {
+			match active.take().unwrap().recv_sending(&mut outgoing).await {
 				Ok((a, update)) => {
+					// The connection works, so if it drops, try again right away
+					retry_delay = None;
 					log::trace!("Sending data to watchers: {:#?}", update);
-					active = Some(a);
 					data_feed_callback(update).await;
+					active = Some(a);
 				}
 				Err(err) => {
 					let display = format!("{}", &err);
 					match err {
-						E::CriticalWs(d, _) => {
-							log::error!("Critical websocket error: {}", display);
-							disconnected = Some(d);
-							break;
-						}
-						E::None(d) => {
+						E::CriticalWs(d, _) | E::None(d) => {
 							log::error!("Critical websocket error: {}", display);
+							status.send_replace(ConnectionStatus::Lost {
+								reason: display,
+							});
 							disconnected = Some(d);
 							break;
 						}
@@ -98,3 +241,51 @@ where
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use tokio::time::timeout;
+
+	#[tokio::test]
+	async fn reports_failed_attempts() {
+		// Nothing listens on a port that was just freed
+		let port = {
+			let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+			listener.local_addr().unwrap().port()
+		};
+		let (status, mut statuses) =
+			watch::channel(ConnectionStatus::Connecting { attempt: 1 });
+		let (_outgoing, outgoing_receiver) = mpsc::unbounded_channel();
+		let run = run_with_status(
+			format!("ws://127.0.0.1:{port}"),
+			ConnectOptions::default(),
+			outgoing_receiver,
+			status,
+			|_| async {},
+		);
+		// A watch only keeps the latest status, so some may be skipped, but the count
+		// of failed attempts never goes down
+		let watch = async {
+			let mut last = 0;
+			while last < 2 {
+				statuses.changed().await.unwrap();
+				let current = statuses.borrow().clone();
+				match current {
+					ConnectionStatus::Failed { attempts, reason } => {
+						assert!(attempts > last, "{attempts} after {last}");
+						assert!(!reason.is_empty());
+						last = attempts;
+					}
+					ConnectionStatus::Connecting { attempt } => assert!(attempt > last),
+					other => panic!("Nothing should be listening, got {other:?}"),
+				}
+			}
+		};
+		tokio::select! {
+			_ = run => unreachable!("never returns"),
+			r = timeout(Duration::from_secs(10), watch) => r.unwrap(),
+		}
+	}
+}
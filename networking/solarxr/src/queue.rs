@@ -0,0 +1,211 @@
+//! A bounded queue for consumers that need every [`FeedUpdate`](crate::FeedUpdate),
+//! such as recording tools.
+//!
+//! A `watch` channel only keeps the latest update, which is what a renderer wants, but
+//! silently skips the ones that it didn't get to. [`channel()`] instead keeps up to
+//! `capacity` updates, and the [`Overflow`] policy decides what happens when the
+//! consumer falls further behind than that:
+//!
+//! - [`Overflow::DropOldest`] never slows down the connection. The oldest update is
+//!   thrown away to make room, so the queue always holds the newest ones, and
+//!   [`Receiver::dropped()`] counts how many were lost.
+//! - [`Overflow::Block`] never loses an update. [`Sender::send()`] waits until there
+//!   is room, so [`run_queued()`](crate::run_queued) stops reading from the server
+//!   in the meantime. The updates then back up in the socket, until the server gives
+//!   up on us if we take too long.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// What [`Sender::send()`] does when the queue is full. See the [module](self)
+/// documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+	/// Remove the oldest update to make room for the new one
+	DropOldest,
+	/// Wait until the receiver takes an update
+	Block,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+	queue: Mutex<VecDeque<T>>,
+	capacity: usize,
+	overflow: Overflow,
+	/// Updates removed by [`Overflow::DropOldest`]
+	dropped: AtomicU64,
+	/// Whether the other half was dropped
+	closed: AtomicBool,
+	/// Wakes the receiver when there is something to receive
+	pushed: Notify,
+	/// Wakes the sender when there is room
+	popped: Notify,
+}
+
+/// Creates a queue that holds at most `capacity` updates.
+///
+/// # Panics
+/// Panics if `capacity` is 0.
+pub fn channel<T>(capacity: usize, overflow: Overflow) -> (Sender<T>, Receiver<T>) {
+	assert!(
+		capacity > 0,
+		"the queue must have room for at least one update"
+	);
+	let shared = Arc::new(Shared {
+		queue: Mutex::new(VecDeque::with_capacity(capacity)),
+		capacity,
+		overflow,
+		dropped: AtomicU64::new(0),
+		closed: AtomicBool::new(false),
+		pushed: Notify::new(),
+		popped: Notify::new(),
+	});
+	(
+		Sender {
+			shared: shared.clone(),
+		},
+		Receiver { shared },
+	)
+}
+
+/// The sending half of a [`channel()`]
+#[derive(Debug)]
+pub struct Sender<T> {
+	shared: Arc<Shared<T>>,
+}
+impl<T> Sender<T> {
+	/// Adds `update` to the queue, following the [`Overflow`] policy if it is full.
+	/// Returns `update` back if the [`Receiver`] was dropped, since nobody would get
+	/// it.
+	pub async fn send(&self, update: T) -> Result<(), T> {
+		let shared = &self.shared;
+		let mut update = Some(update);
+		loop {
+			if shared.closed.load(Ordering::Acquire) {
+				return Err(update.unwrap());
+			}
+			{
+				let mut queue = shared.queue.lock().unwrap();
+				if queue.len() >= shared.capacity
+					&& shared.overflow == Overflow::DropOldest
+				{
+					queue.pop_front();
+					shared.dropped.fetch_add(1, Ordering::Relaxed);
+				}
+				if queue.len() < shared.capacity {
+					queue.push_back(update.take().unwrap());
+				}
+			}
+			if update.is_none() {
+				shared.pushed.notify_one();
+				return Ok(());
+			}
+			// Only `Overflow::Block` gets here. A pop between the check and this
+			// leaves a permit behind, so we can't miss it.
+			shared.popped.notified().await;
+		}
+	}
+}
+impl<T> Drop for Sender<T> {
+	fn drop(&mut self) {
+		self.shared.closed.store(true, Ordering::Release);
+		self.shared.pushed.notify_one();
+	}
+}
+
+/// The receiving half of a [`channel()`]
+#[derive(Debug)]
+pub struct Receiver<T> {
+	shared: Arc<Shared<T>>,
+}
+impl<T> Receiver<T> {
+	/// Takes the oldest update out of the queue, waiting for one if it is empty.
+	/// Returns `None` once the [`Sender`] was dropped and the queue is empty.
+	pub async fn recv(&mut self) -> Option<T> {
+		loop {
+			if let Some(update) = self.try_recv() {
+				return Some(update);
+			}
+			if self.shared.closed.load(Ordering::Acquire) {
+				// The sender may have pushed one last update before closing
+				return self.try_recv();
+			}
+			self.shared.pushed.notified().await;
+		}
+	}
+
+	/// Takes the oldest update out of the queue, if there is one.
+	pub fn try_recv(&mut self) -> Option<T> {
+		let update = self.shared.queue.lock().unwrap().pop_front()?;
+		self.shared.popped.notify_one();
+		Some(update)
+	}
+
+	/// How many updates [`Overflow::DropOldest`] threw away so far
+	pub fn dropped(&self) -> u64 {
+		self.shared.dropped.load(Ordering::Relaxed)
+	}
+}
+impl<T> Drop for Receiver<T> {
+	fn drop(&mut self) {
+		self.shared.closed.store(true, Ordering::Release);
+		self.shared.popped.notify_one();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use futures_util::FutureExt;
+
+	#[tokio::test]
+	async fn drop_oldest_keeps_newest() {
+		let (tx, mut rx) = channel(4, Overflow::DropOldest);
+		for i in 0..100 {
+			// Never waits, however far behind the receiver is
+			tx.send(i).now_or_never().unwrap().unwrap();
+		}
+		assert_eq!(rx.dropped(), 96);
+		drop(tx);
+		let mut received = Vec::new();
+		while let Some(i) = rx.recv().await {
+			received.push(i);
+		}
+		assert_eq!(received, [96, 97, 98, 99]);
+	}
+
+	#[tokio::test]
+	async fn block_waits_for_room() {
+		let (tx, mut rx) = channel(2, Overflow::Block);
+		tx.send(0).await.unwrap();
+		tx.send(1).await.unwrap();
+
+		let mut third = Box::pin(tx.send(2));
+		assert!(
+			(&mut third).now_or_never().is_none(),
+			"should wait while full"
+		);
+		assert_eq!(rx.recv().await, Some(0));
+		third.await.unwrap();
+
+		assert_eq!(rx.recv().await, Some(1));
+		assert_eq!(rx.recv().await, Some(2));
+		assert_eq!(rx.dropped(), 0);
+	}
+
+	#[tokio::test]
+	async fn closed_halves() {
+		let (tx, rx) = channel(1, Overflow::Block);
+		tx.send(0).await.unwrap();
+		// A full queue doesn't block forever once nobody is receiving
+		drop(rx);
+		assert_eq!(tx.send(1).await, Err(1));
+
+		let (tx, mut rx) = channel::<u8>(1, Overflow::Block);
+		drop(tx);
+		assert_eq!(rx.recv().await, None);
+	}
+}
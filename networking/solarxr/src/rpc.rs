@@ -0,0 +1,135 @@
+//! Requests for the server to do something, sent over the RPC channel of SolarXR.
+//!
+//! Send them with [`Request::to_data`] through the `outgoing` channel of
+//! [`run()`](crate::run), like any other message.
+
+use crate::Data;
+
+use solarxr_protocol::flatbuffers::{FlatBufferBuilder, WIPOffset};
+use solarxr_protocol::rpc::{
+	ResetRequest, ResetRequestArgs, ResetType, RpcMessage, RpcMessageHeader,
+	RpcMessageHeaderArgs, SkeletonResetAllRequest, SkeletonResetAllRequestArgs,
+};
+use solarxr_protocol::{MessageBundle, MessageBundleArgs};
+
+/// How much of the tracking a [`Request::Reset`] resets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetKind {
+	/// Only the yaw of the trackers, while the user stands still in any pose
+	Quick,
+	/// The whole orientation of the trackers, while the user stands straight
+	Full,
+}
+
+/// An RPC that the server acts on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+	/// Resets the trackers, like the reset buttons of the server
+	Reset(ResetKind),
+	/// Resets the proportions of the skeleton to the defaults
+	SkeletonResetAll,
+}
+impl Request {
+	/// Builds `Request` from a flatbuffer, or `None` if it is some other RPC
+	pub fn from_fb(header: RpcMessageHeader<'_>) -> Option<Self> {
+		match header.message_type() {
+			RpcMessage::ResetRequest => {
+				let reset = header.message_as_reset_request()?;
+				let kind = match reset.reset_type() {
+					ResetType::Quick => ResetKind::Quick,
+					ResetType::Full => ResetKind::Full,
+					_ => return None,
+				};
+				Some(Self::Reset(kind))
+			}
+			RpcMessage::SkeletonResetAllRequest => Some(Self::SkeletonResetAll),
+			_ => None,
+		}
+	}
+
+	/// Serializes `Request` into a flatbuffer that [`Self::from_fb`] can parse
+	#[allow(clippy::needless_update)]
+	pub fn to_fb<'a>(
+		&self,
+		fbb: &mut FlatBufferBuilder<'a>,
+	) -> WIPOffset<RpcMessageHeader<'a>> {
+		let (message_type, message) = match *self {
+			Self::Reset(kind) => {
+				let reset_type = match kind {
+					ResetKind::Quick => ResetType::Quick,
+					ResetKind::Full => ResetType::Full,
+				};
+				let m = ResetRequest::create(
+					fbb,
+					&ResetRequestArgs {
+						reset_type,
+						..Default::default()
+					},
+				);
+				(RpcMessage::ResetRequest, m.as_union_value())
+			}
+			Self::SkeletonResetAll => {
+				let m = SkeletonResetAllRequest::create(
+					fbb,
+					&SkeletonResetAllRequestArgs {
+						..Default::default()
+					},
+				);
+				(RpcMessage::SkeletonResetAllRequest, m.as_union_value())
+			}
+		};
+		RpcMessageHeader::create(
+			fbb,
+			&RpcMessageHeaderArgs {
+				message_type,
+				message: Some(message),
+				..Default::default()
+			},
+		)
+	}
+
+	/// Builds a [`MessageBundle`] that sends the request to the server
+	#[allow(clippy::needless_update)]
+	pub fn to_data(&self) -> Data {
+		let mut fbb = FlatBufferBuilder::new();
+		let rpc_msgs = {
+			let m = self.to_fb(&mut fbb);
+			fbb.create_vector(&[m])
+		};
+		let root = MessageBundle::create(
+			&mut fbb,
+			&MessageBundleArgs {
+				rpc_msgs: Some(rpc_msgs),
+				..Default::default()
+			},
+		);
+		fbb.finish(root, None);
+		let v = fbb.finished_data().to_vec();
+
+		#[cfg(not(debug_assertions))]
+		unsafe {
+			Data::from_vec_unchecked(v)
+		}
+		#[cfg(debug_assertions)]
+		Data::from_vec(v).unwrap()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trip() {
+		for request in [
+			Request::Reset(ResetKind::Quick),
+			Request::Reset(ResetKind::Full),
+			Request::SkeletonResetAll,
+		] {
+			let data = request.to_data();
+			let msgs = data.table().rpc_msgs().unwrap();
+			assert_eq!(msgs.len(), 1);
+			assert_eq!(Request::from_fb(msgs.get(0)), Some(request));
+		}
+	}
+}
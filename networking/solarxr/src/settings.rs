@@ -1,13 +1,56 @@
-use solarxr_protocol::pub_sub::KeyValues;
+use crate::topic;
+use crate::Data;
 
-#[derive(Debug)]
+use solarxr_protocol::datatypes::BodyPart;
+use solarxr_protocol::flatbuffers::{FlatBufferBuilder, WIPOffset};
+use solarxr_protocol::pub_sub::{
+	KeyValues, KeyValuesArgs, Message, MessageArgs, Payload, PubSubHeader,
+	PubSubHeaderArgs, PubSubUnion, Topic,
+};
+use solarxr_protocol::{MessageBundle, MessageBundleArgs};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A color as red, green, blue and alpha
+pub type Rgba = [u8; 4];
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct DisplaySettings {
 	pub is_visible: bool,
 	pub is_mirrored: bool,
+	/// How opaque the skeleton is, from 0 (invisible) to 1 (opaque)
+	pub opacity: f32,
+	/// Moves the skeleton by this many meters `[right, up, forward]`, without moving
+	/// the trackers
+	pub offset_position: [f32; 3],
+	/// Rotates the skeleton about the origin by this many degrees
+	/// `[yaw left, pitch up, roll clockwise]`, applied before `offset_position`
+	pub offset_rotation: [f32; 3],
+	/// Diameter of the bones in meters, or `None` for the overlay's default
+	pub thickness: Option<f32>,
+	/// Colors to draw body parts with, instead of their default color
+	pub colors: BTreeMap<BodyPart, Rgba>,
+	/// Body parts that are never drawn, even if the server sends them
+	pub hidden: BTreeSet<BodyPart>,
+	/// Moves the skeleton up or down so that its lowest foot stands on the floor
+	pub ground_to_floor: bool,
 }
 impl DisplaySettings {
 	pub const IS_VISIBLE: &str = "is_visible";
 	pub const IS_MIRRORED: &str = "is_mirrored";
+	pub const OPACITY: &str = "opacity";
+	/// Comma separated `offset_position`, like `0.5,0,-1`.
+	pub const OFFSET_POSITION: &str = "offset_position";
+	/// Comma separated `offset_rotation`, like `90,0,0`.
+	pub const OFFSET_ROTATION: &str = "offset_rotation";
+	/// Omitted when `thickness` is `None`.
+	pub const THICKNESS: &str = "thickness";
+	/// Prefix of the keys for `colors`, followed by the name of the `BodyPart`. For
+	/// example `color_LEFT_UPPER_ARM`, with a value like `#ff0000` or `#ff000080`.
+	pub const COLOR_PREFIX: &str = "color_";
+	/// Comma separated names of the `BodyPart`s in `hidden`, like
+	/// `LEFT_FOOT,RIGHT_FOOT`.
+	pub const HIDDEN: &str = "hidden";
+	pub const GROUND_TO_FLOOR: &str = "ground_to_floor";
 
 	/// Builds `DisplaySettings` from a flatbuffer
 	pub fn from_fb(kv: KeyValues<'_>) -> Option<Self> {
@@ -33,12 +76,179 @@ impl DisplaySettings {
 				Self::IS_MIRRORED => {
 					result.is_mirrored = v == "true";
 				}
+				Self::GROUND_TO_FLOOR => {
+					result.ground_to_floor = v == "true";
+				}
+				Self::OPACITY => match v.parse::<f32>() {
+					Ok(opacity) if (0.0..=1.0).contains(&opacity) => {
+						result.opacity = opacity
+					}
+					_ => log::warn!("Ignoring invalid opacity {v:?}"),
+				},
+				Self::OFFSET_POSITION => match parse_vec3(v) {
+					Some(offset) => result.offset_position = offset,
+					None => log::warn!("Ignoring invalid offset position {v:?}"),
+				},
+				Self::OFFSET_ROTATION => match parse_vec3(v) {
+					Some(offset) => result.offset_rotation = offset,
+					None => log::warn!("Ignoring invalid offset rotation {v:?}"),
+				},
+				Self::THICKNESS => match v.parse::<f32>() {
+					Ok(thickness) if thickness.is_finite() && thickness > 0.0 => {
+						result.thickness = Some(thickness)
+					}
+					_ => log::warn!("Ignoring invalid thickness {v:?}"),
+				},
+				Self::HIDDEN => {
+					for name in v.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+						match body_part_from_name(name) {
+							Some(part) => {
+								result.hidden.insert(part);
+							}
+							None => log::warn!("Ignoring unknown hidden bone {name:?}"),
+						}
+					}
+				}
+				k if k.starts_with(Self::COLOR_PREFIX) => {
+					let name = &k[Self::COLOR_PREFIX.len()..];
+					match (body_part_from_name(name), parse_color(v)) {
+						(Some(part), Some(color)) => {
+							result.colors.insert(part, color);
+						}
+						_ => log::warn!("Ignoring invalid color {k:?}: {v:?}"),
+					}
+				}
 				_ => (), // Ignore unexpected keys - publisher may be on a newer schema
 			}
 		}
 
 		Some(result)
 	}
+
+	/// Serializes `DisplaySettings` into a flatbuffer that [`Self::from_fb`] can parse
+	#[allow(clippy::needless_update)]
+	pub fn to_fb<'a>(
+		&self,
+		fbb: &mut FlatBufferBuilder<'a>,
+	) -> WIPOffset<KeyValues<'a>> {
+		const fn as_str(b: bool) -> &'static str {
+			if b {
+				"true"
+			} else {
+				"false"
+			}
+		}
+
+		let mut keys = vec![
+			Self::IS_VISIBLE.to_string(),
+			Self::IS_MIRRORED.to_string(),
+			Self::OPACITY.to_string(),
+			Self::OFFSET_POSITION.to_string(),
+			Self::OFFSET_ROTATION.to_string(),
+			Self::GROUND_TO_FLOOR.to_string(),
+		];
+		let mut values = vec![
+			as_str(self.is_visible).to_string(),
+			as_str(self.is_mirrored).to_string(),
+			self.opacity.to_string(),
+			format_vec3(self.offset_position),
+			format_vec3(self.offset_rotation),
+			as_str(self.ground_to_floor).to_string(),
+		];
+		let hidden: Vec<_> = self
+			.hidden
+			.iter()
+			.filter_map(|part| {
+				let name = part.variant_name();
+				if name.is_none() {
+					log::warn!("Not sending unknown hidden body part {part:?}");
+				}
+				name
+			})
+			.collect();
+		if let Some(thickness) = self.thickness {
+			keys.push(Self::THICKNESS.to_string());
+			values.push(thickness.to_string());
+		}
+		keys.push(Self::HIDDEN.to_string());
+		values.push(hidden.join(","));
+		for (part, color) in &self.colors {
+			let Some(name) = part.variant_name() else {
+				log::warn!("Not sending color of unknown body part {part:?}");
+				continue;
+			};
+			keys.push(format!("{}{name}", Self::COLOR_PREFIX));
+			values.push(format_color(*color));
+		}
+
+		let keys: Vec<_> = keys.iter().map(|s| fbb.create_string(s)).collect();
+		let keys = fbb.create_vector(&keys);
+		let values: Vec<_> = values.iter().map(|s| fbb.create_string(s)).collect();
+		let values = fbb.create_vector(&values);
+		KeyValues::create(
+			fbb,
+			&KeyValuesArgs {
+				keys: Some(keys),
+				values: Some(values),
+				..Default::default()
+			},
+		)
+	}
+
+	/// Builds a `pub_sub::Message` that publishes `DisplaySettings` on the overlay
+	/// topic
+	#[allow(clippy::needless_update)]
+	pub fn to_message<'a>(
+		&self,
+		fbb: &mut FlatBufferBuilder<'a>,
+	) -> WIPOffset<PubSubHeader<'a>> {
+		let topic = topic::create_topic_id(fbb);
+		let kv = self.to_fb(fbb);
+		let m = Message::create(
+			fbb,
+			&MessageArgs {
+				topic_type: Topic::TopicId,
+				topic: Some(topic.as_union_value()),
+				payload_type: Payload::KeyValues,
+				payload: Some(kv.as_union_value()),
+				..Default::default()
+			},
+		);
+		PubSubHeader::create(
+			fbb,
+			&PubSubHeaderArgs {
+				u_type: PubSubUnion::Message,
+				u: Some(m.as_union_value()),
+				..Default::default()
+			},
+		)
+	}
+
+	/// Builds a [`MessageBundle`] that publishes `DisplaySettings` on the overlay topic
+	#[allow(clippy::needless_update)]
+	pub fn to_data(&self) -> Data {
+		let mut fbb = FlatBufferBuilder::new();
+		let pub_sub_msgs = {
+			let m = self.to_message(&mut fbb);
+			fbb.create_vector(&[m])
+		};
+		let root = MessageBundle::create(
+			&mut fbb,
+			&MessageBundleArgs {
+				pub_sub_msgs: Some(pub_sub_msgs),
+				..Default::default()
+			},
+		);
+		fbb.finish(root, None);
+		let v = fbb.finished_data().to_vec();
+
+		#[cfg(not(debug_assertions))]
+		unsafe {
+			Data::from_vec_unchecked(v)
+		}
+		#[cfg(debug_assertions)]
+		Data::from_vec(v).unwrap()
+	}
 }
 #[allow(clippy::derivable_impls)]
 impl Default for DisplaySettings {
@@ -46,6 +256,156 @@ impl Default for DisplaySettings {
 		Self {
 			is_visible: false,
 			is_mirrored: false,
+			opacity: 1.0,
+			offset_position: [0.0; 3],
+			offset_rotation: [0.0; 3],
+			thickness: None,
+			colors: BTreeMap::new(),
+			hidden: BTreeSet::new(),
+			ground_to_floor: false,
+		}
+	}
+}
+
+fn body_part_from_name(name: &str) -> Option<BodyPart> {
+	BodyPart::ENUM_VALUES
+		.into_iter()
+		.find(|p| p.variant_name() == Some(name))
+}
+
+/// Parses three comma separated finite numbers, like `1.5, 0, -2`.
+fn parse_vec3(s: &str) -> Option<[f32; 3]> {
+	let mut result = [0.0; 3];
+	let mut parts = s.split(',');
+	for r in &mut result {
+		*r = parts.next()?.trim().parse().ok()?;
+		if !r.is_finite() {
+			return None;
+		}
+	}
+	parts.next().is_none().then_some(result)
+}
+
+/// The inverse of [`parse_vec3`].
+fn format_vec3([x, y, z]: [f32; 3]) -> String {
+	format!("{x},{y},{z}")
+}
+
+/// Parses `#rrggbb` or `#rrggbbaa`. Colors without alpha are opaque.
+fn parse_color(s: &str) -> Option<Rgba> {
+	let hex = s.strip_prefix('#')?;
+	if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+		return None;
+	}
+	let mut color = [u8::MAX; 4];
+	for (i, c) in color.iter_mut().enumerate().take(hex.len() / 2) {
+		*c = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+	}
+	Some(color)
+}
+
+/// The inverse of [`parse_color`], always including alpha.
+fn format_color([r, g, b, a]: Rgba) -> String {
+	format!("#{r:02x}{g:02x}{b:02x}{a:02x}")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trip() {
+		let colors = BTreeMap::from([
+			(BodyPart::LEFT_UPPER_ARM, [255, 0, 0, 255]),
+			(BodyPart::RIGHT_UPPER_ARM, [0, 0, 255, 128]),
+		]);
+		let hidden = BTreeSet::from([BodyPart::LEFT_FOOT, BodyPart::RIGHT_FOOT]);
+		for ds in [
+			DisplaySettings {
+				is_visible: false,
+				is_mirrored: false,
+				opacity: 1.0,
+				..Default::default()
+			},
+			DisplaySettings {
+				is_visible: true,
+				is_mirrored: false,
+				opacity: 0.5,
+				colors: colors.clone(),
+				thickness: Some(0.01),
+				..Default::default()
+			},
+			DisplaySettings {
+				is_visible: false,
+				is_mirrored: true,
+				opacity: 0.0,
+				offset_position: [0.5, 0.0, -1.25],
+				offset_rotation: [90.0, -10.5, 0.0],
+				hidden: hidden.clone(),
+				..Default::default()
+			},
+			DisplaySettings {
+				is_visible: true,
+				is_mirrored: true,
+				opacity: 0.3,
+				colors,
+				hidden,
+				ground_to_floor: true,
+				..Default::default()
+			},
+		] {
+			let data = ds.to_data();
+			let msgs = data.table().pub_sub_msgs().unwrap();
+			assert_eq!(msgs.len(), 1);
+			let m = msgs.get(0).u_as_message().unwrap();
+			assert!(topic::is_overlay_topic(m));
+			let kv = m.payload_as_key_values().unwrap();
+			assert_eq!(DisplaySettings::from_fb(kv), Some(ds));
 		}
 	}
+
+	#[test]
+	fn unknown_hidden_bones_are_ignored() {
+		let mut fbb = FlatBufferBuilder::new();
+		let keys = [DisplaySettings::HIDDEN].map(|s| fbb.create_string(s));
+		let keys = fbb.create_vector(&keys);
+		let values = ["LEFT_FOOT, TAIL,RIGHT_FOOT"].map(|s| fbb.create_string(s));
+		let values = fbb.create_vector(&values);
+		let kv = KeyValues::create(
+			&mut fbb,
+			&KeyValuesArgs {
+				keys: Some(keys),
+				values: Some(values),
+			},
+		);
+		fbb.finish(kv, None);
+		let kv = solarxr_protocol::flatbuffers::root::<KeyValues>(fbb.finished_data())
+			.unwrap();
+
+		let ds = DisplaySettings::from_fb(kv).unwrap();
+		assert_eq!(
+			ds.hidden,
+			BTreeSet::from([BodyPart::LEFT_FOOT, BodyPart::RIGHT_FOOT])
+		);
+	}
+
+	#[test]
+	fn vec3() {
+		assert_eq!(parse_vec3("1.5, 0,-2"), Some([1.5, 0.0, -2.0]));
+		assert_eq!(parse_vec3("1,2"), None);
+		assert_eq!(parse_vec3("1,2,3,4"), None);
+		assert_eq!(parse_vec3("1,NaN,3"), None);
+		assert_eq!(parse_vec3("1,x,3"), None);
+		assert_eq!(format_vec3([1.5, 0.0, -2.0]), "1.5,0,-2");
+	}
+
+	#[test]
+	fn colors() {
+		assert_eq!(parse_color("#ff8000"), Some([255, 128, 0, 255]));
+		assert_eq!(parse_color("#FF800040"), Some([255, 128, 0, 64]));
+		assert_eq!(parse_color("ff8000"), None);
+		assert_eq!(parse_color("#ff80"), None);
+		assert_eq!(parse_color("#gg8000"), None);
+		assert_eq!(format_color([255, 128, 0, 64]), "#ff800040");
+	}
 }
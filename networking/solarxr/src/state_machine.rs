@@ -1,7 +1,7 @@
 use super::data::FeedUpdate;
 use super::Wss;
 use crate::settings::DisplaySettings;
-use crate::{Data, DecodeError};
+use crate::{ConnectOptions, Data, DecodeError};
 
 use futures_util::stream::SplitStream;
 use futures_util::{Sink, SinkExt, StreamExt};
@@ -10,8 +10,11 @@ use solarxr_protocol::MessageBundle;
 use std::fmt::Debug;
 use std::future;
 use std::pin::Pin;
-use tokio_tungstenite::{connect_async, tungstenite};
-use tungstenite::error::Error as WsError;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{
+	connect_async, connect_async_tls_with_config, tungstenite, Connector,
+};
+use tungstenite::error::{Error as WsError, TlsError};
 use tungstenite::Message;
 
 type DeserializeFn = fn(Result<Message, WsError>) -> Result<Data, DeserializeError>;
@@ -26,6 +29,7 @@ impl<T> SlimeSinkT for T where T: Sink<Data, Error = WsError> + Send + Sync + De
 #[derive(Debug)]
 struct Common {
 	connect_to: String,
+	options: ConnectOptions,
 }
 #[derive(Debug)]
 pub struct ClientStateMachine<State = Disconnected> {
@@ -34,10 +38,13 @@ pub struct ClientStateMachine<State = Disconnected> {
 }
 impl ClientStateMachine {
 	/// Creates a new `NetworkStateMachine`. This starts in the [`Disconnected`] state.
-	pub fn new(connect_to: String) -> Self {
+	pub fn new(connect_to: String, options: ConnectOptions) -> Self {
 		Self {
 			state: Disconnected,
-			common: Common { connect_to },
+			common: Common {
+				connect_to,
+				options,
+			},
 		}
 	}
 }
@@ -71,7 +78,29 @@ pub enum DeserializeError {
 pub struct Disconnected;
 impl M<Disconnected> {
 	pub async fn connect(self) -> Result<M<Connected>, (Self, WsError)> {
-		match connect_async(&self.common.connect_to).await {
+		let connect_to = &self.common.connect_to;
+		// `wss://` URLs use the system's TLS implementation either way, we only need
+		// to configure it ourselves to let invalid certificates through
+		let result = if self.common.options.accept_invalid_certs {
+			let connector = native_tls::TlsConnector::builder()
+				.danger_accept_invalid_certs(true)
+				.danger_accept_invalid_hostnames(true)
+				.build();
+			match connector {
+				Ok(c) => {
+					connect_async_tls_with_config(
+						connect_to,
+						None,
+						Some(Connector::NativeTls(c)),
+					)
+					.await
+				}
+				Err(e) => Err(WsError::Tls(TlsError::Native(e))),
+			}
+		} else {
+			connect_async(connect_to).await
+		};
+		match result {
 			Ok((socket, _)) => {
 				let (sink, stream) = socket.split();
 
@@ -119,83 +148,30 @@ pub struct Connected {
 	fbb: FlatBufferBuilder<'static>,
 }
 impl M<Connected> {
-	/// Sends a `StartDataFeed`, `pub_sub::SubscriptionRequest`, `pub_sub::Message` with
-	/// the initial [`DisplaySettings`]
+	/// Sends a `StartDataFeed` for [`ConnectOptions::feed`], a
+	/// `pub_sub::SubscriptionRequest` for the display settings and the snapshot
+	/// topics, and a `pub_sub::Message` with the initial [`DisplaySettings`]
 	pub async fn request_feed(mut self) -> Result<M<Active>, RecvError> {
 		use solarxr_protocol::MessageBundleArgs;
 		let fbb = &mut self.state.fbb;
 		#[allow(clippy::needless_update)]
 		let data = {
 			let data_feed_header = {
-				use solarxr_protocol::data_feed::tracker::{
-					TrackerDataMask, TrackerDataMaskArgs,
-				};
-				use solarxr_protocol::data_feed::{
-					DataFeedConfig, DataFeedConfigArgs, DataFeedMessage,
-					DataFeedMessageHeader, DataFeedMessageHeaderArgs, StartDataFeed,
-					StartDataFeedArgs,
-				};
-
-				let _tracker_mask = TrackerDataMask::create(
-					fbb,
-					&TrackerDataMaskArgs {
-						// TODO: We only need the body part here, not the whole TrackerInfo
-						info: true,
-						rotation: true,
-						position: true,
-						..Default::default()
-					},
-				);
-
-				let data_feed_config = DataFeedConfig::create(
-					fbb,
-					&DataFeedConfigArgs {
-						minimum_time_since_last: 10,
-						// We don't care about anything but bones
-						bone_mask: true,
-						..Default::default()
-					},
-				);
-				let data_feed_config = fbb.create_vector(&[data_feed_config]);
-
-				let start_data_feed = StartDataFeed::create(
-					fbb,
-					&StartDataFeedArgs {
-						data_feeds: Some(data_feed_config),
-					},
-				);
-				let header = DataFeedMessageHeader::create(
-					fbb,
-					&DataFeedMessageHeaderArgs {
-						message_type: DataFeedMessage::StartDataFeed,
-						message: Some(start_data_feed.as_union_value()),
-						..Default::default()
-					},
-				);
+				let header = self.common.options.feed.to_fb(fbb);
 				fbb.create_vector(&[header])
 			};
 			let pub_sub_header = {
-				use crate::topic::{TOPIC_APP, TOPIC_DISPLAY_SETTINGS, TOPIC_ORG};
+				use solarxr_protocol::flatbuffers::WIPOffset;
 				use solarxr_protocol::pub_sub::{
-					KeyValues, KeyValuesArgs, Message, MessageArgs, Payload,
 					PubSubHeader, PubSubHeaderArgs, PubSubUnion, SubscriptionRequest,
-					SubscriptionRequestArgs, Topic, TopicId, TopicIdArgs,
+					SubscriptionRequestArgs, Topic, TopicId,
 				};
 
-				let organization = fbb.create_string(TOPIC_ORG);
-				let app_name = fbb.create_string(TOPIC_APP);
-				let topic = fbb.create_string(TOPIC_DISPLAY_SETTINGS);
-				let topic = TopicId::create(
-					fbb,
-					&TopicIdArgs {
-						organization: Some(organization),
-						app_name: Some(app_name),
-						topic: Some(topic),
-						..Default::default()
-					},
-				);
-
-				let subscription_request = {
+				/// Subscribes to `topic`
+				fn subscribe<'a>(
+					fbb: &mut FlatBufferBuilder<'a>,
+					topic: WIPOffset<TopicId<'a>>,
+				) -> WIPOffset<PubSubHeader<'a>> {
 					let sr = SubscriptionRequest::create(
 						fbb,
 						&SubscriptionRequestArgs {
@@ -212,54 +188,19 @@ impl M<Connected> {
 							..Default::default()
 						},
 					)
-				};
+				}
+				let topic = crate::topic::create_topic_id(fbb);
+				let subscription_request = subscribe(fbb, topic);
+				let topic = crate::topic::create_snapshot_topic_id(fbb);
+				let snapshot_request = subscribe(fbb, topic);
 
-				let initial_state = {
-					let keys =
-						[DisplaySettings::IS_VISIBLE, DisplaySettings::IS_MIRRORED]
-							.map(|s| fbb.create_string(s));
+				let initial_state = DisplaySettings::default().to_message(fbb);
 
-					let keys = fbb.create_vector(&keys);
-					let ds = DisplaySettings::default();
-					const fn as_str(b: bool) -> &'static str {
-						if b {
-							"true"
-						} else {
-							"false"
-						}
-					}
-					let values = [as_str(ds.is_visible), as_str(ds.is_mirrored)]
-						.map(|s| fbb.create_string(s));
-					let values = fbb.create_vector(&values);
-					let kv = KeyValues::create(
-						fbb,
-						&KeyValuesArgs {
-							keys: Some(keys),
-							values: Some(values),
-							..Default::default()
-						},
-					);
-					let m = Message::create(
-						fbb,
-						&MessageArgs {
-							topic_type: Topic::TopicId,
-							topic: Some(topic.as_union_value()),
-							payload_type: Payload::KeyValues,
-							payload: Some(kv.as_union_value()),
-							..Default::default()
-						},
-					);
-					PubSubHeader::create(
-						fbb,
-						&PubSubHeaderArgs {
-							u_type: PubSubUnion::Message,
-							u: Some(m.as_union_value()),
-							..Default::default()
-						},
-					)
-				};
-
-				fbb.create_vector(&[initial_state, subscription_request])
+				fbb.create_vector(&[
+					initial_state,
+					subscription_request,
+					snapshot_request,
+				])
 			};
 			let root = MessageBundle::create(
 				fbb,
@@ -309,7 +250,7 @@ impl M<Connected> {
 		Ok(M {
 			common: self.common,
 			state: Active {
-				_sink: self.state.sink,
+				sink: self.state.sink,
 				stream: self.state.stream,
 				topic_handle: 0,
 			},
@@ -320,15 +261,55 @@ impl M<Connected> {
 /// Datafeed is active
 #[derive(Debug)]
 pub struct Active {
-	_sink: Pin<SlimeSink>,
+	sink: Pin<SlimeSink>,
 	stream: SlimeStream,
 	#[allow(unused)]
 	topic_handle: u32,
 }
 impl M<Active> {
+	pub async fn send(
+		mut self,
+		data: Data,
+	) -> Result<Self, (M<Disconnected>, WsError)> {
+		let mut sink = self.state.sink.as_mut();
+		match sink.send(data).await {
+			Ok(()) => Ok(self),
+			Err(err) => Err((self.into_state(Disconnected), err)),
+		}
+	}
+
 	pub async fn recv(mut self) -> RecvResult {
+		let next = self.state.stream.next().await;
+		self.received(next)
+	}
+
+	/// Same as [`Self::recv()`], but sends everything that arrives on `outgoing` while
+	/// it waits. A failed send ends the connection like a failed receive does.
+	pub async fn recv_sending(
+		mut self,
+		outgoing: &mut mpsc::UnboundedReceiver<Data>,
+	) -> RecvResult {
+		// Once every sender is gone, `outgoing` would be ready forever
+		let mut open = true;
+		loop {
+			// Both are cancel safe, so whichever loses the race doesn't lose anything
+			tokio::select! {
+				next = self.state.stream.next() => return self.received(next),
+				data = outgoing.recv(), if open => match data {
+					Some(data) => match self.send(data).await {
+						Ok(a) => self = a,
+						Err((d, err)) => return Err(RecvError::CriticalWs(d, err)),
+					},
+					None => open = false,
+				},
+			}
+		}
+	}
+
+	/// Turns the `next` item of the stream into the result of a receive
+	fn received(self, next: Option<Result<Data, DeserializeError>>) -> RecvResult {
 		use RecvError as E;
-		match self.state.stream.next().await {
+		match next {
 			Some(Ok(v)) => Ok((self, FeedUpdate(v))),
 			Some(Err(DeserializeError::Ws(ws_err))) => {
 				Err(E::CriticalWs(self.into_state(Disconnected), ws_err))
@@ -1,8 +1,41 @@
-use solarxr_protocol::pub_sub::Message;
+use solarxr_protocol::flatbuffers::{FlatBufferBuilder, WIPOffset};
+use solarxr_protocol::pub_sub::{Message, TopicId, TopicIdArgs};
 
 pub const TOPIC_ORG: &str = "slimevr.dev";
 pub const TOPIC_APP: &str = "overlay";
 pub const TOPIC_DISPLAY_SETTINGS: &str = "display_settings";
+/// Any message on this topic asks the overlay to save the current pose to a file
+pub const TOPIC_SNAPSHOT: &str = "snapshot";
+
+/// Builds the [`TopicId`] of the overlay's display settings
+pub fn create_topic_id<'a>(fbb: &mut FlatBufferBuilder<'a>) -> WIPOffset<TopicId<'a>> {
+	create_overlay_topic_id(fbb, TOPIC_DISPLAY_SETTINGS)
+}
+
+/// Builds the [`TopicId`] of the overlay's pose snapshots
+pub fn create_snapshot_topic_id<'a>(
+	fbb: &mut FlatBufferBuilder<'a>,
+) -> WIPOffset<TopicId<'a>> {
+	create_overlay_topic_id(fbb, TOPIC_SNAPSHOT)
+}
+
+fn create_overlay_topic_id<'a>(
+	fbb: &mut FlatBufferBuilder<'a>,
+	topic: &str,
+) -> WIPOffset<TopicId<'a>> {
+	let organization = fbb.create_string(TOPIC_ORG);
+	let app_name = fbb.create_string(TOPIC_APP);
+	let topic = fbb.create_string(topic);
+	TopicId::create(
+		fbb,
+		&TopicIdArgs {
+			organization: Some(organization),
+			app_name: Some(app_name),
+			topic: Some(topic),
+			..Default::default()
+		},
+	)
+}
 
 pub fn is_overlay_topic(msg: Message<'_>) -> bool {
 	if let Some(topic_id) = msg.topic_as_topic_id() {
@@ -16,3 +49,13 @@ pub fn is_overlay_topic(msg: Message<'_>) -> bool {
 		false
 	}
 }
+
+/// Whether `msg` asks for a snapshot of the pose. Unlike [`is_overlay_topic`], topic
+/// handles are never taken for this topic, since we can't tell them apart yet.
+pub fn is_snapshot_topic(msg: Message<'_>) -> bool {
+	msg.topic_as_topic_id().map_or(false, |topic_id| {
+		matches!(topic_id.topic(), Some(TOPIC_SNAPSHOT))
+			&& matches!(topic_id.organization(), Some(TOPIC_ORG))
+			&& matches!(topic_id.app_name(), Some(TOPIC_APP))
+	})
+}
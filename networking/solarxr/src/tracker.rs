@@ -0,0 +1,105 @@
+use crate::FeedUpdate;
+
+use solarxr_protocol::data_feed::device_data::DeviceData;
+use solarxr_protocol::data_feed::tracker::TrackerData;
+use solarxr_protocol::datatypes::{BodyPart, TrackerStatus};
+
+/// The status of a single tracker, as reported in the data feed. Anything the server
+/// didn't send is `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackerState {
+	/// The body part the tracker is assigned to
+	pub body_part: Option<BodyPart>,
+	pub status: Option<TrackerStatus>,
+	/// Estimated battery charge of the tracker's device, from 0 to 100
+	pub battery_pct: Option<u8>,
+	pub battery_voltage: Option<f32>,
+}
+impl TrackerState {
+	/// Builds `TrackerState` from a tracker and the device it belongs to
+	pub fn from_fb(device: DeviceData<'_>, tracker: TrackerData<'_>) -> Self {
+		let hw_status = device.hardware_status();
+		Self {
+			body_part: tracker
+				.info()
+				.map(|i| i.body_part())
+				.filter(|&p| p != BodyPart::NONE),
+			status: Some(tracker.status()).filter(|&s| s != TrackerStatus::NONE),
+			battery_pct: hw_status.and_then(|s| s.battery_pct_estimate()),
+			battery_voltage: hw_status.and_then(|s| s.battery_voltage()),
+		}
+	}
+
+	pub fn is_connected(&self) -> Option<bool> {
+		self.status.map(|s| {
+			!matches!(s, TrackerStatus::DISCONNECTED | TrackerStatus::TIMED_OUT)
+		})
+	}
+
+	/// Whether the tracker is busy, for example calibrating
+	pub fn is_busy(&self) -> Option<bool> {
+		self.status.map(|s| s == TrackerStatus::BUSY)
+	}
+}
+
+impl FeedUpdate {
+	/// The status of every tracker of every device in this update
+	pub fn trackers(&self) -> Vec<TrackerState> {
+		let mut result = Vec::new();
+		let Some(msgs) = self.0.table().data_feed_msgs() else {
+			return result;
+		};
+		for m in msgs {
+			let update = m.message_as_data_feed_update();
+			let Some(devices) = update.and_then(|u| u.devices()) else {
+				continue;
+			};
+			for device in devices {
+				let Some(trackers) = device.trackers() else {
+					continue;
+				};
+				let states = trackers.iter().map(|t| TrackerState::from_fb(device, t));
+				result.extend(states);
+			}
+		}
+		result
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use solarxr_protocol::data_feed::device_data::DeviceDataArgs;
+	use solarxr_protocol::data_feed::tracker::TrackerDataArgs;
+	use solarxr_protocol::flatbuffers::{root, FlatBufferBuilder};
+
+	#[test]
+	fn missing_fields_are_none() {
+		let mut fbb = FlatBufferBuilder::new();
+		let tracker = TrackerData::create(&mut fbb, &TrackerDataArgs::default());
+		let trackers = fbb.create_vector(&[tracker]);
+		let device = DeviceData::create(
+			&mut fbb,
+			&DeviceDataArgs {
+				trackers: Some(trackers),
+				..Default::default()
+			},
+		);
+		fbb.finish(device, None);
+		let device = root::<DeviceData>(fbb.finished_data()).unwrap();
+
+		let state = TrackerState::from_fb(device, device.trackers().unwrap().get(0));
+		assert_eq!(
+			state,
+			TrackerState {
+				body_part: None,
+				status: None,
+				battery_pct: None,
+				battery_voltage: None,
+			}
+		);
+		assert_eq!(state.is_connected(), None);
+		assert_eq!(state.is_busy(), None);
+	}
+}
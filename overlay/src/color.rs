@@ -35,3 +35,8 @@ impl RGBA {
 	def_color!(FUCHSIA, 255, 0, 255);
 	def_color!(PURPLE, 128, 0, 128);
 }
+impl From<[u8; 4]> for RGBA {
+	fn from([r, g, b, a]: [u8; 4]) -> Self {
+		Self::new(r, g, b, a)
+	}
+}
@@ -0,0 +1,40 @@
+//! Keeps the feet of the skeleton on the floor of SteamVR, for when the height of the
+//! feed doesn't match the play space.
+//!
+//! The offset follows the lowest foot through a low-pass filter, so that a foot
+//! tracker that glitches for a few frames, or a jump, doesn't make the whole skeleton
+//! bounce.
+
+use std::time::{Duration, Instant};
+
+/// How long the offset takes to get most (63%) of the way to a new floor
+const TIME_CONSTANT: Duration = Duration::from_millis(500);
+
+/// The vertical offset that puts the lowest foot on the floor, see the
+/// [module](self) documentation.
+#[derive(Debug, Default)]
+pub struct FloorOffset {
+	offset: f32,
+	updated: Option<Instant>,
+}
+impl FloorOffset {
+	/// Moves the offset towards the one that lifts `lowest`, the height of the lowest
+	/// foot, to the floor at height 0, and returns it. Without a usable foot, the
+	/// offset stays where it was.
+	pub fn update(&mut self, lowest: Option<f32>, now: Instant) -> f32 {
+		let dt = self.updated.map_or(Duration::ZERO, |t| now - t);
+		self.updated = Some(now);
+		let Some(lowest) = lowest.filter(|l| l.is_finite()) else {
+			return self.offset;
+		};
+		let alpha = 1. - (-dt.as_secs_f32() / TIME_CONSTANT.as_secs_f32()).exp();
+		self.offset += (-lowest - self.offset) * alpha;
+		self.offset
+	}
+
+	/// Goes back to no offset, for when grounding is turned off. Turning it back on
+	/// then slides the skeleton to the floor instead of jumping.
+	pub fn reset(&mut self) {
+		*self = Self::default();
+	}
+}
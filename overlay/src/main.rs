@@ -1,43 +1,147 @@
 mod color;
+mod floor;
+mod metrics;
 mod model;
+mod remap;
 
 pub use self::color::RGBA;
 
-use crate::model::skeleton::SkeletonBuilder;
-use crate::model::{BoneKind, Isometry};
+use crate::floor::FloorOffset;
+use crate::metrics::Metrics;
+use crate::model::skeleton::{Skeleton, SkeletonBuilder};
+use crate::model::{BoneKind, BoneMap, Isometry};
+use crate::remap::{conventional_axes, remap_rotation, Axis};
 
 use clap::Parser;
-use eyre::{Result, WrapErr};
+use eyre::{bail, Result, WrapErr};
 use git_version::git_version;
-use nalgebra::{Translation3, UnitQuaternion};
+use nalgebra::{Point3, Translation3, UnitQuaternion};
 use ovr_overlay as ovr;
+use skeletal_model::pose::Pose;
+use skeletal_model::proportions::{Proportions, DEFAULT_HEIGHT};
 use solarxr::settings::DisplaySettings;
-use solarxr::FeedUpdate;
-use std::collections::HashSet;
-use std::time::Duration;
-use tokio::sync::watch;
+use solarxr::{ConnectOptions, ConnectionStatus, Data, FeedConfig, FeedUpdate};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{self, MissedTickBehavior};
 use tokio_graceful_shutdown::{SubsystemHandle, Toplevel};
 
-const CONNECT_STR: &str = "ws://localhost:21110";
+const DEFAULT_SERVER: &str = "ws://localhost:21110";
 const GIT_VERSION: &str = git_version!();
+/// Bones that weren't in the feed for this long are drawn in [`STALE_COLOR`], so that
+/// a tracker that dropped out can be told apart from one that was never there
+const STALE_AFTER: Duration = Duration::from_millis(500);
+/// Bones that weren't in the feed for this long are hidden, and a skeleton with none
+/// that were is removed
+const HIDE_AFTER: Duration = Duration::from_secs(5);
+const STALE_COLOR: RGBA = RGBA::GRAY;
+/// Bones with an invalid pose are reported at most this often, since a broken tracker
+/// would otherwise log on every update
+const INVALID_WARNING_INTERVAL: Duration = Duration::from_secs(5);
+/// While the server can't be reached, we say that we are still waiting for it at
+/// most this often
+const WAITING_LOG_INTERVAL: Duration = Duration::from_secs(30);
 
 #[derive(Parser, Debug)]
 #[command(version = GIT_VERSION)]
-struct Args {}
+struct Args {
+	/// The websocket URL of the SlimeVR server
+	#[arg(short, long, env = "SLIMEVR_SERVER", default_value = DEFAULT_SERVER)]
+	server: String,
+	/// Accept any certificate from a `wss://` server, such as a self signed one. Only
+	/// use this on networks you trust.
+	#[arg(long)]
+	accept_invalid_certs: bool,
+	/// Exit with an error after this many failed attempts in a row to connect to the
+	/// server, instead of retrying forever. `0` gives up after the first attempt.
+	#[arg(long)]
+	max_retries: Option<u32>,
+	/// Render at most this many times per second, instead of on every update
+	#[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+	max_fps: Option<u32>,
+	/// Draw the local X (red), Y (green) and Z (blue) axes at the head of every bone,
+	/// to check how the trackers are mounted
+	#[arg(long)]
+	axis_gizmos: bool,
+	/// Where to save the pose when a snapshot is requested on the `snapshot` pub-sub
+	/// topic
+	#[arg(long, default_value = ".")]
+	snapshot_dir: PathBuf,
+	/// Your height in meters. Bones that the server sends without a usable length are
+	/// drawn with the typical length for this height.
+	#[arg(long, default_value_t = DEFAULT_HEIGHT)]
+	height: f32,
+	/// The axis of the feed that points up, like `+y` or `-z`. Together with
+	/// `--feed-forward`, this turns the feed into the frame of SteamVR, where `+y` is
+	/// up and `-z` is forward.
+	#[arg(long, default_value_t = Axis::POS_Y, allow_hyphen_values = true)]
+	feed_up: Axis,
+	/// The axis of the feed that points forward
+	#[arg(long, default_value_t = Axis::NEG_Z, allow_hyphen_values = true)]
+	feed_forward: Axis,
+	/// Turns the whole feed this many degrees to the left, for when the playspace
+	/// faces another way than the server
+	#[arg(long, default_value_t = 0., allow_hyphen_values = true)]
+	feed_yaw: f32,
+}
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum ShutdownReason {
-	CtrlC,
+/// The options from the command line that the overlay loop uses
+#[derive(Debug)]
+struct OverlayOptions {
+	max_fps: Option<u32>,
+	axis_gizmos: bool,
+	snapshot_dir: PathBuf,
+	proportions: Proportions,
+	/// Turns the feed into the frame of the overlay, before any other offset
+	remap: UnitQuaternion<f32>,
 }
 
-macro_rules! unwrap_or_continue {
-	($e:expr) => {{
-		if let Some(inner) = $e {
-			inner
-		} else {
-			continue;
+/// A bone as it was in the feed
+#[derive(Debug, Clone, Copy)]
+struct BoneInfo {
+	kind: BoneKind,
+	pos: Translation3<f32>,
+	rot: UnitQuaternion<f32>,
+	length: f32,
+}
+
+/// The skeleton of one data feed, and what we know of its bones
+struct FeedSkeleton {
+	skeleton: Skeleton,
+	/// When each bone was last in the feed
+	last_seen: BoneMap<Option<Instant>>,
+	/// The latest of each bone in the feed, for snapshots
+	latest: BoneMap<Option<BoneInfo>>,
+	floor: FloorOffset,
+}
+impl FeedSkeleton {
+	fn new(skeleton: Skeleton) -> Self {
+		Self {
+			skeleton,
+			last_seen: BoneMap::default(),
+			latest: BoneMap::default(),
+			floor: FloorOffset::default(),
 		}
-	}};
+	}
+
+	/// Whether none of the bones were in the feed for [`HIDE_AFTER`], so the whole
+	/// skeleton is hidden
+	fn is_gone(&self, now: Instant) -> bool {
+		self.last_seen
+			.iter()
+			.all(|(_kind, t)| t.map_or(true, |t| now - t >= HIDE_AFTER))
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownReason {
+	CtrlC,
 }
 
 #[tokio::main]
@@ -48,11 +152,52 @@ pub async fn main() -> Result<()> {
 	pretty_env_logger::init();
 	color_eyre::install()?;
 
-	let _args = Args::parse();
+	let args = Args::parse();
 	log::info!("Overlay version: {GIT_VERSION}");
+	check_server(&args.server)?;
+	let Args {
+		server,
+		accept_invalid_certs,
+		max_retries,
+		max_fps,
+		axis_gizmos,
+		snapshot_dir,
+		height,
+		feed_up,
+		feed_forward,
+		feed_yaw,
+	} = args;
+	if !(height.is_finite() && height > 0.) {
+		bail!("Height must be a positive number of meters, not {height}");
+	}
+	let remap = remap_rotation(feed_up, feed_forward, feed_yaw)?;
+	if remap != UnitQuaternion::identity() {
+		log::info!(
+			"Remapping the feed: {feed_up} is up, {feed_forward} is forward, \
+			 turned {feed_yaw} degrees left"
+		);
+	}
+	log::info!("Using server {server}");
+	if accept_invalid_certs {
+		log::warn!("Accepting invalid TLS certificates");
+	}
+	let options = ConnectOptions {
+		accept_invalid_certs,
+		// We only render the bones
+		feed: FeedConfig::new().bones(true),
+	};
+	let overlay_options = OverlayOptions {
+		max_fps,
+		axis_gizmos,
+		snapshot_dir,
+		proportions: Proportions::new(height),
+		remap,
+	};
 
 	Toplevel::new()
-		.start("Networking", networking)
+		.start("Networking", move |s| {
+			networking(server, options, max_retries, overlay_options, s)
+		})
 		.catch_signals()
 		.handle_shutdown_requests(Duration::from_millis(1000))
 		.await
@@ -62,123 +207,239 @@ pub async fn main() -> Result<()> {
 async fn overlay(
 	mut recv: watch::Receiver<Option<FeedUpdate>>,
 	display_settings: watch::Receiver<DisplaySettings>,
+	shown: watch::Receiver<bool>,
+	mut snapshots: mpsc::UnboundedReceiver<()>,
+	options: OverlayOptions,
+	metrics: Arc<Metrics>,
 	subsys: SubsystemHandle,
 ) -> Result<()> {
+	let OverlayOptions {
+		max_fps,
+		axis_gizmos,
+		snapshot_dir,
+		proportions,
+		remap,
+	} = options;
+	let remap = Isometry::from_parts(Translation3::identity(), remap);
 	log::info!("Initializing OpenVR context");
 	let context = ovr::Context::init().wrap_err("Failed to initialize OpenVR")?;
 	let mngr = &mut context.overlay_mngr();
 
-	let mut skeleton = SkeletonBuilder::default()
-		.build(mngr)
-		.wrap_err("Could not create skeleton")?;
-
 	log::info!("Overlay Loop");
 
 	let loop_ = async {
-		let mut hidden_bones: HashSet<BoneKind> = HashSet::new();
+		// Created when a data feed first sends bones, and removed when it stops
+		let mut skeletons: HashMap<usize, FeedSkeleton> = HashMap::new();
+		// Bones with an invalid pose that weren't reported yet, and when we last did
+		let mut invalid_unreported = 0;
+		let mut last_invalid_warning: Option<Instant> = None;
+		let mut frame_interval = max_fps.map(|fps| {
+			log::info!("Limiting the overlay to {fps} fps");
+			let mut i = time::interval(Duration::from_secs(1) / fps);
+			// Don't render several frames in a row to catch up after a slow update
+			i.set_missed_tick_behavior(MissedTickBehavior::Delay);
+			i
+		});
 		loop {
 			recv.changed()
 				.await
 				.wrap_err("Error while attempting to watch for feed update")?;
-			let is_skeleton_visible = display_settings.borrow().is_visible;
+			// Updates that arrive while we wait replace this one, so we still render
+			// the latest state
+			if let Some(i) = &mut frame_interval {
+				i.tick().await;
+			}
+			// Copy the settings, so that the networking isn't blocked on the lock
+			let ds = display_settings.borrow().clone();
+			// Hidden locally on top of the settings, so that neither undoes the other
+			let shown = *shown.borrow();
+			let mut colors: BoneMap<Option<RGBA>> = BoneMap::default();
+			for (&part, &color) in &ds.colors {
+				if let Ok(kind) = BoneKind::try_from(part) {
+					colors[kind] = Some(color.into());
+				}
+			}
+			let force_hidden: HashSet<BoneKind> = ds
+				.hidden
+				.iter()
+				.filter_map(|&part| BoneKind::try_from(part).ok())
+				.collect();
+			let offset = offset_isometry(&ds);
 
 			log::trace!("Got a feed update");
+			let now = Instant::now();
 
-			// Mark all bones as "need to hide"
-			hidden_bones.extend(BoneKind::iter());
-
-			#[derive(Debug)]
-			struct BoneInfo {
-				kind: BoneKind,
-				pos: Translation3<f32>,
-				rot: UnitQuaternion<f32>,
-				length: f32,
-			}
-			// Extract relevant data about bones from flatbuffers
-			let bones: Vec<BoneInfo> = {
+			// Extract relevant data about bones from flatbuffers. Keep the bones of
+			// every update in order, so that applying them leaves the latest pose
+			let mut invalid: Vec<BoneKind> = Vec::new();
+			let bones: Vec<(usize, BoneInfo)> = {
 				let guard = recv.borrow_and_update();
-				let table = guard.as_ref().unwrap().0.table();
-				log::trace!("update: {:#?}", table);
-
-				let m = unwrap_or_continue!(table.data_feed_msgs());
-
-				// TODO: handle multiple updates?
-				let m = m.get(0);
-				let m = unwrap_or_continue!(m.message_as_data_feed_update());
-				let bones = unwrap_or_continue!(m.bones());
-				log::debug!("Got {} bones before filtering", bones.len());
-
-				bones
-					.iter()
-					.filter_map(|b| {
-						let part = b.body_part();
-						log::trace!("body_part: {part:?}");
-						let bone_kind = BoneKind::try_from(part)
-							.map_err(|e| {
-								log::trace!("Filtering out {e:?}");
-								e
-							})
-							.ok()?;
-						let pos = if let Some(p) = b.head_position_g() {
-							p
-						} else {
-							log::warn!("No position");
-							return None;
-						};
-						let rot = if let Some(r) = b.rotation_g() {
-							r
-						} else {
-							log::warn!("No rotation");
+				let update = guard.as_ref().unwrap();
+				log::trace!("update: {:#?}", update.0.table());
+
+				let msgs = update.0.table().data_feed_msgs();
+				if msgs.map_or(true, |msgs| msgs.is_empty()) {
+					log::trace!("No data feed messages in update");
+					metrics.frame_skipped();
+					continue;
+				}
+				update
+					.feed_bones::<BoneKind>()
+					.filter_map(|(feed, kind, iso, length)| {
+						// A NaN would end up in the transform of the overlay, so the
+						// bone stays where it was for this frame instead
+						if !solarxr::is_valid_pose(&iso) {
+							invalid.push(kind);
 							return None;
-						};
-						let length = b.bone_length();
-
-						let pos = Translation3::new(pos.x(), pos.y(), pos.z());
-						let rot = UnitQuaternion::from_quaternion(
-							[rot.x(), rot.y(), rot.z(), rot.w()].into(),
-						);
-						if is_skeleton_visible {
-							hidden_bones.remove(&bone_kind);
 						}
-						Some(BoneInfo {
-							kind: bone_kind,
-							pos,
-							rot,
+						let bone = BoneInfo {
+							kind,
+							pos: iso.translation,
+							rot: iso.rotation,
 							length,
-						})
+						};
+						Some((feed, bone))
 					})
 					.collect()
 			};
+			if !invalid.is_empty() {
+				metrics.bones_invalid(invalid.len());
+				invalid_unreported += invalid.len();
+				let due = last_invalid_warning
+					.map_or(true, |t| now - t >= INVALID_WARNING_INTERVAL);
+				if due {
+					log::warn!(
+						"Skipped {invalid_unreported} bones with a NaN, infinite or \
+						 non-unit pose, most recently {invalid:?}"
+					);
+					invalid_unreported = 0;
+					last_invalid_warning = Some(now);
+				}
+			}
 
 			log::debug!(
 				"Bones after filtering: {:?}",
-				bones.iter().map(|t| t.kind).collect::<Vec<_>>()
+				bones
+					.iter()
+					.map(|(feed, t)| (feed, t.kind))
+					.collect::<Vec<_>>()
 			);
 			log::trace!("Bone data: {bones:?}");
 
-			// Update all bones in datafeed
-			for BoneInfo {
-				kind,
-				pos,
-				rot,
-				length,
-			} in bones
-			{
-				let iso = Isometry {
-					rotation: rot,
-					translation: pos,
+			for &(feed, bone) in &bones {
+				let state = match skeletons.entry(feed) {
+					Entry::Occupied(entry) => entry.into_mut(),
+					Entry::Vacant(entry) => {
+						log::info!("Adding a skeleton for data feed {feed}");
+						let skeleton = SkeletonBuilder::default()
+							.key(format!("slimevr {feed}"))
+							.axis_gizmos(axis_gizmos)
+							.build(mngr)
+							.wrap_err("Could not create skeleton")?;
+						entry.insert(FeedSkeleton::new(skeleton))
+					}
 				};
-				skeleton.set_isometry(kind, iso);
-				skeleton.set_length(kind, length);
+				state.last_seen[bone.kind] = Some(now);
+				state.latest[bone.kind] = Some(bone);
+			}
+
+			// Give the overlays of feeds that stopped sending bones back to OpenVR
+			let gone: Vec<usize> = skeletons
+				.iter()
+				.filter(|(_feed, state)| state.is_gone(now))
+				.map(|(&feed, _state)| feed)
+				.collect();
+			for feed in gone {
+				log::info!("Removing the skeleton of data feed {feed}");
+				let state = skeletons.remove(&feed).unwrap();
+				if let Err(e) = state.skeleton.destroy(mngr) {
+					log::error!("Error removing skeleton of data feed {feed}: {e:?}");
+				}
+			}
+
+			// Several requests since the last update would all save the same pose
+			let mut snapshot_requested = false;
+			while snapshots.try_recv().is_ok() {
+				snapshot_requested = true;
 			}
+			if snapshot_requested {
+				for (feed, state) in &skeletons {
+					match save_snapshot(&snapshot_dir, &state.latest) {
+						Ok(path) => log::info!(
+							"Saved pose snapshot of data feed {feed} to {}",
+							path.display()
+						),
+						Err(e) => log::error!(
+							"Could not save pose snapshot of data feed {feed}: {e:?}"
+						),
+					}
+				}
+			}
+
+			for (&feed, state) in &mut skeletons {
+				let FeedSkeleton {
+					skeleton,
+					last_seen,
+					latest,
+					floor,
+				} = state;
+				// The whole skeleton moves by the same amount, even the bones that
+				// weren't in this update
+				let ground = if ds.ground_to_floor {
+					let lowest = lowest_foot(
+						latest,
+						last_seen,
+						now,
+						&(offset * remap),
+						&proportions,
+					);
+					floor.update(lowest, now)
+				} else {
+					floor.reset();
+					0.
+				};
+				let ground = Translation3::from(*conventional_axes()[1] * ground);
+
+				// Update all bones of this feed
+				for &(_feed, bone) in bones.iter().filter(|(f, _bone)| *f == feed) {
+					let BoneInfo {
+						kind,
+						pos,
+						rot,
+						length,
+					} = bone;
+					let iso = Isometry {
+						rotation: rot,
+						translation: pos,
+					};
+					skeleton.set_isometry(kind, ground * offset * remap * iso);
+					let length = display_length(&proportions, kind, length);
+					skeleton.set_length(kind, length);
+				}
 
-			// Update rendering state
-			for kind in BoneKind::iter() {
-				skeleton.set_visibility(kind, !hidden_bones.contains(&kind));
-				if let Err(e) = skeleton.update_render(kind, mngr) {
-					log::error!("Error updating render for bone {kind:?}: {:?}", e);
+				// Update rendering state
+				skeleton.set_opacity(ds.opacity);
+				skeleton.set_thickness(ds.thickness);
+				for kind in BoneKind::iter() {
+					let age = last_seen[kind].map(|t| now - t);
+					// Bones hidden by the user stay hidden, whatever the feed says
+					let is_visible = ds.is_visible
+						&& shown && !force_hidden.contains(&kind)
+						&& age.map_or(false, |age| age < HIDE_AFTER);
+					let is_stale = age.map_or(false, |age| age >= STALE_AFTER);
+					let color = if is_stale {
+						Some(STALE_COLOR)
+					} else {
+						colors[kind]
+					};
+					skeleton.set_visibility(kind, is_visible);
+					skeleton.set_color(kind, color);
+					if let Err(e) = skeleton.update_render(kind, mngr) {
+						log::error!("Error updating render for bone {kind:?}: {:?}", e);
+					}
 				}
 			}
+			metrics.frame_rendered();
 		}
 	};
 	tokio::select! {
@@ -194,23 +455,204 @@ async fn overlay(
 	Ok(())
 }
 
-async fn networking(subsys: SubsystemHandle) -> Result<()> {
+/// The isometry that moves the skeleton by the offsets in `ds`
+fn offset_isometry(ds: &DisplaySettings) -> Isometry {
+	let [right, up, forward] = conventional_axes();
+
+	let [x, y, z] = ds.offset_position;
+	let translation = Translation3::from(*right * x + *up * y + *forward * z);
+
+	let [yaw, pitch, roll] = ds.offset_rotation.map(f32::to_radians);
+	// Yaw left and pitch up are counter-clockwise about `up` and `right`, but roll is
+	// clockwise when looking `forward`
+	let rotation = UnitQuaternion::from_axis_angle(&up, yaw)
+		* UnitQuaternion::from_axis_angle(&right, pitch)
+		* UnitQuaternion::from_axis_angle(&forward, roll);
+
+	Isometry::from_parts(translation, rotation)
+}
+
+/// The length to draw a bone of the feed with, replacing unusable lengths with the
+/// typical one from `proportions`
+fn display_length(proportions: &Proportions, kind: BoneKind, length: f32) -> f32 {
+	// The overlay's head has no counterpart in the skeletal model, but it isn't in the
+	// feed either
+	match skeletal_model::bone::BoneKind::try_from(kind) {
+		Ok(k) => proportions.sanitize_length(k, length),
+		Err(()) => length,
+	}
+}
+
+/// The height of the lowest end of either foot after moving it by `transform`, among
+/// the feet that aren't stale. `None` if neither foot is in the feed.
+fn lowest_foot(
+	latest: &BoneMap<Option<BoneInfo>>,
+	last_seen: &BoneMap<Option<Instant>>,
+	now: Instant,
+	transform: &Isometry,
+	proportions: &Proportions,
+) -> Option<f32> {
+	let up = conventional_axes()[1];
+	[BoneKind::FootL, BoneKind::FootR]
+		.into_iter()
+		.filter(|&kind| last_seen[kind].map_or(false, |t| now - t < STALE_AFTER))
+		.filter_map(|kind| latest[kind])
+		.flat_map(|bone| {
+			let iso = transform * Isometry::from_parts(bone.pos, bone.rot);
+			// Bones extend along their -Y from the head
+			let length = display_length(proportions, bone.kind, bone.length);
+			[Point3::origin(), Point3::new(0., -length, 0.)].map(|p| iso * p)
+		})
+		.map(|p| up.dot(&p.coords))
+		.filter(|height| height.is_finite())
+		.min_by(f32::total_cmp)
+}
+
+/// Saves the `latest` bones to a new file in `dir` as a [`Pose`], which
+/// [`Pose::load()`] reads back, and returns the path of the file. Positions and
+/// rotations are as in the feed, without the remap or the display settings. A pose
+/// has every bone of `skeletal_model`, so this fails until all of them were in the
+/// feed.
+fn save_snapshot(dir: &Path, latest: &BoneMap<Option<BoneInfo>>) -> Result<PathBuf> {
+	use skeletal_model::prelude::{Isometry, Translation};
+
+	let mut bones = skeletal_model::bone::BoneMap::new(
+		[Isometry::identity(); skeletal_model::bone::BoneKind::NUM_TYPES],
+	);
+	let mut lengths = skeletal_model::bone::BoneMap::<f32>::default();
+	let mut missing = Vec::new();
+	for (kind, bone) in latest.iter() {
+		// The head isn't part of a pose
+		let Ok(pose_kind) = skeletal_model::bone::BoneKind::try_from(kind) else {
+			continue;
+		};
+		let Some(bone) = bone else {
+			missing.push(kind);
+			continue;
+		};
+		let rotation = pose_rotation(&bone.rot);
+		let translation = Translation::new(bone.pos.x, bone.pos.y, bone.pos.z);
+		bones[pose_kind] = Isometry::from_parts(translation, rotation);
+		lengths[pose_kind] = bone.length;
+	}
+	if !missing.is_empty() {
+		bail!("These bones weren't in the feed yet: {missing:?}");
+	}
+	let pose = Pose::new(bones, lengths);
+
+	let secs = SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.wrap_err("System clock is before 1970")?
+		.as_secs();
+	// Snapshots taken within the same second get a suffix instead of replacing each
+	// other
+	let mut path = dir.join(format!("pose-{secs}.json"));
+	let mut suffix = 0;
+	let file = loop {
+		match OpenOptions::new().write(true).create_new(true).open(&path) {
+			Ok(file) => break file,
+			Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+				suffix += 1;
+				path = dir.join(format!("pose-{secs}-{suffix}.json"));
+			}
+			Err(e) => {
+				return Err(e)
+					.wrap_err_with(|| format!("Could not create {}", path.display()))
+			}
+		}
+	};
+	pose.save(BufWriter::new(file))
+		.wrap_err_with(|| format!("Could not write {}", path.display()))?;
+	Ok(path)
+}
+
+/// Copies a rotation from the feed to the version of nalgebra that `skeletal_model`
+/// uses. The quaternion is copied as is, since going through euler angles would lose
+/// precision near gimbal lock.
+fn pose_rotation(rot: &UnitQuaternion<f32>) -> skeletal_model::prelude::UnitQuat {
+	let q = rot.quaternion();
+	let q = nalgebra031::Quaternion::new(q.w, q.i, q.j, q.k);
+	skeletal_model::prelude::UnitQuat::new_unchecked(q)
+}
+
+/// Catches bad `--server` values before the networking keeps failing to connect to them
+fn check_server(server: &str) -> Result<()> {
+	let Some((scheme, rest)) = server.split_once("://") else {
+		bail!("Server {server:?} is not a URL like \"{DEFAULT_SERVER}\"");
+	};
+	if !matches!(scheme, "ws" | "wss") {
+		bail!("Server {server:?} must use \"ws\" or \"wss\", not {scheme:?}");
+	}
+	if rest.is_empty() {
+		bail!("Server {server:?} is missing a host");
+	}
+	Ok(())
+}
+
+async fn networking(
+	server: String,
+	options: ConnectOptions,
+	max_retries: Option<u32>,
+	overlay_options: OverlayOptions,
+	subsys: SubsystemHandle,
+) -> Result<()> {
 	let (data_sender, data_reciever) = watch::channel(None);
 	let (settings_sender, settings_receiver) =
 		watch::channel(DisplaySettings::default());
-
-	subsys.start("Overlay", |s| overlay(data_reciever, settings_receiver, s));
-
-	let run_future = solarxr::run(CONNECT_STR.to_string(), |update| async {
-		let ds = get_display_settings(&update).await;
-		if let Some(ds) = ds {
-			log::info!("Updating settings: {:?}", ds);
-			settings_sender.send_replace(ds);
+	let (outgoing_sender, outgoing_receiver) = mpsc::unbounded_channel();
+	let (snapshot_sender, snapshot_receiver) = mpsc::unbounded_channel();
+	let (shown_sender, shown_receiver) = watch::channel(true);
+	spawn_visibility_toggle(shown_sender)?;
+	let (status_sender, status_receiver) =
+		watch::channel(ConnectionStatus::Connecting { attempt: 1 });
+	let metrics = Arc::new(Metrics::default());
+
+	let overlay_metrics = metrics.clone();
+	subsys.start("Overlay", move |s| {
+		overlay(
+			data_reciever,
+			settings_receiver,
+			shown_receiver,
+			snapshot_receiver,
+			overlay_options,
+			overlay_metrics,
+			s,
+		)
+	});
+	let report_metrics = metrics.clone();
+	subsys.start("Metrics", move |s| async move {
+		tokio::select! {
+			r = crate::metrics::report(report_metrics) => r,
+			_ = s.on_shutdown_requested() => Ok(()),
 		}
-		data_sender.send_replace(Some(update));
 	});
+
+	let status_future = report_status(server.clone(), status_receiver, max_retries);
+	let run_future = solarxr::run_with_status(
+		server,
+		options,
+		outgoing_receiver,
+		status_sender,
+		|update| async {
+			let current = settings_sender.borrow().clone();
+			let ds = get_display_settings(
+				&update,
+				current,
+				&outgoing_sender,
+				&snapshot_sender,
+			)
+			.await;
+			if let Some(ds) = ds {
+				log::info!("Updating settings: {:?}", ds);
+				settings_sender.send_replace(ds);
+			}
+			metrics.update_received();
+			data_sender.send_replace(Some(update));
+		},
+	);
 	tokio::select! {
 		_ = run_future => { unreachable!("This future never returns") },
+		r = status_future => r,
 		_ = subsys.on_shutdown_requested() => {
 			log::debug!("networking shutdown requested");
 			Ok(())
@@ -218,7 +660,84 @@ async fn networking(subsys: SubsystemHandle) -> Result<()> {
 	}
 }
 
-async fn get_display_settings<'a>(update: &FeedUpdate) -> Option<DisplaySettings> {
+/// Hides the skeleton, or shows it again, whenever Enter is pressed in the terminal.
+/// This is only local, the display settings on the server stay as they are.
+///
+/// Reads on its own thread, since a blocking read of stdin can't be cancelled and
+/// would otherwise hold up the shutdown until Enter is pressed. Without a terminal
+/// the thread ends right away, and the skeleton stays shown.
+fn spawn_visibility_toggle(shown: watch::Sender<bool>) -> Result<()> {
+	std::thread::Builder::new()
+		.name("visibility toggle".into())
+		.spawn(move || {
+			for _ in std::io::stdin().lock().lines().map_while(|l| l.ok()) {
+				shown.send_modify(|shown| *shown = !*shown);
+				if *shown.borrow() {
+					log::info!("Showing the skeleton, press Enter to hide it");
+				} else {
+					log::info!("Hiding the skeleton, press Enter to show it");
+				}
+			}
+		})
+		.wrap_err("Could not start the visibility toggle")?;
+	Ok(())
+}
+
+/// Logs what the connection to the `server` is doing, without repeating ourselves
+/// while it can't be reached. Returns an error once more than `max_retries` attempts
+/// in a row failed, and never returns otherwise.
+async fn report_status(
+	server: String,
+	mut status: watch::Receiver<ConnectionStatus>,
+	max_retries: Option<u32>,
+) -> Result<()> {
+	let mut last_waiting_log: Option<Instant> = None;
+	loop {
+		status
+			.changed()
+			.await
+			.wrap_err("The networking stopped reporting its status")?;
+		let current = status.borrow_and_update().clone();
+		match current {
+			ConnectionStatus::Connecting { .. } => (),
+			ConnectionStatus::Connected => {
+				log::info!("Connected to the server at {server}");
+				last_waiting_log = None;
+			}
+			ConnectionStatus::Failed { attempts, reason } => {
+				if max_retries.map_or(false, |max| attempts > max) {
+					bail!(
+						"Gave up on the server at {server} after {attempts} failed \
+						 attempts, the last one with: {reason}"
+					);
+				}
+				let now = Instant::now();
+				let due =
+					last_waiting_log.map_or(true, |t| now - t >= WAITING_LOG_INTERVAL);
+				if due {
+					log::info!(
+						"Waiting for the server at {server}, {attempts} attempts so \
+						 far: {reason}"
+					);
+					last_waiting_log = Some(now);
+				}
+			}
+			ConnectionStatus::Lost { reason } => {
+				log::warn!("Lost the connection to the server, retrying: {reason}");
+			}
+		}
+	}
+}
+
+/// Returns the last `DisplaySettings` published on the overlay topic, if any. Requests
+/// for the `current` settings are answered by sending them to `outgoing`, and
+/// messages on the snapshot topic are passed on to `snapshots`.
+async fn get_display_settings<'a>(
+	update: &FeedUpdate,
+	current: DisplaySettings,
+	outgoing: &mpsc::UnboundedSender<Data>,
+	snapshots: &mpsc::UnboundedSender<()>,
+) -> Option<DisplaySettings> {
 	let mut result = None;
 	let Some(msgs) = update.0.table().pub_sub_msgs() else {
 		return None;
@@ -229,13 +748,21 @@ async fn get_display_settings<'a>(update: &FeedUpdate) -> Option<DisplaySettings
 		};
 		log::debug!("Received pub-sub message with topic: {:?}", m.topic());
 
+		if solarxr::topic::is_snapshot_topic(m) {
+			log::info!("Pose snapshot requested");
+			// Only fails if the overlay is shutting down, so there is nothing to save
+			let _ = snapshots.send(());
+			continue;
+		}
 		if !solarxr::topic::is_overlay_topic(m) {
 			continue;
 		}
 
 		// Check if they want to know current `DisplaySettings` (empty payload)
 		if m.payload().is_none() {
-			// TODO: Implement publishing the current settings
+			log::debug!("Publishing current settings: {:?}", current);
+			// Only fails if the networking is shutting down, so nobody would get it
+			let _ = outgoing.send(current.to_data());
 			continue;
 		}
 
@@ -251,3 +778,20 @@ async fn get_display_settings<'a>(update: &FeedUpdate) -> Option<DisplaySettings
 	}
 	result
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::f32::consts::FRAC_PI_2;
+
+	#[test]
+	fn pose_rotation_at_gimbal_lock() {
+		// Pitched 90 degrees, where the roll and yaw of the euler angles blend together
+		let rot = UnitQuaternion::from_euler_angles(0.3, FRAC_PI_2, -0.2);
+		let copied = pose_rotation(&rot);
+
+		let (q, c) = (rot.quaternion(), copied.quaternion());
+		assert_eq!([c.w, c.i, c.j, c.k], [q.w, q.i, q.j, q.k]);
+	}
+}
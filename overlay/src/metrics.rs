@@ -0,0 +1,74 @@
+//! Counters for diagnosing the performance of the overlay, which are logged and reset
+//! every [`INTERVAL`] by [`report()`].
+//!
+//! Feed updates that arrive faster than the overlay renders replace each other, so
+//! the update rate can be higher than the frame rate without anything being wrong.
+//! Skipped updates are the ones the overlay looked at but had nothing to draw from.
+
+use eyre::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::{self, MissedTickBehavior};
+
+/// How often the metrics are logged
+const INTERVAL: Duration = Duration::from_secs(10);
+
+/// The counters since the last report, shared between the networking and the overlay
+#[derive(Debug, Default)]
+pub struct Metrics {
+	updates: AtomicU64,
+	frames: AtomicU64,
+	skipped: AtomicU64,
+	invalid_bones: AtomicU64,
+}
+impl Metrics {
+	/// A feed update arrived from the server
+	pub fn update_received(&self) {
+		self.updates.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// The overlay rendered a frame
+	pub fn frame_rendered(&self) {
+		self.frames.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// The overlay skipped an update without rendering it, like one without a data
+	/// feed
+	pub fn frame_skipped(&self) {
+		self.skipped.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Bones that were left out of a frame for having an invalid pose
+	pub fn bones_invalid(&self, count: usize) {
+		self.invalid_bones
+			.fetch_add(count as u64, Ordering::Relaxed);
+	}
+}
+
+/// Logs the `metrics` every [`INTERVAL`], and resets them. Never returns.
+pub async fn report(metrics: Arc<Metrics>) -> Result<()> {
+	let mut interval = time::interval(INTERVAL);
+	interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+	// The first tick completes right away
+	interval.tick().await;
+	let mut last = Instant::now();
+	loop {
+		interval.tick().await;
+		let now = Instant::now();
+		let secs = (now - last).as_secs_f64();
+		last = now;
+
+		let take = |counter: &AtomicU64| counter.swap(0, Ordering::Relaxed);
+		let updates = take(&metrics.updates);
+		let frames = take(&metrics.frames);
+		let skipped = take(&metrics.skipped);
+		let invalid_bones = take(&metrics.invalid_bones);
+		log::info!(
+			"{:.1} updates/s, {:.1} frames/s, {skipped} updates skipped, \
+			 {invalid_bones} invalid bones",
+			updates as f64 / secs,
+			frames as f64 / secs,
+		);
+	}
+}
@@ -13,6 +13,8 @@ pub struct Bone {
 	overlays: (OverlayHandle, OverlayHandle),
 	iso: Isometry,
 	color: RGBA,
+	/// Multiplies the alpha of `color`
+	opacity: f32,
 	radius: f32,
 	length: f32,
 	is_visible: bool,
@@ -48,6 +50,7 @@ impl Bone {
 			radius,
 			length,
 			color,
+			opacity: 1.0,
 			is_visible: false,
 		})
 	}
@@ -62,7 +65,7 @@ impl Bone {
 				r: f(self.color.r),
 				g: f(self.color.g),
 				b: f(self.color.b),
-				a: f(self.color.a),
+				a: f(self.color.a) * self.opacity,
 			};
 			mngr.set_tint(self.overlays.0, tint)
 				.and_then(|_| mngr.set_tint(self.overlays.1, tint))
@@ -171,8 +174,12 @@ impl Bone {
 		self.length = length;
 	}
 
-	#[allow(unused)]
+	pub fn radius(&self) -> f32 {
+		self.radius
+	}
+
 	pub fn set_radius(&mut self, radius: f32) {
+		assert!(radius > 0., "Radius must be positive");
 		self.radius = radius;
 	}
 
@@ -183,4 +190,24 @@ impl Bone {
 	pub fn set_visibility(&mut self, is_visible: bool) {
 		self.is_visible = is_visible;
 	}
+
+	pub fn color(&self) -> RGBA {
+		self.color
+	}
+
+	pub fn set_color(&mut self, color: RGBA) {
+		self.color = color;
+	}
+
+	pub fn set_opacity(&mut self, opacity: f32) {
+		assert!((0.0..=1.0).contains(&opacity), "Opacity must be in 0..=1");
+		self.opacity = opacity;
+	}
+
+	/// Releases the overlays of the bone. Both are released even if one fails.
+	pub fn destroy(self, mngr: &mut OverlayManager<'_>) -> Result<()> {
+		let first = mngr.destroy_overlay(self.overlays.0);
+		let second = mngr.destroy_overlay(self.overlays.1);
+		first.and(second).wrap_err("Failed to destroy overlay")
+	}
 }
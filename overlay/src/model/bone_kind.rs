@@ -125,6 +125,33 @@ impl From<BoneKind> for usize {
 		other as _
 	}
 }
+/// The bones of `skeletal_model` are the same, except that it has no [`BoneKind::Head`]
+impl TryFrom<BoneKind> for skeletal_model::bone::BoneKind {
+	type Error = ();
+	fn try_from(other: BoneKind) -> Result<Self, Self::Error> {
+		use skeletal_model::bone::BoneKind as O;
+		Ok(match other {
+			BoneKind::Head => return Err(()),
+			BoneKind::Neck => O::Neck,
+			BoneKind::Chest => O::Chest,
+			BoneKind::Waist => O::Waist,
+			BoneKind::Hip => O::Hip,
+			BoneKind::ThighL => O::ThighL,
+			BoneKind::ThighR => O::ThighR,
+			BoneKind::AnkleL => O::AnkleL,
+			BoneKind::AnkleR => O::AnkleR,
+			BoneKind::FootL => O::FootL,
+			BoneKind::FootR => O::FootR,
+
+			BoneKind::UpperArmL => O::UpperArmL,
+			BoneKind::UpperArmR => O::UpperArmR,
+			BoneKind::ForearmL => O::ForearmL,
+			BoneKind::ForearmR => O::ForearmR,
+			BoneKind::WristL => O::WristL,
+			BoneKind::WristR => O::WristR,
+		})
+	}
+}
 impl TryFrom<BodyPart> for BoneKind {
 	type Error = BodyPart;
 	fn try_from(other: BodyPart) -> Result<Self, Self::Error> {
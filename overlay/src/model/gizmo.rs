@@ -0,0 +1,86 @@
+use crate::model::bone::{Bone, Isometry};
+use crate::RGBA;
+
+use eyre::Result;
+use nalgebra::{UnitQuaternion, Vector3};
+use ovr_overlay::overlay::OverlayManager;
+use std::f32::consts::FRAC_PI_2;
+use std::f32::consts::PI;
+
+/// How long each axis is, in meters
+const AXIS_LENGTH: f32 = 0.05;
+const AXIS_RADIUS: f32 = 0.001;
+
+/// Three small tubes along the local axes of a bone, to check the orientation of its
+/// tracker: `+X` (right) is red, `+Y` (up) is green and `+Z` (backward) is blue, like
+/// the conventions of `skeletal_model`. A bone that stands upright shows `+Y` up.
+#[derive(Debug)]
+pub struct AxisGizmo {
+	/// The tubes, in the order X, Y, Z
+	axes: [Bone; 3],
+}
+impl AxisGizmo {
+	pub fn new(mngr: &mut OverlayManager, key: &str) -> Result<Self> {
+		let mut axis = |name: &str, color: RGBA| {
+			Bone::new(
+				mngr,
+				color,
+				Default::default(),
+				format!("{key} axis {name}"),
+				AXIS_RADIUS,
+				AXIS_LENGTH,
+			)
+		};
+		Ok(Self {
+			axes: [
+				axis("X", RGBA::RED)?,
+				axis("Y", RGBA::LIME)?,
+				axis("Z", RGBA::BLUE)?,
+			],
+		})
+	}
+
+	/// Places the gizmo at the head of a bone, oriented like the bone
+	pub fn set_isometry(&mut self, iso: Isometry) {
+		// A `Bone` extends along its local -Y from `iso`, so turn -Y onto each axis
+		let turns = [
+			UnitQuaternion::from_axis_angle(&Vector3::z_axis(), FRAC_PI_2),
+			UnitQuaternion::from_axis_angle(&Vector3::z_axis(), PI),
+			UnitQuaternion::from_axis_angle(&Vector3::x_axis(), -FRAC_PI_2),
+		];
+		for (axis, turn) in self.axes.iter_mut().zip(turns) {
+			axis.set_isometry(Isometry::from_parts(
+				iso.translation,
+				iso.rotation * turn,
+			));
+		}
+	}
+
+	pub fn set_visibility(&mut self, is_visible: bool) {
+		for axis in &mut self.axes {
+			axis.set_visibility(is_visible);
+		}
+	}
+
+	pub fn set_opacity(&mut self, opacity: f32) {
+		for axis in &mut self.axes {
+			axis.set_opacity(opacity);
+		}
+	}
+
+	pub fn update_render(&self, mngr: &mut OverlayManager<'_>) -> Result<()> {
+		for axis in &self.axes {
+			axis.update_render(mngr)?;
+		}
+		Ok(())
+	}
+
+	/// Releases the overlays of every axis, returning the first error
+	pub fn destroy(self, mngr: &mut OverlayManager<'_>) -> Result<()> {
+		let mut result = Ok(());
+		for axis in self.axes {
+			result = result.and(axis.destroy(mngr));
+		}
+		result
+	}
+}
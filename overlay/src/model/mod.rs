@@ -1,8 +1,10 @@
 mod bone;
 mod bone_kind;
 mod bone_map;
+mod gizmo;
 pub mod skeleton;
 
 pub use self::bone::{Bone, Isometry};
 pub use self::bone_kind::BoneKind;
 pub use self::bone_map::BoneMap;
+pub use self::gizmo::AxisGizmo;
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use crate::model::bone::Bone;
+use crate::model::AxisGizmo;
 use crate::model::BoneKind;
 use crate::model::BoneMap;
 use crate::RGBA;
@@ -50,8 +51,21 @@ pub struct SkeletonBuilder {
 	key: String,
 	bone_radius: f32,
 	bone_lengths: Option<BoneMap<f32>>,
+	axis_gizmos: bool,
 }
 impl SkeletonBuilder {
+	/// Prefix of the keys of the overlays, which must be unique among the skeletons
+	pub fn key(mut self, key: String) -> Self {
+		self.key = key;
+		self
+	}
+
+	/// Also render an [`AxisGizmo`] at the head of every bone
+	pub fn axis_gizmos(mut self, enabled: bool) -> Self {
+		self.axis_gizmos = enabled;
+		self
+	}
+
 	#[allow(dead_code)]
 	pub fn build(self, overlay_manager: &mut OverlayManager) -> Result<Skeleton> {
 		let colors = if let Some(colors) = self.colors {
@@ -84,7 +98,18 @@ impl SkeletonBuilder {
 			bones.push((kind, bone));
 		}
 		let bones: BoneArena = bones.into_iter().try_collect().unwrap();
-		Ok(Skeleton::new(bones))
+
+		let gizmos = if self.axis_gizmos {
+			let mut gizmos = Vec::new();
+			for kind in BoneKind::iter() {
+				let key = format!("{}: {kind:?}", self.key);
+				gizmos.push((kind, AxisGizmo::new(overlay_manager, &key)?));
+			}
+			Some(gizmos.into_iter().try_collect().unwrap())
+		} else {
+			None
+		};
+		Ok(Skeleton::new(bones, gizmos))
 	}
 }
 impl Default for SkeletonBuilder {
@@ -94,17 +119,39 @@ impl Default for SkeletonBuilder {
 			key: String::from("slimevr"),
 			bone_radius: BONE_RADIUS,
 			bone_lengths: None,
+			axis_gizmos: false,
 		}
 	}
 }
 
 pub struct Skeleton {
 	pub bones: BoneArena,
+	/// Shown along with the bones, if the skeleton was built with them
+	gizmos: Option<BoneMap<AxisGizmo>>,
+	/// The colors the bones were built with, restored by [`Self::set_color`]
+	default_colors: BoneMap<RGBA>,
+	/// The radii the bones were built with, restored by [`Self::set_thickness`]
+	default_radii: BoneMap<f32>,
 }
 #[allow(dead_code)]
 impl Skeleton {
-	pub fn new(bones: BoneArena) -> Self {
-		let mut result = Self { bones };
+	pub fn new(bones: BoneArena, gizmos: Option<BoneMap<AxisGizmo>>) -> Self {
+		let default_colors = bones
+			.iter()
+			.map(|(kind, bone)| (kind, bone.color()))
+			.try_collect()
+			.unwrap();
+		let default_radii = bones
+			.iter()
+			.map(|(kind, bone)| (kind, bone.radius()))
+			.try_collect()
+			.unwrap();
+		let mut result = Self {
+			bones,
+			gizmos,
+			default_colors,
+			default_radii,
+		};
 		// We explicitly set all bones to invisible, to reduce code brittleness.
 		for b in BoneKind::iter() {
 			result.set_visibility(b, false);
@@ -113,6 +160,9 @@ impl Skeleton {
 	}
 
 	pub fn set_isometry(&mut self, bone: BoneKind, iso: Isometry) {
+		if let Some(gizmos) = &mut self.gizmos {
+			gizmos[bone].set_isometry(iso);
+		}
 		let bone = &mut self.bones[bone];
 		bone.set_isometry(iso);
 	}
@@ -127,13 +177,58 @@ impl Skeleton {
 		bone: BoneKind,
 		mngr: &mut OverlayManager,
 	) -> eyre::Result<()> {
+		if let Some(gizmos) = &self.gizmos {
+			gizmos[bone]
+				.update_render(mngr)
+				.wrap_err("could not update render for axis gizmo")?;
+		}
 		let bone = &mut self.bones[bone];
 		bone.update_render(mngr)
 			.wrap_err("could not update render for bone")
 	}
 
 	pub fn set_visibility(&mut self, bone: BoneKind, is_visible: bool) {
+		if let Some(gizmos) = &mut self.gizmos {
+			gizmos[bone].set_visibility(is_visible);
+		}
 		let bone = &mut self.bones[bone];
 		bone.set_visibility(is_visible);
 	}
+
+	/// Sets the opacity of every bone, from 0 (invisible) to 1 (opaque).
+	pub fn set_opacity(&mut self, opacity: f32) {
+		for (_kind, bone) in &mut self.bones {
+			bone.set_opacity(opacity);
+		}
+		for (_kind, gizmo) in self.gizmos.iter_mut().flatten() {
+			gizmo.set_opacity(opacity);
+		}
+	}
+
+	/// Sets the diameter of every bone in meters, or goes back to the radii they were
+	/// built with if `None`.
+	pub fn set_thickness(&mut self, thickness: Option<f32>) {
+		for (kind, bone) in &mut self.bones {
+			bone.set_radius(thickness.map_or(self.default_radii[kind], |t| t / 2.));
+		}
+	}
+
+	/// Sets the color of `bone`, or goes back to the one it was built with if `None`.
+	pub fn set_color(&mut self, bone: BoneKind, color: Option<RGBA>) {
+		let color = color.unwrap_or(self.default_colors[bone]);
+		self.bones[bone].set_color(color);
+	}
+
+	/// Releases the overlays of every bone and gizmo, so that the skeleton can be
+	/// removed while the overlay keeps running. Returns the first error.
+	pub fn destroy(self, mngr: &mut OverlayManager) -> Result<()> {
+		let mut result = Ok(());
+		for (_kind, bone) in self.bones {
+			result = result.and(bone.destroy(mngr));
+		}
+		for (_kind, gizmo) in self.gizmos.into_iter().flatten() {
+			result = result.and(gizmo.destroy(mngr));
+		}
+		result
+	}
 }
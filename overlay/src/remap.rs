@@ -0,0 +1,95 @@
+//! Remapping of the coordinate system of the feed onto the one of the overlay, for
+//! when they don't agree on which way is up or forward.
+//!
+//! The overlay draws in the frame of [`skeletal_model::conventions`], which is right
+//! handed with `+Y` up and `-Z` forward, like SteamVR. The remap is always a proper
+//! rotation, so it can turn the feed around but never mirror it.
+
+use eyre::{bail, Result};
+use nalgebra::{Matrix3, Rotation3, Unit, UnitQuaternion, Vector3};
+use skeletal_model::conventions::{forward_vec, right_vec, up_vec};
+use std::fmt;
+use std::str::FromStr;
+
+/// The right, up and forward directions of `skeletal_model::conventions`.
+pub fn conventional_axes() -> [Unit<Vector3<f32>>; 3] {
+	// `skeletal_model` uses a different version of nalgebra than `ovr_overlay`, so
+	// copy the axes over
+	[right_vec(), up_vec(), forward_vec()]
+		.map(|v| Unit::new_unchecked(Vector3::new(v.x, v.y, v.z)))
+}
+
+/// A direction along one of the axes of the feed, like `+y` or `-z`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Axis {
+	/// 0 for X, 1 for Y and 2 for Z
+	index: usize,
+	negative: bool,
+}
+impl Axis {
+	pub const POS_Y: Self = Self::new(1, false);
+	pub const NEG_Z: Self = Self::new(2, true);
+
+	const fn new(index: usize, negative: bool) -> Self {
+		Self { index, negative }
+	}
+
+	fn vector(self) -> Vector3<f32> {
+		let mut v = Vector3::zeros();
+		v[self.index] = if self.negative { -1. } else { 1. };
+		v
+	}
+}
+impl FromStr for Axis {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (negative, name) = match s.as_bytes() {
+			[b'-', ..] => (true, &s[1..]),
+			[b'+', ..] => (false, &s[1..]),
+			_ => (false, s),
+		};
+		let index = match name {
+			"x" | "X" => 0,
+			"y" | "Y" => 1,
+			"z" | "Z" => 2,
+			_ => return Err(format!("{s:?} is not an axis like \"+y\" or \"-z\"")),
+		};
+		Ok(Self::new(index, negative))
+	}
+}
+impl fmt::Display for Axis {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let sign = if self.negative { '-' } else { '+' };
+		write!(f, "{sign}{}", ['x', 'y', 'z'][self.index])
+	}
+}
+
+/// Builds the rotation that turns the `up` and `forward` axes of the feed into those
+/// of the overlay, and then turns everything `yaw` degrees to the left.
+///
+/// The feed is assumed to be right handed like the overlay, so its right is `forward
+/// × up`. Fails if `up` and `forward` are along the same axis, because then they
+/// don't describe a frame.
+pub fn remap_rotation(
+	up: Axis,
+	forward: Axis,
+	yaw: f32,
+) -> Result<UnitQuaternion<f32>> {
+	if up.index == forward.index {
+		bail!("The up ({up}) and forward ({forward}) axes of the feed must differ");
+	}
+	if !yaw.is_finite() {
+		bail!("Yaw must be a number of degrees, not {yaw}");
+	}
+	let [right_o, up_o, forward_o] = conventional_axes();
+	let (up, forward) = (up.vector(), forward.vector());
+	let feed = Matrix3::from_columns(&[forward.cross(&up), up, forward]);
+	let ours = Matrix3::from_columns(&[*right_o, *up_o, *forward_o]);
+	// Both are orthonormal and right handed, so this is a rotation
+	let basis = Rotation3::from_matrix_unchecked(ours * feed.transpose());
+	debug_assert!((basis.matrix().determinant() - 1.).abs() < 1e-5);
+
+	let yaw = UnitQuaternion::from_axis_angle(&up_o, yaw.to_radians());
+	Ok(yaw * UnitQuaternion::from_rotation_matrix(&basis))
+}
@@ -2,6 +2,7 @@ use crate::prelude::*;
 
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
 
 #[allow(rustdoc::private_intra_doc_links)]
 /// Describes the various types of bones in the skeleton.
@@ -26,11 +27,25 @@ use num_traits::FromPrimitive;
 /// array. This is used for example in [`BoneMap`]. **Please note that we make no
 /// stability guarantees for the particular value that any variant gets, only that
 /// these values are contiguous and start at 0.** Use the variant directly or refer to
-/// the various functions implemented on this type for stability.
+/// the various functions implemented on this type for stability. The names of the
+/// variants are stable, and are used when serializing.
 ///
 /// [`const`]: https://doc.rust-lang.org/std/keyword.const.html
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, FromPrimitive, ToPrimitive)]
+#[derive(
+	Debug,
+	Clone,
+	Copy,
+	Eq,
+	Hash,
+	PartialEq,
+	Ord,
+	PartialOrd,
+	FromPrimitive,
+	ToPrimitive,
+	Serialize,
+	Deserialize,
+)]
 #[allow(dead_code)]
 pub enum BoneKind {
 	Neck = 0,
@@ -129,7 +144,63 @@ impl BoneKind {
 		})
 	}
 
-	pub fn iter() -> std::iter::Map<std::ops::RangeInclusive<u8>, fn(u8) -> BoneKind> {
+	/// Returns the bone on the other side of the body, or the same bone if it is in the
+	/// middle of the body.
+	pub const fn mirrored(self) -> Self {
+		use BoneKind::*;
+		match self {
+			Neck | Chest | Waist | Hip => self,
+			ThighL => ThighR,
+			ThighR => ThighL,
+			AnkleL => AnkleR,
+			AnkleR => AnkleL,
+			FootL => FootR,
+			FootR => FootL,
+
+			UpperArmL => UpperArmR,
+			UpperArmR => UpperArmL,
+			ForearmL => ForearmR,
+			ForearmR => ForearmL,
+			WristL => WristR,
+			WristR => WristL,
+		}
+	}
+
+	/// Returns every bone below this one in the skeletal tree, not including itself.
+	///
+	/// Parents are always returned before their children, so hiding a bone and all of
+	/// its descendants can be done in one pass. The order is always the same, a depth
+	/// first walk of the tree.
+	pub fn descendants(self) -> impl Iterator<Item = BoneKind> {
+		// Each bone is pushed once at most, so this is large enough without allocating
+		let mut bone_stack = [self; Self::NUM_TYPES];
+		let mut len = 0;
+		for &child in self.children() {
+			bone_stack[len] = child;
+			len += 1;
+		}
+		core::iter::from_fn(move || {
+			len = len.checked_sub(1)?;
+			let bone = bone_stack[len];
+			for &child in bone.children() {
+				bone_stack[len] = child;
+				len += 1;
+			}
+			Some(bone)
+		})
+	}
+
+	/// Returns every bone, starting with the [root](Self::root()) and with parents
+	/// always before their children, for solving the skeleton down from the root.
+	///
+	/// Like [`Self::descendants()`], the order is always the same, so it can also be
+	/// used where the output should be deterministic, like when serializing.
+	pub fn iter_hierarchical() -> impl Iterator<Item = BoneKind> {
+		core::iter::once(Self::root()).chain(Self::root().descendants())
+	}
+
+	pub fn iter() -> core::iter::Map<core::ops::RangeInclusive<u8>, fn(u8) -> BoneKind>
+	{
 		(Self::MIN as u8..=Self::MAX as u8).map(|x| x.try_into().unwrap())
 	}
 
@@ -174,3 +245,92 @@ impl From<BoneKind> for usize {
 		other as _
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// `children()` and `parent()` are written out separately, so check they agree
+	#[test]
+	fn parents_match_children() {
+		for kind in BoneKind::iter() {
+			for child in kind.children() {
+				assert_eq!(
+					child.parent(),
+					Some(kind),
+					"{child:?} is a child of {kind:?}"
+				);
+			}
+			match kind.parent() {
+				Some(parent) => assert!(parent.children().contains(&kind)),
+				None => assert_eq!(kind, BoneKind::root()),
+			}
+		}
+	}
+
+	/// Every bone reaches the root by following its parents, without going in circles
+	#[test]
+	fn every_bone_reaches_root() {
+		for kind in BoneKind::iter() {
+			let mut visited = vec![kind];
+			let mut current = kind;
+			while let Some(parent) = current.parent() {
+				assert!(
+					!visited.contains(&parent),
+					"Cycle at {parent:?}: {visited:?}"
+				);
+				visited.push(parent);
+				current = parent;
+			}
+			assert_eq!(current, BoneKind::root(), "{kind:?} doesn't reach the root");
+		}
+	}
+
+	#[test]
+	fn mirrored() {
+		for kind in BoneKind::iter() {
+			assert_eq!(kind.mirrored().mirrored(), kind);
+			// Mirroring keeps the structure of the tree
+			assert_eq!(
+				kind.parent().map(BoneKind::mirrored),
+				kind.mirrored().parent()
+			);
+		}
+	}
+
+	#[test]
+	fn descendants() {
+		use BoneKind::*;
+		let mut arm: Vec<_> = UpperArmL.descendants().collect();
+		arm.sort_by_key(|&b| b as u8);
+		assert_eq!(arm, [ForearmL, WristL]);
+		assert_eq!(WristL.descendants().count(), 0);
+
+		// The root's descendants are every other bone, each once, parents first
+		let all: Vec<_> = BoneKind::root().descendants().collect();
+		assert_eq!(all.len(), BoneKind::NUM_TYPES - 1);
+		for (i, bone) in all.iter().enumerate() {
+			let parent = bone.parent().unwrap();
+			assert!(parent == BoneKind::root() || all[..i].contains(&parent));
+		}
+	}
+
+	#[test]
+	fn iter_hierarchical() {
+		let all: Vec<_> = BoneKind::iter_hierarchical().collect();
+		assert_eq!(all[0], BoneKind::root());
+		let mut sorted = all.clone();
+		sorted.sort();
+		assert!(sorted.iter().copied().eq(BoneKind::iter()), "{all:?}");
+
+		for (i, bone) in all.iter().enumerate() {
+			if let Some(parent) = bone.parent() {
+				assert!(all[..i].contains(&parent), "{bone:?} before {parent:?}");
+			}
+		}
+		assert!(
+			BoneKind::iter_hierarchical().eq(all),
+			"should be deterministic"
+		);
+	}
+}
@@ -1,10 +1,12 @@
 use super::BoneKind;
 
+use core::iter::{Enumerate, Map};
 use core::ops::{Index, IndexMut};
 use derive_more::From;
+#[cfg(feature = "std")]
 use stackvec::{error::IncompleteArrayError, TryCollect, TryFromIterator};
+#[cfg(feature = "std")]
 use std::collections::HashMap;
-use std::iter::{Enumerate, Map};
 
 /// Provides a map of `BoneKind` -> `T`. Every possible `BoneKind` must have a
 /// corresponding value.
@@ -48,12 +50,14 @@ impl<T> BoneMap<T> {
 
 	/// Applies a function to each element of the `BoneMap`, mapping it from `T` to `U`.
 	pub fn map<U>(self, mut f: impl FnMut(BoneKind, T) -> U) -> BoneMap<U> {
-		let it = self.into_iter().map(|(kind, item)| (kind, f(kind, item)));
-		it.try_collect().unwrap()
+		// The array is indexed by the bones in the order they are iterated
+		let mut kinds = BoneKind::iter();
+		BoneMap(self.0.map(|item| f(kinds.next().unwrap(), item)))
 	}
 }
 
 // ---- Type conversion stuff ----
+#[cfg(feature = "std")]
 impl<T> TryFrom<HashMap<BoneKind, T>> for BoneMap<T> {
 	type Error = IncompleteArrayError;
 
@@ -62,6 +66,7 @@ impl<T> TryFrom<HashMap<BoneKind, T>> for BoneMap<T> {
 	}
 }
 
+#[cfg(feature = "std")]
 impl<T> TryFromIterator<(BoneKind, T)> for BoneMap<T> {
 	type Error = IncompleteArrayError;
 
@@ -100,13 +105,13 @@ impl<T> IndexMut<BoneKind> for BoneMap<T> {
 
 type MapIdxFnType<T> = fn((usize, T)) -> (BoneKind, T);
 
-pub type Iter<'a, T> = Map<Enumerate<std::slice::Iter<'a, T>>, MapIdxFnType<&'a T>>;
+pub type Iter<'a, T> = Map<Enumerate<core::slice::Iter<'a, T>>, MapIdxFnType<&'a T>>;
 
 pub type IterMut<'a, T> =
-	Map<Enumerate<std::slice::IterMut<'a, T>>, MapIdxFnType<&'a mut T>>;
+	Map<Enumerate<core::slice::IterMut<'a, T>>, MapIdxFnType<&'a mut T>>;
 
 pub type IntoIter<T> =
-	Map<Enumerate<std::array::IntoIter<T, { BoneKind::NUM_TYPES }>>, MapIdxFnType<T>>;
+	Map<Enumerate<core::array::IntoIter<T, { BoneKind::NUM_TYPES }>>, MapIdxFnType<T>>;
 
 impl<T> IntoIterator for BoneMap<T> {
 	type Item = (BoneKind, T);
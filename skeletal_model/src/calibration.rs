@@ -0,0 +1,159 @@
+//! Corrects the rotations of trackers for how they are mounted on the body.
+//!
+//! A tracker is rarely mounted exactly aligned with the bone it measures, so its raw
+//! rotation is off from the bone's by some fixed offset. To find the offsets, the user
+//! stands in a T-pose, where the rotation of every bone is known, see
+//! [`BoneKind::tpose_rotation()`]. [`Calibration::from_tpose()`] computes the offsets
+//! from the raw rotations captured then, and [`Calibration::apply_calibration()`]
+//! corrects live rotations with them.
+//!
+//! The offset is applied in the tracker's own frame, so a corrected rotation is
+//! `raw * offset`. This keeps the offset fixed while the tracker moves with the bone.
+
+use crate::prelude::*;
+
+use core::f32::consts::FRAC_PI_2;
+use nalgebra::Vector3;
+
+impl BoneKind {
+	/// Returns the global rotation of the bone in a T-pose: standing upright, facing
+	/// [`forward_vec()`], with the arms stretched straight out to the sides.
+	///
+	/// This is the same as [`Self::calibration_rotation()`], except for the arms.
+	pub fn tpose_rotation(self) -> Global<UnitQuat> {
+		use BoneKind::*;
+		match self {
+			// Rotate the bones from pointing down to pointing outwards
+			UpperArmL | ForearmL | WristL => {
+				Global(UnitQuat::from_axis_angle(&Vector3::z_axis(), -FRAC_PI_2))
+			}
+			UpperArmR | ForearmR | WristR => {
+				Global(UnitQuat::from_axis_angle(&Vector3::z_axis(), FRAC_PI_2))
+			}
+			_ => self.calibration_rotation(),
+		}
+	}
+}
+
+/// Someone `height` tall standing in the T-pose of [`BoneKind::tpose_rotation()`],
+/// with the ankles on the floor at the origin. Returns the head of every bone, and the
+/// length of every bone.
+#[cfg(test)]
+pub(crate) fn standing_tpose(height: f32) -> (BoneMap<Global<Isometry>>, BoneMap<f32>) {
+	use crate::kinematics::{forward_kinematics, local_rotations};
+	use crate::proportions::Proportions;
+	use BoneKind::*;
+
+	let lengths = Proportions::new(height).lengths();
+	let rots =
+		BoneMap::new([(); BoneKind::NUM_TYPES]).map(|kind, ()| kind.tpose_rotation());
+	let neck_height: f32 = [Neck, Chest, Waist, Hip, ThighL, AnkleL]
+		.iter()
+		.map(|&kind| lengths[kind])
+		.sum();
+	let root = Global(Isometry::translation(0., neck_height, 0.));
+	let heads = forward_kinematics(&root, &local_rotations(&rots), &lengths);
+	(heads, lengths)
+}
+
+/// The mounting offsets of the trackers on every bone. See the [module](self)
+/// documentation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+	offsets: BoneMap<UnitQuat>,
+}
+impl Calibration {
+	/// Computes the offsets from `raw`, the rotations of the trackers captured while
+	/// the user stood in a T-pose.
+	pub fn from_tpose(raw: &BoneMap<Global<UnitQuat>>) -> Self {
+		let offsets = raw.map(|kind, raw| raw.0.inverse() * kind.tpose_rotation().0);
+		Self { offsets }
+	}
+
+	/// The offset of the tracker on each bone
+	pub fn offsets(&self) -> &BoneMap<UnitQuat> {
+		&self.offsets
+	}
+
+	/// Corrects `raw` tracker rotations into the rotations of the bones
+	pub fn apply_calibration(
+		&self,
+		raw: &BoneMap<Global<UnitQuat>>,
+	) -> BoneMap<Global<UnitQuat>> {
+		raw.map(|kind, raw| Global(raw.0 * self.offsets[kind]))
+	}
+}
+impl Default for Calibration {
+	/// No offsets, so rotations are passed through unchanged
+	fn default() -> Self {
+		Self {
+			offsets: BoneMap::new([UnitQuat::identity(); BoneKind::NUM_TYPES]),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use approx::assert_relative_eq;
+
+	fn tpose() -> BoneMap<Global<UnitQuat>> {
+		BoneMap::new([(); BoneKind::NUM_TYPES]).map(|kind, ()| kind.tpose_rotation())
+	}
+
+	#[test]
+	fn calibrate_from_rotated_tpose() {
+		// Every tracker is mounted with a different, arbitrary offset
+		let mounting = BoneMap::new([(); BoneKind::NUM_TYPES]).map(|kind, ()| {
+			let i = kind as u8 as f32;
+			UnitQuat::from_euler_angles(0.3 * i, 1. - 0.2 * i, 0.1)
+		});
+		let raw = tpose().map(|kind, rot| Global(rot.0 * mounting[kind].inverse()));
+
+		let calibration = Calibration::from_tpose(&raw);
+		let corrected = calibration.apply_calibration(&raw);
+		for kind in BoneKind::iter() {
+			assert_relative_eq!(
+				corrected[kind].0,
+				kind.tpose_rotation().0,
+				epsilon = 1e-6
+			);
+			assert_relative_eq!(
+				calibration.offsets()[kind],
+				mounting[kind],
+				epsilon = 1e-6
+			);
+		}
+
+		// Bending the forearm afterwards is measured as the same bend of the bone
+		let bend = UnitQuat::from_axis_angle(&up_vec(), 1.);
+		let mut moved = raw;
+		moved[BoneKind::ForearmL] = Global(bend * raw[BoneKind::ForearmL].0);
+		let corrected = calibration.apply_calibration(&moved);
+		assert_relative_eq!(
+			corrected[BoneKind::ForearmL].0,
+			bend * BoneKind::ForearmL.tpose_rotation().0,
+			epsilon = 1e-6
+		);
+	}
+
+	#[test]
+	fn tpose_arms_point_outwards() {
+		let down = -up_vec().into_inner();
+		let left = BoneKind::UpperArmL.tpose_rotation().0 * down;
+		let right = BoneKind::WristR.tpose_rotation().0 * down;
+		assert_relative_eq!(left, -right_vec().into_inner(), epsilon = 1e-6);
+		assert_relative_eq!(right, right_vec().into_inner(), epsilon = 1e-6);
+		assert_eq!(
+			BoneKind::Chest.tpose_rotation(),
+			Global(UnitQuat::identity())
+		);
+	}
+
+	#[test]
+	fn default_is_identity() {
+		let raw = tpose();
+		assert_eq!(Calibration::default().apply_calibration(&raw), raw);
+	}
+}
@@ -0,0 +1,194 @@
+//! Keeps bones within the range of motion of a human body.
+//!
+//! Noisy trackers can rotate a bone into a pose that no joint could be in, like a knee
+//! bent forwards. [`clamp_local_rotation()`] pulls such a rotation back to the
+//! nearest limit of what the joint can do.
+//!
+//! # Representation of limits
+//! Limits are relative to the bone's calibration pose, in the bone's local frame. A
+//! rotation from the calibration pose is split into:
+//! - **Twist**: Rotation around the bone itself, i.e. around [`up_vec()`].
+//! - **Swing**: The rotation that tilts the bone afterwards. The axis of this rotation
+//!   is perpendicular to the bone, so it is limited by how far it tilts around
+//!   [`right_vec()`] (pitch) and around [`forward_vec()`] (roll).
+//!
+//! This avoids euler angles, in line with the [`conventions`](crate::conventions).
+//!
+//! Bones point down (`-Y`) in their local frame, so with a positive pitch the tail of
+//! a bone swings forwards, and with a positive roll it swings to the left.
+
+use crate::prelude::*;
+
+use core::ops::RangeInclusive;
+use nalgebra::Quaternion;
+
+/// The range of motion of a joint, in radians. See the [module](self) documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JointLimits {
+	pub pitch: RangeInclusive<f32>,
+	pub roll: RangeInclusive<f32>,
+	pub twist: RangeInclusive<f32>,
+}
+impl JointLimits {
+	/// Builds limits from ranges in degrees, which are easier to read
+	fn from_degrees(
+		pitch: RangeInclusive<f32>,
+		roll: RangeInclusive<f32>,
+		twist: RangeInclusive<f32>,
+	) -> Self {
+		fn rad(r: RangeInclusive<f32>) -> RangeInclusive<f32> {
+			r.start().to_radians()..=r.end().to_radians()
+		}
+		Self {
+			pitch: rad(pitch),
+			roll: rad(roll),
+			twist: rad(twist),
+		}
+	}
+
+	/// Clamps `rot`, a rotation from the calibration pose in the bone's local frame,
+	/// to these limits. Rotations already within them are returned unchanged.
+	pub fn clamp(&self, rot: UnitQuat) -> UnitQuat {
+		let (swing, twist) = swing_twist(rot);
+		let swing = swing.scaled_axis();
+		let pitch = swing.dot(&right_vec());
+		let roll = swing.dot(&forward_vec());
+		let twist = twist.scaled_axis().dot(&up_vec());
+
+		if self.pitch.contains(&pitch)
+			&& self.roll.contains(&roll)
+			&& self.twist.contains(&twist)
+		{
+			return rot;
+		}
+
+		fn clamp(x: f32, r: &RangeInclusive<f32>) -> f32 {
+			x.clamp(*r.start(), *r.end())
+		}
+		let pitch = clamp(pitch, &self.pitch);
+		let roll = clamp(roll, &self.roll);
+		let twist = clamp(twist, &self.twist);
+
+		let swing = UnitQuat::from_scaled_axis(
+			right_vec().into_inner() * pitch + forward_vec().into_inner() * roll,
+		);
+		swing * UnitQuat::from_axis_angle(&up_vec(), twist)
+	}
+}
+
+/// Splits `rot` into `(swing, twist)` so that `rot == swing * twist`, where `twist` is
+/// around [`up_vec()`] and `swing` is around an axis perpendicular to it.
+fn swing_twist(rot: UnitQuat) -> (UnitQuat, UnitQuat) {
+	let q = rot.quaternion();
+	// Project the rotation axis onto `up_vec()`, which is `+Y`
+	let twist = Quaternion::new(q.w, 0., q.j, 0.);
+	// If `rot` swings the bone exactly upside down, any twist is valid
+	let twist = UnitQuat::try_new(twist, 1e-6).unwrap_or_else(UnitQuat::identity);
+	(rot * twist.inverse(), twist)
+}
+
+impl BoneKind {
+	/// Returns the range of motion of the joint at the head of the bone, or `None` if
+	/// it isn't limited. The root bone is never limited, since it has no parent to be
+	/// limited relative to.
+	pub fn joint_limits(self) -> Option<JointLimits> {
+		use BoneKind::*;
+		let l = JointLimits::from_degrees;
+		Some(match self {
+			Neck => return None,
+			Chest | Waist | Hip => l(-40.0..=30.0, -30.0..=30.0, -40.0..=40.0),
+			// Legs spread further outwards than they cross over
+			ThighL => l(-30.0..=130.0, -30.0..=45.0, -45.0..=45.0),
+			ThighR => l(-30.0..=130.0, -45.0..=30.0, -45.0..=45.0),
+			// Knees only bend backwards, and barely sideways
+			AnkleL | AnkleR => l(-150.0..=0.0, -10.0..=10.0, -30.0..=30.0),
+			FootL | FootR => l(-50.0..=30.0, -35.0..=35.0, -30.0..=30.0),
+
+			// Shoulders can reach nearly everywhere
+			UpperArmL | UpperArmR => return None,
+			// Elbows only bend forwards, but the forearm twists a lot
+			ForearmL | ForearmR => l(0.0..=150.0, -10.0..=10.0, -90.0..=90.0),
+			WristL | WristR => l(-80.0..=80.0, -40.0..=40.0, -20.0..=20.0),
+		})
+	}
+}
+
+/// Clamps the rotation of `bone` relative to its parent to the bone's
+/// [`BoneKind::joint_limits()`]. Rotations already within the limits, and rotations of
+/// bones without limits, are returned unchanged.
+///
+/// The limits are relative to [`BoneKind::calibration_rotation_local()`], so for
+/// example a foot is limited relative to pointing forwards.
+pub fn clamp_local_rotation(bone: BoneKind, rot: Local<UnitQuat>) -> Local<UnitQuat> {
+	let Some(limits) = bone.joint_limits() else {
+		return rot;
+	};
+	let calib = bone.calibration_rotation_local().0;
+	let from_calib = calib.inverse() * rot.0;
+	let clamped = limits.clamp(from_calib);
+	if clamped == from_calib {
+		return rot;
+	}
+	Local(calib * clamped)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use approx::assert_relative_eq;
+
+	fn pitch(degrees: f32) -> UnitQuat {
+		UnitQuat::from_axis_angle(&right_vec(), degrees.to_radians())
+	}
+
+	#[test]
+	fn overextended_knee() {
+		// Bending a knee forwards by 20 degrees is pulled back to straight
+		let knee = clamp_local_rotation(BoneKind::AnkleL, Local(pitch(20.)));
+		assert_relative_eq!(knee.0, UnitQuat::identity(), epsilon = 1e-6);
+
+		// Bending it too far backwards stops at the limit
+		let knee = clamp_local_rotation(BoneKind::AnkleR, Local(pitch(-170.)));
+		assert_relative_eq!(knee.0, pitch(-150.), epsilon = 1e-6);
+	}
+
+	#[test]
+	fn overextended_knee_keeps_valid_twist() {
+		let twist = UnitQuat::from_axis_angle(&up_vec(), 20f32.to_radians());
+		let knee = clamp_local_rotation(BoneKind::AnkleL, Local(pitch(20.) * twist));
+		assert_relative_eq!(knee.0, twist, epsilon = 1e-6);
+	}
+
+	#[test]
+	fn in_range_is_unchanged() {
+		let bent = Local(pitch(-90.));
+		assert_eq!(clamp_local_rotation(BoneKind::AnkleL, bent), bent);
+
+		// The calibration pose is always within the limits
+		for bone in BoneKind::iter() {
+			let calib = bone.calibration_rotation_local();
+			assert_eq!(clamp_local_rotation(bone, calib), calib, "{bone:?}");
+		}
+
+		// Bones without limits are never clamped
+		let upside_down = Local(pitch(180.));
+		assert_eq!(
+			clamp_local_rotation(BoneKind::UpperArmL, upside_down),
+			upside_down
+		);
+	}
+
+	#[test]
+	fn swing_twist_recomposes() {
+		let rot = UnitQuat::from_euler_angles(0.3, -1.2, 0.7);
+		let (swing, twist) = swing_twist(rot);
+		assert_relative_eq!(swing * twist, rot, epsilon = 1e-6);
+		assert_relative_eq!(swing.scaled_axis().dot(&up_vec()), 0., epsilon = 1e-6);
+		assert_relative_eq!(
+			twist.scaled_axis().cross(&up_vec()).norm(),
+			0.,
+			epsilon = 1e-6
+		);
+	}
+}
@@ -0,0 +1,113 @@
+//! Keeps quaternions from flipping sign between readings.
+//!
+//! Every rotation is described by two quaternions, `q` and `-q`. Trackers may switch
+//! between them from one reading to the next, which doesn't change the rotation, but
+//! makes interpolating or differentiating the quaternions go the long way around.
+//! [`QuatSmoother`] picks whichever of the two is closer to the previous quaternion,
+//! so that the sequence stays continuous.
+//!
+//! This is not a filter: the rotations that come out are exactly the ones that went
+//! in, only their sign changes.
+
+use crate::prelude::*;
+
+/// Aligns each quaternion of a sequence with the one before it. See the
+/// [module](self) documentation.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QuatSmoother {
+	prev: Option<UnitQuat>,
+}
+impl QuatSmoother {
+	pub const fn new() -> Self {
+		Self { prev: None }
+	}
+
+	/// Returns `q`, or `-q` if that is closer to the previous quaternion, so that the
+	/// dot product of the two is never negative. The first quaternion is returned
+	/// unchanged.
+	pub fn smooth(&mut self, q: UnitQuat) -> UnitQuat {
+		let q = match self.prev {
+			Some(prev) if prev.coords.dot(&q.coords) < 0. => {
+				UnitQuat::new_unchecked(-q.into_inner())
+			}
+			_ => q,
+		};
+		self.prev = Some(q);
+		q
+	}
+
+	/// The last quaternion returned by [`Self::smooth()`], if any since the last reset
+	pub fn previous(&self) -> Option<UnitQuat> {
+		self.prev
+	}
+
+	/// Forgets the previous quaternion, for when the sequence starts over, like when a
+	/// tracker reconnects.
+	pub fn reset(&mut self) {
+		self.prev = None;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use core::f32::consts::PI;
+	use nalgebra::Vector3;
+
+	/// Like many IMUs, only report quaternions with a non-negative `w`
+	fn canonical(q: UnitQuat) -> UnitQuat {
+		if q.w < 0. {
+			UnitQuat::new_unchecked(-q.into_inner())
+		} else {
+			q
+		}
+	}
+
+	#[test]
+	fn crosses_sign_boundary() {
+		let axis = Vector3::new(1., 2., -0.5);
+		let axis = nalgebra::Unit::new_normalize(axis);
+		// Two full turns, which makes `w` cross zero several times
+		let input: Vec<UnitQuat> = (0..=80)
+			.map(|i| {
+				let angle = 4. * PI * i as f32 / 80.;
+				canonical(UnitQuat::from_axis_angle(&axis, angle))
+			})
+			.collect();
+		let flips = input
+			.windows(2)
+			.filter(|w| w[0].coords.dot(&w[1].coords) < 0.)
+			.count();
+		assert!(flips > 0, "the input should flip sign");
+
+		let mut smoother = QuatSmoother::new();
+		let mut prev: Option<UnitQuat> = None;
+		for &q in &input {
+			let out = smoother.smooth(q);
+			// Still the same rotation
+			assert!(out.angle_to(&q) < 1e-4);
+			assert_eq!(out.coords.abs(), q.coords.abs());
+			if let Some(prev) = prev {
+				assert!(prev.coords.dot(&out.coords) >= 0., "{prev:?} {out:?}");
+			}
+			prev = Some(out);
+		}
+		assert_eq!(smoother.previous(), prev);
+	}
+
+	#[test]
+	fn first_and_reset_pass_through() {
+		let q = UnitQuat::new_unchecked(-UnitQuat::identity().into_inner());
+		let mut smoother = QuatSmoother::new();
+		assert_eq!(smoother.smooth(q).coords, q.coords);
+
+		let flipped = smoother.smooth(UnitQuat::identity());
+		assert_eq!(flipped.coords, q.coords);
+
+		smoother.reset();
+		assert_eq!(smoother.previous(), None);
+		let id = UnitQuat::identity();
+		assert_eq!(smoother.smooth(id).coords, id.coords);
+	}
+}
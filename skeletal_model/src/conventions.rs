@@ -12,6 +12,10 @@
 //! # Rotation representation
 //! We use quaternions to represent rotations whenever possible. We try not to use
 //! euler angles in our implementation to avoid possible gimbal lock issues.
+//!
+//! Euler angles are easier for humans to read though, so for logging and UIs
+//! [`to_euler_conventional()`] and [`from_euler_conventional()`] convert to and from
+//! [`EulerAngles`] that follow the axes above.
 
 #[allow(unused)]
 use crate::prelude::*;
@@ -47,7 +51,8 @@ pub fn right_vec() -> Unit<Vector3<f32>> {
 /// * dir - The look direction. It does not need to be normalized.
 /// * up - The vertical direction. It does not need to be normalized.
 ///   The only requirement of this parameter is to not be collinear to `dir`.
-///   Non-collinearity is not guaranteed to be checked.
+///   Non-collinearity is not guaranteed to be checked, use [`try_look_towards()`] for
+///   directions that may be degenerate.
 ///
 /// # Example
 /// ```
@@ -71,15 +76,84 @@ pub fn look_towards(dir: &Vector3<f32>, up: &Vector3<f32>) -> UnitQuat {
 	UnitQuat::face_towards(&-dir, up)
 }
 
+/// A checked version of [`look_towards()`]. Returns `None` if `dir` and `up` are
+/// collinear or nearly so, or if either of them is zero, because then there is no
+/// single rotation that looks toward `dir`.
+pub fn try_look_towards(dir: &Vector3<f32>, up: &Vector3<f32>) -> Option<UnitQuat> {
+	// Sine of the angle between `dir` and `up`, below which they are collinear
+	const EPSILON: f32 = 1e-4;
+	let cross = dir.cross(up).norm();
+	if cross <= EPSILON * dir.norm() * up.norm() {
+		return None;
+	}
+	Some(UnitQuat::face_towards(&-dir, up))
+}
+
+/// Human readable angles of a rotation, in degrees. Each angle is a rotation around
+/// one of the axes of the [coordinate system](self#coordinate-system), with the
+/// right hand rule:
+/// - `yaw` is around [`up_vec()`], so positive values turn left.
+/// - `pitch` is around [`right_vec()`], so positive values look up.
+/// - `roll` is around [`forward_vec()`], so positive values tilt clockwise.
+///
+/// They are applied to an observer in that order, each around the observer's own axes
+/// after the previous rotations.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EulerAngles {
+	pub yaw: f32,
+	pub pitch: f32,
+	pub roll: f32,
+}
+
+/// Converts `q` to [`EulerAngles`]. `yaw` and `roll` are within `-180..=180` degrees,
+/// and `pitch` is within `-90..=90` degrees.
+///
+/// # Gimbal lock
+/// When looking straight up or down, with a pitch of ±90 degrees, yaw and roll rotate
+/// around the same axis, so only their combination is known. In that case all of it
+/// is returned as `yaw`, and `roll` is zero.
+pub fn to_euler_conventional(q: &UnitQuat) -> EulerAngles {
+	// `q` is `yaw * pitch * roll`, with roll around `-Z`. Multiplying the matrices
+	// out, the rows of the result are:
+	// [cy*cr - sy*sp*sr,  cy*sr + sy*sp*cr,  sy*cp]
+	// [          -cp*sr,              cp*cr,   -sp]
+	// [-sy*cr - cy*sp*sr, -sy*sr + cy*sp*cr, cy*cp]
+	let m = q.to_rotation_matrix().into_inner();
+	let cos_pitch = m[(1, 0)].hypot(m[(1, 1)]);
+	let pitch = (-m[(1, 2)]).atan2(cos_pitch);
+	let (yaw, roll) = if cos_pitch > 1e-4 {
+		(m[(0, 2)].atan2(m[(2, 2)]), (-m[(1, 0)]).atan2(m[(1, 1)]))
+	} else {
+		// With `roll == 0`, the first column is `[cy, 0, -sy]`
+		((-m[(2, 0)]).atan2(m[(0, 0)]), 0.)
+	};
+	EulerAngles {
+		yaw: yaw.to_degrees(),
+		pitch: pitch.to_degrees(),
+		roll: roll.to_degrees(),
+	}
+}
+
+/// Converts [`EulerAngles`] back to a rotation. This is the inverse of
+/// [`to_euler_conventional()`].
+pub fn from_euler_conventional(angles: &EulerAngles) -> UnitQuat {
+	UnitQuat::from_axis_angle(&up_vec(), angles.yaw.to_radians())
+		* UnitQuat::from_axis_angle(&right_vec(), angles.pitch.to_radians())
+		* UnitQuat::from_axis_angle(&forward_vec(), angles.roll.to_radians())
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::prelude::*;
 
 	use approx::assert_relative_eq;
-	use nalgebra::Vector3;
-	use std::f32::consts::FRAC_PI_2;
+	use core::f32::consts::FRAC_PI_2;
+	use nalgebra::{Unit, Vector3};
 
-	use super::look_towards;
+	use super::{
+		from_euler_conventional, look_towards, to_euler_conventional, try_look_towards,
+		EulerAngles,
+	};
 
 	/// Example and sanity check of how to use various functions from `nalgebra` to
 	/// describe rotations.
@@ -100,6 +174,12 @@ mod tests {
 			axis_angle: UnitQuat,
 			/// The result of [`UnitQuat::from_euler_angles`]
 			euler: UnitQuat,
+			/// The same rotation, as given to [`from_euler_conventional()`]
+			conventional: EulerAngles,
+		}
+
+		fn angles(yaw: f32, pitch: f32, roll: f32) -> EulerAngles {
+			EulerAngles { yaw, pitch, roll }
 		}
 
 		// Build the set of rotations to check
@@ -114,6 +194,7 @@ mod tests {
 				),
 				axis_angle: UnitQuat::from_axis_angle(&Vector3::x_axis(), FRAC_PI_2),
 				euler: UnitQuat::from_euler_angles(FRAC_PI_2, 0., 0.),
+				conventional: angles(0., 90., 0.),
 			},
 			Rotations {
 				desc: "Pitch down 90 degrees",
@@ -125,6 +206,7 @@ mod tests {
 				),
 				axis_angle: UnitQuat::from_axis_angle(&Vector3::x_axis(), -FRAC_PI_2),
 				euler: UnitQuat::from_euler_angles(-FRAC_PI_2, 0., 0.),
+				conventional: angles(0., -90., 0.),
 			},
 			Rotations {
 				desc: "Yaw right 90 degrees",
@@ -136,6 +218,7 @@ mod tests {
 				),
 				axis_angle: UnitQuat::from_axis_angle(&Vector3::y_axis(), -FRAC_PI_2),
 				euler: UnitQuat::from_euler_angles(0., -FRAC_PI_2, 0.),
+				conventional: angles(-90., 0., 0.),
 			},
 			Rotations {
 				desc: "Yaw left 90 degrees",
@@ -147,6 +230,7 @@ mod tests {
 				),
 				axis_angle: UnitQuat::from_axis_angle(&Vector3::y_axis(), FRAC_PI_2),
 				euler: UnitQuat::from_euler_angles(0., FRAC_PI_2, 0.),
+				conventional: angles(90., 0., 0.),
 			},
 			Rotations {
 				desc: "Roll clockwise 90 degrees",
@@ -158,6 +242,7 @@ mod tests {
 				),
 				axis_angle: UnitQuat::from_axis_angle(&Vector3::z_axis(), -FRAC_PI_2),
 				euler: UnitQuat::from_euler_angles(0., 0., -FRAC_PI_2),
+				conventional: angles(0., 0., 90.),
 			},
 			Rotations {
 				desc: "Roll counter-clockwise 90 degrees",
@@ -169,25 +254,90 @@ mod tests {
 				),
 				axis_angle: UnitQuat::from_axis_angle(&Vector3::z_axis(), FRAC_PI_2),
 				euler: UnitQuat::from_euler_angles(0., 0., FRAC_PI_2),
+				conventional: angles(0., 0., -90.),
 			},
 		];
 
 		for r in rotations {
 			// Check that all 3 coordinate axes are rotated to same direction
 			println!("Checking rotation: {}", r.desc);
+			let conventional = from_euler_conventional(&r.conventional);
 			for axis in [Vector3::x_axis(), Vector3::y_axis(), Vector3::z_axis()] {
 				println!("Testing axis: {axis:?}");
 				assert_relative_eq!(r.axis_angle * axis, r.look_at * axis);
 				assert_relative_eq!(r.axis_angle * axis, r.euler * axis);
 				assert_relative_eq!(r.axis_angle * axis, r.face_towards * axis);
 				assert_relative_eq!(r.axis_angle * axis, r.look_towards * axis);
+				assert_relative_eq!(r.axis_angle * axis, conventional * axis);
 			}
 
+			// Check the conversion back to euler angles
+			let angles = to_euler_conventional(&r.axis_angle);
+			assert_relative_eq!(angles.yaw, r.conventional.yaw, epsilon = 1e-3);
+			assert_relative_eq!(angles.pitch, r.conventional.pitch, epsilon = 1e-3);
+			assert_relative_eq!(angles.roll, r.conventional.roll, epsilon = 1e-3);
+
 			// Check that the angles between rotations are zero
 			assert_relative_eq!(r.axis_angle.angle_to(&r.look_at), 0.0);
 			assert_relative_eq!(r.axis_angle.angle_to(&r.euler), 0.0);
 			assert_relative_eq!(r.axis_angle.angle_to(&r.face_towards), 0.0);
 			assert_relative_eq!(r.axis_angle.angle_to(&r.look_towards), 0.0);
+			assert_relative_eq!(r.axis_angle.angle_to(&conventional), 0.0);
+		}
+	}
+
+	#[test]
+	fn try_look_towards_collinear() {
+		// Exactly collinear, in either direction
+		assert_eq!(try_look_towards(&up_vec(), &up_vec()), None);
+		assert_eq!(
+			try_look_towards(&(-2. * up_vec().into_inner()), &up_vec()),
+			None
+		);
+		// Nearly collinear
+		let dir = Vector3::new(1e-6, 1., 0.);
+		assert_eq!(try_look_towards(&dir, &up_vec()), None);
+		// Zero
+		assert_eq!(try_look_towards(&Vector3::zeros(), &up_vec()), None);
+		assert_eq!(try_look_towards(&forward_vec(), &Vector3::zeros()), None);
+	}
+
+	#[test]
+	fn try_look_towards_normal() {
+		let dir = Vector3::new(1., 2., 3.);
+		let q = try_look_towards(&dir, &up_vec()).unwrap();
+		assert_relative_eq!(q, look_towards(&dir, &up_vec()));
+		assert_relative_eq!(q * forward_vec(), Unit::new_normalize(dir));
+	}
+
+	#[test]
+	fn euler_round_trip() {
+		for (yaw, pitch, roll) in
+			[(30., 20., 10.), (-170., -60., 135.), (45., 0., -90.)]
+		{
+			let q = from_euler_conventional(&EulerAngles { yaw, pitch, roll });
+			let angles = to_euler_conventional(&q);
+			assert_relative_eq!(angles.yaw, yaw, epsilon = 1e-3);
+			assert_relative_eq!(angles.pitch, pitch, epsilon = 1e-3);
+			assert_relative_eq!(angles.roll, roll, epsilon = 1e-3);
 		}
 	}
+
+	#[test]
+	fn euler_gimbal_lock() {
+		// Looking straight up, yaw and roll are indistinguishable
+		let q = from_euler_conventional(&EulerAngles {
+			yaw: 30.,
+			pitch: 90.,
+			roll: 20.,
+		});
+		let angles = to_euler_conventional(&q);
+		assert_relative_eq!(angles.pitch, 90., epsilon = 1e-2);
+		assert_eq!(angles.roll, 0.);
+		assert_relative_eq!(
+			from_euler_conventional(&angles).angle_to(&q),
+			0.,
+			epsilon = 1e-3
+		);
+	}
 }
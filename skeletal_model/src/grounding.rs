@@ -0,0 +1,106 @@
+//! Rough estimates of where the body is as a whole, like where its hips are and where
+//! the floor is, for placing the skeleton in the world.
+//!
+//! The center of mass weighs the middle of each bone by the share of the body mass
+//! that it carries, roughly following the segment tables of Dempster as published by
+//! Winter. Like the [`proportions`](crate::proportions), these are averages, so the
+//! result is only as good as the user is average.
+
+use crate::kinematics::tail;
+use crate::prelude::*;
+
+impl BoneKind {
+	/// The typical mass of the body segment along the bone, as a fraction of the mass
+	/// of the whole body. The neck carries the head, and the wrists the hands.
+	pub const fn mass_fraction(self) -> f32 {
+		use BoneKind::*;
+		match self {
+			Neck => 0.081,
+			Chest => 0.216,
+			Waist => 0.139,
+			Hip => 0.142,
+			ThighL | ThighR => 0.1,
+			AnkleL | AnkleR => 0.0465,
+			FootL | FootR => 0.0145,
+
+			UpperArmL | UpperArmR => 0.028,
+			ForearmL | ForearmR => 0.016,
+			WristL | WristR => 0.006,
+		}
+	}
+}
+
+/// Where the body is, see [`estimate()`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grounding {
+	/// The point between the hips, where the thighs start
+	pub hip: Global<Point>,
+	/// The estimated center of mass of the whole body
+	pub center_of_mass: Global<Point>,
+	/// The height of the floor along [`up_vec()`], which is taken to be at the lowest
+	/// end of either foot
+	pub floor: f32,
+}
+
+/// Estimates where the body is, from the transforms of the heads of its bones, like
+/// those of [`forward_kinematics()`](crate::kinematics::forward_kinematics), and their
+/// lengths.
+pub fn estimate(
+	heads: &BoneMap<Global<Isometry>>,
+	lengths: &BoneMap<f32>,
+) -> Grounding {
+	let tail_of = |kind: BoneKind| tail(&heads[kind], lengths[kind]).0;
+	let head_of = |kind: BoneKind| Point::from(heads[kind].0.translation.vector);
+
+	let mut weighted = Point::origin();
+	let mut total_mass = 0.;
+	for kind in BoneKind::iter() {
+		let middle = nalgebra::center(&head_of(kind), &tail_of(kind));
+		weighted += middle.coords * kind.mass_fraction();
+		total_mass += kind.mass_fraction();
+	}
+
+	let floor = [BoneKind::FootL, BoneKind::FootR]
+		.into_iter()
+		.flat_map(|kind| [head_of(kind), tail_of(kind)])
+		.map(|p| up_vec().dot(&p.coords))
+		.fold(f32::INFINITY, f32::min);
+
+	Grounding {
+		hip: Global(tail_of(BoneKind::Hip)),
+		center_of_mass: Global(weighted / total_mass),
+		floor,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::calibration::standing_tpose;
+
+	#[test]
+	fn mass_fractions_add_up() {
+		let total: f32 = BoneKind::iter().map(|kind| kind.mass_fraction()).sum();
+		assert!((total - 1.).abs() < 1e-4, "{total}");
+	}
+
+	/// Standing upright with the arms out to the sides and the feet flat on the floor
+	#[test]
+	fn t_pose() {
+		let height = 1.8;
+		let (heads, lengths) = standing_tpose(height);
+
+		let grounding = estimate(&heads, &lengths);
+		assert!(grounding.floor.abs() < 1e-4, "{}", grounding.floor);
+
+		let hip = grounding.hip.0;
+		assert!((hip.y - height / 2.).abs() < 0.05 * height, "{hip}");
+		assert!(hip.x.abs() < 1e-4 && hip.z.abs() < 1e-4, "{hip}");
+
+		// A bit above the hips for someone standing, and centered between the sides
+		let com = grounding.center_of_mass.0;
+		assert!((0.5 * height..0.6 * height).contains(&com.y), "{com}");
+		assert!(com.x.abs() < 1e-4, "{com}");
+	}
+}
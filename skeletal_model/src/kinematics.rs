@@ -0,0 +1,509 @@
+//! Computes the pose of the skeleton from the rotations of its bones, and the rotations
+//! of bones from where they should end up.
+//!
+//! The rotations of bones can either be relative to their parents, with
+//! [`forward_kinematics()`], or global like trackers measure them, with
+//! [`solve_from_rotations()`].
+//!
+//! Each bone has a head, the joint closer to [`BoneKind::root()`], and a tail, the
+//! joint further from it. A bone with a global rotation of identity points "down",
+//! i.e. its tail is `length` away from its head in the `-Y` direction. This matches
+//! [`BoneKind::calibration_rotation()`], which rotates the up vector to point from
+//! the tail towards the head.
+
+use crate::conventions::look_towards;
+use crate::prelude::*;
+
+use nalgebra::{Unit, Vector3};
+
+/// Lengths and distances shorter than this are treated as zero
+const EPSILON: f32 = 1e-6;
+
+/// Computes the global transform of every bone's head joint from the rotations of the
+/// bones relative to their parents. This is known as [forward kinematics][fk].
+///
+/// The rotation of each bone is the rotation of its parent composed with its local
+/// rotation, and its head is at the tail of its parent. The root bone's parent is
+/// `root`, whose translation is the position of the root bone's head.
+///
+/// Use [`tail()`] to get the position of the other end of a bone.
+///
+/// [fk]: https://wulverblade.com/advanced-animation-techniques-fk-ik/
+pub fn forward_kinematics(
+	root: &Global<Isometry>,
+	local_rots: &BoneMap<Local<UnitQuat>>,
+	lengths: &BoneMap<f32>,
+) -> BoneMap<Global<Isometry>> {
+	// Option is used for resilience against bugs while the map is being built
+	let mut heads: BoneMap<Option<Isometry>> = BoneMap::default();
+
+	// Parents are always solved before their children
+	for kind in BoneKind::iter_hierarchical() {
+		let parent = match kind.parent() {
+			None => root.0,
+			Some(parent) => {
+				let head = heads[parent].expect("Parent was not yet solved");
+				let parent_tail = tail(&Global(head), lengths[parent]);
+				Isometry::from_parts(parent_tail.0.coords.into(), head.rotation)
+			}
+		};
+		let rotation = parent.rotation * local_rots[kind].0;
+		heads[kind] = Some(Isometry::from_parts(parent.translation, rotation));
+	}
+
+	heads.map(|_kind, head| Global(head.unwrap()))
+}
+
+/// Computes the global transform of every bone's head joint from the global rotations
+/// of the bones, which is what rotation-only trackers measure. Each bone's head is at
+/// the tail of its parent, starting with the root bone's head at `root`.
+///
+/// This is the same as [`forward_kinematics()`], except that the rotations aren't
+/// relative to the parent bones.
+pub fn solve_from_rotations(
+	root: &Global<Point>,
+	global_rots: &BoneMap<Global<UnitQuat>>,
+	lengths: &BoneMap<f32>,
+) -> BoneMap<Global<Isometry>> {
+	let mut heads: BoneMap<Option<Isometry>> = BoneMap::default();
+
+	// Parents are always solved before their children
+	for kind in BoneKind::iter_hierarchical() {
+		let head = match kind.parent() {
+			None => root.0,
+			Some(parent) => {
+				let parent_head = heads[parent].expect("Parent was not yet solved");
+				tail(&Global(parent_head), lengths[parent]).0
+			}
+		};
+		heads[kind] = Some(Isometry::from_parts(
+			head.coords.into(),
+			global_rots[kind].0,
+		));
+	}
+
+	heads.map(|_kind, head| Global(head.unwrap()))
+}
+
+/// Turns the global rotation of every bone into its rotation relative to its parent,
+/// like [`forward_kinematics()`] takes them. The root bone has no parent, so its
+/// rotation stays relative to the world.
+///
+/// Undone by [`global_rotations()`].
+pub fn local_rotations(
+	global_rots: &BoneMap<Global<UnitQuat>>,
+) -> BoneMap<Local<UnitQuat>> {
+	global_rots.map(|kind, rot| {
+		let parent = kind
+			.parent()
+			.map_or(UnitQuat::identity(), |p| global_rots[p].0);
+		Local(parent.inverse() * rot.0)
+	})
+}
+
+/// Turns the rotation of every bone relative to its parent into its global rotation,
+/// by composing the rotations down from the root bone. The root bone's rotation is
+/// taken to be relative to the world.
+///
+/// Undoes [`local_rotations()`].
+pub fn global_rotations(
+	local_rots: &BoneMap<Local<UnitQuat>>,
+) -> BoneMap<Global<UnitQuat>> {
+	let mut globals: BoneMap<Option<UnitQuat>> = BoneMap::default();
+
+	// Parents are always solved before their children
+	for kind in BoneKind::iter_hierarchical() {
+		let parent = kind.parent().map_or(UnitQuat::identity(), |p| {
+			globals[p].expect("Parent was not yet solved")
+		});
+		globals[kind] = Some(parent * local_rots[kind].0);
+	}
+
+	globals.map(|_kind, rot| Global(rot.unwrap()))
+}
+
+/// The position of the tail of a bone, given the transform of its head and its length.
+pub fn tail(head: &Global<Isometry>, length: f32) -> Global<Point> {
+	let offset = head.0.rotation * (-up_vec().into_inner() * length);
+	Global(Point::from(head.0.translation.vector + offset))
+}
+
+/// The solution of [`two_bone_ik()`]
+#[derive(Debug, PartialEq)]
+pub struct TwoBoneIk {
+	/// Global rotation of the first bone, like the upper arm
+	pub upper: Global<UnitQuat>,
+	/// Global rotation of the second bone, like the forearm
+	pub lower: Global<UnitQuat>,
+	/// Position of the joint between the bones, like the elbow
+	pub joint: Global<Point>,
+	/// Where the end of the second bone ended up. This is `target` unless it was out
+	/// of reach.
+	pub end: Global<Point>,
+}
+
+/// Analytically solves the rotations of a chain of two bones, like an upper arm and a
+/// forearm, so that the chain starts at `root` and ends at `target`.
+///
+/// The joint between the bones bends towards `bend_dir`, for example backwards for an
+/// elbow. Each bone's forward direction ([`forward_vec()`] after rotating) points to
+/// the side it bends towards, so the results can be used as-is for bone rotations.
+/// The rotation of whatever is at the end of the chain, like a hand, doesn't depend
+/// on the chain and can be set directly.
+///
+/// If `target` is too far away, the chain is fully extended towards it. If it is too
+/// close, the chain folds as much as the lengths allow. Neither, nor a `bend_dir`
+/// parallel to the chain, produces NaNs.
+pub fn two_bone_ik(
+	root: &Global<Point>,
+	target: &Global<Point>,
+	bend_dir: &Vector3<f32>,
+	upper_len: f32,
+	lower_len: f32,
+) -> TwoBoneIk {
+	let to_target = target.0 - root.0;
+	let dir = Unit::try_new(to_target, EPSILON).unwrap_or(-up_vec());
+	let dist = to_target
+		.norm()
+		.clamp((upper_len - lower_len).abs(), upper_len + lower_len);
+
+	// The part of `bend_dir` perpendicular to the chain. If there is none, bend in
+	// any direction.
+	let bend = Unit::try_new(reject(bend_dir, &dir), EPSILON).unwrap_or_else(|| {
+		let any = if dir.x.abs() < 0.9 {
+			right_vec()
+		} else {
+			up_vec()
+		};
+		Unit::new_normalize(reject(&any, &dir))
+	});
+
+	// Law of cosines for the angle between the chain and the upper bone
+	let denominator = 2. * upper_len * dist;
+	let cos = if denominator < EPSILON {
+		1.
+	} else {
+		((upper_len.powi(2) + dist.powi(2) - lower_len.powi(2)) / denominator)
+			.clamp(-1., 1.)
+	};
+	let sin = (1. - cos.powi(2)).sqrt();
+	let upper_dir =
+		Unit::new_normalize(dir.into_inner() * cos + bend.into_inner() * sin);
+
+	let joint = root.0 + upper_dir.into_inner() * upper_len;
+	let end = root.0 + dir.into_inner() * dist;
+	let lower_dir = Unit::try_new(end - joint, EPSILON).unwrap_or(upper_dir);
+
+	// Both bones bend in the plane of `dir` and `bend`, so their forward direction is
+	// perpendicular to themselves within that plane
+	let normal = dir.cross(&bend);
+	let rotation = |bone_dir: Unit<Vector3<f32>>| {
+		let forward = normal.cross(&bone_dir);
+		look_towards(&forward, &-bone_dir.into_inner())
+	};
+
+	TwoBoneIk {
+		upper: Global(rotation(upper_dir)),
+		lower: Global(rotation(lower_dir)),
+		joint: Global(joint),
+		end: Global(end),
+	}
+}
+
+/// The part of `v` perpendicular to `axis`
+fn reject(v: &Vector3<f32>, axis: &Unit<Vector3<f32>>) -> Vector3<f32> {
+	v - axis.into_inner() * axis.dot(v)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use approx::assert_relative_eq;
+	use core::f32::consts::FRAC_PI_2;
+
+	fn identity_rots() -> BoneMap<Local<UnitQuat>> {
+		BoneMap::new([(); BoneKind::NUM_TYPES]).map(|_, _| Local(UnitQuat::identity()))
+	}
+
+	fn pos(iso: &Global<Isometry>) -> Point {
+		iso.0.translation.vector.into()
+	}
+
+	/// With no rotations, the neck -> chest -> waist chain hangs straight down
+	#[test]
+	fn straight_chain() {
+		let root = Global(Isometry::translation(0., 2., 0.));
+		let lengths = BoneMap::new([0.5; BoneKind::NUM_TYPES]);
+		let heads = forward_kinematics(&root, &identity_rots(), &lengths);
+
+		assert_relative_eq!(pos(&heads[BoneKind::Neck]), Point::new(0., 2., 0.));
+		assert_relative_eq!(pos(&heads[BoneKind::Chest]), Point::new(0., 1.5, 0.));
+		assert_relative_eq!(pos(&heads[BoneKind::Waist]), Point::new(0., 1., 0.));
+		assert_relative_eq!(
+			tail(&heads[BoneKind::Waist], lengths[BoneKind::Waist]).0,
+			Point::new(0., 0.5, 0.)
+		);
+		// The arms hang from the tail of the neck too
+		assert_eq!(
+			pos(&heads[BoneKind::UpperArmL]),
+			pos(&heads[BoneKind::Chest])
+		);
+	}
+
+	/// Rotating the chest swings the bones below it, but not the ones above
+	#[test]
+	fn rotated_chain() {
+		let root = Global(Isometry::identity());
+		let lengths = BoneMap::new([1.; BoneKind::NUM_TYPES]);
+		let mut rots = identity_rots();
+		// Pitching up by 90 degrees makes the chest point forward
+		let pitch_up = UnitQuat::from_axis_angle(&right_vec(), FRAC_PI_2);
+		rots[BoneKind::Chest] = Local(pitch_up);
+		// The waist turns back down, relative to the chest
+		rots[BoneKind::Waist] = Local(pitch_up.inverse());
+		let heads = forward_kinematics(&root, &rots, &lengths);
+
+		assert_relative_eq!(pos(&heads[BoneKind::Neck]), Point::new(0., 0., 0.));
+		assert_relative_eq!(pos(&heads[BoneKind::Chest]), Point::new(0., -1., 0.));
+		let forward = forward_vec().into_inner();
+		assert_relative_eq!(
+			pos(&heads[BoneKind::Waist]),
+			Point::new(0., -1., 0.) + forward
+		);
+		assert_relative_eq!(
+			pos(&heads[BoneKind::Hip]),
+			Point::new(0., -2., 0.) + forward
+		);
+		assert_relative_eq!(heads[BoneKind::Waist].0.rotation, UnitQuat::identity());
+	}
+
+	/// Rotating the root rotates the whole skeleton about the root's position
+	#[test]
+	fn rotated_root() {
+		let yaw_left = UnitQuat::from_axis_angle(&up_vec(), FRAC_PI_2);
+		let root = Global(Isometry::from_parts(Translation::new(1., 0., 0.), yaw_left));
+		let lengths = BoneMap::new([1.; BoneKind::NUM_TYPES]);
+		let mut rots = identity_rots();
+		rots[BoneKind::Chest] =
+			Local(UnitQuat::from_axis_angle(&right_vec(), FRAC_PI_2));
+		let heads = forward_kinematics(&root, &rots, &lengths);
+
+		// Without the yaw, the waist would be 1 forward. Yawing left turns forward
+		// into left.
+		let left = -right_vec().into_inner();
+		assert_relative_eq!(
+			pos(&heads[BoneKind::Waist]),
+			Point::new(1., -1., 0.) + left
+		);
+	}
+
+	/// Standing upright in the T-pose, from its local rotations
+	#[test]
+	fn standing_t_pose() {
+		use BoneKind::*;
+
+		let (heads, lengths) = crate::calibration::standing_tpose(1.8);
+		let (right, forward) = (right_vec().into_inner(), forward_vec().into_inner());
+		let sum = |kinds: &[BoneKind]| kinds.iter().map(|&k| lengths[k]).sum::<f32>();
+		// The ankles are on the floor
+		let neck_height = sum(&[Neck, Chest, Waist, Hip, ThighL, AnkleL]);
+
+		let up = up_vec().into_inner();
+		let top = Point::new(0., neck_height, 0.);
+		let shoulder = top - up * lengths[Neck];
+		let hip = shoulder - up * sum(&[Chest, Waist, Hip]);
+		assert_relative_eq!(pos(&heads[Neck]), top);
+		assert_relative_eq!(pos(&heads[UpperArmL]), shoulder, epsilon = 1e-5);
+		assert_relative_eq!(pos(&heads[UpperArmR]), shoulder, epsilon = 1e-5);
+		assert_relative_eq!(pos(&heads[ThighL]), hip, epsilon = 1e-5);
+		assert_relative_eq!(pos(&heads[ThighR]), hip, epsilon = 1e-5);
+		assert!(top.y > hip.y);
+
+		// The arms reach straight out to the sides
+		let arm = sum(&[UpperArmL, ForearmL, WristL]);
+		for (kind, side) in [(WristL, -right), (WristR, right)] {
+			assert_relative_eq!(
+				tail(&heads[kind], lengths[kind]).0,
+				shoulder + side * arm,
+				epsilon = 1e-5
+			);
+		}
+
+		// The ankles stand on the floor below the hips, and the feet point forward
+		for (ankle, foot) in [(AnkleL, FootL), (AnkleR, FootR)] {
+			let floor = hip - up * sum(&[ThighL, ankle]);
+			assert_relative_eq!(floor, Point::origin(), epsilon = 1e-5);
+			assert_relative_eq!(pos(&heads[foot]), floor, epsilon = 1e-5);
+			assert_relative_eq!(
+				tail(&heads[foot], lengths[foot]).0,
+				floor + forward * lengths[foot],
+				epsilon = 1e-5
+			);
+		}
+	}
+
+	#[test]
+	fn local_global_round_trip() {
+		// Every bone turned some different way, so that each one's local rotation
+		// depends on the whole chain above it
+		let globals = BoneMap::new([(); BoneKind::NUM_TYPES]).map(|kind, _| {
+			let i = kind as u8 as f32;
+			Global(UnitQuat::from_euler_angles(
+				0.3 * i,
+				-0.2 * i + 1.,
+				0.7 - 0.1 * i,
+			))
+		});
+		let locals = local_rotations(&globals);
+		let round_trip = global_rotations(&locals);
+		for kind in BoneKind::iter() {
+			assert_relative_eq!(round_trip[kind].0, globals[kind].0, epsilon = 1e-5);
+		}
+		// The root has no parent to be relative to
+		assert_eq!(locals[BoneKind::Neck].0, globals[BoneKind::Neck].0);
+
+		// Down the chain from the neck to the left foot, each local rotation is the
+		// difference between the bone and its parent
+		let mut kind = BoneKind::FootL;
+		while let Some(parent) = kind.parent() {
+			assert_relative_eq!(
+				globals[parent].0 * locals[kind].0,
+				globals[kind].0,
+				epsilon = 1e-5
+			);
+			kind = parent;
+		}
+		assert_eq!(kind, BoneKind::Neck);
+	}
+
+	/// Follows the bones of `ik` from `root`, like forward kinematics would
+	fn ik_end(root: &Point, ik: &TwoBoneIk, lengths: (f32, f32)) -> Point {
+		let upper = Global(Isometry::from_parts(root.coords.into(), ik.upper.0));
+		let joint = tail(&upper, lengths.0);
+		let lower = Global(Isometry::from_parts(joint.0.coords.into(), ik.lower.0));
+		tail(&lower, lengths.1).0
+	}
+
+	/// An arm held out to the left with the forearm pointing forwards, like a
+	/// "muscle" pose
+	#[test]
+	fn solve_arm_from_rotations() {
+		let mut rots =
+			BoneMap::new([Global(UnitQuat::identity()); BoneKind::NUM_TYPES]);
+		// Rotates down to the left
+		rots[BoneKind::UpperArmL] =
+			Global(UnitQuat::from_axis_angle(&Vector3::z_axis(), -FRAC_PI_2));
+		// Rotates down to forward
+		rots[BoneKind::ForearmL] =
+			Global(UnitQuat::from_axis_angle(&Vector3::x_axis(), FRAC_PI_2));
+		let mut lengths = BoneMap::new([0.1; BoneKind::NUM_TYPES]);
+		lengths[BoneKind::UpperArmL] = 0.3;
+		lengths[BoneKind::ForearmL] = 0.25;
+
+		let root = Global(Point::new(0., 1.6, 0.));
+		let heads = solve_from_rotations(&root, &rots, &lengths);
+
+		// The neck hangs down 0.1, then the upper arm goes 0.3 left, and the forearm
+		// 0.25 forwards
+		let shoulder = Point::new(0., 1.5, 0.);
+		let elbow = Point::new(-0.3, 1.5, 0.);
+		let wrist = Point::new(-0.3, 1.5, -0.25);
+		assert_relative_eq!(pos(&heads[BoneKind::UpperArmL]), shoulder);
+		assert_relative_eq!(pos(&heads[BoneKind::ForearmL]), elbow, epsilon = 1e-6);
+		assert_relative_eq!(pos(&heads[BoneKind::WristL]), wrist, epsilon = 1e-6);
+		assert_eq!(heads[BoneKind::WristL].0.rotation, UnitQuat::identity());
+
+		// The same pose with local rotations gives the same result
+		let local_rots = local_rotations(&rots);
+		let root = Global(Isometry::translation(0., 1.6, 0.));
+		let fk = forward_kinematics(&root, &local_rots, &lengths);
+		for kind in BoneKind::iter() {
+			assert_relative_eq!(fk[kind].0, heads[kind].0, epsilon = 1e-6);
+		}
+	}
+
+	#[test]
+	fn ik_reaches_target() {
+		let shoulder = Point::new(0.2, 1.5, 0.);
+		let backward = -forward_vec().into_inner();
+		let (upper_len, lower_len) = (0.3, 0.25);
+		for target in [
+			Point::new(0.5, 1.3, -0.1),
+			Point::new(0.2, 1.0, 0.),
+			Point::new(0., 1.6, -0.3),
+			Point::new(0.3, 1.5, 0.),
+		] {
+			let ik = two_bone_ik(
+				&Global(shoulder),
+				&Global(target),
+				&backward,
+				upper_len,
+				lower_len,
+			);
+			assert_relative_eq!(ik.end.0, target, epsilon = 1e-5);
+			assert_relative_eq!(
+				ik_end(&shoulder, &ik, (upper_len, lower_len)),
+				target,
+				epsilon = 1e-5
+			);
+			assert_relative_eq!(
+				tail(
+					&Global(Isometry::from_parts(shoulder.coords.into(), ik.upper.0)),
+					upper_len
+				)
+				.0,
+				ik.joint.0,
+				epsilon = 1e-5
+			);
+			// The elbow bends backwards, relative to the line to the target
+			let dir = Unit::new_normalize(target - shoulder);
+			assert!(reject(&(ik.joint.0 - shoulder), &dir).dot(&backward) > 0.);
+		}
+	}
+
+	#[test]
+	fn ik_out_of_reach() {
+		let shoulder = Point::origin();
+		let target = Point::new(3., 0., 4.);
+		let ik = two_bone_ik(&Global(shoulder), &Global(target), &up_vec(), 1., 1.5);
+
+		// Fully extended towards the target
+		let expected = Point::new(0.6 * 2.5, 0., 0.8 * 2.5);
+		assert_relative_eq!(ik.end.0, expected, epsilon = 1e-5);
+		assert_relative_eq!(
+			ik_end(&shoulder, &ik, (1., 1.5)),
+			expected,
+			epsilon = 1e-5
+		);
+		assert_relative_eq!(ik.upper.0, ik.lower.0, epsilon = 1e-5);
+	}
+
+	#[test]
+	fn ik_degenerate() {
+		let shoulder = Point::new(1., 1., 1.);
+		let down = -up_vec().into_inner();
+		let cases = [
+			// Exactly straight, bending along the arm
+			(shoulder + down * 2., down, 1., 1.),
+			// Target at the root
+			(shoulder, forward_vec().into_inner(), 1., 1.),
+			// Zero length bones
+			(shoulder + down, down, 0., 1.),
+			(shoulder + down, down, 1., 0.),
+			(shoulder, down, 0., 0.),
+		];
+		for (target, bend, upper_len, lower_len) in cases {
+			let ik = two_bone_ik(
+				&Global(shoulder),
+				&Global(target),
+				&bend,
+				upper_len,
+				lower_len,
+			);
+			let end = ik_end(&shoulder, &ik, (upper_len, lower_len));
+			assert!(end.coords.iter().all(|x| x.is_finite()), "{ik:?}");
+			assert_relative_eq!(end, ik.end.0, epsilon = 1e-5);
+		}
+	}
+}
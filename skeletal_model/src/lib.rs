@@ -58,7 +58,28 @@
 //!
 //! For an explanation of the mathematical conventions adopted in the codebase,
 //! see the [`conventions`] module.
+//!
+//! To compute the pose of the skeleton from bone rotations alone, or the rotations
+//! of an arm from the position of its hand, see the [`kinematics`] module. To keep
+//! bones within a human's range of motion, see the [`constraints`] module. To save and
+//! load poses, see the [`pose`] module. To correct tracker rotations for how they are
+//! mounted, see the [`calibration`] module. For default bone lengths, see the
+//! [`proportions`] module. To keep tracker quaternions from flipping sign between
+//! readings, see the [`continuity`] module, and for how fast they turn, the
+//! [`velocity`] module. To find the hips, the center of mass and the floor of a pose,
+//! see the [`grounding`] module.
+//!
+//!
+//! # `no_std`
+//!
+//! With the default `std` feature disabled and the `libm` feature enabled, the crate
+//! builds without the standard library or an allocator, so that poses can be checked
+//! on the trackers themselves. This leaves out the [`Skeleton`] and the `pose`
+//! module, but keeps the math of the [`conventions`], [`kinematics`], [`constraints`],
+//! [`calibration`], [`continuity`], [`velocity`] and [`grounding`] modules.
 
+// Tests always have the standard library
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 // These set linter options
 #![deny(
 	invalid_doc_attributes,
@@ -68,9 +89,20 @@
 )]
 
 pub mod bone;
+pub mod calibration;
+pub mod constraints;
+pub mod continuity;
 pub mod conventions;
+pub mod grounding;
+pub mod kinematics;
 mod newtypes;
+#[cfg(feature = "std")]
+pub mod pose;
 pub mod prelude;
+pub mod proportions;
+#[cfg(feature = "std")]
 pub mod skeleton;
+pub mod velocity;
 
+#[cfg(feature = "std")]
 pub use crate::skeleton::Skeleton;
@@ -3,7 +3,7 @@
 use crate::prelude::*;
 
 /// A newtype on `T` that indicates that it is a global transform.
-#[derive(Debug, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct Global<T: private::Sealed>(pub T);
 
 /// Implements `From<T> for $ident<T>`
@@ -20,7 +20,7 @@ impl_helper!(Global);
 impl_helper!(Local);
 
 /// A newtype on `T` that indicates that it is a local transform.
-#[derive(Debug, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct Local<T: private::Sealed>(pub T);
 
 mod private {
@@ -34,4 +34,5 @@ mod private {
 	impl Sealed for Translation {}
 	impl Sealed for UnitQuat {}
 	impl Sealed for Point {}
+	impl Sealed for Isometry {}
 }
@@ -0,0 +1,349 @@
+//! Saving and loading poses of the skeleton, for example to record a frame and compare
+//! it later, blending between them with [`lerp_pose()`], and mirroring them with
+//! [`mirror_pose()`].
+//!
+//! Poses are stored as JSON, with an entry for every [`BoneKind`] by name:
+//! ```json
+//! {
+//!   "Neck": {
+//!     "position": [0.0, 1.6, 0.0],
+//!     "rotation": [0.0, 0.0, 0.0, 1.0],
+//!     "length": 0.1
+//!   },
+//!   ...
+//! }
+//! ```
+//! Rotations are quaternions as `[x, y, z, w]`.
+
+use crate::prelude::*;
+
+use nalgebra::Quaternion;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// The pose of every bone of the skeleton.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "SerializedPose", try_from = "SerializedPose")]
+pub struct Pose {
+	/// The transform of each bone's head, like
+	/// [`forward_kinematics()`](crate::kinematics::forward_kinematics) produces
+	pub bones: BoneMap<Global<Isometry>>,
+	pub lengths: BoneMap<f32>,
+}
+impl Pose {
+	/// A pose with the head of each bone at `bones`, in global space
+	pub fn new(bones: BoneMap<Isometry>, lengths: BoneMap<f32>) -> Self {
+		Self {
+			bones: bones.map(|_, iso| Global(iso)),
+			lengths,
+		}
+	}
+
+	/// Writes the pose as JSON
+	pub fn save(&self, writer: impl Write) -> Result<(), serde_json::Error> {
+		serde_json::to_writer_pretty(writer, self)
+	}
+
+	/// Reads a pose from JSON written by [`Self::save()`]. Rotations are normalized.
+	pub fn load(reader: impl Read) -> Result<Self, serde_json::Error> {
+		serde_json::from_reader(reader)
+	}
+}
+
+/// Blends from pose `a` at `t = 0` to pose `b` at `t = 1`, for example to smooth out
+/// the skeleton between updates. `t` is clamped to that range, and its ends give
+/// exactly `a` and `b`.
+///
+/// Rotations are interpolated along the shortest path with slerp, even if their
+/// quaternions have opposite signs. Translations and lengths are interpolated
+/// linearly.
+pub fn lerp_pose(a: &Pose, b: &Pose, t: f32) -> Pose {
+	if t <= 0. {
+		return a.clone();
+	}
+	if t >= 1. {
+		return b.clone();
+	}
+
+	let bones = a.bones.map(|kind, head| {
+		let (a, b) = (head.0, b.bones[kind].0);
+		let translation = a.translation.vector.lerp(&b.translation.vector, t);
+		let rotation = slerp_shortest(&a.rotation, &b.rotation, t);
+		Global(Isometry::from_parts(translation.into(), rotation))
+	});
+	let lengths = a.lengths.map(|kind, len| len + (b.lengths[kind] - len) * t);
+	Pose { bones, lengths }
+}
+
+/// Reflects a pose across the YZ plane, which is the plane between the left and right
+/// halves of the body when facing [`forward_vec()`]. Left and right bones swap places,
+/// so for example a left arm raised to the side becomes a right arm raised to the
+/// side.
+pub fn mirror_pose(pose: &Pose) -> Pose {
+	let bones = pose.bones.map(|kind, _| {
+		let iso = pose.bones[kind.mirrored()].0;
+		let mut translation = iso.translation;
+		translation.x = -translation.x;
+		Global(Isometry::from_parts(
+			translation,
+			mirror_rotation(&iso.rotation),
+		))
+	});
+	let lengths = pose.lengths.map(|kind, _| pose.lengths[kind.mirrored()]);
+	Pose { bones, lengths }
+}
+
+/// Reflects a rotation across the YZ plane.
+///
+/// Rotations can't be reflected directly, since a reflection flips the handedness of
+/// the coordinate system. Instead, this is the rotation `r'` such that reflecting `v`
+/// and then rotating it by `r'` gives the same result as rotating `v` by `r` and then
+/// reflecting it. The rotation axis is a pseudovector, so reflecting it negates the
+/// components *in* the plane instead of the one across it, and the angle stays the
+/// same.
+fn mirror_rotation(rot: &UnitQuat) -> UnitQuat {
+	let q = rot.quaternion();
+	UnitQuat::new_unchecked(Quaternion::new(q.w, q.i, -q.j, -q.k))
+}
+
+/// Slerp between `a` and `b`, along the shortest path
+fn slerp_shortest(a: &UnitQuat, b: &UnitQuat, t: f32) -> UnitQuat {
+	// `q` and `-q` are the same rotation, but slerp takes the long way around between
+	// quaternions that point away from each other
+	let b = if a.coords.dot(&b.coords) < 0. {
+		-b.into_inner()
+	} else {
+		b.into_inner()
+	};
+	// Fails when `a` and `b` are almost the same, where linear interpolation is
+	// accurate enough anyway
+	a.try_slerp(&UnitQuat::new_unchecked(b), t, 1e-6)
+		.unwrap_or_else(|| UnitQuat::new_normalize(a.quaternion().lerp(&b, t)))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PoseError {
+	#[error("Pose is missing bones: {0:?}")]
+	MissingBones(Vec<BoneKind>),
+	#[error("Rotation of {0:?} is zero")]
+	ZeroRotation(BoneKind),
+}
+
+/// The format of a [`Pose`] on disk. Ordered by `BoneKind`, so that saving the same
+/// pose twice gives the same file.
+type SerializedPose = BTreeMap<BoneKind, SerializedBone>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SerializedBone {
+	position: [f32; 3],
+	/// `[x, y, z, w]`
+	rotation: [f32; 4],
+	length: f32,
+}
+
+impl From<Pose> for SerializedPose {
+	fn from(pose: Pose) -> Self {
+		pose.bones
+			.iter()
+			.map(|(kind, iso)| {
+				let q = iso.0.rotation.quaternion();
+				let bone = SerializedBone {
+					position: iso.0.translation.vector.into(),
+					rotation: [q.i, q.j, q.k, q.w],
+					length: pose.lengths[kind],
+				};
+				(kind, bone)
+			})
+			.collect()
+	}
+}
+
+impl TryFrom<SerializedPose> for Pose {
+	type Error = PoseError;
+
+	fn try_from(mut other: SerializedPose) -> Result<Self, Self::Error> {
+		let missing: Vec<_> = BoneKind::iter()
+			.filter(|kind| !other.contains_key(kind))
+			.collect();
+		if !missing.is_empty() {
+			return Err(PoseError::MissingBones(missing));
+		}
+
+		let mut bones =
+			BoneMap::new([Global(Isometry::identity()); BoneKind::NUM_TYPES]);
+		let mut lengths = BoneMap::<f32>::default();
+		for kind in BoneKind::iter() {
+			let bone = other.remove(&kind).unwrap();
+			let [x, y, z, w] = bone.rotation;
+			let rotation = UnitQuat::try_new(Quaternion::new(w, x, y, z), 1e-6)
+				.ok_or(PoseError::ZeroRotation(kind))?;
+			let translation = Translation::from(bone.position);
+			bones[kind] = Global(Isometry::from_parts(translation, rotation));
+			lengths[kind] = bone.length;
+		}
+		Ok(Self { bones, lengths })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use approx::assert_relative_eq;
+	use nalgebra::Vector3;
+	use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+	fn pose() -> Pose {
+		let mut pose = Pose {
+			bones: BoneMap::new([Global(Isometry::identity()); BoneKind::NUM_TYPES]),
+			lengths: BoneMap::default(),
+		};
+		for kind in BoneKind::iter() {
+			let i = kind as u8 as f32;
+			let rotation = UnitQuat::from_euler_angles(0.1 * i, -0.2, 0.05 * i);
+			let translation = Translation::new(i, -0.5 * i, 1. / (i + 1.));
+			pose.bones[kind] = Global(Isometry::from_parts(translation, rotation));
+			pose.lengths[kind] = 0.1 + 0.01 * i;
+		}
+		pose
+	}
+
+	#[test]
+	fn round_trip() {
+		let pose = pose();
+		let mut json = Vec::new();
+		pose.save(&mut json).unwrap();
+		let loaded = Pose::load(json.as_slice()).unwrap();
+
+		for kind in BoneKind::iter() {
+			let (expected, actual) = (pose.bones[kind].0, loaded.bones[kind].0);
+			assert_relative_eq!(actual, expected, epsilon = 1e-6);
+			assert_relative_eq!(actual.rotation.norm(), 1., epsilon = 1e-6);
+			assert_relative_eq!(loaded.lengths[kind], pose.lengths[kind]);
+		}
+	}
+
+	#[test]
+	fn load_normalizes_rotations() {
+		let mut json = serde_json::to_value(pose()).unwrap();
+		json["Chest"]["rotation"] = serde_json::json!([0., 0., 0., 2.]);
+		let loaded: Pose = serde_json::from_value(json).unwrap();
+		assert_eq!(
+			loaded.bones[BoneKind::Chest].0.rotation,
+			UnitQuat::identity()
+		);
+	}
+
+	#[test]
+	fn lerp_ends_are_exact() {
+		let a = pose();
+		let mut b = pose();
+		for kind in BoneKind::iter() {
+			let iso = &mut b.bones[kind].0;
+			iso.translation.vector *= 2.;
+			iso.rotation = UnitQuat::from_euler_angles(1., 0.5, -2.) * iso.rotation;
+			b.lengths[kind] += 0.2;
+		}
+
+		assert_eq!(lerp_pose(&a, &b, 0.), a);
+		assert_eq!(lerp_pose(&a, &b, 1.), b);
+		assert_eq!(lerp_pose(&a, &b, -1.), a);
+		assert_eq!(lerp_pose(&a, &b, 2.), b);
+	}
+
+	#[test]
+	fn lerp_takes_shortest_path() {
+		let mut a = pose();
+		let mut b = pose();
+		let quarter_turn = UnitQuat::from_axis_angle(&up_vec(), FRAC_PI_2);
+		for kind in BoneKind::iter() {
+			a.bones[kind].0 = Isometry::identity();
+			// The same rotation, but with a negated quaternion
+			let flipped = UnitQuat::new_unchecked(-quarter_turn.into_inner());
+			b.bones[kind].0 =
+				Isometry::from_parts(Translation::new(2., 0., 0.), flipped);
+			a.lengths[kind] = 1.;
+			b.lengths[kind] = 2.;
+		}
+
+		let half = lerp_pose(&a, &b, 0.5);
+		let eighth_turn = UnitQuat::from_axis_angle(&up_vec(), FRAC_PI_4);
+		for kind in BoneKind::iter() {
+			let iso = half.bones[kind].0;
+			assert_relative_eq!(iso.rotation, eighth_turn, epsilon = 1e-6);
+			assert_relative_eq!(iso.translation.vector.x, 1.);
+			assert_relative_eq!(half.lengths[kind], 1.5);
+		}
+
+		// Nearly identical rotations don't trip up slerp
+		let tiny = UnitQuat::from_axis_angle(&up_vec(), 1e-7);
+		let q = slerp_shortest(&UnitQuat::identity(), &tiny, 0.5);
+		assert_relative_eq!(q, UnitQuat::identity(), epsilon = 1e-6);
+	}
+
+	#[test]
+	fn mirror_twice_is_identity() {
+		let pose = pose();
+		assert_eq!(mirror_pose(&mirror_pose(&pose)), pose);
+	}
+
+	#[test]
+	fn mirror_swaps_sides() {
+		let mut pose = pose();
+		// A spine bone in the middle of the body, pitched forwards, is unchanged
+		let chest = Isometry::from_parts(
+			Translation::new(0., 1.2, 0.1),
+			UnitQuat::from_axis_angle(&right_vec(), 0.3),
+		);
+		pose.bones[BoneKind::Chest] = Global(chest);
+		// A left thigh spread outwards to the left
+		let thigh = Isometry::from_parts(
+			Translation::new(-0.1, 0.9, 0.),
+			UnitQuat::from_axis_angle(&forward_vec(), 0.4),
+		);
+		pose.bones[BoneKind::ThighL] = Global(thigh);
+		pose.lengths[BoneKind::ThighL] = 0.45;
+
+		let mirrored = mirror_pose(&pose);
+		assert_relative_eq!(mirrored.bones[BoneKind::Chest].0, chest);
+		// Becomes a right thigh spread outwards to the right
+		let right_thigh = Isometry::from_parts(
+			Translation::new(0.1, 0.9, 0.),
+			UnitQuat::from_axis_angle(&forward_vec(), -0.4),
+		);
+		assert_relative_eq!(mirrored.bones[BoneKind::ThighR].0, right_thigh);
+		assert_eq!(mirrored.lengths[BoneKind::ThighR], 0.45);
+	}
+
+	#[test]
+	fn mirror_rotation_commutes_with_reflection() {
+		let reflect = |v: Vector3<f32>| Vector3::new(-v.x, v.y, v.z);
+		let rot = UnitQuat::from_euler_angles(0.3, -1.2, 0.7);
+		let mirrored = mirror_rotation(&rot);
+		for v in [
+			Vector3::x(),
+			Vector3::y(),
+			Vector3::z(),
+			Vector3::new(1., -2., 3.),
+		] {
+			assert_relative_eq!(
+				mirrored * reflect(v),
+				reflect(rot * v),
+				epsilon = 1e-6
+			);
+		}
+	}
+
+	#[test]
+	fn load_errors() {
+		let mut json = serde_json::to_value(pose()).unwrap();
+		json["Hip"]["rotation"] = serde_json::json!([0., 0., 0., 0.]);
+		let err = serde_json::from_value::<Pose>(json.clone()).unwrap_err();
+		assert!(err.to_string().contains("Hip"), "{err}");
+
+		json.as_object_mut().unwrap().remove("WristL");
+		let err = serde_json::from_value::<Pose>(json).unwrap_err();
+		assert!(err.to_string().contains("WristL"), "{err}");
+	}
+}
@@ -5,7 +5,11 @@
 pub type Translation = nalgebra::Translation3<f32>;
 pub type UnitQuat = nalgebra::UnitQuaternion<f32>;
 pub type Point = nalgebra::Point3<f32>;
+pub type Isometry = nalgebra::Isometry3<f32>;
 
 pub use crate::bone::{BoneKind, BoneMap};
 pub(crate) use crate::conventions::{forward_vec, right_vec, up_vec};
 pub(crate) use crate::newtypes::{Global, Local};
+/// Float math that `core` doesn't have, like `sqrt()`
+#[cfg(not(feature = "std"))]
+pub(crate) use num_traits::Float;
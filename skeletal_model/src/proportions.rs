@@ -0,0 +1,116 @@
+//! Default lengths of the bones, for when the real ones aren't known.
+//!
+//! Lengths are proportions of the height of the user, roughly following the
+//! anthropometric tables of Drillis and Contini, so that they scale with the user.
+//! [`Proportions`] turns them into meters for a given height, and replaces lengths
+//! that can't be right, such as zero or NaN, with the defaults.
+
+use crate::prelude::*;
+
+/// The height of the user when it isn't known, in meters.
+pub const DEFAULT_HEIGHT: f32 = 1.7;
+
+impl BoneKind {
+	/// The typical length of the bone, as a fraction of the height of the user.
+	pub const fn proportion(self) -> f32 {
+		use BoneKind::*;
+		match self {
+			Neck => 0.08,
+			Chest => 0.17,
+			Waist => 0.10,
+			Hip => 0.05,
+			ThighL | ThighR => 0.245,
+			AnkleL | AnkleR => 0.246,
+			FootL | FootR => 0.08,
+
+			UpperArmL | UpperArmR => 0.186,
+			ForearmL | ForearmR => 0.146,
+			WristL | WristR => 0.108,
+		}
+	}
+}
+
+/// The default bone lengths of someone [`Self::height`] meters tall.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Proportions {
+	/// In meters.
+	pub height: f32,
+}
+impl Proportions {
+	pub const fn new(height: f32) -> Self {
+		Self { height }
+	}
+
+	/// The default length of `kind`, in meters.
+	pub fn length(&self, kind: BoneKind) -> f32 {
+		kind.proportion() * self.height
+	}
+
+	/// Returns `length` if it is a usable length for `kind`, or the default length if
+	/// it isn't finite or isn't positive.
+	pub fn sanitize_length(&self, kind: BoneKind, length: f32) -> f32 {
+		if length.is_finite() && length > 0. {
+			length
+		} else {
+			self.length(kind)
+		}
+	}
+
+	/// The default length of every bone.
+	pub fn lengths(&self) -> BoneMap<f32> {
+		let mut lengths = BoneMap::<f32>::default();
+		for kind in BoneKind::iter() {
+			lengths[kind] = self.length(kind);
+		}
+		lengths
+	}
+}
+impl Default for Proportions {
+	fn default() -> Self {
+		Self::new(DEFAULT_HEIGHT)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use approx::assert_relative_eq;
+
+	#[test]
+	fn sanitize_length() {
+		let proportions = Proportions::default();
+		for kind in BoneKind::iter() {
+			let default = proportions.length(kind);
+			assert!(default > 0.);
+			for bad in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY, 0., -0., -0.3] {
+				assert_eq!(proportions.sanitize_length(kind, bad), default, "{bad}");
+			}
+			for good in [f32::MIN_POSITIVE, 0.05, 0.3, 2.] {
+				assert_eq!(proportions.sanitize_length(kind, good), good);
+			}
+		}
+	}
+
+	#[test]
+	fn scales_with_height() {
+		let short = Proportions::new(1.5);
+		let tall = Proportions::new(1.8);
+		for kind in BoneKind::iter() {
+			assert_relative_eq!(short.length(kind) / 1.5, tall.length(kind) / 1.8);
+			assert_eq!(tall.sanitize_length(kind, f32::NAN), tall.length(kind));
+		}
+	}
+
+	/// The bones from the neck down to the ankle should add up to a bit less than the
+	/// height, leaving room for the head and the foot
+	#[test]
+	fn leg_and_torso_fit_height() {
+		use BoneKind::*;
+		let fraction: f32 = [Neck, Chest, Waist, Hip, ThighL, AnkleL]
+			.iter()
+			.map(|kind| kind.proportion())
+			.sum();
+		assert!((0.8..0.95).contains(&fraction), "{fraction}");
+	}
+}
@@ -0,0 +1,125 @@
+//! Angular velocity of a rotation that changes over time, for predicting where a
+//! tracker will be next or telling whether it is holding still.
+//!
+//! The angular velocity is a rotation vector: its direction is the axis of rotation and
+//! its length is the speed in radians per second, turning counterclockwise around the
+//! axis like [`UnitQuat::from_axis_angle()`].
+
+use crate::prelude::*;
+
+use nalgebra::Vector3;
+
+/// Below this length of the vector part of a quaternion, its angle is computed with
+/// the small angle approximation instead of `atan2()`.
+const SMALL_ANGLE: f32 = 1e-6;
+
+/// The constant angular velocity that turns `prev` into `curr` over `dt` seconds, in
+/// radians per second.
+///
+/// The velocity is in the frame that the rotations are relative to. For the global
+/// rotation of a tracker that is the global frame of the [`conventions`] module, and
+/// `prev.inverse() * velocity` turns it into the frame of the tracker itself.
+///
+/// The rotation always takes the short way around, so `curr` and `-curr` give the same
+/// velocity, and rotations of more than half a turn per `dt` can't be told apart from
+/// slower ones in the opposite direction. Returns zero if `dt` isn't a positive number.
+///
+/// [`conventions`]: crate::conventions
+pub fn angular_velocity(prev: UnitQuat, curr: UnitQuat, dt: f32) -> Vector3<f32> {
+	if !(dt.is_finite() && dt > 0.) {
+		return Vector3::zeros();
+	}
+	let delta = curr * prev.inverse();
+	// `q` and `-q` are the same rotation, pick the one with the smaller angle
+	let (w, v) = if delta.w < 0. {
+		(-delta.w, -delta.imag())
+	} else {
+		(delta.w, delta.imag())
+	};
+	let sin_half = v.norm();
+	// The rotation vector is `v / sin(angle / 2) * angle`. `atan2()` is accurate all
+	// the way to zero, but dividing by the tiny `sin_half` isn't
+	let scale = if sin_half < SMALL_ANGLE {
+		2. / w
+	} else {
+		2. * sin_half.atan2(w) / sin_half
+	};
+	v * (scale / dt)
+}
+
+/// Turns `q` at the angular `velocity` for `dt` seconds, to predict where it will be
+/// if it keeps turning like it did. Undoes [`angular_velocity()`], in the same frame.
+pub fn extrapolate(q: UnitQuat, velocity: Vector3<f32>, dt: f32) -> UnitQuat {
+	UnitQuat::from_scaled_axis(velocity * dt) * q
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use approx::assert_relative_eq;
+
+	#[test]
+	fn constant_rate() {
+		let axis = nalgebra::Unit::new_normalize(Vector3::new(1., 2., -0.5));
+		let rate = 3.;
+		let dt = 0.01;
+		let expected = axis.into_inner() * rate;
+		let at = |t: f32| UnitQuat::from_axis_angle(&axis, rate * t);
+		for i in 0..300 {
+			let t = i as f32 * dt;
+			let omega = angular_velocity(at(t), at(t + dt), dt);
+			assert_relative_eq!(omega, expected, epsilon = 1e-2);
+			// The sign of the quaternion doesn't matter
+			let flipped = UnitQuat::new_unchecked(-at(t + dt).into_inner());
+			assert_relative_eq!(
+				angular_velocity(at(t), flipped, dt),
+				expected,
+				epsilon = 1e-2
+			);
+		}
+	}
+
+	#[test]
+	fn extrapolate_constant_rate() {
+		let axis = nalgebra::Unit::new_normalize(Vector3::new(-0.3, 1., 0.2));
+		let rate = 5.;
+		let (dt, lead) = (0.01, 0.004);
+		let at = |t: f32| UnitQuat::from_axis_angle(&axis, rate * t);
+		for i in 1..100 {
+			let t = i as f32 * dt;
+			let omega = angular_velocity(at(t - dt), at(t), dt);
+			let predicted = extrapolate(at(t), omega, lead);
+			assert!(predicted.angle_to(&at(t + lead)) < 1e-3, "{t}");
+		}
+		let q = at(0.3);
+		assert_eq!(extrapolate(q, Vector3::zeros(), lead), q);
+	}
+
+	#[test]
+	fn near_identity() {
+		let q = UnitQuat::from_euler_angles(0.3, -1.2, 2.);
+		assert!(angular_velocity(q, q, 0.01).norm() < 1e-5);
+
+		let axis = Vector3::z_axis();
+		let tiny = UnitQuat::from_axis_angle(&axis, 1e-7) * q;
+		let omega = angular_velocity(q, tiny, 0.01);
+		assert!(omega.iter().all(|x| x.is_finite()), "{omega:?}");
+		assert!(omega.norm() < 1e-4, "{omega:?}");
+
+		let small = UnitQuat::from_axis_angle(&axis, 1e-3);
+		let omega = angular_velocity(UnitQuat::identity(), small, 0.01);
+		assert_relative_eq!(omega, Vector3::new(0., 0., 0.1), epsilon = 1e-4);
+	}
+
+	#[test]
+	fn bad_dt() {
+		let q = UnitQuat::from_euler_angles(0.1, 0.2, 0.3);
+		for dt in [0., -0.01, f32::NAN, f32::INFINITY] {
+			assert_eq!(
+				angular_velocity(UnitQuat::identity(), q, dt),
+				Vector3::zeros()
+			);
+		}
+	}
+}